@@ -0,0 +1,159 @@
+use std::rc::Rc;
+
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{screen, texture};
+
+/*
+*   Straight vs premultiplied alpha, see texture::Alpha. The same small sprite, an orange
+*   disc that fades out towards its edge, is loaded both ways and drawn blown up side by side
+*   over the finished frame: straight on the left, premultiplied on the right.
+*
+*   The sprite is saved the way most tools save PNGs, with black in the transparent pixels.
+*   Magnified with linear filtering, the straight copy picks up a dark ring where the orange
+*   texels blend with the black ones. The premultiplied copy fades out cleanly.
+*/
+pub struct AlphaDemo {
+    pub enabled: bool,
+    sprites: [Sprite; 2],
+}
+
+struct Sprite {
+    // Kept with its bind group, it's what the bind group points at
+    _texture: texture::Texture,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl AlphaDemo {
+    // Texels across the sprite. Small on purpose, the filtering is what we want to see.
+    const SPRITE_SIZE: u32 = 32;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("alpha_demo_bind_group_layout"),
+        });
+
+        let image = Self::soft_disc();
+        let sprites = [texture::Alpha::Straight, texture::Alpha::Premultiplied].map(|alpha| {
+            let texture = texture::Texture::from_image(
+                device,
+                queue,
+                &image,
+                &format!("{:?} Sprite", alpha),
+                texture::TextureKind::Color,
+                alpha,
+            );
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+                label: Some("alpha_demo_bind_group"),
+            });
+            let pipeline = pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label: "Alpha Demo",
+                    shader: include_str!("alpha_demo.wgsl"),
+                    bind_group_layouts: &[screen_layout, &bind_group_layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[],
+                    fragment_entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(alpha.blend_state()),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            );
+            Sprite {
+                _texture: texture,
+                bind_group,
+                pipeline,
+            }
+        });
+
+        Self {
+            enabled: false,
+            sprites,
+        }
+    }
+
+    // An orange disc, opaque in the middle and fading to nothing at the edge, with straight alpha
+    fn soft_disc() -> image::DynamicImage {
+        let size = Self::SPRITE_SIZE;
+        let image = image::RgbaImage::from_fn(size, size, |x, y| {
+            let center = size as f32 * 0.5;
+            let distance = ((x as f32 + 0.5 - center).powi(2) + (y as f32 + 0.5 - center).powi(2)).sqrt() / center;
+            // Solid out to half the radius, then a smooth fade
+            let t = ((1.0 - distance) / 0.5).clamp(0.0, 1.0);
+            let alpha = t * t * (3.0 - 2.0 * t);
+            if alpha > 0.0 {
+                image::Rgba([255, 140, 0, (alpha * 255.0).round() as u8])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        });
+        image::DynamicImage::ImageRgba8(image)
+    }
+
+    // Draws over whatever is in `output` already
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        if !self.enabled {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Alpha Demo Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        for (i, sprite) in self.sprites.iter().enumerate() {
+            let instance = i as u32;
+            render_pass.set_pipeline(&sprite.pipeline);
+            render_pass.set_bind_group(1, &sprite.bind_group, &[]);
+            render_pass.draw(0..6, instance..instance + 1);
+        }
+    }
+}
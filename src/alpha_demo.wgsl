@@ -0,0 +1,52 @@
+// Two copies of a soft edged sprite side by side, see alpha_demo.rs. Instance 0 goes on the
+// left and 1 on the right, each is drawn with its own texture & blend state.
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_sprite: texture_2d<f32>;
+@group(1) @binding(1)
+var s_sprite: sampler;
+
+// Pixels across each sprite, and between their centers
+const SPRITE_SIZE: f32 = 256.0;
+const SPACING: f32 = 320.0;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+    @builtin(instance_index) in_instance_index: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    let center_x = (f32(in_instance_index) - 0.5) * SPACING;
+    let pixel = vec2<f32>(center_x, 0.0) + (corner - 0.5) * SPRITE_SIZE;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(pixel * 2.0 * screen.inv_resolution, 0.0, 1.0);
+    // Texture v goes down, clip space y goes up
+    out.tex_coords = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_sprite, s_sprite, in.tex_coords);
+}
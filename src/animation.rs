@@ -0,0 +1,166 @@
+use cgmath::prelude::*;
+
+use crate::instance::Transform;
+
+/*
+*   Keyframe animation for transforms. A track is a list of (time, Transform) keyframes
+*   sorted by time. Sampling it finds the two keyframes around `t` and blends between them:
+*   position & scale are linearly interpolated, rotation is slerped so it turns at a
+*   constant speed along the shortest arc.
+*/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlaybackMode {
+    // Wrap around to the start once we run off the end
+    Loop,
+    // Hold the first/last keyframe outside of the track's time range
+    Clamp,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationTrack {
+    keyframes: Vec<(f32, Transform)>,
+    pub mode: PlaybackMode,
+}
+
+impl AnimationTrack {
+    pub fn new(mut keyframes: Vec<(f32, Transform)>, mode: PlaybackMode) -> Self {
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keyframes, mode }
+    }
+
+    pub fn start_time(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |k| k.0)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.0) - self.start_time()
+    }
+
+    pub fn sample(&self, t: f32) -> Transform {
+        let (first, last) = match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => (first, last),
+            // Nothing to animate
+            _ => return Transform::default(),
+        };
+
+        let duration = self.duration();
+        let t = match self.mode {
+            PlaybackMode::Loop if duration > 0.0 => first.0 + (t - first.0).rem_euclid(duration),
+            _ => t,
+        };
+
+        // Before the first or after the last keyframe we just hold it. This also covers
+        // tracks with a single keyframe.
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+
+        // Index of the first keyframe after t, there's always one before it as t > first.0
+        let next = self.keyframes.partition_point(|(time, _)| *time <= t);
+        let (t0, a) = self.keyframes[next - 1];
+        let (t1, b) = self.keyframes[next];
+        let amount = (t - t0) / (t1 - t0);
+
+        Transform {
+            position: a.position.lerp(b.position, amount),
+            rotation: a.rotation.slerp(b.rotation, amount),
+            scale: a.scale.lerp(b.scale, amount),
+        }
+    }
+
+    // A cube hopping around a circle while spinning, used to show the animation system off
+    pub fn demo_orbit(radius: f32, height: f32) -> Self {
+        let keyframes = (0..=8)
+            .map(|i| {
+                let angle = cgmath::Deg(45.0 * i as f32);
+                // Touch down on even keyframes, peak of the hop on odd ones
+                let y = if i % 2 == 0 { height } else { height + 1.0 };
+                // Squash when landing, stretch in the air
+                let scale = if i % 2 == 0 {
+                    cgmath::Vector3::new(1.2, 0.8, 1.2)
+                } else {
+                    cgmath::Vector3::new(0.9, 1.2, 0.9)
+                };
+                let transform = Transform {
+                    position: cgmath::Vector3::new(radius * angle.cos(), y, radius * angle.sin()),
+                    rotation: cgmath::Quaternion::from_angle_y(-angle),
+                    scale,
+                };
+                (i as f32 * 0.5, transform)
+            })
+            .collect();
+        Self::new(keyframes, PlaybackMode::Loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32) -> Transform {
+        Transform::from_position(cgmath::Vector3::new(x, 0.0, 0.0))
+    }
+
+    fn track(mode: PlaybackMode) -> AnimationTrack {
+        // Out of order on purpose, new() sorts them
+        AnimationTrack::new(vec![(3.0, at(10.0)), (1.0, at(0.0)), (2.0, at(4.0))], mode)
+    }
+
+    #[test]
+    fn holds_the_ends_when_clamped() {
+        let track = track(PlaybackMode::Clamp);
+        assert_eq!(track.sample(-5.0), at(0.0));
+        assert_eq!(track.sample(0.5), at(0.0));
+        assert_eq!(track.sample(3.5), at(10.0));
+        assert_eq!(track.sample(100.0), at(10.0));
+    }
+
+    #[test]
+    fn wraps_when_looped() {
+        let track = track(PlaybackMode::Loop);
+        // Half a key before the start is half a key before the end
+        assert_eq!(track.sample(0.5).position.x, 7.0);
+        // Once round & a half key more
+        assert_eq!(track.sample(3.5).position.x, 2.0);
+    }
+
+    #[test]
+    fn exactly_on_a_key() {
+        for mode in [PlaybackMode::Clamp, PlaybackMode::Loop] {
+            let track = track(mode);
+            assert_eq!(track.sample(1.0), at(0.0));
+            assert_eq!(track.sample(2.0), at(4.0));
+            // The loop's end is its start again
+            let end = if mode == PlaybackMode::Loop { at(0.0) } else { at(10.0) };
+            assert_eq!(track.sample(3.0), end);
+        }
+    }
+
+    #[test]
+    fn between_keys() {
+        let track = track(PlaybackMode::Clamp);
+        assert_eq!(track.sample(1.25).position.x, 1.0);
+        assert_eq!(track.sample(2.5).position.x, 7.0);
+    }
+
+    #[test]
+    fn single_key() {
+        for mode in [PlaybackMode::Clamp, PlaybackMode::Loop] {
+            let track = AnimationTrack::new(vec![(2.0, at(5.0))], mode);
+            assert_eq!(track.duration(), 0.0);
+            for t in [-1.0, 2.0, 7.0] {
+                assert_eq!(track.sample(t), at(5.0));
+            }
+        }
+    }
+
+    #[test]
+    fn no_keys() {
+        let track = AnimationTrack::new(Vec::new(), PlaybackMode::Loop);
+        assert_eq!(track.sample(1.0), Transform::default());
+    }
+}
@@ -0,0 +1,404 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::TrackedDevice;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{camera, post, texture};
+
+/*
+*   A few ways of getting rid of jagged edges, to compare side by side.
+*
+*   MSAA (multisample anti-aliasing) runs the depth test & stores color for several points
+*   inside every pixel, but only runs the fragment shader once per pixel. The main pass draws
+*   into multisampled color & depth textures, and the color gets averaged ("resolved") into the
+*   regular target at the end of the pass. It only smooths geometry edges, and every pipeline
+*   used in that pass has to be built with the same sample count.
+*
+*   FXAA (fast approximate anti-aliasing) is a post effect instead. It looks for sharp changes
+*   in brightness in the finished image and blurs along them. It's nearly free and catches
+*   aliasing inside textures & shading too, but it can't tell an edge from detail that was
+*   meant to be sharp, so it softens the whole image a little.
+*
+*   TAA (temporal anti-aliasing) spreads the samples over several frames instead of inside a
+*   single one, see taa.rs. It lives in its own module since it needs the velocity buffer.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AaMode {
+    None,
+    // Samples per pixel
+    Msaa(u32),
+    Fxaa,
+    Taa,
+}
+
+impl AaMode {
+    // What the scene pipelines & the main pass' attachments need
+    pub fn sample_count(self) -> u32 {
+        match self {
+            AaMode::Msaa(count) => count,
+            _ => 1,
+        }
+    }
+
+    // None -> 4x MSAA -> FXAA -> TAA -> None. 4 is the one sample count WebGPU guarantees.
+    pub fn next(self) -> Self {
+        match self {
+            AaMode::None => AaMode::Msaa(4),
+            AaMode::Msaa(_) => AaMode::Fxaa,
+            AaMode::Fxaa => AaMode::Taa,
+            AaMode::Taa => AaMode::None,
+        }
+    }
+}
+
+impl std::fmt::Display for AaMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AaMode::None => write!(f, "off"),
+            AaMode::Msaa(count) => write!(f, "{}x MSAA", count),
+            AaMode::Fxaa => write!(f, "FXAA"),
+            AaMode::Taa => write!(f, "TAA"),
+        }
+    }
+}
+
+/*
+*   What happens to the multisampled color when the main pass ends. The samples only exist
+*   while the pass is running unless they're stored, and the regular target only gets the
+*   averaged color if the pass has it as its resolve target.
+*
+*   Storing is the expensive part: it writes every sample out to memory, 4x the bandwidth of
+*   the resolved image at 4x MSAA. On tiled gpus (phones, Apple silicon) the samples live in
+*   fast on-chip memory during the pass and a resolve without a store never writes them out
+*   at all, which is why store: false with a resolve target is what you want most of the time.
+*
+*   Storing without resolving leaves the averaging to a shader pass afterwards (resolve_color),
+*   which costs that write plus reading every sample back. What it buys is control over how
+*   samples are combined. Ours weights each one by 1 / (1 + brightness) first, the usual trick
+*   for HDR: a plain average lets one very bright sample blow out the whole pixel and bring the
+*   jaggies back after tonemapping. Not storing and not resolving keeps nothing, so isn't allowed.
+*
+*   Trails load the last frame's samples, so with trails on they're always stored.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MsaaAttachment {
+    pub store: bool,
+    pub resolve: bool,
+}
+
+impl MsaaAttachment {
+    // Store, then have the gpu resolve, what the main pass has always done
+    pub const STORE_AND_RESOLVE: Self = Self { store: true, resolve: true };
+    pub const RESOLVE_ONLY: Self = Self { store: false, resolve: true };
+    pub const CUSTOM_RESOLVE: Self = Self { store: true, resolve: false };
+
+    // Cycles through the three that keep something
+    pub fn next(self) -> Self {
+        match self {
+            Self::STORE_AND_RESOLVE => Self::RESOLVE_ONLY,
+            Self::RESOLVE_ONLY => Self::CUSTOM_RESOLVE,
+            _ => Self::STORE_AND_RESOLVE,
+        }
+    }
+}
+
+impl Default for MsaaAttachment {
+    fn default() -> Self {
+        Self::STORE_AND_RESOLVE
+    }
+}
+
+impl std::fmt::Display for MsaaAttachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.store, self.resolve) {
+            (true, true) => write!(f, "store & resolve"),
+            (false, true) => write!(f, "resolve only"),
+            (true, false) => write!(f, "store, custom resolve"),
+            (false, false) => write!(f, "nothing kept"),
+        }
+    }
+}
+
+/*
+*   The multisampled attachments for the main pass. Post effects like depth of field want to
+*   read the depth buffer, but a multisampled texture needs a different binding type and wgpu
+*   can't resolve depth for us, so resolve_depth copies it into the regular depth texture with
+*   a small full screen pass.
+*/
+pub struct Msaa {
+    sample_count: u32,
+    color_format: wgpu::TextureFormat,
+    color: wgpu::TextureView,
+    depth: wgpu::TextureView,
+    depth_bind_group_layout: wgpu::BindGroupLayout,
+    depth_bind_group: wgpu::BindGroup,
+    depth_resolve_pipeline: Rc<wgpu::RenderPipeline>,
+    // The closest sample is the smallest depth, or the biggest when it's reversed
+    depth_direction: camera::DepthDirection,
+    // For resolving the color ourselves, see MsaaAttachment
+    color_bind_group_layout: wgpu::BindGroupLayout,
+    color_bind_group: wgpu::BindGroup,
+    color_resolve_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Msaa {
+    // Both the color & depth formats have to support the sample count
+    pub fn is_supported(adapter: &wgpu::Adapter, color_format: wgpu::TextureFormat, sample_count: u32) -> bool {
+        [color_format, texture::Texture::DEPTH_FORMAT]
+            .iter()
+            .all(|format| adapter.get_texture_format_features(*format).flags.sample_count_supported(sample_count))
+    }
+
+    // `color_format` is whatever the samples get resolved into, the surface's or the post chain's
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_direction: camera::DepthDirection,
+    ) -> Self {
+        let depth_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("depth_resolve_bind_group_layout"),
+        });
+
+        let depth_resolve_pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Depth Resolve",
+                shader: include_str!("depth_resolve.wgsl"),
+                bind_group_layouts: &[&depth_bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[],
+                // No color at all, the fragment shader only writes depth
+                fragment_entry_point: match depth_direction {
+                    camera::DepthDirection::Standard => "fs_main",
+                    camera::DepthDirection::Reversed => "fs_reversed",
+                },
+                targets: &[],
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+
+        let color_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("color_resolve_bind_group_layout"),
+        });
+        let color_resolve_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Color Resolve",
+            include_str!("color_resolve.wgsl"),
+            &[&color_bind_group_layout],
+            color_format,
+        );
+
+        let (color, depth, depth_bind_group, color_bind_group) = Self::create_attachments(
+            device,
+            config,
+            color_format,
+            sample_count,
+            &depth_bind_group_layout,
+            &color_bind_group_layout,
+        );
+        Self {
+            sample_count,
+            color_format,
+            color,
+            depth,
+            depth_bind_group_layout,
+            depth_bind_group,
+            depth_resolve_pipeline,
+            depth_direction,
+            color_bind_group_layout,
+            color_bind_group,
+            color_resolve_pipeline,
+        }
+    }
+
+    fn create_attachments(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_bind_group_layout: &wgpu::BindGroupLayout,
+        color_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> (wgpu::TextureView, wgpu::TextureView, wgpu::BindGroup, wgpu::BindGroup) {
+        let create_texture = |label, format, usage| {
+            device.create_tracked_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+        };
+        // Only read directly when it's resolved by resolve_color
+        let color = create_texture(
+            "msaa_color",
+            color_format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        )
+        .create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_texture = create_texture(
+            "msaa_depth",
+            texture::Texture::DEPTH_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let depth = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Only the depth gets resolved, see Texture::depth_only_view
+        let depth_only = depth_texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: depth_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth_only),
+            }],
+            label: Some("depth_resolve_bind_group"),
+        });
+        let color_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: color_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&color),
+            }],
+            label: Some("color_resolve_bind_group"),
+        });
+        (color, depth, depth_bind_group, color_bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.color, self.depth, self.depth_bind_group, self.color_bind_group) = Self::create_attachments(
+            device,
+            config,
+            self.color_format,
+            self.sample_count,
+            &self.depth_bind_group_layout,
+            &self.color_bind_group_layout,
+        );
+    }
+
+    // Draw the main pass into this, with the regular target as the resolve target
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth
+    }
+
+    // Averages the stored samples into `output`, for when the main pass had no resolve target
+    pub fn resolve_color(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Color Resolve Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.color_resolve_pipeline);
+        render_pass.set_bind_group(0, &self.color_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Writes the closest sample of every pixel into `output`, a regular depth texture
+    pub fn resolve_depth(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: output,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_direction.far()),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.depth_resolve_pipeline);
+        render_pass.set_bind_group(0, &self.depth_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// The FXAA half, see fxaa.wgsl
+pub struct Fxaa {
+    pub enabled: bool,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Fxaa {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "FXAA",
+            include_str!("fxaa.wgsl"),
+            &[screen_layout, &post_chain.input_layout],
+            color_format,
+        );
+        Self {
+            enabled: false,
+            pipeline,
+        }
+    }
+}
+
+impl post::PostEffect for Fxaa {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+}
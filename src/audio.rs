@@ -0,0 +1,296 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::Transform;
+use crate::model;
+use crate::texture;
+
+/*
+*   Shaders that react to a beat. Each frame a spectrum is split into BANDS frequency bands,
+*   bass on the left to treble on the right, and their loudness goes into a uniform the scene
+*   shader reads when it's built with AUDIO. The demo is a row of bars, one per band, that
+*   stretch up & glow with theirs.
+*
+*   There's no audio capture, the spectrum is a synthetic one (see synthetic_spectrum), a made
+*   up beat rather than anything heard. Listening to an input device would need cpal & rustfft,
+*   which aren't dependencies. bands_from_spectrum takes FFT magnitudes, so a captured spectrum
+*   could go in where the synthetic one does.
+*
+*   Loudness is in decibels, squeezed from -60 (silence as far as the bars go) up to 0 (full
+*   scale) into 0..1. A band jumps straight up to something louder & falls back slowly, like
+*   the needle on a meter, so the bars don't flicker between frames.
+*/
+pub const BANDS: usize = 8;
+
+// Has to match AudioBands in shader.wgsl. Four bands to a vec4, a uniform array's elements are
+// 16 bytes apart anyway.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct AudioUniform {
+    bands: [[f32; 4]; BANDS / 4],
+}
+
+pub struct AudioVisualizer {
+    pub enabled: bool,
+    bands: [f32; BANDS],
+    // The animation time bands were last worked out at
+    time: f32,
+    buffer: Tracked<wgpu::Buffer>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    mesh: model::Mesh,
+    instance_buffer: Tracked<wgpu::Buffer>,
+}
+
+impl AudioVisualizer {
+    // How loud a band has to be to register at all, in decibels
+    const FLOOR_DB: f32 = -60.0;
+    // Seconds for a band to fall halfway back down
+    const FALL_HALF_LIFE: f32 = 0.12;
+    // The lowest & highest frequencies the bands cover, in Hz, split evenly in octaves
+    const LOWEST: f32 = 40.0;
+    const HIGHEST: f32 = 16000.0;
+    // What the synthetic beat's spectrum is made as if it were, a 2048 sample FFT at 48kHz
+    const SYNTHETIC_BINS: usize = 1025;
+    const SYNTHETIC_BIN_HZ: f32 = 48000.0 / 2048.0;
+
+    // `texture` is what the bars are drawn with
+    pub fn new(device: &wgpu::Device, texture: &texture::Texture) -> Self {
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Audio Bands Buffer"),
+            contents: bytemuck::cast_slice(&[AudioUniform::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // The textured scene's group 2 with the bands after it
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    // The vertices stretch with the bands
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("audio_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("audio_bind_group"),
+        });
+
+        // In a row behind the grid, bass on the left. The shader stretches them up from the
+        // bottom, so they stand on y = 0 however loud.
+        let bars: Vec<_> = (0..BANDS)
+            .map(|band| {
+                Transform {
+                    position: cgmath::Vector3::new((band as f32 - (BANDS - 1) as f32 / 2.0) * 1.2, 0.5, -6.0),
+                    ..Default::default()
+                }
+                .to_raw()
+            })
+            .collect();
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Audio Bars Instance Buffer"),
+            contents: bytemuck::cast_slice(&bars),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self {
+            enabled: false,
+            bands: [0.0; BANDS],
+            time: 0.0,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            mesh: model::Mesh::cube(device),
+            instance_buffer,
+        }
+    }
+
+    // On a (new) device, shown as before
+    pub fn recreate(&self, device: &wgpu::Device, texture: &texture::Texture) -> Self {
+        Self {
+            enabled: self.enabled,
+            ..Self::new(device, texture)
+        }
+    }
+
+    pub fn bands(&self) -> [f32; BANDS] {
+        self.bands
+    }
+
+    // The synthetic beat `time` seconds into the animation, uploaded
+    pub fn update(&mut self, queue: &wgpu::Queue, time: f32) {
+        if !self.enabled {
+            return;
+        }
+        let loudness = bands_from_spectrum(&synthetic_spectrum(time, Self::SYNTHETIC_BINS), Self::SYNTHETIC_BIN_HZ);
+        // Going backwards (a time scale below 0) falls like going forwards
+        let fall = 0.5_f32.powf((time - self.time).abs() / Self::FALL_HALF_LIFE);
+        self.time = time;
+        for (band, loudness) in self.bands.iter_mut().zip(loudness) {
+            *band = loudness.max(*band * fall);
+        }
+
+        let mut uniform = AudioUniform::default();
+        for (i, band) in self.bands.iter().enumerate() {
+            uniform.bands[i / 4][i % 4] = *band;
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // With the AUDIO pipeline & this group 2 already set
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // The instance index is the band
+        render_pass.draw_indexed(0..self.mesh.num_elements, 0, 0..BANDS as u32);
+    }
+}
+
+/*
+*   The loudness of each band, 0..1, from the magnitudes of an FFT's bins up to half the sample
+*   rate. Bin k is at k * bin_hz. A magnitude of 1 is a full scale sine, so rustfft's output
+*   needs dividing by half the FFT's size first. A band is as loud as its loudest bin, bins
+*   the bands don't cover are left out.
+*/
+pub fn bands_from_spectrum(magnitudes: &[f32], bin_hz: f32) -> [f32; BANDS] {
+    let octaves = (AudioVisualizer::HIGHEST / AudioVisualizer::LOWEST).log2();
+    let mut peaks = [0.0_f32; BANDS];
+    // DC isn't a sound
+    for (bin, magnitude) in magnitudes.iter().enumerate().skip(1) {
+        let position = (bin as f32 * bin_hz / AudioVisualizer::LOWEST).log2() / octaves;
+        if (0.0..1.0).contains(&position) {
+            let band = (position * BANDS as f32) as usize;
+            peaks[band] = peaks[band].max(*magnitude);
+        }
+    }
+    peaks.map(|peak| {
+        let db = 20.0 * peak.max(1e-9).log10();
+        (1.0 - db / AudioVisualizer::FLOOR_DB).clamp(0.0, 1.0)
+    })
+}
+
+/*
+*   What the spectrum of a simple beat would look like `time` seconds in, without making the
+*   sound and taking its FFT. Each instrument is a bump around its pitch, a bell curve in
+*   octaves, that fades after every hit: a kick on every beat, a snare on 2 & 4, hi-hats on
+*   the eighth notes and a bass line that changes note every bar.
+*/
+fn synthetic_spectrum(time: f32, bins: usize) -> Vec<f32> {
+    const BEAT: f32 = 0.5;
+    let beat = time.rem_euclid(4.0 * BEAT) / BEAT;
+    // How long ago the last hit was, hits `every` beats starting at `offset`
+    let since = |every: f32, offset: f32| (beat - offset).rem_euclid(every) * BEAT;
+    let bar = (time / (4.0 * BEAT)).rem_euclid(4.0) as usize;
+    let bass_note = [55.0, 41.2, 49.0, 36.7][bar];
+    // (pitch in Hz, width in octaves, loudness)
+    let instruments = [
+        (55.0, 0.6, (-since(1.0, 0.0) * 10.0).exp()),
+        (1200.0, 1.8, 0.4 * (-since(2.0, 1.0) * 12.0).exp()),
+        (10000.0, 0.6, 0.2 * (-since(0.5, 0.0) * 30.0).exp()),
+        (bass_note * 2.0, 0.3, 0.15),
+    ];
+    (0..bins)
+        .map(|bin| {
+            let hz = (bin as f32 * AudioVisualizer::SYNTHETIC_BIN_HZ).max(1.0);
+            instruments
+                .iter()
+                .map(|&(pitch, width, loudness)| {
+                    let octaves = (hz / pitch).log2() / width;
+                    loudness * (-octaves * octaves).exp()
+                })
+                .sum::<f32>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIN_HZ: f32 = 48000.0 / 2048.0;
+
+    // A single full scale tone at `hz`
+    fn tone(hz: f32) -> Vec<f32> {
+        let mut magnitudes = vec![0.0; 1025];
+        magnitudes[(hz / BIN_HZ).round() as usize] = 1.0;
+        magnitudes
+    }
+
+    #[test]
+    fn silence_is_zero() {
+        assert_eq!(bands_from_spectrum(&[0.0; 1025], BIN_HZ), [0.0; BANDS]);
+        assert_eq!(bands_from_spectrum(&[], BIN_HZ), [0.0; BANDS]);
+    }
+
+    #[test]
+    fn tones_land_in_their_band() {
+        let bands = bands_from_spectrum(&tone(60.0), BIN_HZ);
+        assert_eq!(bands, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let bands = bands_from_spectrum(&tone(12000.0), BIN_HZ);
+        assert_eq!(bands, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn outside_the_range_is_left_out() {
+        assert_eq!(bands_from_spectrum(&tone(20000.0), BIN_HZ), [0.0; BANDS]);
+        let mut dc = vec![0.0; 1025];
+        dc[0] = 1.0;
+        assert_eq!(bands_from_spectrum(&dc, BIN_HZ), [0.0; BANDS]);
+    }
+
+    #[test]
+    fn loudness_is_in_decibels() {
+        let mut magnitudes = tone(60.0);
+        magnitudes[(60.0 / BIN_HZ).round() as usize] = 0.1;
+        // -20dB is a third of the way down to the floor
+        let band = bands_from_spectrum(&magnitudes, BIN_HZ)[0];
+        assert!((band - 2.0 / 3.0).abs() < 1e-5, "{}", band);
+    }
+
+    #[test]
+    fn synthetic_kick_is_on_the_beat() {
+        let on = bands_from_spectrum(&synthetic_spectrum(0.0, 1025), BIN_HZ);
+        let between = bands_from_spectrum(&synthetic_spectrum(0.4, 1025), BIN_HZ);
+        assert!(on[0] > 0.9);
+        assert!(between[0] < on[0]);
+    }
+}
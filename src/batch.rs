@@ -0,0 +1,263 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::model;
+
+/*
+*   Several meshes that are drawn with the same pipeline & bind groups, packed into one vertex
+*   and one index buffer so they can be drawn without rebinding anything in between. Each mesh
+*   keeps its own indices, its draw starts at its first index & adds its first vertex to them
+*   (base_index & vertex_offset).
+*
+*   How the draws get issued, from the fewest calls to the most:
+*
+*   MultiDrawIndirect  every mesh's draw arguments sit next to each other in one indirect
+*                      buffer, and one multi_draw_indexed_indirect call draws them all. Native
+*                      only, it needs Features::MULTI_DRAW_INDIRECT.
+*   Indirect           the same buffer, one draw_indexed_indirect per mesh. Anything but WebGL.
+*   Direct             plain draw_indexed calls with the same ranges. Works everywhere.
+*
+*   They all draw the same thing, what changes is how much work wgpu does on the cpu to record
+*   them. Every call gets validated & turned into a backend command, so a single call for the
+*   lot is the cheapest however many meshes there are. State times the recording (see
+*   State::batch_draw_times) so they can be compared.
+*
+*   Every mesh is drawn with the same instances, 0..instance_count, the base_instance of an
+*   indirect draw has to be 0 without Features::INDIRECT_FIRST_INSTANCE.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BatchDrawMode {
+    MultiDrawIndirect,
+    Indirect,
+    Direct,
+}
+
+impl BatchDrawMode {
+    // `features` are the device's
+    pub fn is_supported(self, features: wgpu::Features, downlevel: wgpu::DownlevelFlags) -> bool {
+        match self {
+            BatchDrawMode::MultiDrawIndirect => {
+                features.contains(wgpu::Features::MULTI_DRAW_INDIRECT)
+                    && downlevel.contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+            }
+            BatchDrawMode::Indirect => downlevel.contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION),
+            BatchDrawMode::Direct => true,
+        }
+    }
+
+    // The one with the fewest calls that works here
+    pub fn best(features: wgpu::Features, downlevel: wgpu::DownlevelFlags) -> Self {
+        [BatchDrawMode::MultiDrawIndirect, BatchDrawMode::Indirect]
+            .into_iter()
+            .find(|mode| mode.is_supported(features, downlevel))
+            .unwrap_or(BatchDrawMode::Direct)
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            BatchDrawMode::MultiDrawIndirect => BatchDrawMode::Indirect,
+            BatchDrawMode::Indirect => BatchDrawMode::Direct,
+            BatchDrawMode::Direct => BatchDrawMode::MultiDrawIndirect,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchDrawMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BatchDrawMode::MultiDrawIndirect => write!(f, "multi-draw indirect"),
+            BatchDrawMode::Indirect => write!(f, "indirect"),
+            BatchDrawMode::Direct => write!(f, "direct"),
+        }
+    }
+}
+
+/*
+*   How a batch's indices make triangles. A list takes three indices for every triangle. A
+*   strip only takes one: every index after the first two makes a triangle with the two before
+*   it, every other one wound the other way round, which the gpu flips back. STRIP_RESTART in a
+*   strip's indices ends it, and the next index starts a new one.
+*
+*   The pipeline has to agree with the index buffer. A strip pipeline needs strip_index_format
+*   set to the index buffer's format, that's where the gpu takes the restart value from
+*   (0xFFFF or 0xFFFFFFFF). A list pipeline has to leave it None. wgpu rejects the pipeline or
+*   the draw when they don't match, but nothing can tell a list's indices from a strip's, drawn
+*   with the wrong topology they just make a mess. MeshBatch::new checks what it can.
+*/
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MeshTopology {
+    #[default]
+    TriangleList,
+    TriangleStrip,
+}
+
+impl MeshTopology {
+    pub fn primitive(self) -> wgpu::PrimitiveTopology {
+        match self {
+            MeshTopology::TriangleList => wgpu::PrimitiveTopology::TriangleList,
+            MeshTopology::TriangleStrip => wgpu::PrimitiveTopology::TriangleStrip,
+        }
+    }
+
+    // What the pipeline's PrimitiveState::strip_index_format has to be
+    pub fn strip_index_format(self) -> Option<wgpu::IndexFormat> {
+        match self {
+            MeshTopology::TriangleList => None,
+            MeshTopology::TriangleStrip => Some(MeshBatch::INDEX_FORMAT),
+        }
+    }
+}
+
+pub struct MeshBatch {
+    pub name: String,
+    pub mode: BatchDrawMode,
+    topology: MeshTopology,
+    // Kept on the cpu like Mesh does, for recreating on a new device
+    meshes: Vec<model::MeshData>,
+    // One per mesh, what's in the indirect buffer
+    draws: Vec<wgpu::util::DrawIndexedIndirect>,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    index_buffer: Tracked<wgpu::Buffer>,
+    indirect_buffer: Tracked<wgpu::Buffer>,
+    instance_count: u32,
+}
+
+impl MeshBatch {
+    pub const INDEX_FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+    // Primitive restart, the biggest index there is
+    pub const STRIP_RESTART: u32 = u32::MAX;
+
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        meshes: Vec<model::MeshData>,
+        instance_count: u32,
+        mode: BatchDrawMode,
+        topology: MeshTopology,
+    ) -> Self {
+        for mesh in &meshes {
+            Self::check_indices(mesh, topology);
+        }
+        let mut vertices = Vec::with_capacity(meshes.iter().map(|mesh| mesh.vertices.len()).sum());
+        let mut indices = Vec::with_capacity(meshes.iter().map(|mesh| mesh.indices.len()).sum());
+        let mut draws = Vec::with_capacity(meshes.len());
+        for mesh in &meshes {
+            draws.push(wgpu::util::DrawIndexedIndirect {
+                vertex_count: mesh.indices.len() as u32,
+                instance_count,
+                base_index: indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+                base_instance: 0,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        let vertex_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let args = draws.iter().flat_map(|draw| draw.as_bytes()).copied().collect::<Vec<_>>();
+        let indirect_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Indirect Buffer", name)),
+            contents: &args,
+            usage: wgpu::BufferUsages::INDIRECT,
+        });
+
+        Self {
+            name: name.to_owned(),
+            mode,
+            topology,
+            meshes,
+            draws,
+            vertex_buffer,
+            index_buffer,
+            indirect_buffer,
+            instance_count,
+        }
+    }
+
+    // Catches a list's indices in a strip batch & the other way round, as far as that's
+    // possible. Restarts only mean something in a strip, in a list they're out of range.
+    fn check_indices(mesh: &model::MeshData, topology: MeshTopology) {
+        match topology {
+            MeshTopology::TriangleList => {
+                assert!(mesh.indices.len().is_multiple_of(3), "{}: a triangle list takes 3 indices per triangle", mesh.name);
+                assert!(
+                    !mesh.indices.contains(&Self::STRIP_RESTART),
+                    "{}: primitive restart in a triangle list",
+                    mesh.name
+                );
+            }
+            MeshTopology::TriangleStrip => {
+                assert!(
+                    mesh.indices.len() != 1 && mesh.indices.len() != 2,
+                    "{}: a strip needs at least 3 indices",
+                    mesh.name
+                );
+            }
+        }
+    }
+
+    // Same meshes, mode & topology on a new device
+    pub fn recreate(&self, device: &wgpu::Device) -> Self {
+        Self::new(device, &self.name, self.meshes.clone(), self.instance_count, self.mode, self.topology)
+    }
+
+    pub fn topology(&self) -> MeshTopology {
+        self.topology
+    }
+
+    // The cpu side copies, to build the batch again with different indices
+    pub fn meshes(&self) -> &[model::MeshData] {
+        &self.meshes
+    }
+
+    // Indices in the whole batch, restarts included
+    pub fn index_count(&self) -> usize {
+        self.draws.iter().map(|draw| draw.vertex_count as usize).sum()
+    }
+
+    pub fn mesh_count(&self) -> usize {
+        self.draws.len()
+    }
+
+    // How many draw calls a draw() records in the current mode
+    pub fn draw_calls(&self) -> usize {
+        match self.mode {
+            BatchDrawMode::MultiDrawIndirect => 1,
+            BatchDrawMode::Indirect | BatchDrawMode::Direct => self.draws.len(),
+        }
+    }
+
+    // Binds the meshes in slot 0 & draws every one of them. The instances in slot 1 are up to
+    // the caller, same as LodModel::draw, and so is a pipeline for the batch's topology.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.draws.is_empty() {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), Self::INDEX_FORMAT);
+        let stride = std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress;
+        match self.mode {
+            BatchDrawMode::MultiDrawIndirect => {
+                render_pass.multi_draw_indexed_indirect(&self.indirect_buffer, 0, self.draws.len() as u32);
+            }
+            BatchDrawMode::Indirect => {
+                for i in 0..self.draws.len() {
+                    render_pass.draw_indexed_indirect(&self.indirect_buffer, i as wgpu::BufferAddress * stride);
+                }
+            }
+            BatchDrawMode::Direct => {
+                for draw in &self.draws {
+                    let indices = draw.base_index..draw.base_index + draw.vertex_count;
+                    render_pass.draw_indexed(indices, draw.vertex_offset, 0..draw.instance_count);
+                }
+            }
+        }
+    }
+}
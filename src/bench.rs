@@ -0,0 +1,282 @@
+use std::fmt;
+
+use instant::Duration;
+
+use crate::context::GpuContext;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+
+// Frames rendered and thrown away before measuring, while caches & the driver settle down
+pub const WARMUP_FRAMES: u32 = 10;
+
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTimes {
+    pub min: Duration,
+    pub max: Duration,
+    pub avg: Duration,
+}
+
+impl FrameTimes {
+    pub fn from_samples(samples: &[Duration]) -> Option<Self> {
+        let min = *samples.iter().min()?;
+        let max = *samples.iter().max()?;
+        let avg = samples.iter().sum::<Duration>() / samples.len() as u32;
+        Some(Self { min, max, avg })
+    }
+}
+
+impl fmt::Display for FrameTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "min {:.3}ms, avg {:.3}ms, max {:.3}ms",
+            ms(self.min),
+            ms(self.avg),
+            ms(self.max)
+        )
+    }
+}
+
+/*
+*   What State::benchmark measured. The cpu time is everything from update() to the submit,
+*   not counting the wait for the gpu afterwards. The gpu time is only there when the device
+*   has timestamp queries.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct BenchReport {
+    pub frames: u32,
+    pub cpu: FrameTimes,
+    pub gpu: Option<FrameTimes>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} frames (after {} warm-up frames)", self.frames, WARMUP_FRAMES)?;
+        writeln!(f, "cpu: {}", self.cpu)?;
+        match &self.gpu {
+            Some(gpu) => write!(f, "gpu: {}", gpu),
+            None => write!(f, "gpu: no timestamp queries on this device"),
+        }
+    }
+}
+
+/*
+*   Times a frame on the gpu with a pair of timestamps, one written before anything else in
+*   the frame and one after everything. Timestamps are in ticks, and the queue tells us how
+*   many nanoseconds a tick is.
+*/
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: Tracked<wgpu::Buffer>,
+    period: f32,
+}
+
+impl GpuTimer {
+    const QUERY_SIZE: wgpu::BufferAddress = std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+    // The device needs TIMESTAMP_QUERY, see context::OPTIONAL_FEATURES
+    pub fn new(ctx: &GpuContext) -> Self {
+        let query_set = ctx.device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Bench Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = ctx.device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bench Timestamp Buffer"),
+            size: 2 * Self::QUERY_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Self {
+            query_set,
+            resolve_buffer,
+            period: ctx.queue.get_timestamp_period(),
+        }
+    }
+
+    pub fn begin(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    pub fn end(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+    }
+
+    // Waits for the frame to finish on the gpu
+    pub fn read(&self, ctx: &GpuContext) -> Duration {
+        let data = pollster::block_on(ctx.read_buffer_async(&self.resolve_buffer, 2 * Self::QUERY_SIZE));
+        self.duration(&data)
+    }
+
+    // The time between the two timestamps in `data`, as resolved by end()
+    fn duration(&self, data: &[u8]) -> Duration {
+        // Read back bytes aren't guaranteed to be aligned for u64's
+        let timestamp = |i: usize| bytemuck::pod_read_unaligned::<u64>(&data[i * 8..(i + 1) * 8]);
+        let ticks = timestamp(1).saturating_sub(timestamp(0));
+        Duration::from_nanos((ticks as f64 * self.period as f64) as u64)
+    }
+}
+
+/*
+*   A GpuTimer for every frame while running, without the wait. The timestamps are copied into
+*   a staging buffer that gets mapped in the background, and later frames check whether it's
+*   ready with a non-blocking poll. Frames that start while the last reading is still on its
+*   way aren't timed, so the gpu times are a sample of the frames rather than every one of them.
+*/
+pub struct FrameGpuTimer {
+    timer: GpuTimer,
+    staging: Tracked<wgpu::Buffer>,
+    // Set by map_async's callback, which wants something it can send between threads
+    mapped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    // Timestamps were written this frame and are waiting for after_submit
+    timing: bool,
+    // The staging buffer has a map_async going (or finished) that hasn't been read yet
+    mapping: bool,
+}
+
+impl FrameGpuTimer {
+    // The device needs TIMESTAMP_QUERY, like GpuTimer
+    pub fn new(ctx: &GpuContext) -> Self {
+        let staging = ctx.device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Timestamp Staging Buffer"),
+            size: 2 * GpuTimer::QUERY_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            timer: GpuTimer::new(ctx),
+            staging,
+            mapped: Default::default(),
+            timing: false,
+            mapping: false,
+        }
+    }
+
+    // Does nothing while the staging buffer is still busy with an earlier frame
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.timing = !self.mapping;
+        if self.timing {
+            self.timer.begin(encoder);
+        }
+    }
+
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.timing {
+            self.timer.end(encoder);
+            encoder.copy_buffer_to_buffer(&self.timer.resolve_buffer, 0, &self.staging, 0, 2 * GpuTimer::QUERY_SIZE);
+        }
+    }
+
+    // Buffers can only be mapped once the copy into them has been submitted
+    pub fn after_submit(&mut self) {
+        if !std::mem::take(&mut self.timing) {
+            return;
+        }
+        self.mapping = true;
+        let mapped = self.mapped.clone();
+        self.staging.slice(..).map_async(wgpu::MapMode::Read, move |result| match result {
+            Ok(()) => mapped.store(true, std::sync::atomic::Ordering::SeqCst),
+            Err(e) => log::warn!("couldn't map the frame timestamps: {}", e),
+        });
+    }
+
+    // The gpu time of the last timed frame, if it's come back since the last call
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Duration> {
+        if !self.mapping {
+            return None;
+        }
+        // Runs the callback if the gpu's done, the web does this for us
+        device.poll(wgpu::Maintain::Poll);
+        if !self.mapped.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            return None;
+        }
+        let duration = {
+            let data = self.staging.slice(..).get_mapped_range();
+            self.timer.duration(&data)
+        };
+        self.staging.unmap();
+        self.mapping = false;
+        Some(duration)
+    }
+}
+
+/*
+*   Every `interval` while running, the average gpu frame time since last time and how much
+*   gpu memory we're holding on to (see gpu_memory.rs), for keeping an eye on long runs. The
+*   memory is compared with the last report, a number that only ever goes up is a leak.
+*/
+pub struct StatsLog {
+    interval: Duration,
+    started: instant::Instant,
+    frames: u32,
+    gpu_times: Vec<Duration>,
+    last_memory: Option<u64>,
+}
+
+impl StatsLog {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            started: instant::Instant::now(),
+            frames: 0,
+            gpu_times: Vec::new(),
+            last_memory: None,
+        }
+    }
+
+    // Once per frame, with the frame's gpu time if the timer caught one
+    pub fn push(&mut self, gpu_time: Option<Duration>) {
+        self.frames += 1;
+        self.gpu_times.extend(gpu_time);
+    }
+
+    // The report once `interval` has passed, with `memory` from GpuMemory. Starts over after.
+    pub fn poll(&mut self, memory: crate::gpu_memory::GpuMemory) -> Option<StatsReport> {
+        let elapsed = self.started.elapsed();
+        if elapsed < self.interval {
+            return None;
+        }
+        let report = StatsReport {
+            elapsed,
+            frames: self.frames,
+            gpu: FrameTimes::from_samples(&self.gpu_times),
+            gpu_frames: self.gpu_times.len(),
+            memory,
+            memory_change: self.last_memory.map(|last| memory.total() as i64 - last as i64),
+        };
+        self.started = instant::Instant::now();
+        self.frames = 0;
+        self.gpu_times.clear();
+        self.last_memory = Some(memory.total());
+        Some(report)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StatsReport {
+    pub elapsed: Duration,
+    pub frames: u32,
+    // None without timestamp queries
+    pub gpu: Option<FrameTimes>,
+    // How many of the frames the gpu timer caught
+    pub gpu_frames: usize,
+    pub memory: crate::gpu_memory::GpuMemory,
+    // In bytes since the last report, None for the first one
+    pub memory_change: Option<i64>,
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "last {:.1}s: {} frames, ", self.elapsed.as_secs_f32(), self.frames)?;
+        match &self.gpu {
+            Some(gpu) => write!(f, "gpu avg {:.3}ms over {} timed frames", gpu.avg.as_secs_f64() * 1000.0, self.gpu_frames)?,
+            None => write!(f, "no gpu times")?,
+        }
+        write!(f, ", gpu memory {}", self.memory)?;
+        if let Some(change) = self.memory_change {
+            write!(f, ", {:+.2}MB", change as f64 / (1024.0 * 1024.0))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+/*
+*   The blend modes a draw can pick from, each one a wgpu::BlendState (or none). They all say
+*   what happens to what's already in the target (dst) when a fragment (src) lands on it:
+*
+*   Opaque              src replaces dst, alpha is ignored
+*   AlphaBlend          src * a + dst * (1 - a), src has straight alpha (see texture::Alpha)
+*   Additive            dst + src * a, light that adds up: glows, sparks, fire. Order doesn't
+*                       matter, and it can only ever get brighter.
+*   Multiply            dst * src where a is 1, fading to dst alone where it's 0. Darkens,
+*                       for shadows & stains. Needs premultiplied alpha, see below.
+*   PremultipliedAlpha  src + dst * (1 - a), src already multiplied by a
+*
+*   Multiply can only fade out with the coverage if the color comes premultiplied: dst * src +
+*   dst * (1 - a) is dst * (1 - a + src), which is the lerp from white to the color when src is
+*   color * a. premultiplied() says which modes want that from the shader.
+*
+*   None of the blended modes should write depth, or they'd hide what's drawn after them
+*   through their transparent parts. Pipelines are made once per mode through the
+*   PipelineCache, which knows them apart by their blend states.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Opaque,
+    AlphaBlend,
+    Additive,
+    Multiply,
+    PremultipliedAlpha,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 5] = [
+        BlendMode::Opaque,
+        BlendMode::AlphaBlend,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::PremultipliedAlpha,
+    ];
+
+    // For ColorTargetState::blend
+    pub fn blend_state(self) -> Option<wgpu::BlendState> {
+        // The alpha channel is left as it is by the modes that only change the color
+        let keep_alpha = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::AlphaBlend => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            }),
+            BlendMode::Multiply => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            }),
+            BlendMode::PremultipliedAlpha => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+        }
+    }
+
+    // Whether the shader should output its color already multiplied by alpha
+    pub fn premultiplied(self) -> bool {
+        matches!(self, BlendMode::Multiply | BlendMode::PremultipliedAlpha)
+    }
+
+    pub fn writes_depth(self) -> bool {
+        self == BlendMode::Opaque
+    }
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+impl std::fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            BlendMode::Opaque => "opaque",
+            BlendMode::AlphaBlend => "alpha blend",
+            BlendMode::Additive => "additive",
+            BlendMode::Multiply => "multiply",
+            BlendMode::PremultipliedAlpha => "premultiplied alpha",
+        };
+        write!(f, "{}", name)
+    }
+}
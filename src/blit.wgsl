@@ -0,0 +1,35 @@
+// Copies a texture onto the render target, used to show frames that were drawn offscreen
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+// Same layout as the post processing input, see post.rs
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_color, s_color, in.uv);
+}
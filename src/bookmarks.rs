@@ -0,0 +1,212 @@
+use cgmath::{EuclideanSpace, InnerSpace, VectorSpace};
+
+use crate::camera;
+use crate::json::{field, number, numbers, vector, Json};
+
+/*
+*   Camera viewpoints to jump between, for demos. Ctrl + a number on the numpad remembers where
+*   the camera is in that slot, the number on its own goes back there. Going back glides over
+*   with a CameraTween rather than cutting, so it's clear where the new view is.
+*
+*   On native the slots are saved to a small JSON file every time one changes and loaded again
+*   at startup. Only the view is kept, the aspect ratio & clip planes stay as they are.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraBookmark {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+    pub fovy: f32,
+}
+
+impl CameraBookmark {
+    pub fn from_camera(camera: &camera::Camera) -> Self {
+        Self {
+            eye: camera.eye,
+            target: camera.target,
+            up: camera.up,
+            fovy: camera.fovy,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut camera::Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.up = self.up;
+        camera.fovy = self.fovy;
+    }
+
+    // `t` of the way from self to `other`. The up vectors are renormalized, they're never far
+    // enough apart to cancel out.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let up = self.up.lerp(other.up, t);
+        Self {
+            eye: cgmath::Point3::from_vec(self.eye.to_vec().lerp(other.eye.to_vec(), t)),
+            target: cgmath::Point3::from_vec(self.target.to_vec().lerp(other.target.to_vec(), t)),
+            up: if up.magnitude2() > 1e-8 { up.normalize() } else { other.up },
+            fovy: self.fovy + (other.fovy - self.fovy) * t,
+        }
+    }
+
+    fn to_json(self) -> Json {
+        Json::Object(vec![
+            ("eye".into(), vector(self.eye)),
+            ("target".into(), vector(self.target)),
+            ("up".into(), vector(self.up)),
+            ("fovy".into(), self.fovy.into()),
+        ])
+    }
+
+    fn from_json(json: &Json) -> Result<Self, String> {
+        Ok(Self {
+            eye: numbers::<3>(json, "eye")?.into(),
+            target: numbers::<3>(json, "target")?.into(),
+            up: numbers::<3>(json, "up")?.into(),
+            fovy: number(json, "fovy")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CameraBookmarks {
+    slots: [Option<CameraBookmark>; Self::SLOTS],
+}
+
+impl CameraBookmarks {
+    // Numpad 1 to 9
+    pub const SLOTS: usize = 9;
+    pub const FILE: &str = "camera_bookmarks.json";
+
+    pub fn get(&self, slot: usize) -> Option<CameraBookmark> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    pub fn set(&mut self, slot: usize, bookmark: CameraBookmark) {
+        self.slots[slot] = Some(bookmark);
+    }
+
+    // Which slot a key stands for, if any
+    pub fn slot_for_key(key: winit::event::VirtualKeyCode) -> Option<usize> {
+        use winit::event::VirtualKeyCode;
+        let keys = [
+            VirtualKeyCode::Numpad1,
+            VirtualKeyCode::Numpad2,
+            VirtualKeyCode::Numpad3,
+            VirtualKeyCode::Numpad4,
+            VirtualKeyCode::Numpad5,
+            VirtualKeyCode::Numpad6,
+            VirtualKeyCode::Numpad7,
+            VirtualKeyCode::Numpad8,
+            VirtualKeyCode::Numpad9,
+        ];
+        keys.iter().position(|k| *k == key)
+    }
+
+    // Empty slots are null, so the file always has all of them in order
+    pub fn to_json(&self) -> Json {
+        let slots = self.slots.iter().map(|slot| slot.map_or(Json::Null, CameraBookmark::to_json));
+        Json::Object(vec![("slots".into(), Json::Array(slots.collect()))])
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self, String> {
+        let items = field(json, "slots")?
+            .as_array()
+            .ok_or("\"slots\" should be an array")?;
+        if items.len() > Self::SLOTS {
+            return Err(format!("{} slots, there are only {}", items.len(), Self::SLOTS));
+        }
+        let mut bookmarks = Self::default();
+        for (i, item) in items.iter().enumerate() {
+            if *item != Json::Null {
+                let bookmark = CameraBookmark::from_json(item).map_err(|e| format!("slot {}: {}", i + 1, e))?;
+                bookmarks.set(i, bookmark);
+            }
+        }
+        Ok(bookmarks)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        std::fs::write(path, format!("{}\n", self.to_json()))
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+    }
+
+    // No file yet just means nothing's been bookmarked
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+        };
+        Json::parse(&source)
+            .and_then(|json| Self::from_json(&json))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/*
+*   Moves the camera from one view to another over a fixed time. It eases in & out, a linear
+*   move starts & stops with a jolt.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct CameraTween {
+    from: CameraBookmark,
+    to: CameraBookmark,
+    duration: instant::Duration,
+    elapsed: instant::Duration,
+}
+
+impl CameraTween {
+    pub fn new(from: CameraBookmark, to: CameraBookmark, duration: instant::Duration) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            elapsed: instant::Duration::ZERO,
+        }
+    }
+
+    // Where the camera should be after another `dt`
+    pub fn step(&mut self, dt: instant::Duration) -> CameraBookmark {
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+        if self.finished() {
+            return self.to;
+        }
+        let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        // Smoothstep
+        self.from.lerp(&self.to, t * t * (3.0 - 2.0 * t))
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut bookmarks = CameraBookmarks::default();
+        let bookmark = CameraBookmark {
+            eye: (1.0, 2.5, -3.0).into(),
+            target: (0.0, 0.5, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            fovy: 60.0,
+        };
+        bookmarks.set(0, bookmark);
+        bookmarks.set(4, CameraBookmark { fovy: 30.0, ..bookmark });
+
+        let printed = bookmarks.to_json().to_string();
+        let loaded = CameraBookmarks::from_json(&Json::parse(&printed).unwrap()).unwrap();
+        assert_eq!(loaded.slots, bookmarks.slots);
+    }
+
+    #[test]
+    fn too_many_slots() {
+        let json = Json::parse(r#"{"slots": [null, null, null, null, null, null, null, null, null, null]}"#).unwrap();
+        assert_eq!(CameraBookmarks::from_json(&json).unwrap_err(), "10 slots, there are only 9");
+    }
+}
@@ -0,0 +1,58 @@
+use crate::instance::{InterpolatedTransform, Transform};
+
+/*
+*   A ball flying back & forth over the grid, bouncing off invisible walls. Unlike the hopping
+*   sphere (animation.rs) its position isn't a function of time, each fixed step moves it on
+*   from where the last one left it. So there's no sampling it between steps, it has to be
+*   interpolated, see InterpolatedTransform.
+*
+*   It's fast on purpose: at 60 steps a second it covers about half a grid cell per step, and
+*   on a 144Hz display without interpolation you can see it stutter. Lower the step rate with
+*   --tick-rate to make the difference obvious.
+*/
+pub struct Bouncer {
+    pub transform: InterpolatedTransform,
+    velocity: cgmath::Vector3<f32>,
+    // Spin around its own axis, in radians per second
+    spin: f32,
+}
+
+impl Bouncer {
+    // The walls are at +-HALF_EXTENT on x & z
+    const HALF_EXTENT: f32 = 14.0;
+
+    pub fn new() -> Self {
+        let start = Transform::from_position(cgmath::Vector3::new(0.0, 1.5, 0.0));
+        Self {
+            transform: InterpolatedTransform::new(start),
+            velocity: cgmath::Vector3::new(22.0, 0.0, 9.0),
+            spin: 6.0,
+        }
+    }
+
+    // One fixed step of `dt` seconds
+    pub fn step(&mut self, dt: f32) {
+        let mut next = self.transform.current;
+        next.position += self.velocity * dt;
+        // Reflect off the walls, putting the ball back inside by however far it went through
+        for axis in [0, 2] {
+            if next.position[axis].abs() > Self::HALF_EXTENT {
+                let wall = Self::HALF_EXTENT.copysign(next.position[axis]);
+                next.position[axis] = 2.0 * wall - next.position[axis];
+                self.velocity[axis] = -self.velocity[axis];
+            }
+        }
+        next.rotation = cgmath::Quaternion::from(cgmath::Euler::new(
+            cgmath::Rad(0.0),
+            cgmath::Rad(self.spin * dt),
+            cgmath::Rad(0.0),
+        )) * next.rotation;
+        self.transform.push(next);
+    }
+}
+
+impl Default for Bouncer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
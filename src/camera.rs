@@ -0,0 +1,630 @@
+use crate::physics;
+use crate::uniform_ring::UniformRing;
+
+/*
+*   The coordinate system in wgpu is based on DirectX and Metal's coordinate systems. In
+*   normalized device coordinates the x and y axes are in the range [-1.0, 1.0], and the
+*   z axis is [0.0, 1.0]. cgmath is built for OpenGL's coordinate system, whose z axis is
+*   [-1.0, 1.0], so we use this matrix to scale and translate our scene into wgpu's space.
+*/
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/*
+*   Which way the depth buffer runs.
+*
+*   A perspective projection stores 1/distance, more or less, so most of the depth range goes to
+*   what's right in front of the camera. With the usual near = 0, far = 1 half the values are
+*   used up within twice the near plane's distance. Floats make it worse: they're most precise
+*   around 0, which is exactly where depth has precision to spare already, and around 1, where
+*   everything far away ends up, a 32 bit float has the same 24 bits of mantissa as a 24 bit
+*   integer buffer. Distant surfaces that are close together get the same depth & flicker
+*   through each other as the camera moves (z-fighting).
+*
+*   Reversed puts near at 1 and far at 0. Now the float's precision near 0 goes to the distant
+*   stuff, which cancels out the 1/distance falloff almost exactly and leaves roughly the same
+*   relative precision at every distance. There's so much of it that the far plane can go to
+*   infinity, which is what Camera's reversed projection does: depth = znear / distance. zfar
+*   only matters for the things that want a finite end to the view (the culling frustum, the
+*   depth of field).
+*
+*   Everything that tests against or writes the depth buffer has to agree: clear to far() rather
+*   than 1, test with compare(Less) (Greater) and keep the biggest depth rather than the
+*   smallest when picking the closest sample.
+*
+*   Texture::DEPTH_FORMAT is Depth24PlusStencil8, which wgpu turns into a 32 bit float where the
+*   gpu has no packed 24 bit format (AMD & Apple ones among others), and that's where this
+*   helps. Integers are spaced evenly, so where it really is 24 bits of them reversed comes out
+*   no better or worse than standard.
+*/
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DepthDirection {
+    // Near is 0, far is 1
+    #[default]
+    Standard,
+    // Near is 1, infinitely far away is 0
+    Reversed,
+}
+
+impl DepthDirection {
+    // What the depth buffer gets cleared to, nothing's further away than this
+    pub fn far(self) -> f32 {
+        match self {
+            DepthDirection::Standard => 1.0,
+            DepthDirection::Reversed => 0.0,
+        }
+    }
+
+    // `compare` written for standard depth, turned around for reversed
+    pub fn compare(self, compare: wgpu::CompareFunction) -> wgpu::CompareFunction {
+        use wgpu::CompareFunction::*;
+        match (self, compare) {
+            (DepthDirection::Standard, compare) => compare,
+            (DepthDirection::Reversed, Less) => Greater,
+            (DepthDirection::Reversed, LessEqual) => GreaterEqual,
+            (DepthDirection::Reversed, Greater) => Less,
+            (DepthDirection::Reversed, GreaterEqual) => LessEqual,
+            (DepthDirection::Reversed, compare) => compare,
+        }
+    }
+}
+
+impl std::fmt::Display for DepthDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DepthDirection::Standard => write!(f, "standard (near 0, far 1)"),
+            DepthDirection::Reversed => write!(f, "reversed (near 1, infinitely far 0)"),
+        }
+    }
+}
+
+pub struct Camera {
+    // Where the camera is
+    pub eye: cgmath::Point3<f32>,
+    // What the camera is looking at
+    pub target: cgmath::Point3<f32>,
+    // Which way is up
+    pub up: cgmath::Vector3<f32>,
+    pub aspect: f32,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    // Which way the depth buffer runs, see DepthDirection. Reversed has no far plane.
+    pub depth: DepthDirection,
+}
+
+impl Camera {
+    // How much bigger than the box frame_bounds leaves the view
+    const FRAME_MARGIN: f32 = 1.1;
+    const MIN_FRAME_RADIUS: f32 = 0.1;
+
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        // The view matrix moves the world to be at the position and rotation of the camera.
+        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        self.build_projection_matrix() * view
+    }
+
+    // Just the projection, for things already in view space (in front of the camera, x right &
+    // y up). The projection matrix warps the scene to give the effect of depth.
+    pub fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.depth {
+            DepthDirection::Standard => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+            }
+            // Already in wgpu's clip space: z is znear & w the distance in front of the camera,
+            // so the depth comes out as znear / distance
+            DepthDirection::Reversed => {
+                let f = 1.0 / (cgmath::Rad::from(cgmath::Deg(self.fovy)).0 * 0.5).tan();
+                #[rustfmt::skip]
+                let proj = cgmath::Matrix4::new(
+                    f / self.aspect, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, self.znear, 0.0,
+                );
+                proj
+            }
+        }
+    }
+
+    // Always the standard depth with a far plane, whichever way the depth buffer goes. The
+    // frustum planes come from this, and anything that only wants the view rays or where things
+    // land on the screen can use it too.
+    pub fn build_standard_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    // The ray from the eye through `position` on a screen of `size` pixels, y pointing down.
+    // Built from the field of view rather than by inverting the matrix, that can't fail.
+    pub fn screen_ray(&self, position: winit::dpi::PhysicalPosition<f64>, size: winit::dpi::PhysicalSize<u32>) -> Ray {
+        use cgmath::InnerSpace;
+        let x = (position.x / size.width.max(1) as f64 * 2.0 - 1.0) as f32;
+        let y = (1.0 - position.y / size.height.max(1) as f64 * 2.0) as f32;
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        let half_height = (cgmath::Rad::from(cgmath::Deg(self.fovy)).0 * 0.5).tan();
+        let direction = forward + right * (x * half_height * self.aspect) + up * (y * half_height);
+        Ray {
+            origin: self.eye,
+            direction: direction.normalize(),
+        }
+    }
+
+    /*
+    *   Looks at the middle of the box from `min` to `max` from the same direction as before,
+    *   backed off until a ball around the whole box fits on the screen with FRAME_MARGIN to
+    *   spare. A ball doesn't care which way the box is turned. A box with no size (a single
+    *   point, or nothing at all) still gets a ball of MIN_FRAME_RADIUS, so there's something
+    *   to back off from.
+    */
+    pub fn frame_bounds(&mut self, min: cgmath::Vector3<f32>, max: cgmath::Vector3<f32>) {
+        use cgmath::{EuclideanSpace, InnerSpace};
+        // Empty boxes come out inside out, or not at all
+        let (min, max) = if (0..3).all(|i| min[i] <= max[i] && min[i].is_finite() && max[i].is_finite()) {
+            (min, max)
+        } else {
+            (cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Vector3::new(0.0, 0.0, 0.0))
+        };
+        let center = cgmath::Point3::from_vec((min + max) * 0.5);
+        let radius = ((max - min).magnitude() * 0.5).max(Self::MIN_FRAME_RADIUS) * Self::FRAME_MARGIN;
+        // The narrower of the two fields of view decides
+        let half_height = (cgmath::Rad::from(cgmath::Deg(self.fovy)).0 * 0.5).tan();
+        let half_fov = half_height.min(half_height * self.aspect).atan();
+        let distance = (radius / half_fov.sin()).max(self.znear * 2.0);
+        let forward = (self.target - self.eye).normalize();
+        self.eye = center - forward * distance;
+        self.target = center;
+    }
+}
+
+// A half line out from `origin`, `direction` is normalized
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    // Where the ray crosses the horizontal plane at height `y`. None when it runs parallel to
+    // the plane, or the plane is behind it.
+    pub fn intersect_horizontal_plane(&self, y: f32) -> Option<cgmath::Point3<f32>> {
+        if self.direction.y.abs() < 1e-6 {
+            return None;
+        }
+        let t = (y - self.origin.y) / self.direction.y;
+        (t >= 0.0).then(|| self.origin + self.direction * t)
+    }
+
+    // Same for any plane, through `point` & facing along `normal`
+    pub fn intersect_plane(
+        &self,
+        point: cgmath::Vector3<f32>,
+        normal: cgmath::Vector3<f32>,
+    ) -> Option<cgmath::Point3<f32>> {
+        use cgmath::{EuclideanSpace, InnerSpace};
+        let facing = normal.dot(self.direction);
+        if facing.abs() < 1e-6 {
+            return None;
+        }
+        let t = normal.dot(point - self.origin.to_vec()) / facing;
+        (t >= 0.0).then(|| self.origin + self.direction * t)
+    }
+
+    /*
+    *   How far along the ray it first enters the box, 0 when it starts inside, None on a miss.
+    *   The slab test: on each axis the ray is between the box's two planes for some stretch of
+    *   t, and it's inside the box where all three stretches overlap.
+    */
+    pub fn intersect_aabb(&self, aabb: &physics::Aabb) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::INFINITY;
+        for axis in 0..3 {
+            let origin = self.origin[axis];
+            let direction = self.direction[axis];
+            if direction.abs() < 1e-6 {
+                // Parallel to the slab, it never gets in if it doesn't start in
+                if origin < aabb.min[axis] || origin > aabb.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (aabb.min[axis] - origin) / direction;
+            let b = (aabb.max[axis] - origin) / direction;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+/*
+*   Looks straight down on the xz plane with an orthographic projection, so distances on the
+*   ground come out the same everywhere, like on a map. -z is up on the screen.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct TopDownCamera {
+    // The point in the middle of the view, only its x & z matter
+    pub center: cgmath::Point3<f32>,
+    // World units from the center to each edge of the (square) view
+    pub extent: f32,
+    // Where the camera sits above the ground, anything higher is cut off
+    pub height: f32,
+}
+
+impl TopDownCamera {
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let eye = cgmath::Point3::new(self.center.x, self.height, self.center.z);
+        let target = cgmath::Point3::new(self.center.x, 0.0, self.center.z);
+        let view = cgmath::Matrix4::look_at_rh(eye, target, -cgmath::Vector3::unit_z());
+        let extent = self.extent;
+        // Twice the height deep, so things down to as far below the ground still show up
+        let proj = cgmath::ortho(-extent, extent, -extent, extent, 0.0, self.height * 2.0);
+
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+}
+
+/*
+*   The six planes bounding what the camera can see. Each plane is (normal.xyz, distance) with
+*   the normal pointing into the frustum, so a point p is on the inside of a plane when
+*   dot(normal, p) + distance >= 0. The planes fall straight out of the rows of the view
+*   projection matrix (Gribb & Hartmann's trick), remember wgpu's depth goes from 0 to 1.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    pub planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn from_matrix(view_proj: cgmath::Matrix4<f32>) -> Self {
+        use cgmath::{InnerSpace, Matrix};
+        let row = |i| view_proj.row(i);
+        let planes = [
+            row(3) + row(0), // left
+            row(3) - row(0), // right
+            row(3) + row(1), // bottom
+            row(3) - row(1), // top
+            row(2),          // near
+            row(3) - row(2), // far
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    // The reversed projection's far plane is infinitely far away, the frustum gets zfar's
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self::from_matrix(camera.build_standard_view_projection_matrix())
+    }
+
+    // Where three of the planes meet, for drawing the frustum. Near corners first, then far,
+    // each going bottom left, bottom right, top right, top left.
+    pub fn corners(&self) -> [cgmath::Point3<f32>; 8] {
+        use cgmath::InnerSpace;
+        let [left, right, bottom, top, near, far] = self.planes;
+        let meet = |a: cgmath::Vector4<f32>, b: cgmath::Vector4<f32>, c: cgmath::Vector4<f32>| {
+            let (na, nb, nc) = (a.truncate(), b.truncate(), c.truncate());
+            let point = -(nb.cross(nc) * a.w + nc.cross(na) * b.w + na.cross(nb) * c.w) / na.dot(nb.cross(nc));
+            cgmath::Point3::new(point.x, point.y, point.z)
+        };
+        [
+            meet(left, bottom, near),
+            meet(right, bottom, near),
+            meet(right, top, near),
+            meet(left, top, near),
+            meet(left, bottom, far),
+            meet(right, bottom, far),
+            meet(right, top, far),
+            meet(left, top, far),
+        ]
+    }
+
+    // True if any part of the sphere is inside the frustum
+    pub fn intersects_sphere(&self, center: cgmath::Vector3<f32>, radius: f32) -> bool {
+        use cgmath::InnerSpace;
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    // We can't use cgmath with bytemuck directly so we'll have
+    // to convert the Matrix4 into a 4x4 f32 array
+    view_proj: [[f32; 4]; 4],
+    // vec4 rather than vec3 to satisfy the 16 byte alignment of uniforms
+    view_position: [f32; 4],
+}
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        use cgmath::SquareMatrix;
+        Self {
+            view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
+        }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_position = camera.eye.to_homogeneous().into();
+        self.view_proj = camera.build_view_projection_matrix().into();
+    }
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The gpu side of the camera. Pipelines that draw the scene bind this at @group(1), in as
+// many buffers as the ring has slots (see uniform_ring.rs).
+pub struct CameraBinding {
+    pub uniform: CameraUniform,
+    ring: UniformRing,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl CameraBinding {
+    pub fn new(device: &wgpu::Device, camera: &Camera, slots: usize) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(camera);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_bind_group_layout"),
+        });
+        let ring = UniformRing::new(device, &bind_group_layout, "Camera Buffer", bytemuck::cast_slice(&[uniform]), slots);
+
+        Self {
+            uniform,
+            ring,
+            bind_group_layout,
+        }
+    }
+
+    // The current slot's, see UniformRing
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.ring.bind_group()
+    }
+
+    pub fn ring(&self) -> &UniformRing {
+        &self.ring
+    }
+
+    // Starts again with `slots` buffers, all holding the camera as it was last uploaded
+    pub fn set_ring_slots(&mut self, device: &wgpu::Device, slots: usize) {
+        self.ring.resize(device, &self.bind_group_layout, bytemuck::cast_slice(&[self.uniform]), slots);
+    }
+
+    pub fn finish_frame(&mut self) {
+        self.ring.finish_frame();
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniform.update_view_proj(camera);
+        self.ring.write(queue, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Same as update, with everything shifted by `jitter` in clip space, see taa.rs. Moving
+    // clip space x & y by jitter * w moves the final position by exactly jitter.
+    pub fn update_jittered(&mut self, queue: &wgpu::Queue, camera: &Camera, jitter: [f32; 2]) {
+        let offset = cgmath::Matrix4::from_translation(cgmath::vec3(jitter[0], jitter[1], 0.0));
+        self.uniform.update_view_proj(camera);
+        self.uniform.view_proj = (offset * camera.build_view_projection_matrix()).into();
+        self.ring.write(queue, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+/*
+*   Free flying, first person style camera controls. WASD moves along the view & right
+*   vectors, space/ctrl move straight up/down, and the mouse turns the camera. Mouse movement
+*   should come from DeviceEvent::MouseMotion rather than WindowEvent::CursorMoved: the cursor
+*   stops at the edge of the window, raw mouse motion doesn't.
+*/
+#[derive(Debug)]
+pub struct FlyCamera {
+    // Units per second
+    pub speed: f32,
+    // Radians per pixel of mouse movement
+    pub sensitivity: f32,
+    yaw: cgmath::Rad<f32>,
+    pitch: cgmath::Rad<f32>,
+    amount_forward: f32,
+    amount_backward: f32,
+    amount_left: f32,
+    amount_right: f32,
+    amount_up: f32,
+    amount_down: f32,
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+}
+
+impl FlyCamera {
+    // Looking straight up or down makes the look_at matrix flip, so stop just short of it
+    const MAX_PITCH: cgmath::Rad<f32> = cgmath::Rad(std::f32::consts::FRAC_PI_2 - 0.001);
+
+    // Starts out looking the same way the camera already is
+    pub fn new(camera: &Camera, speed: f32, sensitivity: f32) -> Self {
+        use cgmath::InnerSpace;
+        let forward = (camera.target - camera.eye).normalize();
+        Self {
+            speed,
+            sensitivity,
+            yaw: cgmath::Rad(forward.z.atan2(forward.x)),
+            pitch: cgmath::Rad(forward.y.asin()),
+            amount_forward: 0.0,
+            amount_backward: 0.0,
+            amount_left: 0.0,
+            amount_right: 0.0,
+            amount_up: 0.0,
+            amount_down: 0.0,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+        }
+    }
+
+    // Returns true if the key is one of ours
+    pub fn process_keyboard(&mut self, key: winit::event::VirtualKeyCode, state: winit::event::ElementState) -> bool {
+        use winit::event::{ElementState, VirtualKeyCode};
+        let amount = if state == ElementState::Pressed { 1.0 } else { 0.0 };
+        match key {
+            VirtualKeyCode::W | VirtualKeyCode::Up => self.amount_forward = amount,
+            VirtualKeyCode::S | VirtualKeyCode::Down => self.amount_backward = amount,
+            VirtualKeyCode::A | VirtualKeyCode::Left => self.amount_left = amount,
+            VirtualKeyCode::D | VirtualKeyCode::Right => self.amount_right = amount,
+            VirtualKeyCode::Space => self.amount_up = amount,
+            VirtualKeyCode::LControl => self.amount_down = amount,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        // Several motion events can arrive between frames, add them all up
+        self.rotate_horizontal += mouse_dx as f32;
+        self.rotate_vertical += mouse_dy as f32;
+    }
+
+    // Forget any held keys, e.g. when we stop listening for the key up events
+    pub fn release_all(&mut self) {
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: instant::Duration) {
+        use cgmath::InnerSpace;
+        let dt = dt.as_secs_f32();
+
+        self.yaw += cgmath::Rad(self.rotate_horizontal * self.sensitivity);
+        // Moving the mouse up turns the camera up, while screen y points down
+        self.pitch += cgmath::Rad(-self.rotate_vertical * self.sensitivity);
+        self.pitch = cgmath::Rad(self.pitch.0.clamp(-Self::MAX_PITCH.0, Self::MAX_PITCH.0));
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        let (yaw_sin, yaw_cos) = self.yaw.0.sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.0.sin_cos();
+        let forward = cgmath::Vector3::new(yaw_cos * pitch_cos, pitch_sin, yaw_sin * pitch_cos).normalize();
+        let right = forward.cross(cgmath::Vector3::unit_y()).normalize();
+
+        camera.eye += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
+        camera.eye += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        camera.eye.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        camera.target = camera.eye + forward;
+        camera.up = cgmath::Vector3::unit_y();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Vector3};
+
+    fn camera() -> Camera {
+        Camera {
+            eye: (0.0, 5.0, 10.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_y(),
+            aspect: 16.0 / 9.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 1000.0,
+            depth: DepthDirection::Standard,
+        }
+    }
+
+    fn forward(camera: &Camera) -> Vector3<f32> {
+        (camera.target - camera.eye).normalize()
+    }
+
+    fn inside(point: cgmath::Point3<f32>, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        (0..3).all(|i| min[i] <= point[i] && point[i] <= max[i])
+    }
+
+    #[test]
+    fn looks_at_the_center_from_outside() {
+        let mut camera = camera();
+        let before = forward(&camera);
+        let (min, max) = (Vector3::new(-3.0, 1.0, 2.0), Vector3::new(5.0, 4.0, 8.0));
+        camera.frame_bounds(min, max);
+
+        assert_eq!(camera.target, (1.0, 2.5, 5.0).into());
+        assert!((forward(&camera) - before).magnitude() < 1e-5, "it turned");
+        assert!(!inside(camera.eye, min, max));
+        // Every corner's on the screen
+        let view_projection = camera.build_view_projection_matrix();
+        for corner in 0..8 {
+            let pick = |axis: usize| if corner & (1 << axis) == 0 { min[axis] } else { max[axis] };
+            let clip = view_projection * cgmath::Vector4::new(pick(0), pick(1), pick(2), 1.0);
+            let ndc = clip.truncate() / clip.w;
+            assert!(clip.w > 0.0 && ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "corner {:?} is off screen", ndc);
+        }
+    }
+
+    #[test]
+    fn single_point() {
+        let mut camera = camera();
+        let point = Vector3::new(2.0, -1.0, 3.0);
+        camera.frame_bounds(point, point);
+
+        assert_eq!(camera.target, cgmath::Point3::new(2.0, -1.0, 3.0));
+        // Backed off by at least the smallest ball there is
+        let distance = (camera.eye - camera.target).magnitude();
+        assert!(distance.is_finite() && distance >= Camera::MIN_FRAME_RADIUS, "{}", distance);
+    }
+
+    #[test]
+    fn flat_box() {
+        // No height at all, the ball's still around the rest of it
+        let mut camera = camera();
+        let (min, max) = (Vector3::new(-4.0, 0.0, -4.0), Vector3::new(4.0, 0.0, 4.0));
+        camera.frame_bounds(min, max);
+
+        assert_eq!(camera.target, (0.0, 0.0, 0.0).into());
+        assert!((camera.eye - camera.target).magnitude() > 4.0 * 2f32.sqrt());
+        assert!(!inside(camera.eye, min, max));
+    }
+
+    #[test]
+    fn empty_box_looks_at_the_origin() {
+        for (min, max) in [
+            (Vector3::new(1.0, 1.0, 1.0), Vector3::new(-1.0, -1.0, -1.0)),
+            (Vector3::new(f32::INFINITY, 0.0, 0.0), Vector3::new(f32::NEG_INFINITY, 0.0, 0.0)),
+            (Vector3::new(f32::NAN, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)),
+        ] {
+            let mut camera = camera();
+            camera.frame_bounds(min, max);
+            assert_eq!(camera.target, (0.0, 0.0, 0.0).into());
+            let distance = (camera.eye - camera.target).magnitude();
+            assert!(distance.is_finite() && distance > 0.0, "{}", distance);
+        }
+    }
+}
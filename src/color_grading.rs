@@ -0,0 +1,245 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::PipelineCache;
+use crate::post;
+
+/*
+*   Color grading, for look development. Lift, gamma & gain (in that order) then contrast and
+*   saturation, each a single number applied to all three channels:
+*
+*   - lift raises the shadows, leaving white where it is
+*   - gamma bends the midtones, bigger is brighter
+*   - gain scales everything, which mostly shows in the highlights
+*   - contrast pushes colors away from middle grey (0.18, the colors are linear here)
+*   - saturation mixes toward the luminance, 0 is greyscale and above 1 oversaturates
+*
+*   It's meant to go after tonemapping, but with an HDR format that's the chain's very last
+*   pass (see tonemap.rs). So it runs after the other color effects and before FXAA, which
+*   wants to see the finished image. The defaults
+*   don't change anything, and the pass is skipped until something's moved away from them.
+*/
+
+// Has to match GradingUniform in color_grading.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct GradingUniform {
+    lift: f32,
+    gamma: f32,
+    gain: f32,
+    contrast: f32,
+    saturation: f32,
+    _padding: [f32; 3],
+}
+
+impl GradingUniform {
+    const NEUTRAL: Self = Self {
+        lift: 0.0,
+        gamma: 1.0,
+        gain: 1.0,
+        contrast: 1.0,
+        saturation: 1.0,
+        _padding: [0.0; 3],
+    };
+}
+
+// The numbers that can be tweaked, in the order the keys go through them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GradingParameter {
+    Contrast,
+    Saturation,
+    Lift,
+    Gamma,
+    Gain,
+}
+
+impl GradingParameter {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Contrast => Self::Saturation,
+            Self::Saturation => Self::Lift,
+            Self::Lift => Self::Gamma,
+            Self::Gamma => Self::Gain,
+            Self::Gain => Self::Contrast,
+        }
+    }
+
+    // How much one key press changes it
+    pub fn step(self) -> f32 {
+        match self {
+            Self::Lift => 0.02,
+            _ => 0.05,
+        }
+    }
+
+    // Lift can go a little negative to crush the blacks. Gamma & contrast are powers, which
+    // fall apart at 0.
+    fn range(self) -> (f32, f32) {
+        match self {
+            Self::Lift => (-0.5, 0.5),
+            Self::Gamma | Self::Contrast => (0.1, 4.0),
+            Self::Saturation | Self::Gain => (0.0, 4.0),
+        }
+    }
+}
+
+impl std::fmt::Display for GradingParameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Contrast => "contrast",
+            Self::Saturation => "saturation",
+            Self::Lift => "lift",
+            Self::Gamma => "gamma",
+            Self::Gain => "gain",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub struct ColorGrading {
+    // The one the keys change
+    pub selected: GradingParameter,
+    uniform: GradingUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl ColorGrading {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let uniform = GradingUniform::NEUTRAL;
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Color Grading Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("color_grading_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("color_grading_bind_group"),
+        });
+
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Color Grading",
+            include_str!("color_grading.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &bind_group_layout],
+            color_format,
+        );
+
+        Self {
+            selected: GradingParameter::Contrast,
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device, keeping the current settings
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let mut grading = Self::new(device, pipelines, screen_layout, post_chain, color_format);
+        grading.selected = self.selected;
+        grading.uniform = self.uniform;
+        grading.write_uniform(queue);
+        grading
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    fn value_mut(&mut self, parameter: GradingParameter) -> &mut f32 {
+        match parameter {
+            GradingParameter::Contrast => &mut self.uniform.contrast,
+            GradingParameter::Saturation => &mut self.uniform.saturation,
+            GradingParameter::Lift => &mut self.uniform.lift,
+            GradingParameter::Gamma => &mut self.uniform.gamma,
+            GradingParameter::Gain => &mut self.uniform.gain,
+        }
+    }
+
+    pub fn value(&self, parameter: GradingParameter) -> f32 {
+        match parameter {
+            GradingParameter::Contrast => self.uniform.contrast,
+            GradingParameter::Saturation => self.uniform.saturation,
+            GradingParameter::Lift => self.uniform.lift,
+            GradingParameter::Gamma => self.uniform.gamma,
+            GradingParameter::Gain => self.uniform.gain,
+        }
+    }
+
+    // Clamped to a range that still gives a picture, and snapped to the step so going up &
+    // back down lands exactly on the default again
+    pub fn set_value(&mut self, queue: &wgpu::Queue, parameter: GradingParameter, value: f32) {
+        let (min, max) = parameter.range();
+        let step = parameter.step();
+        *self.value_mut(parameter) = ((value / step).round() * step).clamp(min, max);
+        self.write_uniform(queue);
+    }
+
+    // Back to the defaults, which turns the pass off
+    pub fn reset(&mut self, queue: &wgpu::Queue) {
+        self.uniform = GradingUniform::NEUTRAL;
+        self.write_uniform(queue);
+    }
+
+    pub fn is_neutral(&self) -> bool {
+        self.uniform == GradingUniform::NEUTRAL
+    }
+}
+
+// Every setting on one line, to copy down the good ones
+impl std::fmt::Display for ColorGrading {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let grading = &self.uniform;
+        write!(
+            f,
+            "contrast {:.2}, saturation {:.2}, lift {:.2}, gamma {:.2}, gain {:.2}",
+            grading.contrast, grading.saturation, grading.lift, grading.gamma, grading.gain
+        )
+    }
+}
+
+impl post::PostEffect for ColorGrading {
+    fn enabled(&self) -> bool {
+        !self.is_neutral()
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
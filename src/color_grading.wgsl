@@ -0,0 +1,59 @@
+// Color grading post effect, see color_grading.rs
+
+// @group(0) is the screen uniform, which grading doesn't need
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+
+struct GradingUniform {
+    lift: f32,
+    gamma: f32,
+    gain: f32,
+    contrast: f32,
+    saturation: f32,
+};
+@group(2) @binding(0)
+var<uniform> grading: GradingUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    // Texture coordinates have y pointing down
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+// Middle grey in linear color, what contrast pivots around
+const MIDDLE_GREY: f32 = 0.18;
+// Rec. 709 luminance weights, for linear sRGB
+const LUMA: vec3<f32> = vec3<f32>(0.2126, 0.7152, 0.0722);
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let input = textureSample(t_color, s_color, in.uv);
+    // Negative colors would turn into NaNs in the pow
+    var color = max(input.rgb, vec3<f32>(0.0));
+
+    // Lift moves black up (or down) and leaves white alone, gain scales the lot
+    color = grading.gain * (color + grading.lift * (vec3<f32>(1.0) - color));
+    color = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / grading.gamma));
+
+    // Contrast as a power around middle grey, so it stretches the stops evenly and nothing
+    // goes negative
+    color = pow(color / MIDDLE_GREY, vec3<f32>(grading.contrast)) * MIDDLE_GREY;
+
+    let luminance = dot(color, LUMA);
+    color = max(mix(vec3<f32>(luminance), color, grading.saturation), vec3<f32>(0.0));
+    return vec4<f32>(color, input.a);
+}
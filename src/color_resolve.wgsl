@@ -0,0 +1,29 @@
+// Resolves multisampled color in a shader rather than with the pass' resolve target, see
+// MsaaAttachment in antialiasing.rs
+
+@group(0) @binding(0)
+var t_color: texture_multisampled_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // Bright samples count for less, so one of them can't outweigh all the others. Dividing by
+    // the total weight undoes it for pixels where every sample is the same.
+    let coords = vec2<i32>(position.xy);
+    var total = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(t_color)); i += 1) {
+        let color = textureLoad(t_color, coords, i);
+        let weight = 1.0 / (1.0 + max(color.r, max(color.g, color.b)));
+        total += color * weight;
+        total_weight += weight;
+    }
+    return total / total_weight;
+}
@@ -0,0 +1,248 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::texture;
+
+/*
+*   Colormaps, for showing a number as a color. The map is a 256x1 texture, a scalar value is
+*   turned into 0..1 across the range it's expected in and that picks the texel, with linear
+*   filtering for the in-betweens. Values outside the range get the color at its end.
+*
+*   A lookup texture keeps the shader the same whatever the map: switching maps is a new
+*   texture, and a map could just as well come from an image. Here they're generated from
+*   polynomial fits of the originals, which are within a hair of the published tables.
+*
+*   The scene shader samples it in place of the diffuse texture when it's built with COLORMAP,
+*   with the height in world space as the value. The terrain uses that to color its hills by
+*   height, see State::set_terrain_colormap.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Colormap {
+    // Dark blue through green to yellow. Perceptually uniform & readable in greyscale, the
+    // safe default.
+    Viridis,
+    // Google's improved rainbow, more contrast between neighbouring values at the cost of
+    // uniformity
+    Turbo,
+}
+
+impl Colormap {
+    pub fn next(self) -> Self {
+        match self {
+            Colormap::Viridis => Colormap::Turbo,
+            Colormap::Turbo => Colormap::Viridis,
+        }
+    }
+
+    // The color for `t` in 0..1, in sRGB
+    pub fn color(self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            // Matt Zucker's fit, a polynomial in t of degree 6 per channel
+            Colormap::Viridis => {
+                const C: [[f32; 3]; 7] = [
+                    [0.277_727_3, 0.005_407_344_6, 0.334_099_8],
+                    [0.105_093_04, 1.404_613_5, 1.384_590_1],
+                    [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+                    [-4.634_230_6, -5.799_101, -19.332_441],
+                    [6.228_27, 14.179_933, 56.690_55],
+                    [4.776_385, -13.745_145, -65.353_03],
+                    [-5.435_456, 4.645_852_6, 26.312_435],
+                ];
+                let mut color = [0.0; 3];
+                for (channel, value) in color.iter_mut().enumerate() {
+                    *value = C.iter().rev().fold(0.0, |sum, c| sum * t + c[channel]);
+                }
+                color
+            }
+            // Anton Mikhailov's fit, degree 5
+            Colormap::Turbo => {
+                const C: [[f32; 3]; 6] = [
+                    [0.135_721_38, 0.091_402_61, 0.106_673_3],
+                    [4.615_392_6, 2.194_188_4, 12.641_946],
+                    [-42.660_324, 4.842_966_6, -60.582_05],
+                    [132.131_08, -14.185_033, 110.362_77],
+                    [-152.942_4, 4.277_299, -89.903_11],
+                    [59.286_38, 2.829_566, 27.348_25],
+                ];
+                let mut color = [0.0; 3];
+                for (channel, value) in color.iter_mut().enumerate() {
+                    *value = C.iter().rev().fold(0.0, |sum, c| sum * t + c[channel]);
+                }
+                color
+            }
+        }
+    }
+
+    // The lookup texture's RGBA8 texels, left to right
+    fn texels(self) -> Vec<u8> {
+        let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (0..ColormapLookup::SIZE)
+            .flat_map(|x| {
+                let [r, g, b] = self.color(x as f32 / (ColormapLookup::SIZE - 1) as f32);
+                [to_byte(r), to_byte(g), to_byte(b), 255]
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Colormap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Colormap::Viridis => write!(f, "viridis"),
+            Colormap::Turbo => write!(f, "turbo"),
+        }
+    }
+}
+
+// Has to match ColormapRange in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ColormapUniform {
+    // The values that get the first & the last texel
+    min: f32,
+    max: f32,
+    _padding: [f32; 2],
+}
+
+// A Colormap on the gpu with the range it maps, bound in place of a material's textures
+pub struct ColormapLookup {
+    colormap: Colormap,
+    texture: texture::Texture,
+    uniform: ColormapUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ColormapLookup {
+    // Texels across the lookup texture
+    const SIZE: u32 = 256;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, colormap: Colormap, range: (f32, f32)) -> Self {
+        let texture = Self::create_texture(device, queue, colormap);
+        let uniform = ColormapUniform {
+            min: range.0,
+            max: range.1,
+            _padding: [0.0; 2],
+        };
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Colormap Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("colormap_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture, &buffer);
+
+        Self {
+            colormap,
+            texture,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    // Same map & range on a (new) device
+    pub fn recreate(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::new(device, queue, self.colormap, self.range())
+    }
+
+    fn create_texture(device: &wgpu::Device, queue: &wgpu::Queue, colormap: Colormap) -> texture::Texture {
+        let label = format!("Colormap {}", colormap);
+        // The colors are sRGB, which from_rgba expects, & sampling gives linear values back
+        let mut texture = texture::Texture::from_rgba(device, queue, &colormap.texels(), Self::SIZE, 1, &label)
+            .expect("the colormap is SIZE texels");
+        // Blends between neighbouring texels both ways, from_image only does when magnifying
+        texture.sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(&label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        texture
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("colormap_bind_group"),
+        })
+    }
+
+    pub fn colormap(&self) -> Colormap {
+        self.colormap
+    }
+
+    // A new lookup texture, so a new bind group as well
+    pub fn set_colormap(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, colormap: Colormap) {
+        self.colormap = colormap;
+        self.texture = Self::create_texture(device, queue, colormap);
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.texture, &self.buffer);
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        (self.uniform.min, self.uniform.max)
+    }
+
+    // The values that map to either end. An empty range would divide by zero, so it's kept a
+    // little apart.
+    pub fn set_range(&mut self, queue: &wgpu::Queue, min: f32, max: f32) {
+        self.uniform.min = min;
+        self.uniform.max = max.max(min + 1e-3);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
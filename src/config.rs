@@ -0,0 +1,401 @@
+/*
+*   Settings that are picked once at startup, before there's a window or a gpu. run() uses the
+*   defaults, run_with_config lets the caller choose. On native main.rs fills them in from the
+*   command line with from_args, the web has no command line so it always gets the defaults.
+*/
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+    // The most detailed messages that get printed, changed at runtime with F2. RUST_LOG still
+    // has the final say, see logging.rs.
+    pub log_level: log::LevelFilter,
+    // Render this many frames offscreen, print how long they took and quit, see State::benchmark
+    pub bench_frames: Option<u32>,
+    // Width & height of the main window in physical pixels, winit picks when it's None
+    pub window_size: Option<(u32, u32)>,
+    // Where the main window's top left corner goes, in physical pixels on the desktop. None
+    // leaves it to the OS. F7 centers the window on its monitor later on.
+    pub window_position: Option<(i32, i32)>,
+    // How much simulation time each fixed update covers, see timestep.rs
+    pub fixed_timestep: instant::Duration,
+    // Which graphics APIs wgpu is allowed to pick an adapter from
+    pub backends: wgpu::Backends,
+    // Settle for a software adapter (lavapipe, WARP, ...) when there's no gpu rather than
+    // giving up, see GpuContext::new
+    pub fallback_adapter: bool,
+    // Falls back to Fifo if the surface doesn't support it
+    pub present_mode: wgpu::PresentMode,
+    // What the scene & post effects are drawn into before they reach the surface, None keeps
+    // the surface's format. Falls back to that if the adapter can't render to it, see post.rs.
+    pub offscreen_format: Option<wgpu::TextureFormat>,
+    // Draw the scene into multisampled Rgba16Float, resolve it and tonemap it onto the surface,
+    // see tonemap.rs. Takes over offscreen_format & starts with 4x MSAA, where the adapter can.
+    pub hdr: bool,
+    // .obj files shown in place of the spheres, the first one to begin with. Shift + [ & ]
+    // cycle through them later on.
+    pub model_paths: Vec<std::path::PathBuf>,
+    // Window chrome for the main window, both can be toggled while running (F3 & F4). Neither
+    // does anything on the web, the canvas is part of the page.
+    pub decorations: bool,
+    pub always_on_top: bool,
+    // Color & size of the crosshair shown while flying the camera
+    pub crosshair: crate::crosshair::CrosshairStyle,
+    // Which corner the minimap goes in (Insert), how big it is & how much of the scene it shows
+    pub minimap: crate::minimap::MinimapLayout,
+    // How fast the tearing test's bar moves (F12), in pixels per second
+    pub tearing_speed: f32,
+    // Aim for a present every so often rather than as soon as possible, see frame_pacing.rs.
+    // Toggled with shift + F8 later on.
+    pub frame_pacing: crate::frame_pacing::FramePacing,
+    // How often the gpu frame time & memory get logged, see bench::StatsLog. None never does.
+    pub stats_interval: Option<instant::Duration>,
+    // How batched meshes like the terrain's chunks get drawn, see batch.rs. None picks the
+    // fewest draw calls the device can do. Cycled with shift + I later on.
+    pub batch_draw: Option<crate::batch::BatchDrawMode>,
+    // Which way the depth buffer runs, see camera::DepthDirection. Shift + Z flips it later on.
+    pub depth: crate::camera::DepthDirection,
+    // Composite the surface with premultiplied alpha rather than opaque, where the surface can.
+    // Only matters when the canvas overlaps other HTML content on the page: premultiplied, the
+    // browser expects the colors already multiplied by alpha, opaque it ignores alpha
+    // completely. On by default on the web, native windows are drawn opaque.
+    pub premultiplied_alpha: bool,
+    // Keeps every window hidden. Only allowed with a benchmark, nothing else ends on its own.
+    pub headless: bool,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            log_level: log::LevelFilter::Info,
+            bench_frames: None,
+            window_size: None,
+            window_position: None,
+            fixed_timestep: instant::Duration::from_secs_f64(1.0 / 60.0),
+            backends: wgpu::Backends::all(),
+            fallback_adapter: true,
+            present_mode: wgpu::PresentMode::Fifo,
+            offscreen_format: None,
+            hdr: false,
+            model_paths: Vec::new(),
+            decorations: true,
+            always_on_top: false,
+            crosshair: Default::default(),
+            minimap: Default::default(),
+            tearing_speed: crate::tearing_test::TearingTest::DEFAULT_SPEED,
+            frame_pacing: crate::frame_pacing::FramePacing::Off,
+            stats_interval: None,
+            batch_draw: None,
+            depth: Default::default(),
+            premultiplied_alpha: cfg!(target_arch = "wasm32"),
+            headless: false,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+usage: learn-wgpu [options]
+
+options:
+    --width <pixels>        width of the window, needs --height too
+    --height <pixels>       height of the window, needs --width too
+    --position <x>,<y>      where the window's top left corner goes on the desktop
+    --tick-rate <hz>        fixed simulation updates per second (default 60)
+    --backend <name>        vulkan, metal, dx12, dx11, gl or all (default)
+    --no-fallback-adapter   fail rather than use a software renderer when there's no gpu
+    --present-mode <mode>   fifo (default), fifo-relaxed, mailbox, immediate, auto-vsync or auto-no-vsync
+    --offscreen-format <f>  rgba8, rgba8-srgb or rgba16f to draw the scene into before the
+                            surface (default: the surface's own format)
+    --hdr                   draw the scene in rgba16f with 4x MSAA, resolve it, then tonemap it
+    --model <path>          show an .obj model instead of the spheres, give it more than once
+                            to cycle through them with shift + [ and ]
+    --no-decorations        open the window without a title bar & border
+    --always-on-top         keep the window above all the others
+    --tearing-speed <px/s>  how fast the tearing test's bar moves (default 800)
+    --frame-pacing <fps>    present at an even <fps> rather than as fast as possible
+    --stats <seconds>       log the gpu frame time & memory use every <seconds>
+    --batch-draw <mode>     multi-indirect, indirect or direct draws for the terrain's chunks
+                            (default: the first one the device supports)
+    --alpha <mode>          opaque or premultiplied, how the surface is composited with what's
+                            behind it (default: premultiplied on the web, opaque otherwise)
+    --reverse-z             near is 1 & far is 0 in the depth buffer, for more precision far away
+    --bench <frames>        render <frames> frames offscreen, print the timings and quit
+    --headless              don't show any windows, needs --bench
+    --help                  print this and quit";
+
+#[derive(Debug)]
+pub enum ArgsError {
+    // --help was asked for, not really an error but there's nothing to run
+    Help,
+    Invalid(String),
+}
+
+impl std::fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{}", USAGE),
+            ArgsError::Invalid(message) => write!(f, "{}\n\n{}", message, USAGE),
+        }
+    }
+}
+
+impl RunConfig {
+    // The arguments after the program's name, every option left out keeps its default
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut config = Self::default();
+        let (mut width, mut height) = (None, None);
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ArgsError::Invalid(format!("{} needs a value", arg)))
+            };
+            match arg.as_str() {
+                "--width" => width = Some(parse_size(&arg, &value()?)?),
+                "--height" => height = Some(parse_size(&arg, &value()?)?),
+                "--position" => config.window_position = Some(parse_position(&value()?)?),
+                "--tick-rate" => {
+                    let rate = value()?;
+                    match rate.parse::<f64>() {
+                        Ok(hz) if hz > 0.0 && hz.is_finite() => {
+                            config.fixed_timestep = instant::Duration::from_secs_f64(1.0 / hz)
+                        }
+                        _ => return Err(ArgsError::Invalid(format!("--tick-rate needs a positive rate, got {:?}", rate))),
+                    }
+                }
+                "--backend" => config.backends = parse_backends(&value()?)?,
+                "--present-mode" => config.present_mode = parse_present_mode(&value()?)?,
+                "--batch-draw" => config.batch_draw = Some(parse_batch_draw(&value()?)?),
+                "--alpha" => config.premultiplied_alpha = parse_alpha(&value()?)?,
+                "--offscreen-format" => config.offscreen_format = Some(parse_offscreen_format(&value()?)?),
+                "--model" => config.model_paths.push(value()?.into()),
+                "--bench" => {
+                    let frames = value()?;
+                    let frames = frames.parse().map_err(|_| {
+                        ArgsError::Invalid(format!("--bench needs a number of frames, got {:?}", frames))
+                    })?;
+                    config.bench_frames = Some(frames);
+                }
+                "--tearing-speed" => {
+                    let speed = value()?;
+                    match speed.parse::<f32>() {
+                        Ok(speed) if speed >= 0.0 && speed.is_finite() => config.tearing_speed = speed,
+                        _ => {
+                            return Err(ArgsError::Invalid(format!(
+                                "--tearing-speed needs pixels per second, got {:?}",
+                                speed
+                            )))
+                        }
+                    }
+                }
+                "--frame-pacing" => {
+                    let rate = value()?;
+                    match rate.parse::<f64>() {
+                        Ok(hz) if hz > 0.0 && hz.is_finite() => {
+                            config.frame_pacing = crate::frame_pacing::FramePacing::from_rate(hz)
+                        }
+                        _ => return Err(ArgsError::Invalid(format!("--frame-pacing needs a positive rate, got {:?}", rate))),
+                    }
+                }
+                "--stats" => {
+                    let seconds = value()?;
+                    match seconds.parse::<f64>() {
+                        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                            config.stats_interval = Some(instant::Duration::from_secs_f64(seconds))
+                        }
+                        _ => return Err(ArgsError::Invalid(format!("--stats needs a positive number of seconds, got {:?}", seconds))),
+                    }
+                }
+                "--no-decorations" => config.decorations = false,
+                "--always-on-top" => config.always_on_top = true,
+                "--headless" => config.headless = true,
+                "--no-fallback-adapter" => config.fallback_adapter = false,
+                "--reverse-z" => config.depth = crate::camera::DepthDirection::Reversed,
+                "--hdr" => config.hdr = true,
+                "--help" | "-h" => return Err(ArgsError::Help),
+                _ => return Err(ArgsError::Invalid(format!("unknown argument {:?}", arg))),
+            }
+        }
+
+        config.window_size = match (width, height) {
+            (Some(width), Some(height)) => Some((width, height)),
+            (None, None) => None,
+            _ => return Err(ArgsError::Invalid("--width and --height go together".to_string())),
+        };
+        if config.hdr {
+            if let Some(format) = config.offscreen_format.filter(|format| *format != crate::tonemap::Tonemap::HDR_FORMAT) {
+                return Err(ArgsError::Invalid(format!(
+                    "--hdr draws into rgba16f, it can't go with an offscreen format of {:?}",
+                    format
+                )));
+            }
+        }
+        if config.headless && config.bench_frames.is_none() {
+            return Err(ArgsError::Invalid(
+                "--headless needs --bench, there'd be no window to close otherwise".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+}
+
+fn parse_size(arg: &str, value: &str) -> Result<u32, ArgsError> {
+    // A zero sized surface can't be configured
+    match value.parse() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(ArgsError::Invalid(format!("{} needs a number of pixels, got {:?}", arg, value))),
+    }
+}
+
+fn parse_position(value: &str) -> Result<(i32, i32), ArgsError> {
+    // Negative numbers are fine, monitors to the left of or above the main one have them
+    let parsed = value
+        .split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+    parsed.ok_or_else(|| ArgsError::Invalid(format!("--position needs <x>,<y>, got {:?}", value)))
+}
+
+fn parse_backends(value: &str) -> Result<wgpu::Backends, ArgsError> {
+    Ok(match value.to_lowercase().as_str() {
+        "vulkan" => wgpu::Backends::VULKAN,
+        "metal" => wgpu::Backends::METAL,
+        "dx12" => wgpu::Backends::DX12,
+        "dx11" => wgpu::Backends::DX11,
+        "gl" => wgpu::Backends::GL,
+        "all" => wgpu::Backends::all(),
+        _ => return Err(ArgsError::Invalid(format!("unknown backend {:?}", value))),
+    })
+}
+
+fn parse_batch_draw(value: &str) -> Result<crate::batch::BatchDrawMode, ArgsError> {
+    use crate::batch::BatchDrawMode;
+    Ok(match value.to_lowercase().as_str() {
+        "multi-indirect" => BatchDrawMode::MultiDrawIndirect,
+        "indirect" => BatchDrawMode::Indirect,
+        "direct" => BatchDrawMode::Direct,
+        _ => return Err(ArgsError::Invalid(format!("unknown batch draw mode {:?}", value))),
+    })
+}
+
+fn parse_present_mode(value: &str) -> Result<wgpu::PresentMode, ArgsError> {
+    Ok(match value.to_lowercase().as_str() {
+        "fifo" => wgpu::PresentMode::Fifo,
+        "fifo-relaxed" => wgpu::PresentMode::FifoRelaxed,
+        "mailbox" => wgpu::PresentMode::Mailbox,
+        "immediate" => wgpu::PresentMode::Immediate,
+        "auto-vsync" => wgpu::PresentMode::AutoVsync,
+        "auto-no-vsync" => wgpu::PresentMode::AutoNoVsync,
+        _ => return Err(ArgsError::Invalid(format!("unknown present mode {:?}", value))),
+    })
+}
+
+fn parse_alpha(value: &str) -> Result<bool, ArgsError> {
+    match value.to_lowercase().as_str() {
+        "opaque" => Ok(false),
+        "premultiplied" => Ok(true),
+        _ => Err(ArgsError::Invalid(format!("unknown alpha mode {:?}", value))),
+    }
+}
+
+fn parse_offscreen_format(value: &str) -> Result<wgpu::TextureFormat, ArgsError> {
+    Ok(match value.to_lowercase().as_str() {
+        "rgba8" => wgpu::TextureFormat::Rgba8Unorm,
+        "rgba8-srgb" => wgpu::TextureFormat::Rgba8UnormSrgb,
+        "rgba16f" => wgpu::TextureFormat::Rgba16Float,
+        _ => return Err(ArgsError::Invalid(format!("unknown offscreen format {:?}", value))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<RunConfig, ArgsError> {
+        RunConfig::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // The message without the usage after it
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Err(ArgsError::Invalid(message)) => message,
+            Err(ArgsError::Help) => panic!("{:?} asked for help", args),
+            Ok(_) => panic!("{:?} parsed", args),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.window_size, None);
+        assert!(!config.hdr && !config.headless);
+    }
+
+    #[test]
+    fn window_size_and_position() {
+        let config = parse(&["--width", "800", "--height", "600", "--position", "-1920, 40"]).unwrap();
+        assert_eq!(config.window_size, Some((800, 600)));
+        assert_eq!(config.window_position, Some((-1920, 40)));
+    }
+
+    #[test]
+    fn width_needs_height() {
+        assert_eq!(error(&["--width", "800"]), "--width and --height go together");
+        assert_eq!(error(&["--height", "600"]), "--width and --height go together");
+        assert_eq!(error(&["--width", "0", "--height", "600"]), "--width needs a number of pixels, got \"0\"");
+    }
+
+    #[test]
+    fn hdr_and_offscreen_format() {
+        assert_eq!(
+            error(&["--hdr", "--offscreen-format", "rgba8"]),
+            "--hdr draws into rgba16f, it can't go with an offscreen format of Rgba8Unorm"
+        );
+        let config = parse(&["--offscreen-format", "rgba16f", "--hdr"]).unwrap();
+        assert!(config.hdr);
+    }
+
+    #[test]
+    fn headless_needs_bench() {
+        assert_eq!(
+            error(&["--headless"]),
+            "--headless needs --bench, there'd be no window to close otherwise"
+        );
+        let config = parse(&["--headless", "--bench", "10"]).unwrap();
+        assert_eq!(config.bench_frames, Some(10));
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(error(&["--tick-rate"]), "--tick-rate needs a value");
+        assert_eq!(error(&["--hdr", "--model"]), "--model needs a value");
+    }
+
+    #[test]
+    fn tick_rate() {
+        let config = parse(&["--tick-rate", "120"]).unwrap();
+        assert_eq!(config.fixed_timestep, instant::Duration::from_secs_f64(1.0 / 120.0));
+        assert_eq!(error(&["--tick-rate", "0"]), "--tick-rate needs a positive rate, got \"0\"");
+    }
+
+    #[test]
+    fn alpha() {
+        assert!(parse(&["--alpha", "premultiplied"]).unwrap().premultiplied_alpha);
+        assert!(!parse(&["--alpha", "Opaque"]).unwrap().premultiplied_alpha);
+        assert_eq!(error(&["--alpha", "straight"]), "unknown alpha mode \"straight\"");
+    }
+
+    #[test]
+    fn bad_position() {
+        for position in ["10", "10,", "x,10", "10;20"] {
+            assert_eq!(
+                error(&["--position", position]),
+                format!("--position needs <x>,<y>, got {:?}", position)
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_arguments() {
+        assert_eq!(error(&["--fullscreen"]), "unknown argument \"--fullscreen\"");
+        assert_eq!(error(&["--backend", "glide"]), "unknown backend \"glide\"");
+        assert!(matches!(parse(&["--hdr", "-h"]), Err(ArgsError::Help)));
+    }
+}
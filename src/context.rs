@@ -0,0 +1,380 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use winit::window::Window;
+
+use crate::gpu_memory::TrackedDevice;
+use crate::pipeline_cache::PipelineCache;
+
+/*
+*   Everything that's tied to the physical gpu rather than a particular window. There's
+*   only ever one of these, every window's State holds an Rc to it, so all the windows
+*   share the same device & queue. Each window still gets its own Surface since that's
+*   tied to the window itself.
+*/
+pub struct GpuContext {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    // Every render pipeline goes through here, so windows & rebuilds share identical ones
+    pub pipelines: PipelineCache,
+    // What the instance was created with, a recreated context has to stick to the same ones
+    pub backends: wgpu::Backends,
+    // Whether a software adapter was allowed, see RunConfig::fallback_adapter
+    pub fallback_adapter: bool,
+    // Raised by the device's error handler when the gpu goes away (driver update, TDR, ...)
+    device_lost: Arc<AtomicBool>,
+}
+
+/*
+*   Why there's no GpuContext. Without a gpu (headless CI, a VM, drivers that didn't install)
+*   wgpu has no adapter to give us, which used to end in a panic. These say what went wrong
+*   and what usually fixes it instead, run_with_config hands them back to the caller.
+*/
+#[derive(Debug)]
+pub enum InitError {
+    // Not even a software adapter, or it wasn't allowed to look for one
+    NoAdapter {
+        backends: wgpu::Backends,
+        fallback_adapter: bool,
+    },
+    // There's an adapter but it wouldn't give us a device with the features & limits we asked for
+    NoDevice(wgpu::RequestDeviceError),
+}
+
+impl std::fmt::Display for InitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InitError::NoAdapter {
+                backends,
+                fallback_adapter,
+            } => {
+                write!(f, "no gpu adapter found for {:?}", backends)?;
+                if !fallback_adapter {
+                    write!(f, ", and the software fallback was turned off with --no-fallback-adapter")?;
+                }
+                write!(
+                    f,
+                    "\n\nWithout a gpu, install a software renderer: Mesa's lavapipe on Linux \
+                     (mesa-vulkan-drivers or vulkan-swrast), WARP comes with Windows (--backend dx12). \
+                     Otherwise check the graphics drivers are installed, or try another --backend."
+                )
+            }
+            InitError::NoDevice(error) => write!(f, "the gpu adapter couldn't create a device: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
+impl GpuContext {
+    // Creating some of the wgpu types requires async code. We need a surface to pick a
+    // compatible adapter, so we create the first window's surface here and hand it back.
+    // `backends` limits which graphics APIs the adapter can come from, see RunConfig, and
+    // `fallback_adapter` lets it be a software one when there's no real gpu.
+    pub async fn new(
+        window: &Window,
+        backends: wgpu::Backends,
+        fallback_adapter: bool,
+    ) -> Result<(Self, wgpu::Surface), InitError> {
+        // The instance is a handle to our GPU
+        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            dx12_shader_compiler: Default::default(),
+        });
+
+        // The surface is the part of the window we draw to.
+        // # Safety
+        // The surface needs to live as long as the window that created it. State owns the
+        // window and drops it after the surface.
+        let surface = unsafe {
+            instance.create_surface(window)
+        }.unwrap();
+
+        // The adapter is the handle to our graphics card.
+        // We can use this to get information about the graphics card
+        // including its name and what backend the adapter uses. We will
+        // use this to create our Device & Queue later.
+        let request = |force_fallback_adapter| {
+            instance.request_adapter(&wgpu::RequestAdapterOptions {
+                // power_preference has two variants, LowPower, and HighPerformance.
+                power_preference: wgpu::PowerPreference::default(),
+                // compatible_surface field tells wgpu to find an adapter that can present
+                // to the supplied surface.
+                compatible_surface: Some(&surface),
+                // force_fallback_adapter forces wgpu to pick an adapter that will work on
+                // all hardware. This usually means that the rendering backend will use a
+                // "software" system, instead of hardware such as a GPU.
+                force_fallback_adapter,
+            })
+        };
+        // A real gpu if there is one, a software rasterizer like lavapipe or WARP otherwise
+        let adapter = match request(false).await {
+            Some(adapter) => adapter,
+            None if fallback_adapter => {
+                log::warn!("no hardware adapter found, trying a software one");
+                request(true).await.ok_or(InitError::NoAdapter {
+                    backends,
+                    fallback_adapter,
+                })?
+            }
+            None => {
+                return Err(InitError::NoAdapter {
+                    backends,
+                    fallback_adapter,
+                })
+            }
+        };
+        let info = adapter.get_info();
+        log::info!("adapter: {} ({:?}, {:?})", info.name, info.backend, info.device_type);
+
+        // The options passed to request_adapter aren't guaranteed to work for all devices,
+        // but will work for most of them. If wgpu can''t find an adapter with the required
+        // permissions, request_adapter will return None. If you want to get all the adapters
+        // for a particular backend you can use enumerate_adapters. This will give you an
+        // iterator that you cna loop over to check if one of the adapters work for your needs.
+        //
+        // Another thing to note is that Adapters are locked to a specific backend. If you are
+        // on Windows and have 2 graphics cards you will have at least 4 adapters available to use.
+
+        /*
+            let adapter = instance
+                .enumerate_adapters(wgpu::Backends::all())
+                .filter(|adapter| {
+                    // Check if this adapter supports our surface
+                    adapter.is_surface_supported(&surface)
+                })
+                .next()
+                .unwrap()
+        */
+
+        // Use the adapter to create the device and queue.
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                features: negotiate_features(&adapter, OPTIONAL_FEATURES),
+                // Ask for everything the adapter can do rather than a fixed set of limits. That's
+                // more than Limits::default() on most desktop gpus, and on WebGL it's what the
+                // browser really has instead of the bare downlevel_webgl2_defaults(). Anything
+                // that needs more than the minimum checks device.limits() before using it.
+                limits: adapter.limits(),
+                label: None,
+            },
+            None, // Trace path
+        ).await.map_err(InitError::NoDevice)?;
+
+        let limits = device.limits();
+        log::info!(
+            "limits: {}px textures, {} bind groups, {} storage buffers per stage, {} byte buffers, {} byte uniforms",
+            limits.max_texture_dimension_2d,
+            limits.max_bind_groups,
+            limits.max_storage_buffers_per_shader_stage,
+            limits.max_buffer_size,
+            limits.max_uniform_buffer_binding_size,
+        );
+
+        /*
+        *   wgpu 0.15 doesn't have a dedicated "device lost" callback, but once the device is
+        *   gone every call on it fails, see is_device_lost. We catch those here and raise a
+        *   flag that the event loop checks every frame. Installing a handler replaces wgpu's
+        *   default one, so anything else is still treated as fatal like before.
+        */
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost_flag = device_lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if is_device_lost(&error) {
+                log::error!("gpu device lost: {}", error);
+                lost_flag.store(true, Ordering::SeqCst);
+            } else {
+                log::error!("Handling wgpu errors as fatal by default");
+                panic!("wgpu error: {}\n", error);
+            }
+        }));
+
+        let context = Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            pipelines: PipelineCache::new(),
+            backends,
+            fallback_adapter,
+            device_lost,
+        };
+        Ok((context, surface))
+    }
+
+    // Surfaces for any additional windows come from the same instance
+    pub fn create_surface(&self, window: &Window) -> wgpu::Surface {
+        unsafe { self.instance.create_surface(window) }.unwrap()
+    }
+
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::SeqCst)
+    }
+
+    // For when we figure out the device is gone some other way than the error handler
+    pub fn mark_device_lost(&self) {
+        self.device_lost.store(true, Ordering::SeqCst);
+    }
+
+    /*
+    *   Reads the first `size` bytes of a buffer back to the cpu. The buffer needs COPY_SRC.
+    *   We can't map most buffers directly, so we copy into a MAP_READ staging buffer first.
+    *   Copies have to be a multiple of 4 bytes, so `size` gets rounded up for the copy.
+    *
+    *   Mapping is asynchronous: map_async only queues the request and calls us back once the
+    *   gpu is done with the buffer. On native nothing happens until we poll the device, on
+    *   the web the browser polls for us, so awaiting the callback works on both.
+    */
+    pub async fn read_buffer_async(&self, buffer: &wgpu::Buffer, size: wgpu::BufferAddress) -> Vec<u8> {
+        let copy_size = wgpu::util::align_to(size, wgpu::COPY_BUFFER_ALIGNMENT);
+        let staging = self.device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Staging Buffer"),
+            size: copy_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, copy_size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        // Blocks until the gpu is done. Does nothing on the web.
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.receive().await.unwrap().expect("Couldn't map the readback buffer");
+
+        let data = slice.get_mapped_range()[..size as usize].to_vec();
+        // The mapped range has to be dropped before we can unmap
+        staging.unmap();
+        data
+    }
+
+    /*
+    *   Reads a whole 2d texture back to the cpu, tightly packed row after row. The texture needs
+    *   COPY_SRC. Texture to buffer copies need every row padded to a multiple of 256 bytes
+    *   (COPY_BYTES_PER_ROW_ALIGNMENT), so we copy into a padded buffer and strip the padding
+    *   off each row afterwards.
+    */
+    pub async fn read_texture_async(&self, texture: &wgpu::Texture, format: wgpu::TextureFormat, size: wgpu::Extent3d) -> Vec<u8> {
+        let bytes_per_pixel = format.describe().block_size as u32;
+        let unpadded_bytes_per_row = size.width * bytes_per_pixel;
+        let padded_bytes_per_row = wgpu::util::align_to(unpadded_bytes_per_row, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let staging = self.device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture Readback Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(size.height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.receive().await.unwrap().expect("Couldn't map the texture readback buffer");
+
+        let data = {
+            let padded = slice.get_mapped_range();
+            padded
+                .chunks_exact(padded_bytes_per_row as usize)
+                .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        staging.unmap();
+        data
+    }
+}
+
+/*
+*   Features we make use of when they're there, and do without when they're not:
+*
+*   - TIMESTAMP_QUERY times the gpu side of a frame in benchmarks
+*   - POLYGON_MODE_LINE draws the scene as a wireframe (F5)
+*   - MULTI_DRAW_INDIRECT draws the terrain's chunks in one call, see batch.rs
+*
+*   Everything that depends on one of these checks State::features (the device's, not the
+*   adapter's) before using it, asking for a feature the device wasn't created with is a
+*   validation error.
+*/
+pub const OPTIONAL_FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY
+        .union(wgpu::Features::POLYGON_MODE_LINE)
+        .union(wgpu::Features::MULTI_DRAW_INDIRECT);
+
+// The part of `wanted` the adapter has
+fn negotiate_features(adapter: &wgpu::Adapter, wanted: wgpu::Features) -> wgpu::Features {
+    let granted = wanted & adapter.features();
+    log::info!("optional features granted: {:?}", granted);
+    if granted != wanted {
+        log::info!("optional features missing: {:?}", wanted - granted);
+    }
+    granted
+}
+
+/*
+*   Whether an uncaptured error is the device being lost. Checked against wgpu 0.15.1, which
+*   has no device lost callback (Device::set_device_lost_callback came later) and doesn't
+*   hand the error's kind out either: it's wgpu-core's DeviceError::Lost, and all that's left
+*   of it by the time it gets here is its message, "parent device is lost". Look again when
+*   upgrading, once there's a callback this can go.
+*/
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    error.to_string().contains("device is lost")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validation_error(description: &str) -> wgpu::Error {
+        wgpu::Error::Validation {
+            source: Box::new(std::io::Error::other(description.to_string())),
+            description: description.to_string(),
+        }
+    }
+
+    #[test]
+    fn device_lost() {
+        // How wgpu 0.15 formats it, the error's chain a line each
+        assert!(is_device_lost(&validation_error("Validation Error\n\nCaused by:\n    parent device is lost\n")));
+        assert!(!is_device_lost(&validation_error("Validation Error\n\nCaused by:\n    Buffer is invalid\n")));
+        let out_of_memory = wgpu::Error::OutOfMemory { source: Box::new(std::io::Error::other("oom")) };
+        assert!(!is_device_lost(&out_of_memory));
+    }
+}
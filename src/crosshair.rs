@@ -0,0 +1,163 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::screen;
+
+/*
+*   A crosshair in the middle of the screen while the fly camera has the cursor, so you can
+*   tell what you're looking at. Its size is in pixels (the vertex shader divides by the
+*   resolution from the screen uniform), so it looks the same at any window size.
+*
+*   It's drawn in its own pass on top of the finished frame, after the post effects, so it
+*   doesn't get blurred or anti-aliased along with the scene.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct CrosshairStyle {
+    // Alpha blends it over the scene
+    pub color: [f32; 4],
+    // From the center to the end of each arm, in pixels
+    pub size: f32,
+    pub thickness: f32,
+}
+
+impl Default for CrosshairStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 0.8],
+            size: 8.0,
+            thickness: 2.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CrosshairUniform {
+    color: [f32; 4],
+    size: f32,
+    thickness: f32,
+    _padding: [f32; 2],
+}
+
+impl From<CrosshairStyle> for CrosshairUniform {
+    fn from(style: CrosshairStyle) -> Self {
+        Self {
+            color: style.color,
+            size: style.size,
+            thickness: style.thickness,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+pub struct Crosshair {
+    style: CrosshairStyle,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Crosshair {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        style: CrosshairStyle,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Crosshair Buffer"),
+            contents: bytemuck::cast_slice(&[CrosshairUniform::from(style)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("crosshair_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("crosshair_bind_group"),
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Crosshair",
+                shader: include_str!("crosshair.wgsl"),
+                bind_group_layouts: &[screen_layout, &bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+
+        Self {
+            style,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new surface format, same style
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self::new(device, pipelines, screen_layout, color_format, self.style)
+    }
+
+    pub fn style(&self) -> CrosshairStyle {
+        self.style
+    }
+
+    pub fn set_style(&mut self, queue: &wgpu::Queue, style: CrosshairStyle) {
+        self.style = style;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[CrosshairUniform::from(style)]));
+    }
+
+    // Draws over whatever is in `output` already
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Crosshair Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..18, 0..1);
+    }
+}
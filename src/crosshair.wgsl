@@ -0,0 +1,54 @@
+// Crosshair at the center of the screen, see crosshair.rs. A horizontal bar plus the two halves
+// of the vertical one, so no pixel is drawn twice and a see-through color stays even.
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+struct Crosshair {
+    color: vec4<f32>,
+    // From the center to the end of each arm, in pixels
+    size: f32,
+    thickness: f32,
+};
+@group(1) @binding(0)
+var<uniform> crosshair: Crosshair;
+
+// 3 quads of 2 triangles each, 18 vertices
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let half_thickness = crosshair.thickness * 0.5;
+    let size = crosshair.size;
+    // Corners of the quad in pixels from the center: min x, min y, max x, max y
+    var rect: vec4<f32>;
+    switch (in_vertex_index / 6u) {
+        case 0u: {
+            rect = vec4<f32>(-size, -half_thickness, size, half_thickness);
+        }
+        case 1u: {
+            rect = vec4<f32>(-half_thickness, half_thickness, half_thickness, size);
+        }
+        default: {
+            rect = vec4<f32>(-half_thickness, -size, half_thickness, -half_thickness);
+        }
+    }
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let pixel = mix(rect.xy, rect.zw, corners[in_vertex_index % 6u]);
+    // Clip space is 2 units across the screen, and y goes up in both
+    return vec4<f32>(pixel * 2.0 * screen.inv_resolution, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return crosshair.color;
+}
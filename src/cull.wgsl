@@ -0,0 +1,72 @@
+// Gpu frustum culling, see gpu_cull.rs
+
+struct Frustum {
+    // (normal, distance) with the normals pointing inwards
+    planes: array<vec4<f32>, 6>,
+};
+@group(0) @binding(0)
+var<uniform> frustum: Frustum;
+
+struct CullParams {
+    instance_count: u32,
+    // Bounding sphere radius of the mesh before scaling
+    radius: f32,
+};
+@group(1) @binding(0)
+var<uniform> params: CullParams;
+
+// InstanceRaw from instance.rs: a mat4x4 model matrix followed by a mat3x3 normal matrix.
+// A mat3x3 in a storage buffer would be padded to 48 bytes, so we treat the whole thing as
+// 25 plain floats and copy it across as is.
+struct Instance {
+    values: array<f32, 25>,
+};
+@group(1) @binding(1)
+var<storage, read> instances_in: array<Instance>;
+@group(1) @binding(2)
+var<storage, read_write> instances_out: array<Instance>;
+
+// Same layout as the arguments draw_indexed_indirect reads
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+@group(1) @binding(3)
+var<storage, read_write> draw: DrawIndexedIndirect;
+
+// Reads straight from the storage buffer, arrays in local values can only be indexed by constants
+fn model_column(index: u32, column: u32) -> vec3<f32> {
+    let i = column * 4u;
+    let values = &instances_in[index].values;
+    return vec3<f32>((*values)[i], (*values)[i + 1u], (*values)[i + 2u]);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.instance_count) {
+        return;
+    }
+    // The translation is the last column, and the largest axis scale is the length of the
+    // longest of the first three
+    let center = model_column(index, 3u);
+    let scale = max(
+        length(model_column(index, 0u)),
+        max(length(model_column(index, 1u)), length(model_column(index, 2u))),
+    );
+    let radius = params.radius * scale;
+
+    for (var i = 0; i < 6; i += 1) {
+        let plane = frustum.planes[i];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return;
+        }
+    }
+
+    // Visible, grab the next free slot in the output
+    let slot = atomicAdd(&draw.instance_count, 1u);
+    instances_out[slot] = instances_in[index];
+}
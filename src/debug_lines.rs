@@ -0,0 +1,225 @@
+use std::rc::Rc;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   Immediate mode debug lines. Anything can queue a line or a box in world space whenever it
+*   likes (State::debug_line & debug_box), with no buffers of its own to look after. Everything
+*   queued gets uploaded in one go when the frame is prepared and the queue starts over empty,
+*   so a line that should stay has to be queued again every frame.
+*
+*   The vertex buffer only ever grows, to the next power of two that fits, so a frame with a
+*   lot of lines costs one new buffer rather than one every frame after it.
+*
+*   They're drawn in the main pass after the scene. With depth_test on they're hidden behind
+*   whatever's in front of them, without it they're drawn over everything. Neither way writes
+*   depth, the lines shouldn't hide anything themselves.
+*/
+pub struct DebugLines {
+    pub depth_test: bool,
+    queued: Vec<LineVertex>,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    // How many vertices the buffer has room for
+    capacity: usize,
+    // Uploaded for this frame
+    vertex_count: u32,
+    tested_pipeline: Rc<wgpu::RenderPipeline>,
+    overlay_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+// A line's end, for lines.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl LineVertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Every pair of LineVertex's is a line in world space, drawn with the camera at @group(1)
+#[allow(clippy::too_many_arguments)]
+pub fn line_pipeline(
+    device: &wgpu::Device,
+    pipelines: &PipelineCache,
+    label: &str,
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+    screen_layout: &wgpu::BindGroupLayout,
+    camera_layout: &wgpu::BindGroupLayout,
+    depth_stencil: wgpu::DepthStencilState,
+) -> Rc<wgpu::RenderPipeline> {
+    pipelines.render_pipeline(
+        device,
+        &RenderPipelineDesc {
+            label,
+            shader: include_str!("lines.wgsl"),
+            bind_group_layouts: &[screen_layout, camera_layout],
+            vertex_entry_point: "vs_main",
+            vertex_buffers: &[LineVertex::desc()],
+            fragment_entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        },
+    )
+}
+
+impl DebugLines {
+    // Room for this many lines to begin with
+    const INITIAL_LINES: usize = 256;
+
+    // Drawn in the main pass, so built for its format, sample count & depth direction
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let depth_state = |depth_compare| wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let pipeline = |label, depth_compare| {
+            line_pipeline(
+                device,
+                pipelines,
+                label,
+                color_format,
+                sample_count,
+                screen_layout,
+                camera_layout,
+                depth_state(depth_compare),
+            )
+        };
+        // LessEqual so lines along a surface's edges still show
+        let tested_pipeline = pipeline("Debug Lines", depth.compare(wgpu::CompareFunction::LessEqual));
+        let overlay_pipeline = pipeline("Debug Lines Overlay", wgpu::CompareFunction::Always);
+
+        let capacity = Self::INITIAL_LINES * 2;
+        Self {
+            depth_test: true,
+            queued: Vec::new(),
+            vertex_buffer: Self::create_vertex_buffer(device, capacity),
+            capacity,
+            vertex_count: 0,
+            tested_pipeline,
+            overlay_pipeline,
+        }
+    }
+
+    // On a (new) device or for a new main pass. Whatever's queued stays queued.
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let mut lines = Self::new(device, pipelines, color_format, sample_count, depth, screen_layout, camera_layout);
+        lines.depth_test = self.depth_test;
+        lines.queued = self.queued.clone();
+        lines
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Lines Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn line(&mut self, start: cgmath::Vector3<f32>, end: cgmath::Vector3<f32>, color: [f32; 3]) {
+        self.queued.push(LineVertex { position: start.into(), color });
+        self.queued.push(LineVertex { position: end.into(), color });
+    }
+
+    // The 12 edges of the box between two opposite corners
+    pub fn aabb(&mut self, min: cgmath::Vector3<f32>, max: cgmath::Vector3<f32>, color: [f32; 3]) {
+        // Bit 0 picks x from max, bit 1 y & bit 2 z
+        let corner = |i: usize| {
+            cgmath::Vector3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        // Each edge joins two corners one bit apart
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    // Lines drawn last frame, and how many there's room for before the buffer has to grow
+    pub fn line_count(&self) -> usize {
+        self.vertex_count as usize / 2
+    }
+
+    pub fn line_capacity(&self) -> usize {
+        self.capacity / 2
+    }
+
+    // Uploads everything queued since last time for this frame, and empties the queue
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.queued.len() > self.capacity {
+            self.capacity = self.queued.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+            log::debug!("debug lines: room for {} now", self.capacity / 2);
+        }
+        if !self.queued.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.queued));
+        }
+        self.vertex_count = self.queued.len() as u32;
+        self.queued.clear();
+    }
+
+    // Expects the screen & camera bind groups to already be set at @group(0) & @group(1)
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        let pipeline = if self.depth_test { &self.tested_pipeline } else { &self.overlay_pipeline };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
@@ -0,0 +1,311 @@
+use std::rc::Rc;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance;
+use crate::model::{self, Vertex};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   Hedgehog debug view: every vertex gets a short line sticking out along its normal.
+*   Each line is two NormalLineVertex's sharing the same position & normal, the vertex
+*   shader pushes the second one out by `normal * length`. Because the length is applied
+*   on the gpu we can change it through the uniform without rebuilding the line buffer.
+*/
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct NormalLineVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    // 0.0 for the start of the line, 1.0 for the tip
+    extent: f32,
+}
+
+impl Vertex for NormalLineVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<NormalLineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct NormalLinesUniform {
+    color: [f32; 4],
+    length: f32,
+    // Uniforms need to be 16 byte aligned
+    _padding: [f32; 3],
+}
+
+pub struct NormalLines {
+    pub enabled: bool,
+    uniform: NormalLinesUniform,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    num_vertices: u32,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl NormalLines {
+    pub const DEFAULT_LENGTH: f32 = 0.2;
+    // Magenta doesn't show up anywhere else in the scene so the lines are easy to spot
+    pub const DEFAULT_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        meshes: &[&model::Mesh],
+    ) -> Self {
+        let vertices = meshes
+            .iter()
+            .flat_map(|mesh| mesh.vertices.iter())
+            .flat_map(|v| {
+                [0.0, 1.0].map(|extent| NormalLineVertex {
+                    position: v.position,
+                    normal: v.normal,
+                    extent,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Self::from_lines(
+            device,
+            pipelines,
+            color_format,
+            sample_count,
+            depth,
+            screen_layout,
+            camera_layout,
+            &vertices,
+            Self::DEFAULT_COLOR,
+        )
+    }
+
+    // Any set of line segments, drawn with the same pipeline
+    #[allow(clippy::too_many_arguments)]
+    fn from_lines(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        vertices: &[NormalLineVertex],
+        color: [f32; 4],
+    ) -> Self {
+        let vertex_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Normal Lines Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let uniform = NormalLinesUniform {
+            color,
+            length: Self::DEFAULT_LENGTH,
+            _padding: [0.0; 3],
+        };
+        let uniform_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Normal Lines Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("normal_lines_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("normal_lines_bind_group"),
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Normal Lines",
+                shader: include_str!("normals.wgsl"),
+                bind_group_layouts: &[screen_layout, camera_layout, &bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[NormalLineVertex::desc(), instance::InstanceRaw::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                // Every pair of vertices is its own line
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                // Depth tested so normals on the far side of a mesh stay hidden
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            uniform_buffer,
+            bind_group,
+            vertex_buffer,
+            num_vertices: vertices.len() as u32,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device, keeping the current settings
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        meshes: &[&model::Mesh],
+    ) -> Self {
+        let mut lines = Self::new(device, pipelines, color_format, sample_count, depth, screen_layout, camera_layout, meshes);
+        lines.enabled = self.enabled;
+        lines.uniform = self.uniform;
+        queue.write_buffer(&lines.uniform_buffer, 0, bytemuck::cast_slice(&[lines.uniform]));
+        lines
+    }
+
+    pub fn length(&self) -> f32 {
+        self.uniform.length
+    }
+
+    pub fn set_length(&mut self, queue: &wgpu::Queue, length: f32) {
+        self.uniform.length = length.max(0.0);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 4]) {
+        self.uniform.color = color;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Expects the screen & camera bind groups to already be set at @group(0) & @group(1),
+    // and the instance buffer the meshes were drawn with in vertex slot 1
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instances: std::ops::Range<u32>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, instances);
+    }
+}
+
+/*
+*   The outline of a camera frustum, 12 lines between its 8 corners. It goes through the
+*   normal lines pipeline with every extent at 0, so the lines stay exactly where they're put,
+*   and a single identity instance in place of the model's instance buffer.
+*/
+pub struct FrustumLines {
+    lines: NormalLines,
+    instance_buffer: Tracked<wgpu::Buffer>,
+}
+
+impl FrustumLines {
+    // Yellow, to stand apart from the magenta normals
+    pub const COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        frustum: &camera::Frustum,
+    ) -> Self {
+        let corners = frustum.corners();
+        // Around the near face, around the far face, then near to far
+        let edges = (0..4).flat_map(|i| {
+            let next = (i + 1) % 4;
+            [(i, next), (i + 4, next + 4), (i, i + 4)]
+        });
+        let vertices = edges
+            .flat_map(|(a, b)| [corners[a], corners[b]])
+            .map(|corner| NormalLineVertex {
+                position: corner.into(),
+                // Never used with an extent of 0, but normalizing a zero vector would give NaNs
+                normal: [0.0, 1.0, 0.0],
+                extent: 0.0,
+            })
+            .collect::<Vec<_>>();
+
+        let mut lines = NormalLines::from_lines(
+            device,
+            pipelines,
+            color_format,
+            sample_count,
+            depth,
+            screen_layout,
+            camera_layout,
+            &vertices,
+            Self::COLOR,
+        );
+        lines.enabled = true;
+
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Frustum Lines Instance Buffer"),
+            contents: bytemuck::cast_slice(&[instance::Transform::default().to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self { lines, instance_buffer }
+    }
+
+    // Same expectations as NormalLines::draw, minus the instance buffer
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        self.lines.draw(render_pass, 0..1);
+    }
+}
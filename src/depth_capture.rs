@@ -0,0 +1,183 @@
+use crate::camera;
+use crate::context::GpuContext;
+use crate::gpu_memory::TrackedDevice;
+use crate::pipeline_cache::RenderPipelineDesc;
+use crate::texture;
+
+/*
+*   Reads the depth buffer back to the cpu, for saving it as an image.
+*
+*   Our depth texture is Depth24PlusStencil8, and the 24 bit part of that has no layout anyone
+*   is allowed to copy out of, wgpu refuses. So a full screen pass samples it into an R32Float
+*   texture first. R32Float copies like any color format: 4 bytes a pixel, rows padded to 256
+*   bytes, which read_texture_async takes care of.
+*
+*   The values are still the depth buffer's, 0 at the near plane to 1 at the far one and very
+*   much not linear: most of the range goes to the first few units in front of the camera.
+*   linearize turns them back into distances.
+*/
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+pub async fn read_depth(ctx: &GpuContext, depth_texture: &texture::Texture, size: wgpu::Extent3d) -> Vec<f32> {
+    let device = &ctx.device;
+    let target = device.create_tracked_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Capture Target"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Depth,
+            },
+            count: None,
+        }],
+        label: Some("depth_capture_bind_group_layout"),
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&depth_texture.depth_only_view()),
+        }],
+        label: Some("depth_capture_bind_group"),
+    });
+    let pipeline = ctx.pipelines.render_pipeline(
+        device,
+        &RenderPipelineDesc {
+            label: "Depth Capture",
+            shader: include_str!("depth_capture.wgsl"),
+            bind_group_layouts: &[&bind_group_layout],
+            vertex_entry_point: "vs_main",
+            vertex_buffers: &[],
+            fragment_entry_point: "fs_main",
+            // Float32 formats can't be blended
+            targets: &[Some(wgpu::ColorTargetState {
+                format: FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        },
+    );
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Depth Capture Encoder"),
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Capture Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    ctx.queue.submit(std::iter::once(encoder.finish()));
+
+    let bytes = ctx.read_texture_async(&target, FORMAT, size).await;
+    // The Vec<u8> isn't guaranteed to be aligned for f32's
+    bytes.chunks_exact(4).map(bytemuck::pod_read_unaligned::<f32>).collect()
+}
+
+// Depth buffer value to distance from the camera, for a perspective projection. Reversed depth
+// has no far plane, its background is infinitely far away and comes out as zfar.
+pub fn linearize(depth: f32, znear: f32, zfar: f32, direction: camera::DepthDirection) -> f32 {
+    match direction {
+        camera::DepthDirection::Standard => znear * zfar / (zfar - depth * (zfar - znear)),
+        camera::DepthDirection::Reversed => (znear / depth.max(f32::MIN_POSITIVE)).min(zfar),
+    }
+}
+
+/*
+*   Distances to 8 bit grayscale, white up close and black far away. The scene usually only
+*   fills a small part of the near to far range, so the closest & furthest distances in the
+*   image are stretched over the whole 0-255 instead. Pixels at the far plane are the cleared
+*   background and don't count, they stay black.
+*/
+pub fn to_grayscale(distances: &[f32], zfar: f32) -> Vec<u8> {
+    let is_background = |distance: f32| distance >= zfar * 0.9999;
+    let (near, far) = distances
+        .iter()
+        .filter(|&&distance| !is_background(distance))
+        .fold((f32::MAX, f32::MIN), |(near, far), &distance| (near.min(distance), far.max(distance)));
+    let range = (far - near).max(f32::EPSILON);
+    distances
+        .iter()
+        .map(|&distance| {
+            if is_background(distance) {
+                0
+            } else {
+                ((1.0 - (distance - near) / range) * 255.0).round() as u8
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::DepthDirection;
+
+    const ZNEAR: f32 = 0.1;
+    const ZFAR: f32 = 100.0;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= b.abs() * 1e-4
+    }
+
+    #[test]
+    fn linearize_standard() {
+        let linearize = |depth| linearize(depth, ZNEAR, ZFAR, DepthDirection::Standard);
+        assert!(close(linearize(0.0), ZNEAR));
+        assert!(close(linearize(1.0), ZFAR));
+        // Most of the depth range goes on what's close up
+        assert!(close(linearize(0.5), 2.0 * ZNEAR * ZFAR / (ZFAR + ZNEAR)));
+    }
+
+    #[test]
+    fn linearize_reversed() {
+        let linearize = |depth| linearize(depth, ZNEAR, ZFAR, DepthDirection::Reversed);
+        assert!(close(linearize(1.0), ZNEAR));
+        assert!(close(linearize(ZNEAR / 10.0), 10.0));
+        // The cleared background is 0, the far plane rather than infinity
+        assert_eq!(linearize(0.0), ZFAR);
+    }
+
+    #[test]
+    fn grayscale_stretches_what_was_drawn() {
+        assert_eq!(to_grayscale(&[1.0, 3.0, 2.0, ZFAR], ZFAR), [255, 0, 128, 0]);
+    }
+
+    #[test]
+    fn grayscale_one_distance() {
+        assert_eq!(to_grayscale(&[5.0, 5.0, ZFAR], ZFAR), [255, 255, 0]);
+    }
+
+    #[test]
+    fn grayscale_all_background() {
+        assert_eq!(to_grayscale(&[ZFAR; 4], ZFAR), [0; 4]);
+        assert!(to_grayscale(&[], ZFAR).is_empty());
+    }
+}
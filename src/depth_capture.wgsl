@@ -0,0 +1,18 @@
+// Copies the depth buffer into a color texture that can be read back, see depth_capture.rs
+
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    // Same size as the depth buffer, so every pixel reads exactly one texel
+    return textureLoad(t_depth, vec2<i32>(position.xy), 0);
+}
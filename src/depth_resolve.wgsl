@@ -0,0 +1,34 @@
+// Copies a multisampled depth buffer into a regular one, see antialiasing.rs
+
+@group(0) @binding(0)
+var t_depth: texture_depth_multisampled_2d;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    // Averaging depths makes up surfaces that aren't there, keep the closest sample instead
+    let coords = vec2<i32>(position.xy);
+    var depth = 1.0;
+    for (var i = 0; i < i32(textureNumSamples(t_depth)); i += 1) {
+        depth = min(depth, textureLoad(t_depth, coords, i));
+    }
+    return depth;
+}
+
+// Same for reversed depth, where the closest sample is the biggest
+@fragment
+fn fs_reversed(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let coords = vec2<i32>(position.xy);
+    var depth = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(t_depth)); i += 1) {
+        depth = max(depth, textureLoad(t_depth, coords, i));
+    }
+    return depth;
+}
@@ -0,0 +1,164 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::PipelineCache;
+use crate::{camera, post};
+
+/*
+*   Depth of field. Every pixel works out its circle of confusion from how far it is from the
+*   focal distance (scaled by the aperture), then averages the scene color over a disc of that
+*   radius. Things at the focal distance stay sharp and everything else gets blurrier the
+*   further away from it they are.
+*
+*   It reads the depth buffer so it has to know the camera's near & far planes, and which way
+*   its depth runs, to turn the depth values back into distances.
+*/
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DofUniform {
+    focal_distance: f32,
+    aperture: f32,
+    // Biggest blur radius in pixels
+    max_radius: f32,
+    znear: f32,
+    zfar: f32,
+    // 1 when the depth buffer is reversed, see camera::DepthDirection
+    reverse_z: u32,
+    _padding: [u32; 2],
+}
+
+pub struct DepthOfField {
+    pub enabled: bool,
+    uniform: DofUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl DepthOfField {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+        camera: &camera::Camera,
+    ) -> Self {
+        let uniform = DofUniform {
+            focal_distance: 11.0,
+            aperture: 1.0,
+            max_radius: 8.0,
+            znear: camera.znear,
+            zfar: camera.zfar,
+            reverse_z: (camera.depth == camera::DepthDirection::Reversed) as u32,
+            _padding: [0; 2],
+        };
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth Of Field Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("dof_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("dof_bind_group"),
+        });
+
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Depth Of Field",
+            include_str!("dof.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &bind_group_layout],
+            color_format,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device, keeping the current settings
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+        camera: &camera::Camera,
+    ) -> Self {
+        let mut dof = Self::new(device, pipelines, screen_layout, post_chain, color_format, camera);
+        dof.enabled = self.enabled;
+        // The camera's depth direction might not be the same as before
+        dof.uniform = DofUniform { reverse_z: dof.uniform.reverse_z, ..self.uniform };
+        dof.write_uniform(queue);
+        dof
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn focal_distance(&self) -> f32 {
+        self.uniform.focal_distance
+    }
+
+    // Distance from the camera that's in perfect focus
+    pub fn set_focal_distance(&mut self, queue: &wgpu::Queue, distance: f32) {
+        self.uniform.focal_distance = distance.clamp(self.uniform.znear, self.uniform.zfar);
+        self.write_uniform(queue);
+    }
+
+    pub fn aperture(&self) -> f32 {
+        self.uniform.aperture
+    }
+
+    // Bigger apertures give a shallower depth of field
+    pub fn set_aperture(&mut self, queue: &wgpu::Queue, aperture: f32) {
+        self.uniform.aperture = aperture.max(0.0);
+        self.write_uniform(queue);
+    }
+
+    // Needs to be kept in sync with the camera to linearize the depth buffer correctly
+    pub fn set_clip_planes(&mut self, queue: &wgpu::Queue, znear: f32, zfar: f32) {
+        self.uniform.znear = znear;
+        self.uniform.zfar = zfar;
+        self.write_uniform(queue);
+    }
+}
+
+impl post::PostEffect for DepthOfField {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
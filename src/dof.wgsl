@@ -0,0 +1,89 @@
+// Depth of field post effect, see dof.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+@group(1) @binding(2)
+var t_depth: texture_depth_2d;
+
+struct DofUniform {
+    focal_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    znear: f32,
+    zfar: f32,
+    reverse_z: u32,
+};
+@group(2) @binding(0)
+var<uniform> dof: DofUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    // Texture coordinates have y pointing down
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+// Turns the non-linear [0, 1] depth buffer value back into a distance from the camera
+fn linearize_depth(depth: f32) -> f32 {
+    // Reversed depth is znear / distance, with nothing past it (0) counting as zfar
+    if dof.reverse_z != 0u {
+        return min(dof.znear / max(depth, 1e-30), dof.zfar);
+    }
+    return dof.znear * dof.zfar / (dof.zfar - depth * (dof.zfar - dof.znear));
+}
+
+// Circle of confusion radius in pixels
+fn circle_of_confusion(pixel: vec2<f32>) -> f32 {
+    let max_pixel = vec2<i32>(screen.resolution) - vec2<i32>(1, 1);
+    let coords = clamp(vec2<i32>(pixel), vec2<i32>(0, 0), max_pixel);
+    let distance = linearize_depth(textureLoad(t_depth, coords, 0));
+    let coc = dof.aperture * abs(distance - dof.focal_distance) / max(distance, 0.0001);
+    return clamp(coc, 0.0, 1.0) * dof.max_radius;
+}
+
+const NUM_TAPS: i32 = 24;
+// Spreads the taps evenly over a disc
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // textureSampleLevel since the plain textureSample isn't allowed in non-uniform control flow
+    let radius = circle_of_confusion(in.clip_position.xy);
+    if (radius < 0.5) {
+        return textureSampleLevel(t_color, s_color, in.uv, 0.0);
+    }
+
+    // Gather taps in a spiral out to the circle of confusion. Taps that are more in focus
+    // than us are weighted down so sharp foreground objects don't bleed into the blur.
+    var color = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var i = 0; i < NUM_TAPS; i = i + 1) {
+        let r = sqrt((f32(i) + 0.5) / f32(NUM_TAPS)) * radius;
+        let theta = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(theta), sin(theta)) * r;
+        let tap_coc = circle_of_confusion(in.clip_position.xy + offset);
+        let weight = clamp(tap_coc / max(r, 0.0001), 0.0, 1.0) + 0.0001;
+        color = color + textureSampleLevel(t_color, s_color, in.uv + offset * screen.inv_resolution, 0.0).rgb * weight;
+        total = total + weight;
+    }
+    return vec4<f32>(color / total, 1.0);
+}
@@ -0,0 +1,15 @@
+// Solid white full screen triangle. The render pass' scissor rect decides which
+// part of the screen actually gets flashed.
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen: (-1,-1), (3,-1), (-1,3)
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+}
@@ -0,0 +1,95 @@
+/*
+*   Distance fog. The further a fragment is from the camera the more of the fog color gets
+*   mixed into it, so distant things fade into the background instead of stopping at a hard
+*   edge. It's worked out at the end of the main fragment shader from the distance to the camera,
+*   rather than the view space z, so turning the camera doesn't make the fog move.
+*
+*   Linear fog goes from none at `start` to all fog at `end`. Exponential squared fog has no
+*   end, it thickens as 1 - e^-(density * distance)², which stays clear for a while and then
+*   closes in quickly, a lot like real haze.
+*
+*   It's part of the scene globals uniform, see light.rs.
+*/
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FogMode {
+    Off = 0,
+    Linear = 1,
+    ExponentialSquared = 2,
+}
+
+impl FogMode {
+    // Off -> linear -> exponential squared -> off
+    pub fn next(self) -> Self {
+        match self {
+            FogMode::Off => FogMode::Linear,
+            FogMode::Linear => FogMode::ExponentialSquared,
+            FogMode::ExponentialSquared => FogMode::Off,
+        }
+    }
+}
+
+// Has to match Fog in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Fog {
+    pub color: [f32; 3],
+    mode: u32,
+    // Only used by the exponential mode
+    pub density: f32,
+    // Only used by the linear mode, in world units from the camera
+    pub start: f32,
+    pub end: f32,
+    _padding: u32,
+}
+
+impl Fog {
+    pub fn linear(color: [f32; 3], start: f32, end: f32) -> Self {
+        Self {
+            color,
+            mode: FogMode::Linear as u32,
+            density: 0.04,
+            start,
+            // The shader divides by end - start
+            end: end.max(start + 0.001),
+            _padding: 0,
+        }
+    }
+
+    pub fn exponential_squared(color: [f32; 3], density: f32) -> Self {
+        Self {
+            mode: FogMode::ExponentialSquared as u32,
+            density,
+            ..Self::linear(color, 10.0, 40.0)
+        }
+    }
+
+    pub fn mode(&self) -> FogMode {
+        match self.mode {
+            1 => FogMode::Linear,
+            2 => FogMode::ExponentialSquared,
+            _ => FogMode::Off,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: FogMode) -> Self {
+        self.mode = mode as u32;
+        self
+    }
+
+    // Brings the fog closer (factor > 1) or pushes it back (factor < 1), in either mode
+    pub fn thicker(mut self, factor: f32) -> Self {
+        self.density *= factor;
+        self.start /= factor;
+        self.end /= factor;
+        self
+    }
+}
+
+impl Default for Fog {
+    // Set up for the demo terrain, but off until it's asked for. The color is the same blue the
+    // frame gets cleared to, so the terrain fades into the background.
+    fn default() -> Self {
+        Self::linear([0.1, 0.2, 0.3], 10.0, 40.0).with_mode(FogMode::Off)
+    }
+}
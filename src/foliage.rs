@@ -0,0 +1,326 @@
+use std::rc::Rc;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   A bush of leaves, each one a quad with a leaf shaped hole cut out of it by its texture's
+*   alpha. Cutout geometry like this is either fully there or not at all, so unlike the
+*   blended stuff (blend.rs) it writes depth and doesn't need sorting. How the cut gets made:
+*
+*   AlphaTest        the fragment shader discards where alpha is under 0.5. The edge is
+*                    decided once per pixel, so it's as jagged as it gets, and MSAA doesn't
+*                    help: all the samples in a pixel come from the same fragment.
+*   AlphaToCoverage  MultisampleState::alpha_to_coverage_enabled turns the alpha the shader
+*                    outputs into a coverage mask instead, alpha 0.5 with 4x MSAA covers 2 of
+*                    the 4 samples. The resolve averages them, so the edges get anti-aliased
+*                    like any triangle edge. fs_coverage sharpens the alpha first so it goes
+*                    from 0 to 1 over about a pixel, otherwise magnified leaves look blurry.
+*
+*   Alpha to coverage needs MSAA, wgpu won't build it with a sample count of 1, so without MSAA
+*   the bush falls back to the alpha test. It's also instead of blending, not as well as: the
+*   alpha has already been spent deciding how many samples get covered, blending with it on
+*   top would fade the edges out twice. The pipeline blends with REPLACE.
+*
+*   MultisampleState::mask goes with it: it's ANDed with the coverage of every fragment, a
+*   sample whose bit is 0 is never written. Cycling it shows the edges getting coarser as
+*   fewer samples are left to spread the coverage over.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cutout {
+    AlphaTest,
+    AlphaToCoverage,
+}
+
+impl Cutout {
+    pub fn next(self) -> Self {
+        match self {
+            Cutout::AlphaTest => Cutout::AlphaToCoverage,
+            Cutout::AlphaToCoverage => Cutout::AlphaTest,
+        }
+    }
+}
+
+impl std::fmt::Display for Cutout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Cutout::AlphaTest => write!(f, "alpha test"),
+            Cutout::AlphaToCoverage => write!(f, "alpha to coverage"),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LeafVertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+}
+
+impl LeafVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x2,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LeafVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+pub struct Foliage {
+    pub enabled: bool,
+    // What's asked for, see pipeline_cutout for what's actually drawn
+    pub cutout: Cutout,
+    // MultisampleState::mask, one bit per sample
+    pub sample_mask: u64,
+    sample_count: u32,
+    // Kept with its bind group, it's what the bind group points at
+    _texture: texture::Texture,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    vertex_count: u32,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Foliage {
+    const LEAVES: usize = 24;
+    const LEAF_TEXTURE_SIZE: u32 = 64;
+    const LEAF_LENGTH: f32 = 0.6;
+    const LEAF_WIDTH: f32 = 0.4;
+    // In a gap in the grid of spheres
+    const CENTER: cgmath::Vector3<f32> = cgmath::Vector3::new(3.0, -0.3, 3.0);
+    pub const ALL_SAMPLES: u64 = !0;
+
+    // Drawn in the main pass, so it takes that pass' sample count
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        cutout: Cutout,
+        sample_mask: u64,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("foliage_bind_group_layout"),
+        });
+        let texture = texture::Texture::from_image(
+            device,
+            queue,
+            &Self::leaf_image(),
+            "Leaf",
+            texture::TextureKind::Color,
+            texture::Alpha::Straight,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("foliage_bind_group"),
+        });
+
+        let vertices = Self::bush();
+        let vertex_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Foliage Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let alpha_to_coverage = Self::pipeline_cutout(cutout, sample_count) == Cutout::AlphaToCoverage;
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Foliage",
+                shader: include_str!("foliage.wgsl"),
+                bind_group_layouts: &[screen_layout, camera_layout, &bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[LeafVertex::desc()],
+                fragment_entry_point: if alpha_to_coverage { "fs_coverage" } else { "fs_alpha_test" },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // Never blended, see the top
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                // Leaves are seen from both sides
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: sample_mask,
+                    alpha_to_coverage_enabled: alpha_to_coverage,
+                },
+            },
+        );
+
+        Self {
+            enabled: false,
+            cutout,
+            sample_mask,
+            sample_count,
+            _texture: texture,
+            bind_group,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device, or after the cutout, sample mask or the
+    // main pass changed
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let mut foliage = Self::new(
+            device,
+            pipelines,
+            queue,
+            color_format,
+            sample_count,
+            depth,
+            screen_layout,
+            camera_layout,
+            self.cutout,
+            self.sample_mask,
+        );
+        foliage.enabled = self.enabled;
+        foliage
+    }
+
+    // Alpha to coverage falls back to the alpha test without MSAA
+    fn pipeline_cutout(cutout: Cutout, sample_count: u32) -> Cutout {
+        if sample_count > 1 {
+            cutout
+        } else {
+            Cutout::AlphaTest
+        }
+    }
+
+    // What's being drawn right now
+    pub fn current_cutout(&self) -> Cutout {
+        Self::pipeline_cutout(self.cutout, self.sample_count)
+    }
+
+    // Every sample -> every other one -> only the first, for 4x MSAA
+    pub fn next_sample_mask(mask: u64) -> u64 {
+        match mask {
+            Self::ALL_SAMPLES => 0b0101,
+            0b0101 => 0b0001,
+            _ => Self::ALL_SAMPLES,
+        }
+    }
+
+    // A pointy leaf along the texture's height, soft edged over a texel or two so filtering &
+    // alpha to coverage have something to work with. Straight alpha.
+    fn leaf_image() -> image::DynamicImage {
+        let size = Self::LEAF_TEXTURE_SIZE;
+        let image = image::RgbaImage::from_fn(size, size, |x, y| {
+            let u = (x as f32 + 0.5) / size as f32;
+            // 0 at the stem, 1 at the tip
+            let v = 1.0 - (y as f32 + 0.5) / size as f32;
+            let half_width = 0.45 * (std::f32::consts::PI * v).sin().powf(0.8);
+            let inside = (half_width - (u - 0.5).abs()) * size as f32;
+            let alpha = (inside / 1.5 + 0.5).clamp(0.0, 1.0);
+            // Lighter towards the tip, with a darker vein down the middle
+            let vein = 1.0 - 0.35 * (1.0 - ((u - 0.5).abs() * size as f32 / 1.5).min(1.0));
+            let green = (110.0 + 90.0 * v) * vein;
+            image::Rgba([(40.0 * vein) as u8, green as u8, (30.0 * vein) as u8, (alpha * 255.0).round() as u8])
+        });
+        image::DynamicImage::ImageRgba8(image)
+    }
+
+    // Leaves spiralling around & up a stem. No rng, the golden ratio spreads them around like
+    // the particle fountain's.
+    fn bush() -> Vec<LeafVertex> {
+        use cgmath::InnerSpace;
+
+        let golden = 0.618_034;
+        let mut vertices = Vec::with_capacity(Self::LEAVES * 6);
+        for i in 0..Self::LEAVES {
+            let angle = (i as f32 * golden).fract() * std::f32::consts::TAU;
+            let height = (i as f32 + 0.5) / Self::LEAVES as f32;
+            let outward = cgmath::Vector3::new(angle.cos(), 0.0, angle.sin());
+            let side = cgmath::Vector3::new(-angle.sin(), 0.0, angle.cos());
+            // The lower leaves stick out further & droop more
+            let along = (outward * (1.0 - 0.6 * height) + cgmath::Vector3::unit_y() * (0.3 + height)).normalize();
+            let base = Self::CENTER + cgmath::Vector3::unit_y() * height + outward * 0.1;
+            let tip = base + along * Self::LEAF_LENGTH;
+            let half = side * Self::LEAF_WIDTH * 0.5;
+            let corner = |position: cgmath::Vector3<f32>, tex_coords: [f32; 2]| LeafVertex {
+                position: position.into(),
+                tex_coords,
+            };
+            vertices.extend_from_slice(&[
+                corner(base - half, [0.0, 1.0]),
+                corner(base + half, [1.0, 1.0]),
+                corner(tip + half, [1.0, 0.0]),
+                corner(base - half, [0.0, 1.0]),
+                corner(tip + half, [1.0, 0.0]),
+                corner(tip - half, [0.0, 0.0]),
+            ]);
+        }
+        vertices
+    }
+
+    // Expects the screen & camera bind groups to already be set at @group(0) & @group(1)
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
@@ -0,0 +1,52 @@
+// Cutout leaves, see foliage.rs. fs_alpha_test throws away the transparent parts and
+// fs_coverage hands the alpha to alpha to coverage instead.
+
+// @group(0) is the screen uniform, which the leaves don't need
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var t_leaf: texture_2d<f32>;
+@group(2) @binding(1)
+var s_leaf: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_alpha_test(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_leaf, s_leaf, in.tex_coords);
+    if color.a < 0.5 {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+@fragment
+fn fs_coverage(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_leaf, s_leaf, in.tex_coords);
+    // Sharpened so the edge goes from uncovered to covered across about a pixel, however big
+    // the leaf is on screen
+    let alpha = (color.a - 0.5) / max(fwidth(color.a), 0.0001) + 0.5;
+    return vec4<f32>(color.rgb, clamp(alpha, 0.0, 1.0));
+}
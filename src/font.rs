@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::json::{array, field, number, Json};
+use crate::model::Vertex;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{screen, texture};
+
+// Where a glyph is in the atlas and how to place it, all in atlas pixels
+#[derive(Copy, Clone, Debug)]
+pub struct Glyph {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    // From the pen to the glyph's top left corner. The pen's at the top of the line, y goes down.
+    pub xoffset: f32,
+    pub yoffset: f32,
+    // How far the pen moves on afterwards
+    pub xadvance: f32,
+}
+
+/*
+*   The glyph metrics of a bitmap font, in the JSON flavour of AngelCode's BMFont format that
+*   most bitmap font tools can export:
+*
+*       { "common": { "lineHeight": 16, "scaleW": 128, "scaleH": 128, ... },
+*         "chars": [ { "id": 65, "x": 1, "y": 1, "width": 9, "height": 10,
+*                      "xoffset": 0, "yoffset": 3, "xadvance": 8 }, ... ] }
+*
+*   Only what left to right text needs is read. Kerning pairs are ignored, and so is "pages",
+*   the atlas is passed in separately and there's only ever the one.
+*/
+#[derive(Clone, Debug)]
+pub struct FontMetrics {
+    pub line_height: f32,
+    // Size of the atlas in pixels, to turn the glyph rectangles into texture coordinates
+    pub atlas_size: [f32; 2],
+    glyphs: HashMap<char, Glyph>,
+}
+
+impl FontMetrics {
+    pub fn from_json(json: &Json) -> Result<Self, String> {
+        let common = field(json, "common")?;
+        let mut glyphs = HashMap::new();
+        for (i, item) in array(json, "chars")?.iter().enumerate() {
+            let glyph = || -> Result<(char, Glyph), String> {
+                let id = number(item, "id")? as u32;
+                let c = char::from_u32(id).ok_or_else(|| format!("{} isn't a character", id))?;
+                Ok((
+                    c,
+                    Glyph {
+                        x: number(item, "x")?,
+                        y: number(item, "y")?,
+                        width: number(item, "width")?,
+                        height: number(item, "height")?,
+                        xoffset: number(item, "xoffset")?,
+                        yoffset: number(item, "yoffset")?,
+                        xadvance: number(item, "xadvance")?,
+                    },
+                ))
+            };
+            let (c, glyph) = glyph().map_err(|e| format!("chars[{}]: {}", i, e))?;
+            glyphs.insert(c, glyph);
+        }
+        Ok(Self {
+            line_height: number(common, "lineHeight")?,
+            atlas_size: [number(common, "scaleW")?, number(common, "scaleH")?],
+            glyphs,
+        })
+    }
+
+    // Characters the font doesn't have come out as '?', or not at all if it hasn't got that either
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    // x, y, width & height in pixels from the top left of the screen
+    rect: [f32; 4],
+    // Same for the glyph in the atlas, in texture coordinates
+    uv_region: [f32; 4],
+    color: [f32; 4],
+}
+
+impl Vertex for GlyphInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/*
+*   Text drawn straight from a font atlas, one instanced quad per glyph, the same way the
+*   tilemap draws its tiles. Every draw_text during the frame lays its string out into glyph
+*   instances, prepare uploads them all and render draws every string in a single draw call.
+*   Nothing's kept between frames, text that should stay on the screen is drawn every frame.
+*
+*   Layout is as basic as it gets: left to right, '\n' starts a new line back at the x the
+*   string started at, and tabs are four spaces. Each glyph gets a dark copy under it, a pixel
+*   down & right, so it reads over the bright parts of the scene as well as the dark ones.
+*/
+pub struct BitmapFont {
+    pub color: [f32; 4],
+    // Pixels added after every glyph, before the scale
+    pub letter_spacing: f32,
+    // Multiplies the font's line height
+    pub line_spacing: f32,
+    metrics: FontMetrics,
+    atlas: image::DynamicImage,
+    glyphs: Vec<GlyphInstance>,
+    shadows: Vec<GlyphInstance>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    capacity: usize,
+    instance_count: u32,
+    // Kept with its bind group, it's what the bind group points at
+    _texture: texture::Texture,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl BitmapFont {
+    const SHADOW_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        atlas: image::DynamicImage,
+        metrics: FontMetrics,
+    ) -> Self {
+        let texture = texture::Texture::from_image(
+            device,
+            queue,
+            &atlas,
+            "Font Atlas",
+            texture::TextureKind::Color,
+            texture::Alpha::Straight,
+        );
+        // Linear, so text at fractional scales isn't all jagged. The glyphs have a pixel of
+        // space around them in the atlas so their neighbours don't bleed in.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Font Atlas Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("font_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("font_bind_group"),
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Bitmap Font",
+                shader: include_str!("font.wgsl"),
+                bind_group_layouts: &[screen_layout, &bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[GlyphInstance::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+
+        Self {
+            color: [1.0; 4],
+            letter_spacing: 0.0,
+            line_spacing: 1.0,
+            metrics,
+            atlas,
+            glyphs: Vec::new(),
+            shadows: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, 0),
+            capacity: 0,
+            instance_count: 0,
+            _texture: texture,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Glyph Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<GlyphInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new surface format, same font
+    // and spacing. Text comes back with the next frame's draw_text.
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            color: self.color,
+            letter_spacing: self.letter_spacing,
+            line_spacing: self.line_spacing,
+            ..Self::new(
+                device,
+                pipelines,
+                queue,
+                screen_layout,
+                color_format,
+                self.atlas.clone(),
+                self.metrics.clone(),
+            )
+        }
+    }
+
+    pub fn metrics(&self) -> &FontMetrics {
+        &self.metrics
+    }
+
+    /*
+    *   Queues `text` with the top left of its first line at `pos`, in pixels from the top left
+    *   of the screen. `scale` 1 draws the glyphs at their size in the atlas. Whole pixel
+    *   positions keep it crisp at whole scales, so the start gets rounded.
+    */
+    pub fn draw_text(&mut self, text: &str, pos: cgmath::Vector2<f32>, scale: f32) {
+        let [atlas_width, atlas_height] = self.metrics.atlas_size;
+        let start = cgmath::Vector2::new(pos.x.round(), pos.y.round());
+        let mut pen = start;
+        for c in text.chars() {
+            let (c, repeat) = match c {
+                '\n' => {
+                    pen = cgmath::Vector2::new(start.x, pen.y + self.metrics.line_height * self.line_spacing * scale);
+                    continue;
+                }
+                '\t' => (' ', 4),
+                c => (c, 1),
+            };
+            let glyph = match self.metrics.glyph(c) {
+                Some(glyph) => *glyph,
+                None => continue,
+            };
+            for _ in 0..repeat {
+                // Spaces have an advance but nothing to draw
+                if glyph.width > 0.0 && glyph.height > 0.0 {
+                    let rect = [
+                        pen.x + glyph.xoffset * scale,
+                        pen.y + glyph.yoffset * scale,
+                        glyph.width * scale,
+                        glyph.height * scale,
+                    ];
+                    let uv_region = [
+                        glyph.x / atlas_width,
+                        glyph.y / atlas_height,
+                        glyph.width / atlas_width,
+                        glyph.height / atlas_height,
+                    ];
+                    let shadow_offset = scale.max(1.0).round();
+                    self.shadows.push(GlyphInstance {
+                        rect: [rect[0] + shadow_offset, rect[1] + shadow_offset, rect[2], rect[3]],
+                        uv_region,
+                        color: Self::SHADOW_COLOR,
+                    });
+                    self.glyphs.push(GlyphInstance {
+                        rect,
+                        uv_region,
+                        color: self.color,
+                    });
+                }
+                pen.x += (glyph.xadvance + self.letter_spacing) * scale;
+            }
+        }
+    }
+
+    // How much room draw_text takes up with `text`, in pixels: as wide as the widest line's
+    // advances & as tall as all of its lines
+    pub fn measure(&self, text: &str, scale: f32) -> cgmath::Vector2<f32> {
+        let advance = |c: char| self.metrics.glyph(c).map_or(0.0, |glyph| (glyph.xadvance + self.letter_spacing) * scale);
+        let width = text
+            .lines()
+            .map(|line| line.chars().map(|c| if c == '\t' { advance(' ') * 4.0 } else { advance(c) }).sum::<f32>())
+            .fold(0.0, f32::max);
+        let height = text.lines().count() as f32 * self.metrics.line_height * self.line_spacing * scale;
+        cgmath::Vector2::new(width, height)
+    }
+
+    // Uploads everything drawn since the last prepare, for render to draw. Call once per frame.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        // All the shadows go first so none of them end up over a neighbouring glyph
+        let count = self.shadows.len() + self.glyphs.len();
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        self.shadows.append(&mut self.glyphs);
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.shadows));
+        self.instance_count = count as u32;
+        self.shadows.clear();
+    }
+
+    // Draws over whatever is in `output` already
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        if self.instance_count == 0 {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instance_count);
+    }
+}
+
+/*
+*   The font that comes built in: DejaVu Sans Mono at 16 pixels, printable ASCII only. The
+*   atlas is white with the coverage in alpha, so draw_text's color tints it. Rasterized from
+*   the DejaVu fonts, which are free to use & share (their license is Bitstream Vera's).
+*/
+pub fn default_atlas() -> image::DynamicImage {
+    image::load_from_memory(include_bytes!("font.png")).expect("the built in font atlas is broken")
+}
+
+pub fn default_metrics() -> FontMetrics {
+    Json::parse(include_str!("font.json"))
+        .and_then(|json| FontMetrics::from_json(&json))
+        .expect("the built in font metrics are broken")
+}
@@ -0,0 +1,58 @@
+// Bitmap font text, one quad per glyph, see font.rs. Works just like tilemap.wgsl, plus a
+// color per glyph to tint the white atlas with.
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var s_atlas: sampler;
+
+struct GlyphInstance {
+    // Top left corner & size in pixels, y goes down from the top of the screen
+    @location(0) rect: vec4<f32>,
+    // Top left corner & size in the atlas
+    @location(1) uv_region: vec4<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+    glyph: GlyphInstance,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    let pixel = glyph.rect.xy + corner * glyph.rect.zw;
+    let ndc = pixel * 2.0 * screen.inv_resolution - 1.0;
+
+    var out: VertexOutput;
+    // Pixels count down from the top, clip space counts up from the bottom
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.tex_coords = glyph.uv_region.xy + corner * glyph.uv_region.zw;
+    out.color = glyph.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_atlas, s_atlas, in.tex_coords) * in.color;
+}
@@ -0,0 +1,105 @@
+/*
+*   The last `capacity` frame times, for graphs & stats. Pushing after it's full drops the
+*   oldest one.
+*
+*   A plain ring buffer would hand out the samples in two pieces once it wraps around, which
+*   graphs don't want. So every sample is written twice, `capacity` apart, and the samples in
+*   order are always one contiguous window of the doubled storage. Twice the memory, but no
+*   copying when they're read and nothing allocated after new().
+*/
+#[derive(Clone, Debug)]
+pub struct FrameHistory {
+    samples: Box<[f32]>,
+    // Where the next sample goes, in the first half
+    next: usize,
+    len: usize,
+}
+
+impl FrameHistory {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a frame history needs room for at least one frame");
+        Self {
+            samples: vec![0.0; 2 * capacity].into_boxed_slice(),
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len() / 2
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let capacity = self.capacity();
+        self.samples[self.next] = sample;
+        self.samples[self.next + capacity] = sample;
+        self.next = (self.next + 1) % capacity;
+        self.len = (self.len + 1).min(capacity);
+    }
+
+    // Oldest first
+    pub fn as_slice(&self) -> &[f32] {
+        // The newest sample is at next - 1 (+ capacity), the window ends right after it
+        let end = self.next + self.capacity();
+        &self.samples[end - self.len..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pushed(capacity: usize, count: usize) -> FrameHistory {
+        let mut history = FrameHistory::new(capacity);
+        for i in 0..count {
+            history.push(i as f32);
+        }
+        history
+    }
+
+    #[test]
+    fn empty() {
+        let history = FrameHistory::new(4);
+        assert!(history.is_empty());
+        assert_eq!(history.as_slice(), &[] as &[f32]);
+    }
+
+    #[test]
+    fn fewer_than_capacity() {
+        let history = pushed(4, 3);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.as_slice(), &[0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn exactly_full() {
+        let history = pushed(4, 4);
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.as_slice(), &[0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn wrapping_around() {
+        // The first one over drops the oldest & starts writing over the front
+        assert_eq!(pushed(4, 5).as_slice(), &[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(pushed(4, 7).as_slice(), &[3.0, 4.0, 5.0, 6.0]);
+        // All the way round, & then some
+        assert_eq!(pushed(4, 8).as_slice(), &[4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(pushed(4, 13).as_slice(), &[9.0, 10.0, 11.0, 12.0]);
+        assert_eq!(pushed(4, 13).len(), 4);
+    }
+
+    #[test]
+    fn room_for_one() {
+        assert_eq!(pushed(1, 1).as_slice(), &[0.0]);
+        assert_eq!(pushed(1, 5).as_slice(), &[4.0]);
+    }
+}
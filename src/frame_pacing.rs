@@ -0,0 +1,184 @@
+use instant::{Duration, Instant};
+
+/*
+*   Frame pacing. Left alone, a frame starts as soon as the window asks for one. With Fifo the
+*   vsync wait keeps that even, but with Mailbox & Immediate (or a compositor that doesn't
+*   block) frames reach the screen whenever they happen to finish. The animations move by the
+*   time between frame starts, so when the gaps between presents wobble around that, motion
+*   stutters even at a high frame rate.
+*
+*   FramePacing::Interval aims for a present exactly every `interval` instead. The pacer keeps
+*   an exponential average of how long a frame takes to make, from the start of the update to
+*   present returning, and holds each frame back until that long before its deadline. A frame
+*   that misses its deadline by more than a whole interval re-anchors the schedule rather than
+*   rushing the next few out to catch up.
+*
+*   Holding back is a sleep on native, with the last millisecond spun since sleeps overshoot.
+*   On the web there's no sleeping, the browser's requestAnimationFrame decides when we get to
+*   draw, so redraws that come too early for the interval are skipped instead.
+*
+*   Either way the gaps between presents are recorded and their jitter (how far they stray
+*   from their average) is logged every REPORT_EVERY, so pacing on & off can be compared.
+*   Off only logs at debug level, F2 turns that on.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FramePacing {
+    // Draw whenever the window asks, the present mode does the pacing (or doesn't)
+    Off,
+    // A frame every interval
+    Interval(Duration),
+}
+
+impl FramePacing {
+    pub fn from_rate(hz: f64) -> Self {
+        FramePacing::Interval(Duration::from_secs_f64(1.0 / hz))
+    }
+}
+
+impl std::fmt::Display for FramePacing {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FramePacing::Off => write!(f, "off"),
+            FramePacing::Interval(interval) => {
+                write!(f, "{:.1} fps ({:.2}ms)", 1.0 / interval.as_secs_f64(), interval.as_secs_f64() * 1000.0)
+            }
+        }
+    }
+}
+
+pub struct FramePacer {
+    mode: FramePacing,
+    // Average of how long a frame takes to make, in seconds
+    predicted_work: f32,
+    // When the next frame should be presented, None until the first one has been
+    deadline: Option<Instant>,
+    work_start: Instant,
+    last_present: Option<Instant>,
+    // Milliseconds between presents since the last report
+    intervals: Vec<f32>,
+    last_report: Instant,
+}
+
+impl FramePacer {
+    // How much the newest frame counts in the average, the rest is history
+    const SMOOTHING: f32 = 0.1;
+    // Started this much earlier than the prediction says, for the frames that run a bit long
+    const MARGIN: Duration = Duration::from_micros(500);
+    #[cfg(not(target_arch = "wasm32"))]
+    const SPIN: Duration = Duration::from_millis(1);
+    const REPORT_EVERY: Duration = Duration::from_secs(5);
+
+    pub fn new(mode: FramePacing) -> Self {
+        let now = Instant::now();
+        Self {
+            mode,
+            predicted_work: 0.0,
+            deadline: None,
+            work_start: now,
+            last_present: None,
+            intervals: Vec::new(),
+            last_report: now,
+        }
+    }
+
+    pub fn mode(&self) -> FramePacing {
+        self.mode
+    }
+
+    // Starts the schedule & the statistics over
+    pub fn set_mode(&mut self, mode: FramePacing) {
+        *self = Self {
+            predicted_work: self.predicted_work,
+            ..Self::new(mode)
+        };
+    }
+
+    // On the web anything up to half an interval early is close enough, or we'd skip every
+    // other animation frame for being a few microseconds early. Native can wait it out.
+    #[cfg(target_arch = "wasm32")]
+    fn tolerance(interval: Duration) -> Duration {
+        interval / 2
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn tolerance(_interval: Duration) -> Duration {
+        Duration::ZERO
+    }
+
+    // Call before starting on a frame. Waits until it's time on native, on the web it says
+    // whether to draw this one at all.
+    pub fn begin_frame(&mut self) -> bool {
+        let start = match (self.mode, self.deadline) {
+            (FramePacing::Interval(interval), Some(deadline)) => {
+                let lead = Duration::from_secs_f32(self.predicted_work) + Self::MARGIN + Self::tolerance(interval);
+                deadline.checked_sub(lead).unwrap_or(deadline)
+            }
+            _ => Instant::now(),
+        };
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                if Instant::now() < start {
+                    return false;
+                }
+            } else {
+                let wait = start.saturating_duration_since(Instant::now());
+                if wait > Self::SPIN {
+                    std::thread::sleep(wait - Self::SPIN);
+                }
+                while Instant::now() < start {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+        self.work_start = Instant::now();
+        true
+    }
+
+    // Call once the frame's been presented
+    pub fn end_frame(&mut self) {
+        let now = Instant::now();
+        let work = (now - self.work_start).as_secs_f32();
+        self.predicted_work = if self.predicted_work == 0.0 {
+            work
+        } else {
+            self.predicted_work + (work - self.predicted_work) * Self::SMOOTHING
+        };
+        if let Some(last) = self.last_present {
+            self.intervals.push((now - last).as_secs_f32() * 1000.0);
+        }
+        self.last_present = Some(now);
+        if let FramePacing::Interval(interval) = self.mode {
+            self.deadline = Some(match self.deadline {
+                Some(deadline) if now < deadline + interval => deadline + interval,
+                // Too late to catch up, go again from here
+                _ => now + interval,
+            });
+        }
+        if now - self.last_report >= Self::REPORT_EVERY {
+            self.report();
+            self.last_report = now;
+            self.intervals.clear();
+        }
+    }
+
+    fn report(&self) {
+        if self.intervals.is_empty() {
+            return;
+        }
+        let count = self.intervals.len() as f32;
+        let mean = self.intervals.iter().sum::<f32>() / count;
+        let deviation = (self.intervals.iter().map(|ms| (ms - mean) * (ms - mean)).sum::<f32>() / count).sqrt();
+        let worst = self.intervals.iter().fold(0.0f32, |worst, ms| worst.max((ms - mean).abs()));
+        let level = if self.mode == FramePacing::Off { log::Level::Debug } else { log::Level::Info };
+        log::log!(
+            level,
+            "frame pacing {}: {} frames, {:.2}ms apart ± {:.2}ms, worst {:.2}ms off, {:.2}ms of work",
+            self.mode,
+            self.intervals.len(),
+            mean,
+            deviation,
+            worst,
+            self.predicted_work * 1000.0
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use instant::Duration;
+
+/*
+*   Freeze frame & single stepping, for going through an animation a frame at a time. While
+*   frozen nothing runs at all: no update, no fixed steps, no render, and the window keeps the
+*   last frame it was shown. Each step asked for runs exactly one frame of each, with one fixed
+*   step's worth of time rather than however long it's been, so every step moves the
+*   simulation by the same amount and lands on the next fixed step.
+*
+*   That's a step past a time scale of 0 (see State::time_scale), which stops the animations
+*   but keeps drawing, the camera still flies around a paused scene. Steps go by the time
+*   scale too, so a slowed down animation takes smaller steps.
+*
+*   Unfreezing carries on in real time from where it stopped, the time spent frozen is
+*   dropped rather than caught up on.
+*/
+#[derive(Debug, Default)]
+pub struct FrameStepper {
+    frozen: bool,
+    // Asked for while frozen & not run yet
+    pending_steps: u32,
+    // Frames run so far, stepped or not
+    frame: u64,
+}
+
+impl FrameStepper {
+    pub fn frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+        self.pending_steps = 0;
+    }
+
+    // One more frame to run while frozen. Freezes first when it's running, the frame stepped
+    // to is the one after what's on screen now.
+    pub fn step(&mut self) {
+        self.frozen = true;
+        self.pending_steps += 1;
+    }
+
+    // The number of the last frame run, counting from 1
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Whether there's a frame to run, when there isn't the window needn't be redrawn
+    pub fn wants_frame(&self) -> bool {
+        !self.frozen || self.pending_steps > 0
+    }
+
+    // Call at the start of a frame with the real time since the last one. Gives back the time
+    // the frame should run for, `step` when stepping, or None for no frame at all.
+    pub fn next_frame(&mut self, elapsed: Duration, step: Duration) -> Option<Duration> {
+        let dt = if !self.frozen {
+            elapsed
+        } else if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            step
+        } else {
+            return None;
+        };
+        self.frame += 1;
+        Some(dt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ELAPSED: Duration = Duration::from_millis(40);
+    const STEP: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn runs_in_real_time() {
+        let mut stepper = FrameStepper::default();
+        assert!(stepper.wants_frame());
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), Some(ELAPSED));
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), Some(ELAPSED));
+        assert_eq!(stepper.frame(), 2);
+    }
+
+    #[test]
+    fn frozen_runs_nothing() {
+        let mut stepper = FrameStepper::default();
+        stepper.next_frame(ELAPSED, STEP);
+        stepper.set_frozen(true);
+        assert!(!stepper.wants_frame());
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), None);
+        assert_eq!(stepper.frame(), 1);
+    }
+
+    #[test]
+    fn each_step_is_one_fixed_frame() {
+        let mut stepper = FrameStepper::default();
+        // Stepping while running freezes first
+        stepper.step();
+        stepper.step();
+        assert!(stepper.frozen() && stepper.wants_frame());
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), Some(STEP));
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), Some(STEP));
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), None);
+        assert!(!stepper.wants_frame());
+        assert_eq!(stepper.frame(), 2);
+    }
+
+    #[test]
+    fn unfreezing_drops_pending_steps() {
+        let mut stepper = FrameStepper::default();
+        stepper.step();
+        stepper.set_frozen(false);
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), Some(ELAPSED));
+        stepper.set_frozen(true);
+        assert_eq!(stepper.next_frame(ELAPSED, STEP), None);
+    }
+}
@@ -0,0 +1,179 @@
+// Fast approximate anti-aliasing post effect, see antialiasing.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+
+// Edges with less contrast than this are left alone
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+// How much the subpixel aliasing gets smoothed out, 0 turns it off
+const SUBPIXEL_QUALITY: f32 = 0.75;
+// How many steps we take along an edge looking for its ends
+const ITERATIONS: i32 = 12;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    // Texture coordinates have y pointing down
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    // sRGB targets hand us linear values, the square root gets close enough to perceived brightness
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+// Loops make the control flow non-uniform, so every lookup picks its mip level explicitly
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_color, s_color, uv, 0.0).rgb);
+}
+
+fn luma_offset(uv: vec2<f32>, offset: vec2<f32>) -> f32 {
+    return luma_at(uv + offset * screen.inv_resolution);
+}
+
+// The search speeds up the further along the edge it gets
+fn step_scale(i: i32) -> f32 {
+    if (i < 5) {
+        return 1.0;
+    }
+    if (i == 5) {
+        return 1.5;
+    }
+    if (i < 10) {
+        return 2.0;
+    }
+    if (i == 10) {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(t_color, s_color, in.uv, 0.0);
+
+    // Skip anything that isn't on an edge, which is most of the screen
+    let luma_center = luma(color.rgb);
+    let luma_up = luma_offset(in.uv, vec2<f32>(0.0, -1.0));
+    let luma_down = luma_offset(in.uv, vec2<f32>(0.0, 1.0));
+    let luma_left = luma_offset(in.uv, vec2<f32>(-1.0, 0.0));
+    let luma_right = luma_offset(in.uv, vec2<f32>(1.0, 0.0));
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+    if (luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        return color;
+    }
+
+    // Work out whether the edge runs horizontally or vertically from all 8 neighbours
+    let luma_up_left = luma_offset(in.uv, vec2<f32>(-1.0, -1.0));
+    let luma_up_right = luma_offset(in.uv, vec2<f32>(1.0, -1.0));
+    let luma_down_left = luma_offset(in.uv, vec2<f32>(-1.0, 1.0));
+    let luma_down_right = luma_offset(in.uv, vec2<f32>(1.0, 1.0));
+    let luma_up_down = luma_up + luma_down;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_up_left + luma_down_left;
+    let luma_right_corners = luma_up_right + luma_down_right;
+    let luma_up_corners = luma_up_left + luma_up_right;
+    let luma_down_corners = luma_down_left + luma_down_right;
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_up_down) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // The edge is on whichever side of the pixel the luma changes the most
+    let luma1 = select(luma_left, luma_up, is_horizontal);
+    let luma2 = select(luma_right, luma_down, is_horizontal);
+    let gradient1 = luma1 - luma_center;
+    let gradient2 = luma2 - luma_center;
+    let is1_steepest = abs(gradient1) >= abs(gradient2);
+    let gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+    var step_length = select(screen.inv_resolution.x, screen.inv_resolution.y, is_horizontal);
+    var luma_local_average = 0.5 * (luma2 + luma_center);
+    if (is1_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    }
+
+    // Move half a pixel onto the edge, then walk along it both ways until the luma changes
+    var current_uv = in.uv;
+    if (is_horizontal) {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+    let offset = select(
+        vec2<f32>(0.0, screen.inv_resolution.y),
+        vec2<f32>(screen.inv_resolution.x, 0.0),
+        is_horizontal,
+    );
+    var uv1 = current_uv - offset;
+    var uv2 = current_uv + offset;
+    var luma_end1 = luma_at(uv1) - luma_local_average;
+    var luma_end2 = luma_at(uv2) - luma_local_average;
+    var reached1 = abs(luma_end1) >= gradient_scaled;
+    var reached2 = abs(luma_end2) >= gradient_scaled;
+    for (var i = 1; i < ITERATIONS && !(reached1 && reached2); i += 1) {
+        if (!reached1) {
+            uv1 -= offset * step_scale(i);
+            luma_end1 = luma_at(uv1) - luma_local_average;
+            reached1 = abs(luma_end1) >= gradient_scaled;
+        }
+        if (!reached2) {
+            uv2 += offset * step_scale(i);
+            luma_end2 = luma_at(uv2) - luma_local_average;
+            reached2 = abs(luma_end2) >= gradient_scaled;
+        }
+    }
+
+    // Pixels near the end of the edge that's closest get blended the most
+    let distance1 = select(in.uv.y - uv1.y, in.uv.x - uv1.x, is_horizontal);
+    let distance2 = select(uv2.y - in.uv.y, uv2.x - in.uv.x, is_horizontal);
+    let is_direction1 = distance1 < distance2;
+    let distance_final = min(distance1, distance2);
+    let edge_length = distance1 + distance2;
+    let pixel_offset = 0.5 - distance_final / edge_length;
+    // Only blend if the end we found is on the same side of the average as this pixel
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let correct_variation = select(luma_end2 < 0.0, luma_end1 < 0.0, is_direction1) != is_luma_center_smaller;
+    var final_offset = select(0.0, pixel_offset, correct_variation);
+
+    // Single pixel details (thin lines, specks) don't have a proper edge to walk, smooth those
+    // by how different the pixel is from the average of its neighbours
+    let luma_average = (1.0 / 12.0) * (2.0 * (luma_up_down + luma_left_right) + luma_left_corners + luma_right_corners);
+    let subpixel_offset1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    let subpixel_offset2 = (-2.0 * subpixel_offset1 + 3.0) * subpixel_offset1 * subpixel_offset1;
+    final_offset = max(final_offset, subpixel_offset2 * subpixel_offset2 * SUBPIXEL_QUALITY);
+
+    // Sampling a bit towards the edge lets the linear filtering do the blending
+    var final_uv = in.uv;
+    if (is_horizontal) {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return textureSampleLevel(t_color, s_color, final_uv, 0.0);
+}
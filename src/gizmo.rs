@@ -0,0 +1,413 @@
+use std::rc::Rc;
+
+use cgmath::{EuclideanSpace, InnerSpace, Rotation3};
+
+use winit::event::ModifiersState;
+
+use crate::camera;
+use crate::debug_lines::{self, LineVertex};
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance;
+use crate::physics;
+use crate::pipeline_cache::PipelineCache;
+use crate::texture;
+
+/*
+*   Editor style handles on the selected object: three arrows to slide it along x, y or z, or
+*   three rings to turn it around them. The axes are the world's, not the object's.
+*
+*   Picking a handle is a ray cast from the cursor. Arrows are thin boxes, so they go through
+*   Ray::intersect_aabb like the objects do, rings are the band around the circle where the ray
+*   crosses the ring's plane. Dragging works out where the cursor's ray is now:
+*
+*   Translate  the point on the arrow's axis closest to the ray. The object moves by however
+*              far that is from where the drag started, so it doesn't jump to the cursor.
+*   Rotate     where the ray crosses the ring's plane. The object turns by the angle between
+*              that & where the drag started, as seen from the middle of the ring.
+*
+*   Both are relative to the object's transform when the drag started, rather than adding up
+*   small steps, which would drift. Holding ctrl snaps to SNAP_DISTANCE & SNAP_ANGLE, holding
+*   shift moves it by only PRECISE of what the cursor does, for fine adjustments. Pressing or
+*   letting go of shift mid drag starts it over from where the object is, so it doesn't jump.
+*
+*   The handles scale with their distance from the camera so they're always about the same size
+*   on screen, and are drawn with the depth test off so objects in front can't hide them.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+}
+
+impl GizmoMode {
+    pub fn next(self) -> Self {
+        match self {
+            GizmoMode::Translate => GizmoMode::Rotate,
+            GizmoMode::Rotate => GizmoMode::Translate,
+        }
+    }
+}
+
+impl std::fmt::Display for GizmoMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GizmoMode::Translate => write!(f, "translate"),
+            GizmoMode::Rotate => write!(f, "rotate"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+
+    fn vector(self) -> cgmath::Vector3<f32> {
+        let mut vector = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        vector[self.index()] = 1.0;
+        vector
+    }
+
+    // The other two axes, the plane a ring lies in
+    fn others(self) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let (a, b) = match self {
+            Axis::X => (Axis::Y, Axis::Z),
+            Axis::Y => (Axis::Z, Axis::X),
+            Axis::Z => (Axis::X, Axis::Y),
+        };
+        (a.vector(), b.vector())
+    }
+
+    // The usual red, green & blue for x, y & z
+    fn color(self) -> [f32; 3] {
+        match self {
+            Axis::X => [0.9, 0.15, 0.15],
+            Axis::Y => [0.15, 0.85, 0.15],
+            Axis::Z => [0.2, 0.35, 1.0],
+        }
+    }
+}
+
+impl std::fmt::Display for Axis {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Axis::X => write!(f, "x"),
+            Axis::Y => write!(f, "y"),
+            Axis::Z => write!(f, "z"),
+        }
+    }
+}
+
+// Where on the handle the drag started
+#[derive(Copy, Clone, Debug)]
+enum Grab {
+    // How far along the axis from the object
+    Along(f32),
+    // The direction from the middle of the ring
+    Around(cgmath::Vector3<f32>),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Drag {
+    axis: Axis,
+    start: instance::Transform,
+    grab: Grab,
+    // Moving at PRECISE speed, for shift being held. Changing it starts the drag over from
+    // where the object is.
+    precise: bool,
+}
+
+pub struct Gizmo {
+    pub enabled: bool,
+    pub mode: GizmoMode,
+    // Highlighted, under the cursor or being dragged
+    hovered: Option<Axis>,
+    drag: Option<Drag>,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    vertex_count: u32,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Gizmo {
+    // The handles' length (& the rings' radius) as a fraction of their distance from the camera
+    const SCREEN_SIZE: f32 = 0.15;
+    // How much of that around the handles still counts as a hit
+    const PICK_TOLERANCE: f32 = 0.08;
+    const RING_SEGMENTS: usize = 48;
+    // Both plenty for the biggest of the two modes
+    const MAX_VERTICES: usize = 3 * Self::RING_SEGMENTS * 2;
+    const SNAP_DISTANCE: f32 = 0.25;
+    const SNAP_ANGLE: cgmath::Deg<f32> = cgmath::Deg(15.0);
+    // How much of the cursor's movement a drag with shift held follows
+    const PRECISE: f32 = 0.1;
+    const HIGHLIGHT: [f32; 3] = [1.0, 0.9, 0.1];
+
+    // Drawn in the main pass, so it takes that pass' sample count. The depth test is off, so
+    // it doesn't care which way the depth goes.
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let vertex_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gizmo Vertex Buffer"),
+            size: (Self::MAX_VERTICES * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pipeline = debug_lines::line_pipeline(
+            device,
+            pipelines,
+            "Gizmo",
+            color_format,
+            sample_count,
+            screen_layout,
+            camera_layout,
+            // On top of everything, neither tested against nor hiding anything
+            wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            },
+        );
+
+        Self {
+            enabled: false,
+            mode: GizmoMode::Translate,
+            hovered: None,
+            drag: None,
+            vertex_buffer,
+            vertex_count: 0,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new main pass, keeping the mode.
+    // A drag in progress is dropped.
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let mut gizmo = Self::new(device, pipelines, color_format, sample_count, screen_layout, camera_layout);
+        gizmo.enabled = self.enabled;
+        gizmo.mode = self.mode;
+        gizmo
+    }
+
+    fn size(camera: &camera::Camera, origin: cgmath::Vector3<f32>) -> f32 {
+        (origin - camera.eye.to_vec()).magnitude() * Self::SCREEN_SIZE
+    }
+
+    // The handle `ray` hits first
+    fn pick(&self, camera: &camera::Camera, ray: &camera::Ray, target: &instance::Transform) -> Option<Axis> {
+        let origin = target.position;
+        let size = Self::size(camera, origin);
+        let tolerance = size * Self::PICK_TOLERANCE;
+        let hits = Axis::ALL.into_iter().filter_map(|axis| {
+            let distance = match self.mode {
+                GizmoMode::Translate => {
+                    let thickness = cgmath::Vector3::new(tolerance, tolerance, tolerance);
+                    let handle = physics::Aabb::new(origin - thickness, origin + axis.vector() * size + thickness);
+                    ray.intersect_aabb(&handle)?
+                }
+                GizmoMode::Rotate => {
+                    let hit = ray.intersect_plane(origin, axis.vector())?;
+                    let radius = (hit.to_vec() - origin).magnitude();
+                    if (radius - size).abs() > tolerance {
+                        return None;
+                    }
+                    (hit - ray.origin).magnitude()
+                }
+            };
+            Some((axis, distance))
+        });
+        hits.min_by(|a, b| a.1.total_cmp(&b.1)).map(|(axis, _)| axis)
+    }
+
+    // Highlights the handle under the cursor, if nothing's being dragged
+    pub fn hover(&mut self, camera: &camera::Camera, ray: Option<camera::Ray>, target: Option<&instance::Transform>) {
+        if self.drag.is_none() {
+            self.hovered = ray.zip(target).and_then(|(ray, target)| self.pick(camera, &ray, target));
+        }
+    }
+
+    // Starts dragging the handle under the cursor, false if there isn't one. Shift held already
+    // starts it precise.
+    pub fn begin_drag(
+        &mut self,
+        camera: &camera::Camera,
+        ray: &camera::Ray,
+        target: &instance::Transform,
+        modifiers: ModifiersState,
+    ) -> bool {
+        let Some(axis) = self.pick(camera, ray, target) else {
+            return false;
+        };
+        let Some(grab) = self.grab(ray, target, axis) else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            axis,
+            start: *target,
+            grab,
+            precise: modifiers.shift(),
+        });
+        self.hovered = Some(axis);
+        true
+    }
+
+    // Where the ray meets the handle for `axis` on `target`
+    fn grab(&self, ray: &camera::Ray, target: &instance::Transform, axis: Axis) -> Option<Grab> {
+        match self.mode {
+            GizmoMode::Translate => Self::closest_along(ray, target.position, axis.vector()).map(Grab::Along),
+            GizmoMode::Rotate => {
+                let hit = ray.intersect_plane(target.position, axis.vector())?;
+                Some(Grab::Around(hit.to_vec() - target.position))
+            }
+        }
+    }
+
+    // Where the dragged object should be for the cursor's `ray`, with ctrl snapping & shift
+    // slowing it down. None when nothing's being dragged, or the ray runs along the axis (or
+    // the ring's plane) and can't say.
+    pub fn drag(&mut self, ray: &camera::Ray, modifiers: ModifiersState) -> Option<instance::Transform> {
+        let mut drag = self.drag?;
+        if drag.precise != modifiers.shift() {
+            // Where it's shown now, snapped if it is. Rotations snap relative to the start, an
+            // unsnapped one would move it onto another set of steps.
+            let here = Self::dragged(&drag, ray, modifiers.ctrl())?;
+            drag = Drag {
+                start: here,
+                grab: self.grab(ray, &here, drag.axis)?,
+                precise: modifiers.shift(),
+                ..drag
+            };
+            self.drag = Some(drag);
+        }
+        Self::dragged(&drag, ray, modifiers.ctrl())
+    }
+
+    fn dragged(drag: &Drag, ray: &camera::Ray, snap: bool) -> Option<instance::Transform> {
+        let Drag { axis, start, grab, precise } = *drag;
+        let scale = if precise { Self::PRECISE } else { 1.0 };
+        let mut transform = start;
+        match grab {
+            Grab::Along(from) => {
+                let to = Self::closest_along(ray, start.position, axis.vector())?;
+                let mut position = start.position[axis.index()] + (to - from) * scale;
+                if snap {
+                    position = (position / Self::SNAP_DISTANCE).round() * Self::SNAP_DISTANCE;
+                }
+                transform.position[axis.index()] = position;
+            }
+            Grab::Around(from) => {
+                let to = ray.intersect_plane(start.position, axis.vector())?.to_vec() - start.position;
+                let mut angle = cgmath::Rad(axis.vector().dot(from.cross(to)).atan2(from.dot(to)) * scale);
+                if snap {
+                    let step = cgmath::Rad::from(Self::SNAP_ANGLE);
+                    angle = step * (angle / step).round();
+                }
+                transform.rotation = cgmath::Quaternion::from_axis_angle(axis.vector(), angle) * start.rotation;
+            }
+        }
+        Some(transform)
+    }
+
+    // Lets go of the handle, returns the axis if there was a drag to finish
+    pub fn end_drag(&mut self) -> Option<Axis> {
+        self.drag.take().map(|drag| drag.axis)
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /*
+    *   How far from `origin` along `direction` the line through them comes closest to the ray,
+    *   from the two lines' closest points. None when they're close to parallel: then every
+    *   point is about as close & the answer jumps around with the tiniest move of the mouse.
+    */
+    fn closest_along(ray: &camera::Ray, origin: cgmath::Vector3<f32>, direction: cgmath::Vector3<f32>) -> Option<f32> {
+        let between = origin - ray.origin.to_vec();
+        let cos = direction.dot(ray.direction);
+        let denominator = 1.0 - cos * cos;
+        if denominator < 1e-4 {
+            return None;
+        }
+        Some((cos * ray.direction.dot(between) - direction.dot(between)) / denominator)
+    }
+
+    // Rebuilds the lines around `target` for this frame's camera, nothing to draw without one
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &camera::Camera, target: Option<&instance::Transform>) {
+        let Some(target) = target.filter(|_| self.enabled) else {
+            self.vertex_count = 0;
+            return;
+        };
+        let origin = target.position;
+        let size = Self::size(camera, origin);
+        let mut vertices = Vec::with_capacity(Self::MAX_VERTICES);
+        for axis in Axis::ALL {
+            let color = if self.hovered == Some(axis) { Self::HIGHLIGHT } else { axis.color() };
+            let mut line = |a: cgmath::Vector3<f32>, b: cgmath::Vector3<f32>| {
+                vertices.push(LineVertex { position: a.into(), color });
+                vertices.push(LineVertex { position: b.into(), color });
+            };
+            let (u, v) = axis.others();
+            match self.mode {
+                GizmoMode::Translate => {
+                    let tip = origin + axis.vector() * size;
+                    let back = tip - axis.vector() * size * 0.2;
+                    line(origin, tip);
+                    // An arrowhead of four lines
+                    for side in [u, -u, v, -v] {
+                        line(tip, back + side * size * 0.07);
+                    }
+                }
+                GizmoMode::Rotate => {
+                    let point = |i: usize| {
+                        let angle = i as f32 / Self::RING_SEGMENTS as f32 * std::f32::consts::TAU;
+                        origin + (u * angle.cos() + v * angle.sin()) * size
+                    };
+                    for i in 0..Self::RING_SEGMENTS {
+                        line(point(i), point(i + 1));
+                    }
+                }
+            }
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    // Expects the screen & camera bind groups to already be set at @group(0) & @group(1)
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
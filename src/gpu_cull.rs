@@ -0,0 +1,328 @@
+use crate::camera::Frustum;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::InstanceRaw;
+
+/*
+*   Frustum culling on the gpu. A compute shader tests every instance's bounding sphere against
+*   the frustum planes and copies the ones that are visible into an output instance buffer.
+*   Each visible instance bumps an atomic counter, and that counter is the instance_count of
+*   an indirect draw, so the cpu never needs to know how many instances survived: the render
+*   pass just calls draw_indexed_indirect with the buffer the compute shader filled in.
+*
+*   Compute shaders & indirect draws aren't available on WebGL, see GpuCuller::is_supported.
+*/
+pub struct GpuCuller {
+    pipeline: wgpu::ComputePipeline,
+    frustum_buffer: Tracked<wgpu::Buffer>,
+    frustum_bind_group: wgpu::BindGroup,
+    target_layout: wgpu::BindGroupLayout,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrustumUniform {
+    planes: [[f32; 4]; 6],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    instance_count: u32,
+    radius: f32,
+    // Uniforms like to be a multiple of 16 bytes
+    _padding: [u32; 2],
+}
+
+// Everything needed to cull one instance buffer for one mesh
+pub struct CullTarget {
+    params_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // The visible instances, bind this as the instance vertex buffer
+    pub instances: Tracked<wgpu::Buffer>,
+    // Arguments for draw_indexed_indirect
+    pub indirect: Tracked<wgpu::Buffer>,
+    index_count: u32,
+    radius: f32,
+}
+
+impl GpuCuller {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    // cull.wgsl uses three storage buffers & a full workgroup of WORKGROUP_SIZE threads
+    pub fn is_supported(adapter: &wgpu::Adapter, limits: &wgpu::Limits) -> bool {
+        let flags = adapter.get_downlevel_capabilities().flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+        );
+        flags
+            && limits.max_storage_buffers_per_shader_stage >= 3
+            && limits.max_compute_workgroup_size_x >= Self::WORKGROUP_SIZE
+            && limits.max_compute_invocations_per_workgroup >= Self::WORKGROUP_SIZE
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let frustum_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("cull_frustum_bind_group_layout"),
+        });
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let target_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+            ],
+            label: Some("cull_target_bind_group_layout"),
+        });
+
+        let frustum_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Frustum Buffer"),
+            contents: bytemuck::cast_slice(&[FrustumUniform { planes: [[0.0; 4]; 6] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let frustum_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &frustum_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: frustum_buffer.as_entire_binding(),
+            }],
+            label: Some("cull_frustum_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("cull.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&frustum_layout, &target_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cull Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        Self {
+            pipeline,
+            frustum_buffer,
+            frustum_bind_group,
+            target_layout,
+        }
+    }
+
+    // `input` needs STORAGE usage and room for `capacity` InstanceRaws. `index_count` and
+    // `radius` describe the mesh the instances get drawn with.
+    pub fn create_target(
+        &self,
+        device: &wgpu::Device,
+        input: &wgpu::Buffer,
+        capacity: usize,
+        index_count: u32,
+        radius: f32,
+    ) -> CullTarget {
+        let params_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Params Buffer"),
+            contents: bytemuck::cast_slice(&[CullParams {
+                instance_count: 0,
+                radius,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let instances = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culled Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let indirect = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Indirect Buffer"),
+            contents: draw_args(index_count).as_bytes(),
+            // COPY_SRC so the visible count can be read back
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.target_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: input.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: instances.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: indirect.as_entire_binding(),
+                },
+            ],
+            label: Some("cull_target_bind_group"),
+        });
+
+        CullTarget {
+            params_buffer,
+            bind_group,
+            instances,
+            indirect,
+            index_count,
+            radius,
+        }
+    }
+
+    pub fn update_frustum(&self, queue: &wgpu::Queue, frustum: &Frustum) {
+        let uniform = FrustumUniform {
+            planes: frustum.planes.map(Into::into),
+        };
+        queue.write_buffer(&self.frustum_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Queues the culling of the first `instance_count` instances of the target's input. The
+    // writes happen when the encoder is submitted, before any of its commands run.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, target: &CullTarget, instance_count: u32) {
+        queue.write_buffer(
+            &target.params_buffer,
+            0,
+            bytemuck::cast_slice(&[CullParams {
+                instance_count,
+                radius: target.radius,
+                _padding: [0; 2],
+            }]),
+        );
+        // The shader counts up from 0 every frame
+        queue.write_buffer(&target.indirect, 0, draw_args(target.index_count).as_bytes());
+
+        if instance_count == 0 {
+            return;
+        }
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.frustum_bind_group, &[]);
+        compute_pass.set_bind_group(1, &target.bind_group, &[]);
+        compute_pass.dispatch_workgroups(instance_count.div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+// The indirect draw arguments before the shader has counted any instances. The shader bumps
+// instance_count with an atomic, so it has to sit at the same offset as in the WGSL struct.
+fn draw_args(index_count: u32) -> wgpu::util::DrawIndexedIndirect {
+    wgpu::util::DrawIndexedIndirect {
+        vertex_count: index_count,
+        instance_count: 0,
+        base_index: 0,
+        vertex_offset: 0,
+        base_instance: 0,
+    }
+}
+
+/*
+*   Culls `count` instances scattered around the camera both ways and logs how long each took.
+*   The gpu timing includes submitting and waiting for the result, which is what a frame would
+*   actually pay, but not uploading the instances.
+*/
+#[cfg(not(target_arch = "wasm32"))]
+pub fn compare_with_cpu(ctx: &crate::context::GpuContext, frustum: &Frustum, count: usize) {
+    use crate::instance::Transform;
+
+    if !GpuCuller::is_supported(&ctx.adapter, &ctx.device.limits()) {
+        log::warn!("This adapter can't run the gpu culling pass");
+        return;
+    }
+
+    // A big slab of instances, roughly a cube 200 units across
+    let side = (count as f32).cbrt().ceil() as usize;
+    let transforms = (0..count)
+        .map(|i| {
+            let (x, y, z) = (i % side, (i / side) % side, i / (side * side));
+            let spacing = 200.0 / side as f32;
+            Transform::from_position(cgmath::Vector3::new(
+                x as f32 * spacing - 100.0,
+                y as f32 * spacing - 100.0,
+                z as f32 * spacing - 100.0,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let radius = 0.5;
+
+    let start = instant::Instant::now();
+    let cpu_visible = transforms
+        .iter()
+        .filter(|t| frustum.intersects_sphere(t.position, radius))
+        .count();
+    let cpu_time = start.elapsed();
+
+    let device = &ctx.device;
+    let culler = GpuCuller::new(device);
+    culler.update_frustum(&ctx.queue, frustum);
+    let raw = transforms.iter().map(Transform::to_raw).collect::<Vec<_>>();
+    let input = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Cull Benchmark Instances"),
+        contents: bytemuck::cast_slice(&raw),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let target = culler.create_target(device, &input, count, 0, radius);
+    // Make sure the upload is done before we start timing
+    ctx.queue.submit(None);
+    device.poll(wgpu::Maintain::Wait);
+
+    let start = instant::Instant::now();
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Cull Benchmark Encoder"),
+    });
+    culler.cull(&mut encoder, &ctx.queue, &target, count as u32);
+    ctx.queue.submit(std::iter::once(encoder.finish()));
+    device.poll(wgpu::Maintain::Wait);
+    let gpu_time = start.elapsed();
+
+    // instance_count is the second u32 of the arguments
+    let args = pollster::block_on(ctx.read_buffer_async(&target.indirect, 8));
+    let gpu_visible = bytemuck::pod_read_unaligned::<u32>(&args[4..8]);
+
+    log::info!(
+        "culling {} instances: cpu {:.3}ms ({} visible), gpu {:.3}ms ({} visible)",
+        count,
+        cpu_time.as_secs_f64() * 1000.0,
+        cpu_visible,
+        gpu_time.as_secs_f64() * 1000.0,
+        gpu_visible,
+    );
+}
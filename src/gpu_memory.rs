@@ -0,0 +1,240 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use wgpu::util::DeviceExt;
+
+/*
+*   A rough count of the gpu memory we've asked for, to catch leaks: a number that keeps
+*   climbing while nothing new is being shown means something's being made over & over and
+*   kept around. Every buffer & texture is created through TrackedDevice, which adds its size
+*   here and hands it back wrapped in a Tracked that takes the size off again when it's dropped.
+*
+*   It's only what we asked for. Drivers round sizes up, pad rows, keep their own copies and
+*   so on, so the real number is higher. Query sets, shaders & pipelines aren't counted.
+*   The counters are shared by every device, there's only ever one at a time (see context.rs).
+*
+*   How many of each are alive is counted the same way, see ResourceCounts. All of it is a
+*   relaxed atomic add when something's made or dropped, nothing a release build would notice.
+*/
+static BUFFER_BYTES: AtomicU64 = AtomicU64::new(0);
+static TEXTURE_BYTES: AtomicU64 = AtomicU64::new(0);
+static BUFFER_COUNT: AtomicU64 = AtomicU64::new(0);
+static TEXTURE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuMemory {
+    pub buffers: u64,
+    pub textures: u64,
+}
+
+impl GpuMemory {
+    pub fn current() -> Self {
+        Self {
+            buffers: BUFFER_BYTES.load(Ordering::Relaxed),
+            textures: TEXTURE_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buffers + self.textures
+    }
+}
+
+impl std::fmt::Display for GpuMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        write!(
+            f,
+            "{:.1}MB ({:.1}MB buffers, {:.1}MB textures)",
+            mb(self.total()),
+            mb(self.buffers),
+            mb(self.textures)
+        )
+    }
+}
+
+// How many buffers & textures are alive right now
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceCounts {
+    pub buffers: u64,
+    pub textures: u64,
+}
+
+impl ResourceCounts {
+    pub fn current() -> Self {
+        Self {
+            buffers: BUFFER_COUNT.load(Ordering::Relaxed),
+            textures: TEXTURE_COUNT.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buffers + self.textures
+    }
+}
+
+impl std::fmt::Display for ResourceCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} buffers, {} textures", self.buffers, self.textures)
+    }
+}
+
+/*
+*   Watches the resource counts for leaks. Loading things, turning effects on and resizing all
+*   make new resources, but they level off once the old ones are dropped. So every CHECK_EVERY
+*   the count is compared with the highest it's been, and if it's set a new record LEAK_CHECKS
+*   times in a row it's probably never coming back down, which gets a warning.
+*/
+pub struct ResourceTracker {
+    last_check: instant::Instant,
+    highest: u64,
+    // Checks in a row that set a new highest count
+    growing: u32,
+}
+
+impl ResourceTracker {
+    const CHECK_EVERY: instant::Duration = instant::Duration::from_secs(10);
+    const LEAK_CHECKS: u32 = 6;
+
+    pub fn new() -> Self {
+        Self {
+            last_check: instant::Instant::now(),
+            highest: ResourceCounts::current().total(),
+            growing: 0,
+        }
+    }
+
+    // Cheap enough to call every frame, it only looks every CHECK_EVERY
+    pub fn check(&mut self) {
+        if self.last_check.elapsed() < Self::CHECK_EVERY {
+            return;
+        }
+        self.last_check = instant::Instant::now();
+        let counts = ResourceCounts::current();
+        if counts.total() <= self.highest {
+            self.growing = 0;
+            return;
+        }
+        self.highest = counts.total();
+        self.growing += 1;
+        if self.growing == Self::LEAK_CHECKS {
+            log::warn!(
+                "{} and still growing after {}s, something's probably not being freed",
+                counts,
+                (Self::CHECK_EVERY * Self::LEAK_CHECKS).as_secs()
+            );
+            self.growing = 0;
+        }
+    }
+}
+
+impl Default for ResourceTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A buffer or texture that's counted in GpuMemory & ResourceCounts until it's dropped.
+// Derefs to the wgpu type.
+#[derive(Debug)]
+pub struct Tracked<T: Resource> {
+    resource: T,
+    size: u64,
+}
+
+impl<T: Resource> Tracked<T> {
+    fn new(resource: T, size: u64) -> Self {
+        T::bytes().fetch_add(size, Ordering::Relaxed);
+        T::count().fetch_add(1, Ordering::Relaxed);
+        Self { resource, size }
+    }
+
+    // The size it's counted as, in bytes
+    pub fn tracked_size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<T: Resource> std::ops::Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.resource
+    }
+}
+
+impl<T: Resource> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        T::bytes().fetch_sub(self.size, Ordering::Relaxed);
+        T::count().fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub trait Resource {
+    // Bytes alive
+    fn bytes() -> &'static AtomicU64;
+    // How many are alive
+    fn count() -> &'static AtomicU64;
+}
+
+impl Resource for wgpu::Buffer {
+    fn bytes() -> &'static AtomicU64 {
+        &BUFFER_BYTES
+    }
+
+    fn count() -> &'static AtomicU64 {
+        &BUFFER_COUNT
+    }
+}
+
+impl Resource for wgpu::Texture {
+    fn bytes() -> &'static AtomicU64 {
+        &TEXTURE_BYTES
+    }
+
+    fn count() -> &'static AtomicU64 {
+        &TEXTURE_COUNT
+    }
+}
+
+// What the textures take up, every mip level of every layer & sample
+fn texture_size(desc: &wgpu::TextureDescriptor) -> u64 {
+    let info = desc.format.describe();
+    let (block_width, block_height) = (info.block_dimensions.0 as u64, info.block_dimensions.1 as u64);
+    let size = desc.size;
+    (0..desc.mip_level_count)
+        .map(|level| {
+            let width = (size.width >> level).max(1) as u64;
+            let height = (size.height >> level).max(1) as u64;
+            // Array layers stay the same down the mip chain, 3d textures get shallower
+            let depth = match desc.dimension {
+                wgpu::TextureDimension::D3 => (size.depth_or_array_layers >> level).max(1),
+                _ => size.depth_or_array_layers,
+            } as u64;
+            width.div_ceil(block_width) * height.div_ceil(block_height) * depth * info.block_size as u64
+        })
+        .sum::<u64>()
+        * desc.sample_count as u64
+}
+
+// The wgpu::Device methods, with the results counted
+pub trait TrackedDevice {
+    fn create_tracked_buffer(&self, desc: &wgpu::BufferDescriptor) -> Tracked<wgpu::Buffer>;
+    fn create_tracked_buffer_init(&self, desc: &wgpu::util::BufferInitDescriptor) -> Tracked<wgpu::Buffer>;
+    fn create_tracked_texture(&self, desc: &wgpu::TextureDescriptor) -> Tracked<wgpu::Texture>;
+}
+
+impl TrackedDevice for wgpu::Device {
+    fn create_tracked_buffer(&self, desc: &wgpu::BufferDescriptor) -> Tracked<wgpu::Buffer> {
+        Tracked::new(self.create_buffer(desc), desc.size)
+    }
+
+    fn create_tracked_buffer_init(&self, desc: &wgpu::util::BufferInitDescriptor) -> Tracked<wgpu::Buffer> {
+        // create_buffer_init pads the contents out to COPY_BUFFER_ALIGNMENT
+        let size = (desc.contents.len() as u64).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        Tracked::new(self.create_buffer_init(desc), size)
+    }
+
+    fn create_tracked_texture(&self, desc: &wgpu::TextureDescriptor) -> Tracked<wgpu::Texture> {
+        Tracked::new(self.create_texture(desc), texture_size(desc))
+    }
+}
@@ -0,0 +1,74 @@
+use std::rc::Rc;
+
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   A grey ramp along the bottom of the screen. The shader writes the same values whatever the
+*   surface format is, so switching between an sRGB and a linear surface (State::set_surface_format)
+*   shows exactly what the format's transfer function does to them.
+*/
+pub struct GradientStrip {
+    pub enabled: bool,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl GradientStrip {
+    // Fraction of the screen's height the strip takes up
+    const HEIGHT: f32 = 0.15;
+
+    // Drawn in the main pass, so it takes that pass' sample count
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Gradient",
+                shader: include_str!("gradient.wgsl"),
+                bind_group_layouts: &[],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                // Drawn on top of everything in the main pass, like the latency flash
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        );
+
+        Self {
+            enabled: false,
+            pipeline,
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, size: winit::dpi::PhysicalSize<u32>) {
+        if !self.enabled {
+            return;
+        }
+        let (width, height) = (size.width as f32, size.height as f32);
+        // The viewport squeezes the full screen triangle into the strip
+        render_pass.set_viewport(0.0, height * (1.0 - Self::HEIGHT), width, height * Self::HEIGHT, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.draw(0..3, 0..1);
+        render_pass.set_viewport(0.0, 0.0, width, height, 0.0, 1.0);
+    }
+}
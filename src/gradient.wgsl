@@ -0,0 +1,28 @@
+// Black to white ramp for comparing surface formats, see gradient.rs. The top half is a smooth
+// ramp, the bottom half is 16 flat steps. On an sRGB surface the steps look evenly spaced, on a
+// linear (Unorm) one the dark end gets crushed together and the bright end washes out.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole viewport
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var value = in.uv.x;
+    if (in.uv.y > 0.5) {
+        value = floor(in.uv.x * 16.0) / 15.0;
+    }
+    return vec4<f32>(vec3<f32>(value), 1.0);
+}
@@ -0,0 +1,194 @@
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, Rotation3};
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::model::{self, Vertex};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   A spinning cube held in the bottom right of the view, like a first person game's weapon. It
+*   lives in view space, so it goes wherever the camera goes, and is drawn with the camera's
+*   projection so it has the same perspective as the scene.
+*
+*   It gets a render pass of its own on top of the finished frame, after the post effects, with
+*   the color & depth attachments loaded or cleared independently (State's clear_color_enabled
+*   & clear_depth_enabled):
+*
+*   color Load,  depth Clear  the usual way. The scene stays, but its depth is gone, so the cube
+*                             only hides behind itself. Walk into a sphere and it stays in view
+*                             instead of sinking into it.
+*   color Load,  depth Load   the scene's depth is still there, the cube disappears into
+*                             whatever's closer than it.
+*   color Clear, ...          the scene's gone, only the cube is left.
+*
+*   The pass draws into the regular depth texture, which holds the cube's depth afterwards
+*   whenever it's cleared. Nothing after it reads the depth, but a depth capture (backslash)
+*   shows the cube rather than the scene.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct HudModelUniform {
+    clip_from_model: [[f32; 4]; 4],
+    // Just the spin, to turn the normals the same way
+    rotation: [[f32; 4]; 4],
+}
+
+pub struct HudModel {
+    pub enabled: bool,
+    cube: model::Mesh,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl HudModel {
+    // Where the cube is held, in view space: right, down & in front of the camera
+    const POSITION: [f32; 3] = [0.35, -0.25, -0.8];
+    const SIZE: f32 = 0.15;
+    // Turns per second
+    const SPIN: f32 = 0.25;
+
+    // Drawn after the post effects, single sampled, into the depth texture
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("HUD Model Buffer"),
+            size: std::mem::size_of::<HudModelUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("hud_model_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("hud_model_bind_group"),
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "HUD Model",
+                shader: include_str!("hud_model.wgsl"),
+                bind_group_layouts: &[&bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[model::ModelVertex::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+
+        Self {
+            enabled: false,
+            cube: model::Mesh::cube(device),
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device, for a new surface format or depth direction
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let mut hud_model = Self::new(device, pipelines, color_format, depth);
+        hud_model.enabled = self.enabled;
+        hud_model
+    }
+
+    // `time` in seconds turns the cube
+    pub fn update(&self, queue: &wgpu::Queue, camera: &camera::Camera, time: f32) {
+        let rotation = cgmath::Matrix4::from(cgmath::Quaternion::from_axis_angle(
+            cgmath::Vector3::new(1.0, 2.0, 0.5).normalize(),
+            cgmath::Rad(time * Self::SPIN * std::f32::consts::TAU),
+        ));
+        let model = cgmath::Matrix4::from_translation(Self::POSITION.into())
+            * rotation
+            * cgmath::Matrix4::from_scale(Self::SIZE);
+        let uniform = HudModelUniform {
+            clip_from_model: (camera.build_projection_matrix() * model).into(),
+            rotation: rotation.into(),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Draws over `output`, loading or clearing it & `depth` as asked
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        color_load: wgpu::LoadOp<wgpu::Color>,
+        depth_load: wgpu::LoadOp<f32>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("HUD Model Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: color_load,
+                    store: true,
+                },
+            })],
+            // Nothing to do with the color's, see the top
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: depth_load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.cube.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.cube.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.cube.num_elements, 0, 0..1);
+    }
+}
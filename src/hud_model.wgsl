@@ -0,0 +1,39 @@
+// The cube held in the corner of the view, see hud_model.rs. It's already in view space, so
+// one matrix takes it all the way to clip space.
+
+struct HudModelUniform {
+    clip_from_model: mat4x4<f32>,
+    rotation: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> hud: HudModelUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    // Which face it is, for its color
+    @location(1) face: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = hud.clip_from_model * vec4<f32>(in.position, 1.0);
+    out.normal = (hud.rotation * vec4<f32>(in.normal, 0.0)).xyz;
+    out.face = abs(in.normal);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Lit from over the camera's shoulder, in view space like everything else here
+    let light = normalize(vec3<f32>(-0.4, 0.6, 0.7));
+    let diffuse = max(dot(normalize(in.normal), light), 0.0);
+    let color = mix(vec3<f32>(0.3), in.face, 0.7);
+    return vec4<f32>(color * (0.25 + 0.75 * diffuse), 1.0);
+}
@@ -0,0 +1,251 @@
+use crate::region_clear::ClearRect;
+
+/*
+*   Debug inspector windows drawn over the frame: the scene (which object's selected & where
+*   it is), the material & lights, the camera, and a graph of the frame times. Each one is
+*   docked down the left or right edge of the screen, stacked in the order they're listed.
+*
+*   It's hand rolled on the bitmap font & the region clears rather than egui, which we don't
+*   have, so it's driven from the keyboard: up & down go through the rows of every window,
+*   left & right change the value on the row (shift for smaller steps), and ctrl + left or
+*   right docks the window the row's in on that side. The inspector only keeps track of the
+*   layout & which row's focused. The values belong to the State, which applies the change
+*   for a row and re-uploads the uniform it lives in straight away.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Window {
+    Scene,
+    Material,
+    Camera,
+    Stats,
+}
+
+impl Window {
+    pub const ALL: [Window; 4] = [Window::Scene, Window::Material, Window::Camera, Window::Stats];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Scene => "scene",
+            Self::Material => "material & lights",
+            Self::Camera => "camera",
+            Self::Stats => "frame times",
+        }
+    }
+
+    // The rows that can be changed, top to bottom
+    pub fn fields(self) -> &'static [Field] {
+        match self {
+            Self::Scene => &[Field::Selected, Field::PositionX, Field::PositionY, Field::PositionZ],
+            Self::Material => &[Field::Material, Field::Ambient, Field::Hemisphere, Field::SunAngle],
+            Self::Camera => &[Field::EyeX, Field::EyeY, Field::EyeZ, Field::Fovy],
+            Self::Stats => &[],
+        }
+    }
+
+    // How many lines of text it has under the title, the graph goes below them
+    fn lines(self) -> u32 {
+        match self {
+            // Cpu & gpu
+            Self::Stats => 2,
+            _ => self.fields().len() as u32,
+        }
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&window| window == self).unwrap()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    Selected,
+    PositionX,
+    PositionY,
+    PositionZ,
+    Material,
+    Ambient,
+    Hemisphere,
+    SunAngle,
+    EyeX,
+    EyeY,
+    EyeZ,
+    Fovy,
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Selected => "object",
+            Self::PositionX => "x",
+            Self::PositionY => "y",
+            Self::PositionZ => "z",
+            Self::Material => "material",
+            Self::Ambient => "ambient",
+            Self::Hemisphere => "hemisphere",
+            Self::SunAngle => "sun",
+            Self::EyeX => "eye x",
+            Self::EyeY => "eye y",
+            Self::EyeZ => "eye z",
+            Self::Fovy => "fov",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dock {
+    Left,
+    Right,
+}
+
+pub struct Inspector {
+    pub enabled: bool,
+    // Where each window is, in Window::ALL order
+    docks: [Dock; 4],
+    // The focused row, counting through the fields of every window in Window::ALL order
+    focus: usize,
+}
+
+impl Inspector {
+    pub const WIDTH: u32 = 280;
+    pub const PADDING: u32 = 4;
+    // From the edge of the screen & between windows
+    const MARGIN: u32 = 8;
+    pub const GRAPH_HEIGHT: u32 = 64;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            docks: [Dock::Left, Dock::Left, Dock::Right, Dock::Right],
+            focus: 0,
+        }
+    }
+
+    fn fields() -> impl Iterator<Item = Field> {
+        Window::ALL.into_iter().flat_map(|window| window.fields().iter().copied())
+    }
+
+    pub fn focused(&self) -> Field {
+        Self::fields().nth(self.focus).unwrap()
+    }
+
+    // Moves the focus `rows` down, or up when it's negative, round from the last row to the first
+    pub fn move_focus(&mut self, rows: isize) {
+        let count = Self::fields().count() as isize;
+        self.focus = (self.focus as isize + rows).rem_euclid(count) as usize;
+    }
+
+    pub fn dock(&self, window: Window) -> Dock {
+        self.docks[window.index()]
+    }
+
+    // Docks the window with the focused row on the `dock` side, at the bottom of what's there
+    pub fn dock_focused(&mut self, dock: Dock) {
+        let focused = self.focused();
+        let window = Window::ALL.into_iter().find(|window| window.fields().contains(&focused)).unwrap();
+        self.docks[window.index()] = dock;
+    }
+
+    // Where each window goes on a screen of `size`, with the text `line_height` pixels apart
+    pub fn layout(&self, size: winit::dpi::PhysicalSize<u32>, line_height: u32) -> Vec<(Window, ClearRect)> {
+        let mut bottoms = [Self::MARGIN; 2];
+        Window::ALL
+            .into_iter()
+            .map(|window| {
+                let dock = self.dock(window);
+                let x = match dock {
+                    Dock::Left => Self::MARGIN,
+                    Dock::Right => size.width.saturating_sub(Self::WIDTH + Self::MARGIN),
+                };
+                let graph = if window == Window::Stats { Self::GRAPH_HEIGHT + Self::PADDING } else { 0 };
+                let height = (1 + window.lines()) * line_height + graph + 2 * Self::PADDING;
+                let y = &mut bottoms[dock as usize];
+                let rect = ClearRect { x, y: *y, width: Self::WIDTH, height };
+                *y += height + Self::MARGIN;
+                (window, rect)
+            })
+            .collect()
+    }
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+*   The bars of a graph of `samples` in `rect`, a pixel wide each & as tall as they are out of
+*   `top`. The newest sample is at the right edge, when there are more than fit the oldest
+*   ones are left out.
+*/
+pub fn graph(samples: &[f32], rect: ClearRect, top: f32) -> Vec<ClearRect> {
+    let shown = &samples[samples.len().saturating_sub(rect.width as usize)..];
+    let left = rect.x + rect.width - shown.len() as u32;
+    shown
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &sample)| {
+            let height = ((sample / top).clamp(0.0, 1.0) * rect.height as f32).round() as u32;
+            (height > 0).then_some(ClearRect {
+                x: left + i as u32,
+                y: rect.y + rect.height - height,
+                width: 1,
+                height,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focus_goes_round() {
+        let mut inspector = Inspector::new();
+        assert_eq!(inspector.focused(), Field::Selected);
+        inspector.move_focus(-1);
+        assert_eq!(inspector.focused(), Field::Fovy);
+        inspector.move_focus(1);
+        assert_eq!(inspector.focused(), Field::Selected);
+        inspector.move_focus(4);
+        assert_eq!(inspector.focused(), Field::Material);
+    }
+
+    #[test]
+    fn windows_stack_on_their_dock() {
+        let mut inspector = Inspector::new();
+        let size = winit::dpi::PhysicalSize::new(1000, 800);
+        // Material is docked under the scene
+        let layout = inspector.layout(size, 16);
+        assert_eq!(layout[0].1.x, 8);
+        assert_eq!(layout[1].1.x, 8);
+        assert_eq!(layout[1].1.y, layout[0].1.y + layout[0].1.height + 8);
+        assert_eq!(layout[2].1.x, 1000 - Inspector::WIDTH - 8);
+        assert_eq!(layout[2].1.y, 8);
+
+        // Moving the scene over leaves the material at the top on the left
+        inspector.dock_focused(Dock::Right);
+        assert_eq!(inspector.dock(Window::Scene), Dock::Right);
+        let layout = inspector.layout(size, 16);
+        assert_eq!((layout[0].1.x, layout[0].1.y), (1000 - Inspector::WIDTH - 8, 8));
+        assert_eq!((layout[1].1.x, layout[1].1.y), (8, 8));
+        assert_eq!(layout[2].1.y, layout[0].1.y + layout[0].1.height + 8);
+    }
+
+    #[test]
+    fn graph_bars() {
+        let rect = ClearRect { x: 10, y: 20, width: 4, height: 10 };
+        let bars = graph(&[5.0, 0.0, 20.0], rect, 10.0);
+        // Right aligned, nothing for the zero & clamped to the top
+        assert_eq!(bars, [
+            ClearRect { x: 11, y: 25, width: 1, height: 5 },
+            ClearRect { x: 13, y: 20, width: 1, height: 10 },
+        ]);
+        // Only the newest that fit
+        let bars = graph(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], rect, 10.0);
+        assert_eq!(bars.len(), 4);
+        assert_eq!(bars[0], ClearRect { x: 10, y: 27, width: 1, height: 3 });
+    }
+}
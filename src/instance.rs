@@ -0,0 +1,160 @@
+use cgmath::prelude::*;
+
+use crate::model::Vertex;
+
+/*
+*   Instancing lets us draw the same mesh many times in one draw call. Each instance gets its
+*   own Transform, which we flatten into an InstanceRaw (a model matrix, plus a normal matrix
+*   for lighting) and store in a vertex buffer that steps once per instance.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub position: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: cgmath::Vector3::zero(),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_position(position: cgmath::Vector3<f32>) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    // `t` of the way from self to other. nlerp is close enough to slerp for the small turns
+    // between two frames or steps.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            rotation: self.rotation.nlerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn to_raw(&self) -> InstanceRaw {
+        // Normals need the inverse transpose of the model matrix. Translation doesn't matter
+        // for directions and rotations are orthogonal, so that boils down to rotation / scale.
+        let inv_scale = cgmath::Matrix3::from_diagonal(cgmath::Vector3::new(
+            1.0 / self.scale.x,
+            1.0 / self.scale.y,
+            1.0 / self.scale.z,
+        ));
+        InstanceRaw {
+            model: self.to_matrix().into(),
+            normal: (cgmath::Matrix3::from(self.rotation) * inv_scale).into(),
+        }
+    }
+}
+
+/*
+*   The transform as of the last two fixed steps (see timestep.rs), for objects that move by
+*   simulation rather than by a function of time. Drawing `current` would make them jump once
+*   per step and stand still in between, which stutters whenever the frame rate isn't the step
+*   rate. Drawing at(alpha) blends to where they were between the two steps instead, at the
+*   price of a step of latency.
+*
+*   Opt-in per object, it keeps two transforms where a plain Transform keeps one.
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InterpolatedTransform {
+    pub previous: Transform,
+    pub current: Transform,
+}
+
+impl InterpolatedTransform {
+    pub fn new(transform: Transform) -> Self {
+        Self {
+            previous: transform,
+            current: transform,
+        }
+    }
+
+    // Call once per fixed step with the newly simulated transform
+    pub fn push(&mut self, transform: Transform) {
+        self.previous = self.current;
+        self.current = transform;
+    }
+
+    pub fn at(&self, alpha: f32) -> Transform {
+        self.previous.lerp(&self.current, alpha)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+}
+
+impl Vertex for InstanceRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // We need to switch from using a step mode of Vertex to Instance
+            // This means that our shaders will only change to use the next
+            // instance when the shader starts processing a new instance
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
+                // for each vec4. We'll have to reassemble the mat4 in the shader.
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    // ModelVertex uses locations 0 to 2 and we keep 3 & 4 free for more vertex
+                    // data later on, so instances start at slot 5
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // The normal matrix is a mat3, so 3 more slots
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
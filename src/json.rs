@@ -0,0 +1,428 @@
+use std::fmt;
+
+/*
+*   Just enough JSON for scene files (see scene.rs) & camera bookmarks. serde_json would be the
+*   usual choice, but serde & serde_json aren't among the crates this builds against (it has to
+*   build offline), and the handful of shapes we store read fine by hand. Objects keep their
+*   keys in the order they were written, which keeps saved files diffable. Numbers are all f64,
+*   which covers every f32 & u32 we store exactly. No \u escapes outside the basic plane, scene
+*   files don't need emoji.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(source: &str) -> Result<Json, String> {
+        let mut parser = Parser { source, position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return Err(parser.error("trailing characters after the value"));
+        }
+        Ok(value)
+    }
+
+    // None if this isn't an object or doesn't have the key
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        // Arrays of plain numbers stay on one line, vectors & colors would be unreadable otherwise
+        let short = |items: &[Json]| items.iter().all(|item| matches!(item, Json::Number(_)));
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if number.is_finite() => write!(f, "{}", number),
+            // JSON has no infinity or NaN
+            Json::Number(_) => write!(f, "null"),
+            Json::String(string) => write_string(f, string),
+            Json::Array(items) if items.is_empty() => write!(f, "[]"),
+            Json::Array(items) if short(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.write(f, indent)?;
+                }
+                write!(f, "]")
+            }
+            Json::Array(items) => {
+                writeln!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{:width$}", "", width = indent + 2)?;
+                    item.write(f, indent + 2)?;
+                    writeln!(f, "{}", if i + 1 < items.len() { "," } else { "" })?;
+                }
+                write!(f, "{:width$}]", "", width = indent)
+            }
+            Json::Object(members) if members.is_empty() => write!(f, "{{}}"),
+            Json::Object(members) => {
+                writeln!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    write!(f, "{:width$}", "", width = indent + 2)?;
+                    write_string(f, key)?;
+                    write!(f, ": ")?;
+                    value.write(f, indent + 2)?;
+                    writeln!(f, "{}", if i + 1 < members.len() { "," } else { "" })?;
+                }
+                write!(f, "{:width$}}}", "", width = indent)
+            }
+        }
+    }
+}
+
+// Pretty printed, two spaces per level
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl From<f32> for Json {
+    fn from(number: f32) -> Self {
+        Json::Number(number as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(string: &str) -> Self {
+        Json::String(string.to_string())
+    }
+}
+
+impl<const N: usize> From<[f32; N]> for Json {
+    fn from(numbers: [f32; N]) -> Self {
+        Json::Array(numbers.iter().map(|&number| number.into()).collect())
+    }
+}
+
+// cgmath's points & vectors as [x, y, z]
+pub fn vector(vector: impl Into<[f32; 3]>) -> Json {
+    vector.into().into()
+}
+
+// Reading the members of an object, with errors that say what was wrong
+
+pub fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+    json.get(key).ok_or_else(|| format!("missing \"{}\"", key))
+}
+
+pub fn number(json: &Json, key: &str) -> Result<f32, String> {
+    field(json, key)?
+        .as_f64()
+        .map(|number| number as f32)
+        .ok_or_else(|| format!("\"{}\" should be a number", key))
+}
+
+pub fn string<'a>(json: &'a Json, key: &str) -> Result<&'a str, String> {
+    field(json, key)?
+        .as_str()
+        .ok_or_else(|| format!("\"{}\" should be a string", key))
+}
+
+pub fn array<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], String> {
+    field(json, key)?
+        .as_array()
+        .ok_or_else(|| format!("\"{}\" should be an array", key))
+}
+
+// An array of exactly N numbers, like a position or color
+pub fn numbers<const N: usize>(json: &Json, key: &str) -> Result<[f32; N], String> {
+    let error = || format!("\"{}\" should be {} numbers", key, N);
+    let items = array(json, key).map_err(|_| error())?;
+    if items.len() != N {
+        return Err(error());
+    }
+    let mut numbers = [0.0; N];
+    for (number, item) in numbers.iter_mut().zip(items) {
+        *number = item.as_f64().ok_or_else(error)? as f32;
+    }
+    Ok(numbers)
+}
+
+fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    // In bytes
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        let line = self.source[..self.position].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    // The keyword has to be next, e.g. "true"
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        if self.source[self.position..].starts_with(keyword) {
+            self.position += keyword.len();
+            Ok(value)
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.position += 1;
+        let mut string = String::new();
+        let mut chars = self.source[self.position..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(string);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let hex = (0..4).filter_map(|_| chars.next().map(|(_, c)| c)).collect::<String>();
+                            // from_str_radix would take a sign as well
+                            Some(&hex)
+                                .filter(|hex| hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error(&format!("bad escape \\u{}", hex)))?
+                        }
+                        Some(c @ ('"' | '\\' | '/')) => c,
+                        _ => return Err(self.error("bad escape in string")),
+                    };
+                    string.push(escaped);
+                }
+                c => string.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.position += 1;
+        }
+        let text = &self.source[start..self.position];
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| self.error(&format!("{:?} isn't a number", text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_nested_values_in_order() {
+        let source = r#"{"z": [1, [2, {"b": null, "a": true}], "x"], "a": {"y": [], "x": {}}, "m": false}"#;
+        let json = Json::parse(source).unwrap();
+        let keys = match &json {
+            Json::Object(members) => members.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(),
+            _ => panic!("not an object"),
+        };
+        assert_eq!(keys, ["z", "a", "m"]);
+        let printed = json.to_string();
+        assert_eq!(Json::parse(&printed).unwrap(), json);
+        assert_eq!(Json::parse(&printed).unwrap().to_string(), printed);
+    }
+
+    #[test]
+    fn string_escapes() {
+        let json = Json::parse(r#""a\"b\\c\/d\n\t\r\b\f\u0041\u00e9""#).unwrap();
+        assert_eq!(json, Json::String("a\"b\\c/d\n\t\r\u{8}\u{c}A\u{e9}".into()));
+        let control = Json::String("\u{1}\"\\\n".into());
+        assert_eq!(control.to_string(), r#""\u0001\"\\\n""#);
+        assert_eq!(Json::parse(&control.to_string()).unwrap(), control);
+    }
+
+    #[test]
+    fn bad_unicode_escapes() {
+        assert_eq!(Json::parse(r#""\u+041""#).unwrap_err(), "line 1: bad escape \\u+041");
+        assert_eq!(Json::parse(r#""\u12""#).unwrap_err(), "line 1: bad escape \\u12\"");
+        assert_eq!(Json::parse(r#""\q""#).unwrap_err(), "line 1: bad escape in string");
+    }
+
+    #[test]
+    fn parses_numbers() {
+        let parse = |source| Json::parse(source).unwrap().as_f64().unwrap();
+        assert_eq!(parse("-0"), 0.0);
+        assert!(parse("-0").is_sign_negative());
+        assert_eq!(parse("1e3"), 1000.0);
+        assert_eq!(parse("1.5E-2"), 0.015);
+        assert_eq!(parse("-12.25"), -12.25);
+        assert_eq!(Json::parse("1-").unwrap_err(), "line 1: \"1-\" isn't a number");
+        // No infinity in JSON
+        assert_eq!(Json::Number(f64::INFINITY).to_string(), "null");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Json::parse("{\"a\": 1}\n  x").unwrap_err(),
+            "line 2: trailing characters after the value"
+        );
+        assert_eq!(Json::parse("[1, \"abc").unwrap_err(), "line 1: unterminated string");
+        assert_eq!(Json::parse("[1 2]").unwrap_err(), "line 1: expected ',' or ']'");
+        assert_eq!(Json::parse("{\"a\" 1}").unwrap_err(), "line 1: expected ':'");
+        assert_eq!(Json::parse("").unwrap_err(), "line 1: unexpected end of file");
+        assert_eq!(Json::parse("nul").unwrap_err(), "line 1: expected a value");
+    }
+
+    #[test]
+    fn field_errors() {
+        let json = Json::parse(r#"{"a": [1, 2], "b": "x"}"#).unwrap();
+        assert_eq!(numbers::<2>(&json, "a").unwrap(), [1.0, 2.0]);
+        assert_eq!(numbers::<3>(&json, "a").unwrap_err(), "\"a\" should be 3 numbers");
+        assert_eq!(number(&json, "b").unwrap_err(), "\"b\" should be a number");
+        assert_eq!(field(&json, "c").unwrap_err(), "missing \"c\"");
+    }
+}
@@ -0,0 +1,99 @@
+use std::rc::Rc;
+
+use instant::{Duration, Instant};
+
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   Input-to-present latency probe for the present mode lesson.
+*
+*   State::mark_input_event() stamps the moment an input arrives. The next time a frame is
+*   presented we log how long it took, which tells us how much latency the present mode
+*   (Fifo vs Mailbox vs Immediate) is adding on top of our own frame time.
+*
+*   Present returning isn't the same as photons leaving the display though, so the frame
+*   that answers the input also flashes the top left quadrant of the screen white. Film the
+*   screen and keyboard with a high speed camera and you can measure the full latency.
+*/
+pub struct LatencyProbe {
+    pub enabled: bool,
+    pending_input: Option<Instant>,
+    flash_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl LatencyProbe {
+    // `sample_count` has to match the main pass' attachments, see antialiasing.rs
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let flash_pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Latency Flash",
+                shader: include_str!("flash.wgsl"),
+                bind_group_layouts: &[],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                // The main pass has a depth attachment so we have to declare one, but the flash
+                // always draws on top and leaves the depth buffer alone.
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        );
+
+        Self {
+            enabled: false,
+            pending_input: None,
+            flash_pipeline,
+        }
+    }
+
+    // Only the first input before a present is kept, that's the one that waited the longest
+    pub fn mark(&mut self) {
+        if self.enabled && self.pending_input.is_none() {
+            self.pending_input = Some(Instant::now());
+        }
+    }
+
+    // Flashes the top left quadrant if there's an input waiting to be answered
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, size: winit::dpi::PhysicalSize<u32>) {
+        if self.pending_input.is_none() {
+            return;
+        }
+        render_pass.set_scissor_rect(0, 0, (size.width / 2).max(1), (size.height / 2).max(1));
+        render_pass.set_pipeline(&self.flash_pipeline);
+        render_pass.draw(0..3, 0..1);
+        render_pass.set_scissor_rect(0, 0, size.width, size.height);
+    }
+
+    // Call right after present(). Logs and returns the latency of the pending input, if any.
+    pub fn presented(&mut self) -> Option<Duration> {
+        let input_time = self.pending_input.take()?;
+        let latency = input_time.elapsed();
+        log::info!(
+            "input-to-present latency: {:.2}ms",
+            latency.as_secs_f64() * 1000.0
+        );
+        Some(latency)
+    }
+}
@@ -0,0 +1,73 @@
+use cgmath::Rotation3;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::Transform;
+use crate::material;
+use crate::model;
+
+/*
+*   A single quad "leaf" drawn with a double sided material (see material.rs), turning slowly
+*   so both of its sides get a turn facing the camera & the sun. With the material's normals
+*   flipped on the back, whichever side the sun's on is the lit one, whichever way round the
+*   leaf is. Shift + N takes the tint off the back to compare the two sides.
+*/
+pub struct Leaf {
+    pub enabled: bool,
+    pub material: material::Material,
+    transform: Transform,
+    mesh: model::Mesh,
+    instance_buffer: Tracked<wgpu::Buffer>,
+}
+
+impl Leaf {
+    // Radians a second around the vertical
+    const SPIN: f32 = 0.6;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        // Above the middle of the grid, between it & the starting camera
+        let transform = Transform {
+            position: cgmath::Vector3::new(0.0, 3.0, 3.0),
+            scale: cgmath::Vector3::new(1.5, 2.0, 1.0),
+            ..Default::default()
+        };
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Leaf Instance Buffer"),
+            contents: bytemuck::cast_slice(&[transform.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            enabled: false,
+            material: material::Material::LEAF,
+            transform,
+            mesh: model::Mesh::quad(device),
+            instance_buffer,
+        }
+    }
+
+    // On a (new) device, shown & tinted as before
+    pub fn recreate(&self, device: &wgpu::Device) -> Self {
+        Self {
+            enabled: self.enabled,
+            material: self.material,
+            ..Self::new(device)
+        }
+    }
+
+    // Turned for `time` seconds into the animation, tilted back a little so the sun catches it
+    pub fn update(&mut self, queue: &wgpu::Queue, time: f32) {
+        self.transform.rotation = cgmath::Quaternion::from_angle_y(cgmath::Rad(time * Self::SPIN))
+            * cgmath::Quaternion::from_angle_x(cgmath::Deg(-20.0));
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&[self.transform.to_raw()]));
+    }
+
+    // With the material's pipeline & a texture at @group(2) already set
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.mesh.num_elements, 0, 0..1);
+    }
+}
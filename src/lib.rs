@@ -1,33 +1,114 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use context::GpuContext;
+use model::Vertex;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-    window::Window,
+    window::{CursorGrabMode, Window, WindowBuilder, WindowId, WindowLevel},
 };
 
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
 
+pub mod alpha_demo;
+pub mod animation;
+pub mod antialiasing;
+pub mod audio;
+pub mod batch;
+pub mod bench;
+pub mod blend;
+pub mod bookmarks;
+pub mod bouncer;
+pub mod camera;
+pub mod color_grading;
+pub mod colormap;
+pub mod config;
+pub mod context;
+pub mod crosshair;
+pub mod debug_lines;
+pub mod debug_normals;
+pub mod depth_capture;
+pub mod dof;
+pub mod fog;
+pub mod foliage;
+pub mod font;
+pub mod frame_pacing;
+pub mod frame_history;
+pub mod frame_step;
+pub mod gizmo;
+pub mod gpu_cull;
+pub mod gpu_memory;
+pub mod gradient;
+pub mod hud_model;
+pub mod inspector;
+pub mod instance;
+pub mod json;
+pub mod latency;
+pub mod leaf;
+pub mod light;
+pub mod loading;
+pub mod lod;
+pub mod logging;
+pub mod material;
+pub mod minimap;
+pub mod mip_bias_demo;
+pub mod model;
+pub mod morph;
+pub mod motion_blur;
+pub mod noise;
+pub mod outline;
+pub mod overdraw;
+pub mod particles;
+pub mod physics;
+pub mod pipeline_cache;
+pub mod portal;
+pub mod post;
+pub mod procedural;
+pub mod recording;
+pub mod region_clear;
+pub mod scene;
+pub mod screen;
+pub mod skinning;
+pub mod sky;
+pub mod splat;
+pub mod ssr;
+pub mod taa;
+pub mod tearing_test;
+pub mod test_pattern;
+pub mod terrain;
+pub mod texture;
+pub mod tilemap;
+pub mod timestep;
+pub mod tonemap;
+pub mod trails;
+pub mod transparency;
+pub mod uniform_ring;
+pub mod velocity;
+pub mod zfighting;
+
 
 #[cfg_attr(target_arch="wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    if let Err(error) = run_with_config(config::RunConfig::default()).await {
+        log::error!("{}", error);
+    }
+}
+
+// Only returns if there's no gpu to run on, see InitError. Otherwise the event loop takes over
+// for good.
+pub async fn run_with_config(config: config::RunConfig) -> Result<(), context::InitError> {
     /*
-    *   It is very important to enable logging via env_logger::init();. When gpu hits any
-    *   error it panics with a generic message, while logging the real error via the log
-    *   crate. This means if you don't include env_logger::init(), wgpu will fail
-    *   silently, leaving you very confused!
+    *   It is very important to enable logging. When gpu hits any error it panics with a
+    *   generic message, while logging the real error via the log crate. This means if you
+    *   don't set up a logger, wgpu will fail silently, leaving you very confused!
     *
-    *   We are then using cfg-if to toggle what logger we are using based on if we're in
-    *   WASM or not.
+    *   logging.rs picks env_logger or console_log depending on if we're in WASM or not.
     */
-    cfg_if::cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-            console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
-        } else {
-            env_logger::init();
-        }
-    }
+    #[cfg(target_arch = "wasm32")]
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    logging::init(config.log_level);
 
     /*
     *   The code below crates a window and keeps it open until the user closes it, or
@@ -35,16 +116,84 @@ pub async fn run() {
     */
 
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    // Even headless we need a window, the adapter has to be compatible with some surface
+    let mut builder = WindowBuilder::new()
+        .with_visible(!config.headless)
+        .with_decorations(config.decorations)
+        .with_window_level(window_level(config.always_on_top));
+    if let Some((width, height)) = config.window_size {
+        builder = builder.with_inner_size(winit::dpi::PhysicalSize::new(width, height));
+    }
+    // The canvas sits wherever the page puts it
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some((x, y)) = config.window_position {
+        builder = builder.with_position(winit::dpi::PhysicalPosition::new(x, y));
+    }
+    let window = builder.build(&event_loop).unwrap();
+
+    /*
+    *   If we're on WASM, we need to add a canvas to the HTML document that we'll host our
+    *   application. This has to happen before the event loop starts since run never returns.
+    */
+    #[cfg(target_arch = "wasm32")] {
+        // Winit prevents sizing with css, so we have to set the size manually
+        // when on the web
+        use winit::dpi::PhysicalSize;
+        window.set_inner_size(PhysicalSize::new(450, 400));
+
+        use winit::platform::web::WindowExtWebSys;
+        web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| {
+                let dst = doc.get_element_by_id("wasm_example")?;
+                let canvas = web_sys::Element::from(window.canvas());
+                dst.append_child(&canvas).ok()?;
+                Some(())
+            })
+            .expect("Couldn't append canvas to document body.");
+    }
+
+    // The instance, device & queue are created once and shared by every window
+    let (ctx, surface) = GpuContext::new(&window, config.backends, config.fallback_adapter).await?;
+    // It only gets replaced after a device loss, which WASM can't recover from
+    #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+    let mut ctx = Rc::new(ctx);
+
+    let mut state = State::new(window, surface, ctx.clone(), &config);
+    // winit can't tell us the window level, so the State has to be told
+    state.always_on_top = config.always_on_top;
+    if let Some(frames) = config.bench_frames {
+        println!("{}", state.benchmark(frames));
+        return Ok(());
+    }
+
+    // Each window gets its own State, we look them up by the id winit gives us in events
+    let mut states: HashMap<WindowId, State> = HashMap::new();
+    states.insert(state.window.id(), state);
 
-    // After we build the window, create a mutable state
-    let mut state = State::new(&window).await;
+    // On native we also open a second "inspector" window drawing with the same device.
+    // The web only has the one canvas.
+    #[cfg(not(target_arch = "wasm32"))] {
+        let inspector = WindowBuilder::new()
+            .with_title("Inspector")
+            .build(&event_loop)
+            .unwrap();
+        let surface = ctx.create_surface(&inspector);
+        states.insert(inspector.id(), State::new(inspector, surface, ctx.clone(), &config));
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => if !state.input(event) {
+        } => {
+            let state = match states.get_mut(&window_id) {
+                Some(state) => state,
+                None => return,
+            };
+            if state.input(event) {
+                return;
+            }
             match event {
                 WindowEvent::CloseRequested
                 | WindowEvent::KeyboardInput {
@@ -55,7 +204,17 @@ pub async fn run() {
                             ..
                     },
                     ..
-                } => *control_flow = ControlFlow::Exit,
+                } => {
+                    // Closing one window leaves the others running, dropping the State
+                    // closes its window. Once they're all gone we're done.
+                    if let Some(mut state) = states.remove(&window_id) {
+                        // Let ffmpeg finish writing the video
+                        state.stop_recording();
+                    }
+                    if states.is_empty() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
 
                 WindowEvent::Resized(physical_size) => {
                     state.resize(*physical_size);
@@ -66,17 +225,64 @@ pub async fn run() {
                     state.resize(**new_inner_size);
                 }
 
-                
                 _ => {}
-
             }
         }
-        Event::RedrawRequested(window_id) if window_id == window.id() => {
-            state.update();
-            match state.render() {
-                Ok(_) => {}
-                // Reconfigure the surface if lost
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+        /*
+        *   WindowEvents are input as the window sees it: the cursor position in the window,
+        *   keys with repeats while held, and only while the window has focus. That's what you
+        *   want for UI and hotkeys.
+        *
+        *   DeviceEvents come straight from the hardware and aren't tied to any window. Mouse
+        *   motion is the raw movement, so it keeps going when the cursor hits the edge of the
+        *   window (or the screen), and keys report a single press & release. That's what you
+        *   want for mouse look and movement. They arrive whether or not we have focus, so each
+        *   State decides for itself whether it cares.
+        */
+        Event::DeviceEvent { ref event, .. } => {
+            for state in states.values_mut() {
+                if state.device_input(event) {
+                    break;
+                }
+            }
+        }
+        Event::RedrawRequested(window_id) if states.contains_key(&window_id) => {
+            // If the gpu went away rebuild everything before trying to draw again. The
+            // device is shared, so every window has to move over to the new one.
+            if ctx.device_lost() {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        // We can't block on the browser's executor, the best we can do is bail
+                        log::error!("gpu device lost, reload the page to recover");
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    } else {
+                        ctx = match recreate_gpu(&mut states) {
+                            Ok(ctx) => ctx,
+                            // The gpu that was there a moment ago is gone for good
+                            Err(error) => {
+                                log::error!("{}", error);
+                                *control_flow = ControlFlow::Exit;
+                                return;
+                            }
+                        };
+                    }
+                }
+            }
+
+            let state = states.get_mut(&window_id).unwrap();
+            // Frozen, the last frame stays up until the next single step
+            if !state.stepper.wants_frame() {
+                return;
+            }
+            // Waits for the frame's turn, on the web it might not be this one's
+            if !state.frame_pacer.begin_frame() {
+                return;
+            }
+            match state.run_frame() {
+                Ok(_) => state.frame_pacer.end_frame(),
+                // Reconfigure the surface if lost or outdated
+                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => state.surface_lost(),
                 // The system is out of memory, we should probably quit
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                 // All other errors
@@ -85,110 +291,419 @@ pub async fn run() {
         }
 
         Event::MainEventsCleared => {
-            // RedrawRequest will only trigger once, unless we manually request it
-            window.request_redraw();
+            // RedrawRequest will only trigger once, unless we manually request it. Frozen
+            // windows only need one when there's a step to run.
+            for state in states.values().filter(|state| state.stepper.wants_frame()) {
+                state.window.request_redraw();
+            }
         }
         _ => {}
     });
+}
 
-    /*
-    *   After our event loop & window, if we're on WASM, we need to add a canvas to
-    *   the HTML document that we'll host our application
-    */
-    #[cfg(target_arch = "wasm32")] {
-        // Winit prevents sizing with css, so we have to set the size manually
-        // when on the web
-        use winit::dpi::PhysicalSize;
-        window.set_inner_size(PhysicalSize::new(450, 400));
+fn window_level(always_on_top: bool) -> WindowLevel {
+    if always_on_top {
+        WindowLevel::AlwaysOnTop
+    } else {
+        WindowLevel::Normal
+    }
+}
 
-        use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
-            .and_then(|win| win.document())
-            .and_then(|doc| {
-                let dst = doc.get_element_by_id("wasm_example")?;
-                let canvas = web_sys::Element::from(window.canvas());
-                dst.append_child(&canvas).ok()?;
-                Some(())
-            })
-            .expect("Couldn't append canvas to document body.");
+// Replaces the lost GpuContext with a new one and moves every window's State over to it
+#[cfg(not(target_arch = "wasm32"))]
+fn recreate_gpu(states: &mut HashMap<WindowId, State>) -> Result<Rc<GpuContext>, context::InitError> {
+    log::warn!("Recreating the gpu device and all gpu resources");
+    let first_id = *states.keys().next().unwrap();
+    let first = &states[&first_id];
+    let (ctx, surface) =
+        pollster::block_on(GpuContext::new(&first.window, first.ctx.backends, first.ctx.fallback_adapter))?;
+    let ctx = Rc::new(ctx);
+
+    // The first window already got its surface from GpuContext::new
+    let mut first_surface = Some(surface);
+    for (id, state) in states.iter_mut() {
+        let surface = if *id == first_id {
+            first_surface.take().unwrap()
+        } else {
+            ctx.create_surface(&state.window)
+        };
+        state.recreate(surface, ctx.clone());
     }
+    Ok(ctx)
+}
+
+// Scene resources still loading in the background, with the loading screen showing meanwhile
+struct SceneLoad {
+    progress: loading::LoadProgress,
+    meshes: std::sync::mpsc::Receiver<(usize, model::MeshData)>,
+    // Filled in by job index as the meshes arrive
+    loaded: Vec<Option<model::MeshData>>,
+    // Where each mesh is loaded from, in job order. Empty for the spheres' high & low meshes.
+    model_paths: Vec<std::path::PathBuf>,
+    screen: loading::LoadingScreen,
+}
+
+// Which vertex shader a scene pipeline runs. They all share fs_main and the bind groups, except
+// that group 2 holds whatever else the vertex shader reads next to the texture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SceneVertex {
+    Static,
+    Skinned,
+    Morphed,
 }
 
+/*
+*   One State per window. The device & queue live in the shared GpuContext, but each window
+*   has its own surface, surface config, depth texture, camera, and so on.
+*/
 struct State {
+    ctx: Rc<GpuContext>,
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    // What the surface can do on this adapter, read when it's made (and again on a new device)
+    surface_caps: wgpu::SurfaceCapabilities,
+    // From RunConfig::premultiplied_alpha, kept for picking the alpha mode on a new device
+    premultiplied_alpha: bool,
     size: winit::dpi::PhysicalSize<u32>,
+    // Surface resolution uniform shared by every pipeline at @group(0)
+    screen: screen::Screen,
+    // Scene pipelines for each set of material features in use, see material.rs
+    render_pipelines: HashMap<material::ShaderDefines, Rc<wgpu::RenderPipeline>>,
+    // What the spheres are drawn with, swapped between textured & untextured with F1. The
+    // terrain stays textured.
+    material: material::Material,
+    camera: camera::Camera,
+    camera_binding: camera::CameraBinding,
+    // Free flight controls, active while the cursor is captured (C)
+    fly_camera: camera::FlyCamera,
+    cursor_captured: bool,
+    // What the cursor's been set to, by capturing or on its own with shift + C (grab) and
+    // ctrl + C (visibility). winit can't be asked, so we keep track.
+    cursor_grab: CursorGrabMode,
+    cursor_visible: bool,
+    // Views saved & recalled with the numpad, and the glide to the one last recalled
+    camera_bookmarks: bookmarks::CameraBookmarks,
+    camera_tween: Option<bookmarks::CameraTween>,
+    // Held down right now, see modifiers()
+    modifiers: ModifiersState,
+    // Where the cursor is in the window, None once it's left
+    cursor_position: Option<winit::dpi::PhysicalPosition<f64>>,
+    // Where the cursor points on the ground (FOLLOWER_PLANE_Y), kept up to date as the cursor
+    // and the camera move. The follower sphere goes there.
+    mouse_world: Option<cgmath::Vector3<f32>>,
+    depth_texture: texture::Texture,
+    // The sun, the ambient light & the other lights. The sun turns with U, X toggles a flashlight,
+    // Q & E darken & brighten the ambient light and J switches it between flat & hemisphere.
+    lights: light::Lights,
+    // What the meshes are textured with, generated by a compute shader
+    procedural: procedural::ProceduralTexture,
+    // Missing where compute shaders can't write textures
+    noise: Option<noise::NoiseGenerator>,
+    // Scrolling noise clouds replace the procedural texture, toggled with K
+    clouds: bool,
+    // High & low detail versions of the mesh, drawn once per visible instance
+    model: lod::LodModel,
+    // Everything that can be shown as the model, cycled with shift + [ & ]. Empty until it's
+    // all loaded.
+    models: Vec<model::Model>,
+    // Which one is shown. Where it came from gets saved with the scene (/ & ').
+    current_model: usize,
+    // What the model's .mtl files point at, in the right format for each slot. A diffuse map
+    // gets drawn on the model in place of the procedural texture.
+    model_textures: material::MaterialTextures,
+    model_bind_group: Option<wgpu::BindGroup>,
+    instances: Vec<instance::Transform>,
+    // Rolling hills under the spheres, built the first time they're shown (O)
+    terrain: Option<terrain::Terrain>,
+    // Textured like the rest, but built for however the terrain's chunks are put together,
+    // cycled with ctrl + O
+    terrain_pipeline: Rc<wgpu::RenderPipeline>,
+    terrain_indices: terrain::TerrainIndices,
+    // What the terrain's colored with. The colormap's cycled with ctrl + Y, its range fits the
+    // hills when they're built and shift + Y narrows it to the upper half. Ctrl + I splats
+    // grass, rock & snow over them, ctrl + M sets the splatting's anisotropic filtering.
+    terrain_shading: terrain::TerrainShading,
+    colormap: colormap::ColormapLookup,
+    splat: splat::TerrainSplat,
+    // A TV screen behind the grid showing the scene from a camera of its own, toggled with
+    // ctrl + P. Shift + P moves its camera to where the main one is.
+    portal: portal::Portal,
+    // A spinning quad with a double sided material, toggled with ctrl + N. Shift + N tints its
+    // back or not.
+    leaf: leaf::Leaf,
+    // A row of bars pulsing to the frequency bands of a synthetic beat, toggled with ctrl + U
+    audio: audio::AudioVisualizer,
+    // The textured scene pipeline with the bands stretching the vertices, built when it's shown
+    audio_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    // A swaying tentacle, skinned on the gpu and toggled with B. Missing without vertex storage.
+    skinned: Option<skinning::SkinnedModel>,
+    // The scene pipeline with the skinning vertex shader
+    skinned_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    // A sphere morphing into a cube & an egg, toggled with Z. Also needs vertex storage.
+    morphed: Option<morph::MorphedModel>,
+    morphed_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    // Drives the last instance around the others
+    animation: animation::AnimationTrack,
+    // Moved by the fixed steps, drawn as the second to last instance
+    bouncer: bouncer::Bouncer,
+    // Whether the bouncer is drawn between its last two steps or just at the last one, F8
+    interpolate: bool,
+    // Cubes bouncing off each other above the grid, F9
+    physics: physics::PhysicsDemo,
+    // Simulation time as of the last fixed step
+    animation_time: f32,
+    // The time the current frame is drawn at, between the last two fixed steps
+    frame_time: f32,
+    // Multiplies the time given to animations, changed with +/-
+    time_scale: f32,
+    // Splits the real time between frames into fixed simulation steps
+    timestep: timestep::FixedTimestep,
+    last_render_time: instant::Instant,
+    // Holds frames back to present them evenly, when it's on (shift + F8)
+    frame_pacer: frame_pacing::FramePacer,
+    // Freezes everything on the frame that's up with ctrl + F, ctrl + . runs one more
+    stepper: frame_step::FrameStepper,
+    // What shift + F8 turns pacing on to, --frame-pacing or 60fps
+    paced: frame_pacing::FramePacing,
+    // Set until everything has loaded
+    loading: Option<SceneLoad>,
+    // Hedgehog debug view, toggled with N
+    normal_lines: debug_normals::NormalLines,
+    // Culling keeps using this frustum while it's set, so you can fly out and see what gets
+    // culled. Frozen & thawed with 0, the lines show where it is.
+    frozen_frustum: Option<camera::Frustum>,
+    frustum_lines: Option<debug_normals::FrustumLines>,
+    // Input-to-present latency measurement, toggled with L
+    latency: latency::LatencyProbe,
+    // Calibration pattern over the whole screen, toggled with T
+    test_pattern: test_pattern::TestPattern,
+    // Bar racing across the screen to show tearing, toggled with F12
+    tearing_test: tearing_test::TearingTest,
+    // Shown while the cursor is captured for the fly camera
+    crosshair: crosshair::Crosshair,
+    // Straight & premultiplied alpha side by side, toggled with F6
+    alpha_demo: alpha_demo::AlphaDemo,
+    // A mipmapped floor with and without a mip level bias, toggled with ctrl + F7. Comma & period
+    // change the bias, with shift the lowest level, ahead of the normal lines while it's shown.
+    mip_bias_demo: mip_bias_demo::MipBiasDemo,
+    // A scrolling 2d tilemap over the scene, toggled with F11
+    tilemap: tilemap::TileMap,
+    // Text over everything else, whatever's drawn with it in prepare_frame
+    font: font::BitmapFont,
+    // Frame times, the camera & so on in the top left corner, toggled with shift + F10
+    hud: bool,
+    // Parts of the finished frame cleared to a color, see clear_region
+    region_clear: region_clear::RegionClear,
+    // A panel cleared behind the HUD so it reads over anything, toggled with ctrl + F10
+    hud_panel: bool,
+    // Windows for the scene, material, camera & frame times that edit them live, toggled with
+    // ctrl + `. See inspector.rs for the keys.
+    inspector: inspector::Inspector,
+    // A cube held in the corner of the view over the finished frame, toggled with shift + V
+    hud_model: hud_model::HudModel,
+    // Whether its pass clears the color & depth it draws over, instead of loading them. Ctrl + B
+    // & ctrl + V, see hud_model.rs for what each pair does.
+    clear_color_enabled: bool,
+    clear_depth_enabled: bool,
+    // Top down map in a corner, toggled with Insert. Delete moves it to the next corner.
+    minimap: minimap::Minimap,
+    // Grey ramp for comparing surface formats, shown while cycling through them with Y
+    gradient: gradient::GradientStrip,
+    // Gradient sky behind everything instead of the clear color, toggled with 3
+    sky: sky::Sky,
+    // Outline around the animated instance, toggled with 7, 8 switches between the methods
+    outline: outline::Outline,
+    // How many times every pixel gets shaded, as a heatmap in place of the scene. Shift + O.
+    overdraw: overdraw::Overdraw,
+    // Screen space reflections off a glossy floor, toggled with shift + K. Ctrl + K cycles the
+    // step count & ctrl + L the thickness.
+    ssr: ssr::Ssr,
+    // Anti-aliasing technique, cycled with M
+    aa_mode: antialiasing::AaMode,
+    // Multisampled attachments for the main pass, set while aa_mode is Msaa
+    msaa: Option<antialiasing::Msaa>,
+    // Whether the multisampled color gets stored and/or resolved by the pass, cycled with ;
+    msaa_attachment: antialiasing::MsaaAttachment,
+    // Off leaves the last frame in place & draws over it, for trails. Toggled with Tab.
+    clear_each_frame: bool,
+    trails: trails::Trails,
+    // Translucent panels over the scene, toggled with numpad *, numpad / switches between
+    // weighted blended OIT & sorting
+    transparency: transparency::Transparency,
+    // A fountain of sparks over the scene, toggled with shift + F6. Ctrl + F6 cycles its blend mode.
+    particles: particles::Particles,
+    // Pairs of squares that fight over the same depth unless it's reversed, toggled with
+    // ctrl + Z. Shift + Z reverses the depth, see camera::DepthDirection.
+    zfighting: zfighting::ZFightingDemo,
+    // A bush of cutout leaves, toggled with shift + G. Ctrl + G switches between the alpha test
+    // & alpha to coverage, shift + H cycles the sample mask. See foliage::Cutout.
+    foliage: foliage::Foliage,
+    // Translate & rotate handles on the selected object, toggled with shift + T. Ctrl + T
+    // switches between the two.
+    gizmo: gizmo::Gizmo,
+    // Lines & boxes queued with debug_line & debug_box, drawn for one frame. Shift + J
+    // switches their depth test.
+    debug_lines: debug_lines::DebugLines,
+    // Queues the world's axes, the physics boxes, the selected object's box & where the TV
+    // camera looks every frame, toggled with ctrl + J
+    debug_shapes: bool,
+    // Which of the instances the gizmo is on, picked by clicking it
+    selected: Option<usize>,
+    // Offscreen targets for the post processing effects below
+    post_chain: post::PostChain,
+    // The format asked for with --offscreen-format (or --hdr), if the adapter can't do it the
+    // chain uses the surface's. Picked again whenever the chain is rebuilt.
+    offscreen_format: Option<wgpu::TextureFormat>,
+    // How the chain's last pass tonemaps HDR targets, cycled with ctrl + H, see tonemap.rs
+    tonemap_curve: tonemap::TonemapCurve,
+    // Depth of field, toggled with F
+    dof: dof::DepthOfField,
+    // Screen space motion since the last frame, missing where half float targets aren't supported
+    velocity: Option<velocity::VelocityBuffer>,
+    // Toggled with 1, 2 doubles the intensity. Set whenever velocity is.
+    motion_blur: Option<motion_blur::MotionBlur>,
+    fxaa: antialiasing::Fxaa,
+    // Off until one of its settings is changed with Numpad0/NumpadDecimal
+    color_grading: color_grading::ColorGrading,
+    // On while aa_mode is Taa, also only there with a velocity buffer to reproject with
+    taa: Option<taa::Taa>,
+    // Video capture, toggled with R
+    recorder: Option<recording::Recorder>,
+    // Consecutive frames where the surface reported Lost even after being reconfigured
+    surface_lost_frames: u32,
+    // What the device can do, for features that need more than the bare minimum. Set again
+    // whenever the device is recreated, a new device can come from a different adapter.
+    limits: wgpu::Limits,
+    // The optional features the device got, see context::OPTIONAL_FEATURES
+    features: wgpu::Features,
+    // Line shows the scene as a wireframe, toggled with F5 when the device can
+    polygon_mode: wgpu::PolygonMode,
+    // How long the last few hundred frames took, in milliseconds. The cpu side is render()
+    // from start to present, the gpu side only has the frames the timer could catch (see
+    // bench::FrameGpuTimer) and stays empty without timestamp queries. F10 logs a summary.
+    cpu_frame_times: frame_history::FrameHistory,
+    gpu_frame_times: frame_history::FrameHistory,
+    gpu_frame_timer: Option<bench::FrameGpuTimer>,
+    // How batched meshes (the terrain's chunks) get drawn, cycled with shift + I. How long
+    // recording those draws took on the cpu, in milliseconds, goes in batch_draw_times.
+    batch_draw: batch::BatchDrawMode,
+    batch_draw_times: frame_history::FrameHistory,
+    // Logs the gpu frame time & memory every so often, with --stats
+    stats_log: Option<bench::StatsLog>,
+    // Warns when the number of buffers & textures keeps going up
+    resource_tracker: gpu_memory::ResourceTracker,
+    // Whether the window stays above the others, toggled with F4
+    always_on_top: bool,
+    // Fields are dropped in order, the window has to outlive its surface
+    window: Window,
 }
 
 impl State {
-    // Creating some of the wgpu types requires async code
-    async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
+    const MAX_SURFACE_LOST_FRAMES: u32 = 3;
+    // 0 pauses, negative plays the animations backwards
+    const TIME_SCALE_RANGE: (f32, f32) = (-4.0, 4.0);
+    const TIME_SCALE_STEP: f32 = 0.25;
+    const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+    };
+    const INSPECTOR_GRAPH_COLOR: wgpu::Color = wgpu::Color {
+        r: 0.3,
+        g: 0.8,
+        b: 0.4,
+        a: 1.0,
+    };
+    // About 4 seconds at 60fps
+    const FRAME_HISTORY_LEN: usize = 240;
+    // How long going back to a camera bookmark takes
+    const BOOKMARK_TWEEN: instant::Duration = instant::Duration::from_millis(600);
+    // Room around the HUD's text in its panel, in pixels, and what it's cleared to
+    const HUD_PANEL_PADDING: u32 = 4;
+    const HUD_PANEL_COLOR: wgpu::Color = wgpu::Color {
+        r: 0.02,
+        g: 0.02,
+        b: 0.03,
+        a: 1.0,
+    };
+    // The follower, the bouncing ball & the hopping sphere, in that order at the end of the
+    // instances. They move on their own and aren't part of a saved scene.
+    const MOVING_INSTANCES: usize = 3;
+    // The height of the plane the follower slides around on, the one the grid sits on
+    const FOLLOWER_PLANE_Y: f32 = 0.0;
 
-        // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+    /*
+    *   The surface format to start out with. The surface lists its formats in the order it
+    *   prefers them, but that doesn't always put an sRGB one first (some Vulkan drivers lead with
+    *   Bgra8Unorm), and the shaders write linear colors for an sRGB surface to encode. So it's
+    *   the first sRGB format, or the first of all when there isn't one (like on the web).
+    */
+    fn preferred_surface_format(caps: &wgpu::SurfaceCapabilities) -> wgpu::TextureFormat {
+        caps.formats.iter().copied().find(|format| format.describe().srgb).unwrap_or(caps.formats[0])
+    }
 
-        // The surface is the part of the window we draw to.
-        let surface = unsafe {
-            instance.create_surface(window)
+    // Picks the surface format & alpha mode out of what this surface supports on the adapter,
+    // see RunConfig::premultiplied_alpha
+    fn surface_format_and_alpha_mode(
+        caps: &wgpu::SurfaceCapabilities,
+        premultiplied_alpha: bool,
+    ) -> (wgpu::TextureFormat, wgpu::CompositeAlphaMode) {
+        let preferred = if premultiplied_alpha {
+            wgpu::CompositeAlphaMode::PreMultiplied
+        } else {
+            wgpu::CompositeAlphaMode::Opaque
         };
+        // Not every surface supports every mode, fall back to whatever it lists first
+        // (Auto is always allowed and lets wgpu pick between Opaque & Inherit)
+        let alpha_mode = if caps.alpha_modes.contains(&preferred) {
+            preferred
+        } else {
+            log::warn!("{:?} alpha isn't supported by this surface, using {:?}", preferred, caps.alpha_modes[0]);
+            caps.alpha_modes[0]
+        };
+        (Self::preferred_surface_format(caps), alpha_mode)
+    }
 
-        // The adapter is the handle to our graphics card.
-        // We can use this to get information about the graphics card
-        // including its name and what backend the adapter uses. We will
-        // use this to create our Device & Queue later.
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                // power_preference has two variants, LowPower, and HighPerformance.
-                power_preference: wgpu::PowerPreference::default(),
-                // compatible_surface field tells wgpu to find an adapter that can present
-                // to the supplied surface.
-                compatible_surface: Some(&surface),
-                // force_fallback_adapter forces wgpu to pick an adapter that will work on
-                // all hardware. This usually means that the rendering backend will use a
-                // "software" system, instead of hardware such as a GPU.
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
-
-        // The options passed to request_adapter aren't guaranteed to work for all devices,
-        // but will work for most of them. If wgpu can''t find an adapter with the required
-        // permissions, request_adapter will return None. If you want to get all the adapters
-        // for a particular backend you can use enumerate_adapters. This will give you an
-        // iterator that you cna loop over to check if one of the adapters work for your needs.
-        //
-        // Another thing to note is that Adapters are locked to a specific backend. If you are
-        // on Windows and have 2 graphics cards you will have at least 4 adapters available to use.
-        
-        /*
-            let adapter = instance
-                .enumerate_adapters(wgpu::Backends::all())
-                .filter(|adapter| {
-                    // Check if this adapter supports our surface
-                    surface.get_preferred_format(&adapter).is_some()
-                })
-                .next()
-                .unwrap()
-        */
+    fn log_surface_capabilities(caps: &wgpu::SurfaceCapabilities) {
+        log::info!(
+            "surface formats: {:?}, present modes: {:?}, alpha modes: {:?}",
+            caps.formats,
+            caps.present_modes,
+            caps.alpha_modes
+        );
+    }
 
-        // Use the adapter to create the device and queue.
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
-                // WebGL doesn't support all of wgpu's features, so if we're building for
-                // the web we'll have to disable some.
-                limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
-                },
-                label: None,
-            },
-            None, // Trace path
-        ).await.unwrap();
+    // Only devices with timestamp queries can time frames on the gpu
+    fn frame_gpu_timer(ctx: &GpuContext) -> Option<bench::FrameGpuTimer> {
+        ctx.device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| bench::FrameGpuTimer::new(ctx))
+    }
+
+    // Fifo is always there to fall back on
+    fn present_mode(caps: &wgpu::SurfaceCapabilities, wanted: wgpu::PresentMode) -> wgpu::PresentMode {
+        // The Auto modes pick from the supported ones themselves
+        let auto = matches!(wanted, wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync);
+        if auto || caps.present_modes.contains(&wanted) {
+            wanted
+        } else {
+            log::warn!("{:?} isn't supported by this surface, using Fifo", wanted);
+            wgpu::PresentMode::Fifo
+        }
+    }
+
+    fn new(window: Window, surface: wgpu::Surface, ctx: Rc<GpuContext>, run_config: &config::RunConfig) -> Self {
+        let size = window.inner_size();
+        let device = &ctx.device;
+        let limits = device.limits();
+        let features = device.features();
+        let gpu_frame_timer = Self::frame_gpu_timer(&ctx);
+        let batch_draw = Self::pick_batch_draw(&ctx, run_config.batch_draw);
+        let surface_caps = surface.get_capabilities(&ctx.adapter);
+        Self::log_surface_capabilities(&surface_caps);
+        let (format, alpha_mode) = Self::surface_format_and_alpha_mode(&surface_caps, run_config.premultiplied_alpha);
 
         // Surface config
         let config = wgpu::SurfaceConfiguration {
@@ -196,9 +711,9 @@ impl State {
             // specifices that the textures will be used to write to the screen.
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             // Format describes how SurfaceTexture(s) will be stored on the gpu. We use
-            // get_preferred_format(&adapter) to figure out the best format to use based on the
-            // display you're using.
-            format: surface.get_supported_formats(&adapter)[0],
+            // get_capabilities(&adapter) to figure out which formats the display supports and
+            // take the first sRGB one, see preferred_surface_format.
+            format,
             // Width & height are the width & height in pixels of a SurfaceTexture. This should
             // usually be the width and height of the window. Don't set this to 0, this WILL crash lol.
             width: size.width,
@@ -207,89 +722,4169 @@ impl State {
             // the display. The option we picked, PresentMode::Fifo, will cap the display rate at the
             // display's framerate (essentially VSync). This mode is guaranteed to be supoorted on all platforms.
 
-            // If we want to let our users pick what PresentMode they use, you can use Surface::get_supported_modes()
-            // to get a list of all the PresentModes the surface supports
-            // let modes = surface.get_supported_modes(&adapter);
-            present_mode: wgpu::PresentMode::Fifo,
+            // Users can pick another one with --present-mode, the capabilities list which
+            // PresentModes the surface supports to check it against
+            present_mode: Self::present_mode(&surface_caps, run_config.present_mode),
+            // How the surface's alpha is composited with whatever is behind the window/canvas
+            alpha_mode,
+            // Other formats we might want to create views of the surface texture in
+            view_formats: vec![],
+        };
+        surface.configure(device, &config);
+
+        let screen = screen::Screen::new(device, size);
+
+        let camera = camera::Camera {
+            // position the camera up and back, looking at the origin
+            eye: (0.0, 5.0, 10.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: config.width as f32 / config.height as f32,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            depth: run_config.depth,
+        };
+        let camera_binding = camera::CameraBinding::new(device, &camera, uniform_ring::UniformRing::DEFAULT_SLOTS);
+        let fly_camera = camera::FlyCamera::new(&camera, 4.0, 0.003);
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let camera_bookmarks = bookmarks::CameraBookmarks::default();
+            } else {
+                let camera_bookmarks =
+                    bookmarks::CameraBookmarks::load(std::path::Path::new(bookmarks::CameraBookmarks::FILE))
+                        .unwrap_or_else(|e| {
+                            log::warn!("{}, starting without camera bookmarks", e);
+                            Default::default()
+                        });
+            }
+        }
+
+        let depth_texture = texture::Texture::create_depth_texture(device, &config, "depth_texture");
+
+        // A grid of spheres plus one that hops around them
+        const GRID_SIZE: i32 = 10;
+        const GRID_SPACING: f32 = 3.0;
+        let mut instances = (0..GRID_SIZE)
+            .flat_map(|z| (0..GRID_SIZE).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let offset = (GRID_SIZE - 1) as f32 * 0.5;
+                instance::Transform::from_position(cgmath::Vector3::new(
+                    (x as f32 - offset) * GRID_SPACING,
+                    0.0,
+                    (z as f32 - offset) * GRID_SPACING,
+                ))
+            })
+            .collect::<Vec<_>>();
+        // Then the sphere following the cursor (starting in the middle of the grid, in a gap),
+        // the bouncing ball second to last, and the hopping sphere has to stay last
+        instances.push(instance::Transform::from_position(cgmath::Vector3::new(0.0, Self::FOLLOWER_PLANE_Y, 0.0)));
+        let bouncer = bouncer::Bouncer::new();
+        instances.push(bouncer.transform.current);
+        let animation = animation::AnimationTrack::demo_orbit(4.0, 1.5);
+        instances.push(animation.sample(0.0));
+        let physics = physics::PhysicsDemo::new(device);
+
+        // The spheres get built in the background, cubes stand in for them until they're done
+        let model = lod::LodModel::new(device, model::Mesh::cube(device), model::Mesh::cube(device), instances.len());
+        let progress = loading::LoadProgress::new();
+        // One job per model from the command line, they have no low detail version
+        let jobs: Vec<loading::MeshJob> = if run_config.model_paths.is_empty() {
+            vec![
+                Box::new(|| model::Mesh::uv_sphere_data("Sphere High", 32, 16)),
+                Box::new(|| model::Mesh::uv_sphere_data("Sphere Low", 8, 6)),
+            ]
+        } else {
+            run_config
+                .model_paths
+                .iter()
+                .cloned()
+                .map(|path| -> loading::MeshJob {
+                    Box::new(move || {
+                        model::MeshData::load_obj(&path).unwrap_or_else(|e| {
+                            log::error!("{}, showing spheres instead", e);
+                            model::Mesh::uv_sphere_data("Sphere High", 32, 16)
+                        })
+                    })
+                })
+                .collect()
+        };
+        let loading = SceneLoad {
+            loaded: jobs.iter().map(|_| None).collect(),
+            meshes: loading::spawn_mesh_jobs(jobs, &progress),
+            model_paths: run_config.model_paths.clone(),
+            progress,
+            screen: loading::LoadingScreen::new(device, &ctx.pipelines, &screen.bind_group_layout, config.format),
+        };
+
+        let offscreen_format = if run_config.hdr {
+            Some(tonemap::Tonemap::HDR_FORMAT)
+        } else {
+            run_config.offscreen_format
+        };
+        let tonemap_curve = tonemap::TonemapCurve::default();
+        // The main pass draws into the post chain's targets, so everything in it follows their format
+        let post_chain = post::PostChain::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            &config,
+            post::PostChain::pick_format(&ctx.adapter, &config, offscreen_format),
+            tonemap_curve,
+        );
+        // No anti-aliasing until it's switched on, so everything starts out single sampled.
+        // Except with --hdr, resolving the samples before tonemapping is what it's there for.
+        let aa_mode = if run_config.hdr && tonemap::Tonemap::is_supported(&ctx.adapter, 4) {
+            antialiasing::AaMode::Msaa(4)
+        } else {
+            if run_config.hdr {
+                log::warn!(
+                    "--hdr needs {:?} drawn with 4x MSAA, which this adapter can't do (sample counts it can: {:?})",
+                    tonemap::Tonemap::HDR_FORMAT,
+                    tonemap::Tonemap::supported_sample_counts(&ctx.adapter)
+                );
+            }
+            antialiasing::AaMode::None
         };
-        surface.configure(&device, &config);
+        let sample_count = aa_mode.sample_count();
+        let msaa = (sample_count > 1).then(|| {
+            antialiasing::Msaa::new(device, &ctx.pipelines, &config, post_chain.format(), sample_count, camera.depth)
+        });
+        let lights = light::Lights::demo(device);
+        let procedural = procedural::ProceduralTexture::new(device, &ctx.adapter, &ctx.queue);
+        // Loaded with the model, once it's here
+        let model_textures = material::MaterialTextures::default();
+        let model_bind_group =
+            model_textures.bind_group(device, &procedural.bind_group_layout, material::TextureSlot::Diffuse);
+        let noise = noise::NoiseGenerator::is_supported(&ctx.adapter).then(|| noise::NoiseGenerator::new(device));
+        let material = material::Material::TEXTURED;
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            &ctx.pipelines,
+            post_chain.format(),
+            sample_count,
+            camera.depth,
+            wgpu::PolygonMode::Fill,
+            batch::MeshTopology::TriangleList,
+            &screen,
+            &camera_binding,
+            &procedural.bind_group_layout,
+            &lights,
+            SceneVertex::Static,
+            &material.defines(),
+        );
+        // There's no terrain yet, and it starts out as a triangle list
+        let terrain_pipeline = render_pipeline.clone();
+        // The range gets fitted to the terrain once there is one
+        let colormap = colormap::ColormapLookup::new(device, &ctx.queue, colormap::Colormap::Viridis, (0.0, 1.0));
+        let splat = splat::TerrainSplat::new(device, &ctx.queue, splat::SplatSettings::default());
+        let render_pipelines = HashMap::from([(material.defines(), render_pipeline)]);
+        let leaf = leaf::Leaf::new(device);
+        let audio = audio::AudioVisualizer::new(device, &procedural.texture);
+        let portal = portal::Portal::new(
+            device,
+            post_chain.format(),
+            sample_count,
+            &procedural.bind_group_layout,
+            camera.depth,
+            instances.len(),
+        );
+        // Between two rows & columns of the grid, out of the hopping sphere's way
+        let skinned = skinning::SkinnedModel::is_supported(&ctx.adapter, &limits).then(|| {
+            let transform = instance::Transform::from_position(cgmath::Vector3::new(0.0, -0.5, -6.0));
+            skinning::SkinnedModel::demo_tentacle(device, &procedural.texture, transform)
+        });
+        let skinned_pipeline = skinned.as_ref().map(|skinned| {
+            Self::create_render_pipeline(
+                device,
+                &ctx.pipelines,
+                post_chain.format(),
+                sample_count,
+                camera.depth,
+                wgpu::PolygonMode::Fill,
+                batch::MeshTopology::TriangleList,
+                &screen,
+                &camera_binding,
+                &skinned.bind_group_layout,
+                &lights,
+                SceneVertex::Skinned,
+                &material::Material::TEXTURED.defines(),
+            )
+        });
+        // Between the next two rows on the other side
+        let morphed = morph::MorphedModel::is_supported(&ctx.adapter, &limits).then(|| {
+            let transform = instance::Transform::from_position(cgmath::Vector3::new(0.0, 0.0, 6.0));
+            morph::MorphedModel::demo_blob(device, &procedural.texture, transform)
+        });
+        let morphed_pipeline = morphed.as_ref().map(|morphed| {
+            Self::create_render_pipeline(
+                device,
+                &ctx.pipelines,
+                post_chain.format(),
+                sample_count,
+                camera.depth,
+                wgpu::PolygonMode::Fill,
+                batch::MeshTopology::TriangleList,
+                &screen,
+                &camera_binding,
+                &morphed.bind_group_layout,
+                &lights,
+                SceneVertex::Morphed,
+                &material::Material::TEXTURED.defines(),
+            )
+        });
+
+        let normal_lines = debug_normals::NormalLines::new(
+            device,
+            &ctx.pipelines,
+            post_chain.format(),
+            sample_count,
+            camera.depth,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            &[model.high_detail()],
+        );
+
+        let latency = latency::LatencyProbe::new(device, &ctx.pipelines, post_chain.format(), sample_count);
+        let gradient = gradient::GradientStrip::new(device, &ctx.pipelines, post_chain.format(), sample_count);
+        let sky = sky::Sky::new(device, &ctx.pipelines, post_chain.format(), sample_count, camera.depth, sky::SkySettings::default());
+        let test_pattern = test_pattern::TestPattern::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            config.format,
+        );
+        let tearing_test = tearing_test::TearingTest::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            config.format,
+            run_config.tearing_speed,
+        );
+        let crosshair = crosshair::Crosshair::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            config.format,
+            run_config.crosshair,
+        );
+        let minimap = minimap::Minimap::new(device, &ctx.pipelines, config.format, run_config.minimap);
+        let region_clear = region_clear::RegionClear::new(device, &ctx.pipelines, config.format);
+        let hud_model = hud_model::HudModel::new(device, &ctx.pipelines, config.format, camera.depth);
+        let alpha_demo = alpha_demo::AlphaDemo::new(
+            device,
+            &ctx.pipelines,
+            &ctx.queue,
+            &screen.bind_group_layout,
+            config.format,
+        );
+        let mip_bias_demo = mip_bias_demo::MipBiasDemo::new(
+            device,
+            &ctx.pipelines,
+            &ctx.queue,
+            &screen.bind_group_layout,
+            config.format,
+        );
+        let tilemap = tilemap::TileMap::new(
+            device,
+            &ctx.pipelines,
+            &ctx.queue,
+            &screen.bind_group_layout,
+            config.format,
+            tilemap::demo_map(),
+            tilemap::demo_atlas(),
+            tilemap::DEMO_TILESET,
+        );
+        let font = font::BitmapFont::new(
+            device,
+            &ctx.pipelines,
+            &ctx.queue,
+            &screen.bind_group_layout,
+            config.format,
+            font::default_atlas(),
+            font::default_metrics(),
+        );
 
+        let transparency = transparency::Transparency::new(
+            device,
+            &ctx.pipelines,
+            &ctx.adapter,
+            &config,
+            post_chain.format(),
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            camera.depth,
+        );
+        let particles = particles::Particles::new(
+            device,
+            &ctx.pipelines,
+            post_chain.format(),
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            camera.depth,
+        );
+        let zfighting = zfighting::ZFightingDemo::new(
+            device,
+            &ctx.pipelines,
+            post_chain.format(),
+            sample_count,
+            camera.depth,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+        );
+        let foliage = foliage::Foliage::new(
+            device,
+            &ctx.pipelines,
+            &ctx.queue,
+            post_chain.format(),
+            sample_count,
+            camera.depth,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            foliage::Cutout::AlphaToCoverage,
+            foliage::Foliage::ALL_SAMPLES,
+        );
+        let gizmo = gizmo::Gizmo::new(
+            device,
+            &ctx.pipelines,
+            post_chain.format(),
+            sample_count,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+        );
+        let debug_lines = debug_lines::DebugLines::new(
+            device,
+            &ctx.pipelines,
+            post_chain.format(),
+            sample_count,
+            camera.depth,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+        );
+        let dof = dof::DepthOfField::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            &post_chain,
+            post_chain.format(),
+            &camera,
+        );
+        let fxaa = antialiasing::Fxaa::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            &post_chain,
+            post_chain.format(),
+        );
+        let color_grading = color_grading::ColorGrading::new(
+            device,
+            &ctx.pipelines,
+            &screen.bind_group_layout,
+            &post_chain,
+            post_chain.format(),
+        );
+        let velocity = velocity::VelocityBuffer::is_supported(&ctx.adapter)
+            .then(|| velocity::VelocityBuffer::new(device, &ctx.pipelines, &config, instances.len()));
+        let motion_blur = velocity.as_ref().map(|velocity| {
+            motion_blur::MotionBlur::new(
+                device,
+                &ctx.pipelines,
+                &screen.bind_group_layout,
+                &post_chain,
+                post_chain.format(),
+                velocity,
+            )
+        });
+        let outline = outline::Outline::new(
+            device,
+            &ctx.pipelines,
+            &config,
+            sample_count,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            &post_chain,
+            &camera,
+        );
+        let overdraw = overdraw::Overdraw::new(
+            device,
+            &ctx.pipelines,
+            &config,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            &post_chain,
+        );
+        let ssr = ssr::Ssr::new(
+            device,
+            &ctx.pipelines,
+            &config,
+            camera.depth,
+            &screen.bind_group_layout,
+            &camera_binding.bind_group_layout,
+            &post_chain,
+            &camera,
+        );
+        let taa = velocity
+            .as_ref()
+            .map(|_| taa::Taa::new(device, &ctx.pipelines, &config, &screen.bind_group_layout, &post_chain));
+        let trails =
+            trails::Trails::new(
+                device,
+                &ctx.pipelines,
+                &config,
+                sample_count,
+                &screen.bind_group_layout,
+                &post_chain,
+                Self::CLEAR_COLOR,
+            );
 
         Self {
+            window,
+            ctx,
             surface,
-            device,
-            queue,
             config,
+            surface_caps,
+            premultiplied_alpha: run_config.premultiplied_alpha,
             size,
+            screen,
+            render_pipelines,
+            material,
+            camera,
+            camera_binding,
+            fly_camera,
+            cursor_captured: false,
+            cursor_grab: CursorGrabMode::None,
+            cursor_visible: true,
+            camera_bookmarks,
+            camera_tween: None,
+            modifiers: ModifiersState::empty(),
+            cursor_position: None,
+            mouse_world: None,
+            depth_texture,
+            lights,
+            procedural,
+            noise,
+            clouds: false,
+            model,
+            models: Vec::new(),
+            current_model: 0,
+            model_textures,
+            model_bind_group,
+            instances,
+            terrain: None,
+            terrain_pipeline,
+            terrain_indices: terrain::TerrainIndices::default(),
+            terrain_shading: terrain::TerrainShading::default(),
+            colormap,
+            splat,
+            portal,
+            leaf,
+            audio,
+            audio_pipeline: None,
+            skinned,
+            skinned_pipeline,
+            morphed,
+            morphed_pipeline,
+            animation,
+            bouncer,
+            physics,
+            interpolate: true,
+            animation_time: 0.0,
+            frame_time: 0.0,
+            time_scale: 1.0,
+            timestep: timestep::FixedTimestep::new(run_config.fixed_timestep),
+            loading: Some(loading),
+            last_render_time: instant::Instant::now(),
+            frame_pacer: frame_pacing::FramePacer::new(run_config.frame_pacing),
+            stepper: frame_step::FrameStepper::default(),
+            paced: match run_config.frame_pacing {
+                frame_pacing::FramePacing::Off => frame_pacing::FramePacing::from_rate(60.0),
+                paced => paced,
+            },
+            normal_lines,
+            frozen_frustum: None,
+            frustum_lines: None,
+            latency,
+            gradient,
+            sky,
+            outline,
+            overdraw,
+            ssr,
+            test_pattern,
+            tearing_test,
+            crosshair,
+            alpha_demo,
+            mip_bias_demo,
+            tilemap,
+            font,
+            hud: false,
+            region_clear,
+            hud_panel: false,
+            inspector: inspector::Inspector::new(),
+            hud_model,
+            clear_color_enabled: false,
+            clear_depth_enabled: true,
+            minimap,
+            aa_mode,
+            msaa,
+            msaa_attachment: Default::default(),
+            clear_each_frame: true,
+            trails,
+            transparency,
+            particles,
+            zfighting,
+            foliage,
+            gizmo,
+            debug_lines,
+            debug_shapes: false,
+            selected: None,
+            post_chain,
+            offscreen_format,
+            tonemap_curve,
+            dof,
+            velocity,
+            motion_blur,
+            fxaa,
+            color_grading,
+            taa,
+            recorder: None,
+            surface_lost_frames: 0,
+            limits,
+            features,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            cpu_frame_times: frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN),
+            gpu_frame_times: frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN),
+            gpu_frame_timer,
+            batch_draw,
+            batch_draw_times: frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN),
+            stats_log: run_config.stats_interval.map(bench::StatsLog::new),
+            resource_tracker: gpu_memory::ResourceTracker::new(),
+            always_on_top: false,
         }
     }
 
-    // Handles window resizing
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+    // Skinned & morphed pipelines swap in their own vertex shader, and expect texture_layout to
+    // be the model's (the texture plus the joint palette or morph targets)
+    #[allow(clippy::too_many_arguments)]
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        pipelines: &pipeline_cache::PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        polygon_mode: wgpu::PolygonMode,
+        topology: batch::MeshTopology,
+        screen: &screen::Screen,
+        camera_binding: &camera::CameraBinding,
+        texture_layout: &wgpu::BindGroupLayout,
+        lights: &light::Lights,
+        vertex: SceneVertex,
+        defines: &material::ShaderDefines,
+    ) -> Rc<wgpu::RenderPipeline> {
+        // include_str! bakes the shader source into the binary at compile time
+        let (label, source, vertex_entry_point, vertex_layout) = match vertex {
+            SceneVertex::Static => ("Scene", include_str!("shader.wgsl").into(), "vs_main", model::ModelVertex::desc()),
+            SceneVertex::Skinned => (
+                "Skinned Scene",
+                skinning::SkinnedModel::shader_source(),
+                "vs_skinned",
+                skinning::SkinnedVertex::desc(),
+            ),
+            SceneVertex::Morphed => (
+                "Morphed Scene",
+                morph::MorphedModel::shader_source(),
+                "vs_morphed",
+                model::ModelVertex::desc(),
+            ),
+        };
+        let source = lights.preprocess(&defines.preprocess(&source));
+        pipelines.render_pipeline(
+            device,
+            &pipeline_cache::RenderPipelineDesc {
+                label,
+                shader: &source,
+                bind_group_layouts: &[
+                    &screen.bind_group_layout,
+                    &camera_binding.bind_group_layout,
+                    texture_layout,
+                    &lights.bind_group_layout,
+                ],
+                vertex_entry_point,
+                vertex_buffers: &[vertex_layout, instance::InstanceRaw::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState {
+                    topology: topology.primitive(),
+                    // Strips have to say which index ends them, see batch::MeshTopology
+                    strip_index_format: topology.strip_index_format(),
+                    front_face: wgpu::FrontFace::Ccw,
+                    // Double sided materials show their back faces too, see material.rs
+                    cull_mode: (!defines.contains("DOUBLE_SIDED")).then_some(wgpu::Face::Back),
+                    // Line needs Features::POLYGON_MODE_LINE, see the wireframe key
+                    polygon_mode,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    // Fragments closer to the camera replace the ones behind them
+                    depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    // More than 1 with MSAA on, see antialiasing.rs
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+            },
+        )
+    }
+
+    /*
+    *   Rebuilds the surface and every gpu resource on a fresh GpuContext after the device has
+    *   been lost. Everything we need is kept on the cpu side (the camera, mesh geometry, debug
+    *   view settings...) so the scene comes back exactly as it was.
+    */
+    #[cfg(not(target_arch = "wasm32"))]
+    fn recreate(&mut self, surface: wgpu::Surface, ctx: Rc<GpuContext>) {
+        // The recording target belongs to the old device
+        self.stop_recording();
+        self.surface = surface;
+        self.ctx = ctx;
+        self.limits = self.ctx.device.limits();
+        self.features = self.ctx.device.features();
+        if !self.features.contains(wgpu::Features::POLYGON_MODE_LINE) {
+            self.polygon_mode = wgpu::PolygonMode::Fill;
         }
+        self.gpu_frame_timer = Self::frame_gpu_timer(&self.ctx);
+        self.surface_lost_frames = 0;
+
+        // The new adapter might not like the old format
+        self.surface_caps = self.surface.get_capabilities(&self.ctx.adapter);
+        Self::log_surface_capabilities(&self.surface_caps);
+        (self.config.format, self.config.alpha_mode) = Self::surface_format_and_alpha_mode(&self.surface_caps, self.premultiplied_alpha);
+        self.surface.configure(&self.ctx.device, &self.config);
+
+        self.screen = screen::Screen::new(&self.ctx.device, self.size);
+        self.camera_binding =
+            camera::CameraBinding::new(&self.ctx.device, &self.camera, self.camera_binding.ring().slots());
+        self.depth_texture = texture::Texture::create_depth_texture(&self.ctx.device, &self.config, "depth_texture");
+        self.lights = self.lights.recreate(&self.ctx.device, &self.ctx.queue);
+        self.procedural = self.procedural.recreate(&self.ctx.device, &self.ctx.adapter, &self.ctx.queue);
+        self.load_model_textures();
+        self.noise = self.noise.as_ref().map(|_| noise::NoiseGenerator::new(&self.ctx.device));
+        self.model = self.model.recreate(&self.ctx.device);
+        self.physics = self.physics.recreate(&self.ctx.device);
+        self.terrain = self.terrain.as_ref().map(|terrain| terrain.recreate(&self.ctx.device));
+        self.colormap = self.colormap.recreate(&self.ctx.device, &self.ctx.queue);
+        self.splat = self.splat.recreate(&self.ctx.device, &self.ctx.queue);
+        self.leaf = self.leaf.recreate(&self.ctx.device);
+        self.audio = self.audio.recreate(&self.ctx.device, &self.procedural.texture);
+        // The new device might not do multi-draw
+        self.set_batch_draw(Self::pick_batch_draw(&self.ctx, Some(self.batch_draw)));
+        self.skinned = self.skinned.as_ref().map(|skinned| skinned.recreate(&self.ctx.device, &self.procedural.texture));
+        // Last frame's matrices are kept, the motion starts over from nothing
+        self.velocity = self
+            .velocity
+            .as_ref()
+            .map(|_| {
+                velocity::VelocityBuffer::new(&self.ctx.device, &self.ctx.pipelines, &self.config, self.instances.len())
+            });
+        self.morphed = self
+            .morphed
+            .as_ref()
+            .map(|morphed| morphed.recreate(&self.ctx.device, &self.ctx.queue, &self.procedural.texture));
+        self.recreate_surface_pipelines();
+
+        log::warn!("Gpu resources recreated for window {:?}", self.window.id());
     }
 
-    // Returns a bool to indicate whether an event has been fully processed. If the method returns true,
-    // the main loop won't process the event any further.
+    // Compiles the scene pipeline for the material's features, unless it's already been built
+    fn scene_pipeline(&mut self, material: material::Material) {
+        let defines = material.defines();
+        if self.render_pipelines.contains_key(&defines) {
+            return;
+        }
+        let pipeline = Self::create_render_pipeline(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            self.post_chain.format(),
+            self.aa_mode.sample_count(),
+            self.camera.depth,
+            self.polygon_mode,
+            batch::MeshTopology::TriangleList,
+            &self.screen,
+            &self.camera_binding,
+            &self.procedural.bind_group_layout,
+            &self.lights,
+            SceneVertex::Static,
+            &defines,
+        );
+        log::info!("compiled the scene pipeline for {:?}", defines);
+        self.render_pipelines.insert(defines, pipeline);
+    }
 
-    // TODO: We're just going to return false for now because we don't have any events we want to capture.
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        false
+    // The textured list one is the same as the textured scene pipeline, the cache hands that
+    // back. Colormapped or splatted, group 2 is the colormap's or the splatting's instead.
+    fn create_terrain_pipeline(&self) -> Rc<wgpu::RenderPipeline> {
+        let (texture_layout, defines) = match self.terrain_shading {
+            terrain::TerrainShading::Textured => {
+                (&self.procedural.bind_group_layout, material::Material::TEXTURED.defines())
+            }
+            terrain::TerrainShading::Colormap => {
+                (&self.colormap.bind_group_layout, material::ShaderDefines::new(&["COLORMAP"]))
+            }
+            terrain::TerrainShading::Splat => (&self.splat.bind_group_layout, material::ShaderDefines::new(&["SPLAT"])),
+        };
+        Self::create_render_pipeline(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            self.post_chain.format(),
+            self.aa_mode.sample_count(),
+            self.camera.depth,
+            self.polygon_mode,
+            self.terrain_indices.topology(),
+            &self.screen,
+            &self.camera_binding,
+            texture_layout,
+            &self.lights,
+            SceneVertex::Static,
+            &defines,
+        )
     }
 
-    fn update(&mut self) {
-        // yeah.  
+    // Textured, with the audio's group 2 holding the bands next to the texture
+    fn create_audio_pipeline(&self) -> Rc<wgpu::RenderPipeline> {
+        Self::create_render_pipeline(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            self.post_chain.format(),
+            self.aa_mode.sample_count(),
+            self.camera.depth,
+            self.polygon_mode,
+            batch::MeshTopology::TriangleList,
+            &self.screen,
+            &self.camera_binding,
+            &self.audio.bind_group_layout,
+            &self.lights,
+            SceneVertex::Static,
+            &material::ShaderDefines::new(&["TEXTURED", "AUDIO"]),
+        )
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        // First we need to get a frame to render to
-        // the get_current_texture function will wait for the surface to provide a new SurfaceTexture that
-        // we will render to. We'll store this in output for later.
-        let output = self.surface.get_current_texture()?;
+    fn set_terrain_shading(&mut self, shading: terrain::TerrainShading) {
+        self.terrain_shading = shading;
+        self.terrain_pipeline = self.create_terrain_pipeline();
+        log::info!("terrain shading: {}", self.terrain_shading_info());
+    }
 
-        // view creates a TextureView with default settings. We need to do this because we want to control
-        // how the render code interacts with the texture.
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // The shading with whatever goes with it, for the logs & the dump
+    fn terrain_shading_info(&self) -> String {
+        match self.terrain_shading {
+            terrain::TerrainShading::Textured => self.terrain_shading.to_string(),
+            terrain::TerrainShading::Colormap => {
+                let (min, max) = self.colormap.range();
+                format!("{}, heights {:.2} to {:.2}", self.colormap.colormap(), min, max)
+            }
+            terrain::TerrainShading::Splat => {
+                let settings = self.splat.settings();
+                format!(
+                    "{}, snow from {:.2} of the way up, rock below a normal y of {:.2}, {}x anisotropic",
+                    self.terrain_shading,
+                    settings.snow_line,
+                    settings.rock_slope,
+                    self.splat.anisotropy()
+                )
+            }
+        }
+    }
 
-        // We also need to create a CommandEncoder to create the actual commands to send to the gpu. Most
-        // modern graphics frameworks expect commands to be stored in a command buffer before being sent
-        // to the gpu. The encoder builds a command buffer that we can then send to the gpu.
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
+    // Colors the terrain by height with `colormap`, or goes back to the texture with None
+    fn set_terrain_colormap(&mut self, colormap: Option<colormap::Colormap>) {
+        match colormap {
+            Some(colormap) => {
+                if colormap != self.colormap.colormap() {
+                    self.colormap.set_colormap(&self.ctx.device, &self.ctx.queue, colormap);
+                }
+                self.set_terrain_shading(terrain::TerrainShading::Colormap);
+            }
+            None => self.set_terrain_shading(terrain::TerrainShading::Textured),
+        }
+    }
+
+    // Logs how big the indices were & how long they took to draw before switching, so the
+    // ways can be compared
+    fn set_terrain_indices(&mut self, indices: terrain::TerrainIndices) {
+        if let Some(terrain) = &self.terrain {
+            let index_count = terrain.chunks().index_count();
+            log::info!(
+                "terrain {}: {} indices ({:.1} KiB){}",
+                self.terrain_indices,
+                index_count,
+                (index_count * std::mem::size_of::<u32>()) as f32 / 1024.0,
+                self.batch_draw_time().map_or(String::new(), |us| format!(", recorded in {:.1}µs", us))
+            );
+        }
+        self.terrain_indices = indices;
+        if let Some(terrain) = &mut self.terrain {
+            terrain.set_indices(&self.ctx.device, indices);
+        }
+        self.terrain_pipeline = self.create_terrain_pipeline();
+        // The timings are for one way only
+        self.batch_draw_times = frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN);
+        log::info!("terrain indices: {}", indices);
+    }
+
+    // Drawn in the main pass, so built for its sample count
+    fn create_frustum_lines(&self, frustum: &camera::Frustum) -> debug_normals::FrustumLines {
+        debug_normals::FrustumLines::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            self.post_chain.format(),
+            self.aa_mode.sample_count(),
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            frustum,
+        )
+    }
+
+    // Rebuilds everything that renders to the surface or the offscreen targets, after the
+    // device or the surface format changed
+    fn recreate_surface_pipelines(&mut self) {
+        // First, the main pass follows the format of its targets
+        self.post_chain = post::PostChain::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.screen.bind_group_layout,
+            &self.config,
+            post::PostChain::pick_format(&self.ctx.adapter, &self.config, self.offscreen_format),
+            self.tonemap_curve,
+        );
+        let scene_format = self.post_chain.format();
+        // Not every format can be multisampled
+        if let antialiasing::AaMode::Msaa(count) = self.aa_mode {
+            if !antialiasing::Msaa::is_supported(&self.ctx.adapter, scene_format, count) {
+                log::warn!("{}x MSAA isn't supported with {:?}, turning anti-aliasing off", count, scene_format);
+                self.aa_mode = antialiasing::AaMode::None;
+            }
+        }
+        if self.aa_mode == antialiasing::AaMode::Taa && self.velocity.is_none() {
+            log::warn!("TAA needs the velocity buffer, turning anti-aliasing off");
+            self.aa_mode = antialiasing::AaMode::None;
+        }
+        let sample_count = self.aa_mode.sample_count();
+        self.msaa = (sample_count > 1).then(|| {
+            antialiasing::Msaa::new(
+                &self.ctx.device,
+                &self.ctx.pipelines,
+                &self.config,
+                scene_format,
+                sample_count,
+                self.camera.depth,
+            )
         });
 
-        // Extra block borrows encoder mutably (aka &mut self). We can't call encoder.finish() until
-        // we release that mutable borrow. The block tells rust to drop any variables within it when the
-        // code leaves that scope thus releasing the mutable borrow on encoder and allowing us to finish() it.
-        {
-            let _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    }
-                })],
-                depth_stencil_attachment: None,
-            });
+        // Only the variants in use get built again, the rest as they're needed
+        self.render_pipelines.clear();
+        self.scene_pipeline(self.material);
+        self.scene_pipeline(material::Material::TEXTURED);
+        if self.leaf.enabled {
+            self.scene_pipeline(self.leaf.material);
+        }
+        self.terrain_pipeline = self.create_terrain_pipeline();
+        self.audio_pipeline = self.audio.enabled.then(|| self.create_audio_pipeline());
+        self.skinned_pipeline = self.skinned.as_ref().map(|skinned| {
+            Self::create_render_pipeline(
+                &self.ctx.device,
+                &self.ctx.pipelines,
+                scene_format,
+                sample_count,
+                self.camera.depth,
+                self.polygon_mode,
+                batch::MeshTopology::TriangleList,
+                &self.screen,
+                &self.camera_binding,
+                &skinned.bind_group_layout,
+                &self.lights,
+                SceneVertex::Skinned,
+                &material::Material::TEXTURED.defines(),
+            )
+        });
+        self.morphed_pipeline = self.morphed.as_ref().map(|morphed| {
+            Self::create_render_pipeline(
+                &self.ctx.device,
+                &self.ctx.pipelines,
+                scene_format,
+                sample_count,
+                self.camera.depth,
+                self.polygon_mode,
+                batch::MeshTopology::TriangleList,
+                &self.screen,
+                &self.camera_binding,
+                &morphed.bind_group_layout,
+                &self.lights,
+                SceneVertex::Morphed,
+                &material::Material::TEXTURED.defines(),
+            )
+        });
+        self.portal = self.portal.recreate(
+            &self.ctx.device,
+            scene_format,
+            sample_count,
+            &self.procedural.bind_group_layout,
+            self.camera.depth,
+        );
+        self.normal_lines = self.normal_lines.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            scene_format,
+            sample_count,
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            &[self.model.high_detail()],
+        );
+        self.frustum_lines = self.frozen_frustum.map(|frustum| self.create_frustum_lines(&frustum));
+        if let Some(loading) = &mut self.loading {
+            loading.screen = loading.screen.recreate(
+                &self.ctx.device,
+                &self.ctx.pipelines,
+                &self.ctx.queue,
+                &self.screen.bind_group_layout,
+                self.config.format,
+            );
+        }
+        let latency_enabled = self.latency.enabled;
+        self.latency = latency::LatencyProbe::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            scene_format,
+            sample_count,
+        );
+        self.latency.enabled = latency_enabled;
+        let gradient_enabled = self.gradient.enabled;
+        self.gradient = gradient::GradientStrip::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            scene_format,
+            sample_count,
+        );
+        self.gradient.enabled = gradient_enabled;
+        self.sky = self.sky.recreate(&self.ctx.device, &self.ctx.pipelines, scene_format, sample_count, self.camera.depth);
+        let test_pattern_enabled = self.test_pattern.enabled;
+        self.test_pattern = test_pattern::TestPattern::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.test_pattern.enabled = test_pattern_enabled;
+        self.tearing_test = self.tearing_test.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.crosshair = self.crosshair.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.minimap = self.minimap.recreate(&self.ctx.device, &self.ctx.pipelines, self.config.format);
+        self.region_clear = self.region_clear.recreate(&self.ctx.device, &self.ctx.pipelines, self.config.format);
+        self.hud_model =
+            self.hud_model.recreate(&self.ctx.device, &self.ctx.pipelines, self.config.format, self.camera.depth);
+        let alpha_demo_enabled = self.alpha_demo.enabled;
+        self.alpha_demo = alpha_demo::AlphaDemo::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.alpha_demo.enabled = alpha_demo_enabled;
+        self.mip_bias_demo = self.mip_bias_demo.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.tilemap = self.tilemap.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.font = self.font.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            self.config.format,
+        );
+        self.transparency = self.transparency.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.adapter,
+            &self.config,
+            scene_format,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            self.camera.depth,
+        );
+        self.particles = self.particles.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            scene_format,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            self.camera.depth,
+        );
+        self.zfighting = self.zfighting.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            scene_format,
+            sample_count,
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+        );
+        self.foliage = self.foliage.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            scene_format,
+            sample_count,
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+        );
+        self.gizmo = self.gizmo.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            scene_format,
+            sample_count,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+        );
+        self.debug_lines = self.debug_lines.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            scene_format,
+            sample_count,
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+        );
+        self.dof = self.dof.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            &self.post_chain,
+            scene_format,
+            &self.camera,
+        );
+        self.fxaa = antialiasing::Fxaa::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.screen.bind_group_layout,
+            &self.post_chain,
+            scene_format,
+        );
+        self.color_grading = self.color_grading.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.screen.bind_group_layout,
+            &self.post_chain,
+            scene_format,
+        );
+        if let (Some(motion_blur), Some(velocity)) = (&mut self.motion_blur, &self.velocity) {
+            *motion_blur = motion_blur.recreate(
+                &self.ctx.device,
+                &self.ctx.pipelines,
+                &self.ctx.queue,
+                &self.screen.bind_group_layout,
+                &self.post_chain,
+                scene_format,
+                velocity,
+            );
         }
+        self.fxaa.enabled = self.aa_mode == antialiasing::AaMode::Fxaa;
+        self.outline = self.outline.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.config,
+            sample_count,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            &self.post_chain,
+            &self.camera,
+        );
+        self.overdraw = self.overdraw.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.config,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            &self.post_chain,
+        );
+        self.ssr = self.ssr.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            &self.config,
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            &self.post_chain,
+            &self.camera,
+        );
+        self.taa = self.velocity.as_ref().map(|_| {
+            let mut taa = taa::Taa::new(
+                &self.ctx.device,
+                &self.ctx.pipelines,
+                &self.config,
+                &self.screen.bind_group_layout,
+                &self.post_chain,
+            );
+            taa.enabled = self.aa_mode == antialiasing::AaMode::Taa;
+            taa
+        });
+        // Without TAA the camera goes back to sitting still
+        self.camera_binding.update(&self.ctx.queue, &self.camera);
+        // Starts over with nothing to fade, the MSAA targets are new too
+        self.trails = trails::Trails::new(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.config,
+            sample_count,
+            &self.screen.bind_group_layout,
+            &self.post_chain,
+            Self::CLEAR_COLOR,
+        );
+        let stats = self.ctx.pipelines.stats();
+        log::info!(
+            "pipeline cache: {} hits, {} misses, {} pipelines alive",
+            stats.hits,
+            stats.misses,
+            stats.live
+        );
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    // How HDR targets get squeezed down to the surface. Kept for when the chain is rebuilt,
+    // even while the offscreen format isn't HDR.
+    fn set_tonemap_curve(&mut self, curve: tonemap::TonemapCurve) {
+        self.tonemap_curve = curve;
+        match self.post_chain.tonemap_mut() {
+            Some(tonemap) => {
+                tonemap.set_curve(&self.ctx.queue, curve);
+                log::info!("tonemapping: {}", curve);
+            }
+            None => log::info!("tonemapping: {}, once the offscreen format is HDR (--hdr)", curve),
+        }
+    }
 
-        Ok(())
+    /*
+    *   Switches anti-aliasing technique. MSAA changes the sample count every pipeline in the
+    *   main pass was built with, so those all get rebuilt, same as for a new surface format.
+    */
+    fn set_aa_mode(&mut self, mode: antialiasing::AaMode) -> bool {
+        if let antialiasing::AaMode::Msaa(count) = mode {
+            if !antialiasing::Msaa::is_supported(&self.ctx.adapter, self.post_chain.format(), count) {
+                log::warn!("{}x MSAA isn't supported with {:?}", count, self.post_chain.format());
+                return false;
+            }
+        }
+        if mode == antialiasing::AaMode::Taa && self.velocity.is_none() {
+            log::warn!("TAA needs a {:?} velocity buffer, which this adapter can't render to", velocity::VelocityBuffer::FORMAT);
+            return false;
+        }
+        self.aa_mode = mode;
+        self.recreate_surface_pipelines();
+        log::info!("anti-aliasing: {}", self.aa_mode);
+        true
+    }
+
+    /*
+    *   Switches the surface to another format, e.g. Bgra8UnormSrgb -> Bgra8Unorm to see what the
+    *   sRGB conversion does. Every pipeline bakes the format of its color targets in, so they
+    *   all have to be rebuilt along with the offscreen targets that match the surface.
+    *   (Older wgpu versions called the format list surface.get_supported_formats(&adapter).)
+    */
+    fn set_surface_format(&mut self, format: wgpu::TextureFormat) -> bool {
+        let supported = &self.surface_caps.formats;
+        if !supported.contains(&format) {
+            log::warn!("{:?} isn't supported by this surface, it supports {:?}", format, supported);
+            return false;
+        }
+        if format == self.config.format {
+            return true;
+        }
+
+        log::info!("switching the surface format from {:?} to {:?}", self.config.format, format);
+        // The recording target & ffmpeg were set up for the old format
+        self.stop_recording();
+        self.config.format = format;
+        self.surface.configure(&self.ctx.device, &self.config);
+        self.recreate_surface_pipelines();
+        true
+    }
+
+    // Moves on to the next format the surface supports
+    fn cycle_surface_format(&mut self) {
+        let supported = &self.surface_caps.formats;
+        let current = supported.iter().position(|f| *f == self.config.format).unwrap_or(0);
+        let next = supported[(current + 1) % supported.len()];
+        self.set_surface_format(next);
+    }
+
+    // Called when the surface reports Lost/Outdated. Usually reconfiguring it is enough, but
+    // if it keeps happening the device itself is probably gone.
+    fn surface_lost(&mut self) {
+        self.surface_lost_frames += 1;
+        if self.surface_lost_frames > Self::MAX_SURFACE_LOST_FRAMES {
+            log::error!("surface lost {} frames in a row, assuming the device is lost", self.surface_lost_frames);
+            self.ctx.mark_device_lost();
+        } else {
+            self.resize(self.size);
+        }
+    }
+
+    // The formats (preferred first), present modes & alpha modes the surface supports
+    fn surface_capabilities(&self) -> &wgpu::SurfaceCapabilities {
+        &self.surface_caps
+    }
+
+    // Handles window resizing
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(&self.ctx.device, &self.config);
+            // Keep the resolution uniform in sync with the surface
+            self.screen.resize(&self.ctx.queue, new_size);
+            // The depth texture has to match the surface size, and the projection the aspect ratio
+            self.depth_texture = texture::Texture::create_depth_texture(&self.ctx.device, &self.config, "depth_texture");
+            self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+            self.camera_binding.update(&self.ctx.queue, &self.camera);
+            self.post_chain.resize(&self.ctx.device, &self.config);
+            self.trails.resize(&self.ctx.device, &self.config);
+            self.transparency.resize(&self.ctx.device, &self.config);
+            self.outline.resize(&self.ctx.device, &self.config);
+            self.overdraw.resize(&self.ctx.device, &self.config);
+            self.ssr.resize(&self.ctx.device, &self.config);
+            if let (Some(velocity), Some(motion_blur)) = (&mut self.velocity, &mut self.motion_blur) {
+                velocity.resize(&self.ctx.device, &self.config);
+                motion_blur.set_velocity(&self.ctx.device, velocity);
+            }
+            if let Some(taa) = &mut self.taa {
+                taa.resize(&self.ctx.device, &self.config);
+            }
+            if let Some(msaa) = &mut self.msaa {
+                msaa.resize(&self.ctx.device, &self.config);
+            }
+            if self.recorder.as_ref().is_some_and(|recorder| !recorder.matches_size(&self.config)) {
+                log::warn!("window resized, stopping the recording");
+                self.stop_recording();
+            }
+        }
+    }
+
+    // Returns a bool to indicate whether an event has been fully processed. If the method returns true,
+    // the main loop won't process the event any further.
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = *modifiers;
+        }
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some(*position);
+                self.mouse_world = self.mouse_world_on_plane(Self::FOLLOWER_PLANE_Y);
+                if self.gizmo.enabled {
+                    self.drag_gizmo();
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+                self.mouse_world = None;
+            }
+            _ => {}
+        }
+        // Alt-tabbing away shouldn't leave the cursor stuck, or alt held: nothing says when
+        // modifiers are let go of while another window has focus
+        if let WindowEvent::Focused(false) = event {
+            self.modifiers = ModifiersState::empty();
+            if self.cursor_captured {
+                self.set_cursor_captured(false);
+            } else if self.cursor_grab != CursorGrabMode::None {
+                self.set_cursor_grab(CursorGrabMode::None);
+            }
+        }
+
+        if self.cursor_captured {
+            if let WindowEvent::KeyboardInput {
+                input: KeyboardInput { state, virtual_keycode: Some(VirtualKeyCode::Escape), .. },
+                ..
+            } = event
+            {
+                // Escape lets go of the cursor rather than closing the window
+                if *state == ElementState::Pressed {
+                    self.set_cursor_captured(false);
+                }
+                return true;
+            }
+        }
+
+        // Any press counts as an input for the latency probe
+        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, .. }, .. }
+            | WindowEvent::MouseInput { state: ElementState::Pressed, .. } = event
+        {
+            self.mark_input_event();
+        }
+
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.handle_key(*key),
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. }
+                if self.gizmo.enabled && !self.cursor_captured =>
+            {
+                self.handle_click(*state)
+            }
+            _ => false,
+        }
+    }
+
+    /*
+    *   Ctrl, shift, alt & logo as they are right now, tracked from ModifiersChanged for every
+    *   input handler to share: the hotkeys that do something else with ctrl or shift, and
+    *   the mouse, e.g. ctrl snaps a gizmo drag and shift slows it down. Only up to date while
+    *   the window has focus, they're all let go of when it loses it.
+    */
+    fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    // Pressing grabs the selected object's gizmo handle under the cursor, or failing that
+    // selects whatever's under it (nothing included). Releasing lets go of the handle.
+    fn handle_click(&mut self, state: ElementState) -> bool {
+        match state {
+            ElementState::Pressed => {
+                let Some(ray) = self.cursor_ray() else {
+                    return false;
+                };
+                if let Some(index) = self.selected {
+                    if self.gizmo.begin_drag(&self.camera, &ray, &self.instances[index], self.modifiers) {
+                        return true;
+                    }
+                }
+                self.selected = self.pick_object(&ray);
+                match self.selected {
+                    Some(index) => log::info!("selected object {}", index),
+                    None => log::info!("nothing selected"),
+                }
+            }
+            ElementState::Released => {
+                if let (Some(axis), Some(index)) = (self.gizmo.end_drag(), self.selected) {
+                    let transform = &self.instances[index];
+                    log::info!(
+                        "object {} {} on {}: position {:?}, rotation {:?}",
+                        index,
+                        self.gizmo.mode,
+                        axis,
+                        transform.position,
+                        transform.rotation
+                    );
+                }
+            }
+        }
+        true
+    }
+
+    // The closest of the scene's objects under `ray`, each boxed around the model's bounding
+    // sphere. The moving ones at the end would just get moved back, so they can't be picked.
+    fn pick_object(&self, ray: &camera::Ray) -> Option<usize> {
+        self.instances[..self.instances.len() - Self::MOVING_INSTANCES]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, transform)| {
+                ray.intersect_aabb(&self.object_box(transform)).map(|distance| (index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
+
+    // What pick_object tests against for the object at `transform`
+    fn object_box(&self, transform: &instance::Transform) -> physics::Aabb {
+        // The biggest scale, so the box holds the object however it's turned
+        let radius = self.model.high_detail().bounding_radius();
+        let extent = radius * transform.scale.x.max(transform.scale.y).max(transform.scale.z);
+        physics::Aabb::from_center(transform.position, cgmath::Vector3::new(extent, extent, extent))
+    }
+
+    // A line in world space for this frame only, see debug_lines.rs
+    fn debug_line(&mut self, start: cgmath::Vector3<f32>, end: cgmath::Vector3<f32>, color: [f32; 3]) {
+        self.debug_lines.line(start, end, color);
+    }
+
+    // The edges of a box in world space for this frame only
+    fn debug_box(&mut self, min: cgmath::Vector3<f32>, max: cgmath::Vector3<f32>, color: [f32; 3]) {
+        self.debug_lines.aabb(min, max, color);
+    }
+
+    // A few things that are otherwise invisible, queued anew every frame while debug_shapes is on
+    fn queue_debug_shapes(&mut self) {
+        let origin = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        self.debug_line(origin, cgmath::Vector3::unit_x() * 2.0, [0.9, 0.15, 0.15]);
+        self.debug_line(origin, cgmath::Vector3::unit_y() * 2.0, [0.15, 0.85, 0.15]);
+        self.debug_line(origin, cgmath::Vector3::unit_z() * 2.0, [0.2, 0.35, 1.0]);
+        if self.physics.enabled {
+            let bounds = self.physics.world.bounds;
+            self.debug_box(bounds.min, bounds.max, [0.5, 0.5, 0.5]);
+            let boxes = self.physics.world.bodies.iter().map(|body| body.aabb()).collect::<Vec<_>>();
+            for aabb in boxes {
+                self.debug_box(aabb.min, aabb.max, [1.0, 0.55, 0.1]);
+            }
+        }
+        if let Some(index) = self.selected {
+            let aabb = self.object_box(&self.instances[index]);
+            self.debug_box(aabb.min, aabb.max, [1.0, 0.9, 0.1]);
+        }
+        if self.portal.enabled {
+            use cgmath::EuclideanSpace;
+            let camera = &self.portal.view.camera;
+            let (eye, target) = (camera.eye.to_vec(), camera.target.to_vec());
+            self.debug_line(eye, target, [0.2, 0.9, 0.9]);
+        }
+    }
+
+    // Moves the selected object with the handle being dragged, or highlights the one under
+    // the cursor when there's no drag
+    fn drag_gizmo(&mut self) {
+        let Some(index) = self.selected else {
+            return;
+        };
+        let ray = self.cursor_ray();
+        let modifiers = self.modifiers();
+        if let Some(transform) = ray.and_then(|ray| self.gizmo.drag(&ray, modifiers)) {
+            self.instances[index] = transform;
+        }
+        self.gizmo.hover(&self.camera, ray, Some(&self.instances[index]));
+    }
+
+    // Raw input from the devices, see the event loop in run. Returns true if the event was
+    // used, nobody else gets to see it then. We only fly with the raw input for now, and only
+    // while this window has the cursor captured.
+    fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        if !self.cursor_captured {
+            return false;
+        }
+        match event {
+            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
+                self.fly_camera.process_mouse(*dx, *dy);
+                true
+            }
+            DeviceEvent::Key(KeyboardInput {
+                state,
+                virtual_keycode: Some(key),
+                ..
+            }) => self.fly_camera.process_keyboard(*key, *state),
+            _ => false,
+        }
+    }
+
+    /*
+    *   Confined keeps the cursor inside the window, Locked pins it where it is (the mouse still
+    *   sends motion as DeviceEvents). Platforms differ in which they support: Windows can only
+    *   confine the cursor, macOS can only lock it, X11 does both & the web only locks. Where
+    *   the mode asked for isn't supported we settle for the other one, they both keep the
+    *   cursor from wandering off. Returns false if neither worked, the grab is left as it was.
+    */
+    fn set_cursor_grab(&mut self, mode: CursorGrabMode) -> bool {
+        let other = match mode {
+            CursorGrabMode::Locked => Some(CursorGrabMode::Confined),
+            CursorGrabMode::Confined => Some(CursorGrabMode::Locked),
+            CursorGrabMode::None => None,
+        };
+        let result = match (self.window.set_cursor_grab(mode), other) {
+            (Err(winit::error::ExternalError::NotSupported(_)), Some(other)) => {
+                log::info!("{:?} cursor grab isn't supported here, trying {:?}", mode, other);
+                self.window.set_cursor_grab(other).map(|_| other)
+            }
+            (result, _) => result.map(|_| mode),
+        };
+        match result {
+            Ok(grab) => {
+                self.cursor_grab = grab;
+                true
+            }
+            Err(e) => {
+                log::warn!("couldn't set the cursor grab to {:?}: {}", mode, e);
+                false
+            }
+        }
+    }
+
+    // Can't fail, but some platforms only hide it while it's over the window
+    fn set_cursor_visible(&mut self, visible: bool) {
+        self.window.set_cursor_visible(visible);
+        self.cursor_visible = visible;
+    }
+
+    // Capturing hides the cursor and grabs it so the mouse can turn the camera forever
+    fn set_cursor_captured(&mut self, captured: bool) {
+        if captured {
+            if !self.set_cursor_grab(CursorGrabMode::Locked) {
+                return;
+            }
+        } else {
+            self.set_cursor_grab(CursorGrabMode::None);
+        }
+        self.set_cursor_visible(!captured);
+        self.cursor_captured = captured;
+        // We won't see the key up events of anything still held down
+        self.fly_camera.release_all();
+    }
+
+    // `wanted` if the device can do it, the fewest draw calls it can do otherwise
+    fn pick_batch_draw(ctx: &GpuContext, wanted: Option<batch::BatchDrawMode>) -> batch::BatchDrawMode {
+        let features = ctx.device.features();
+        let downlevel = ctx.adapter.get_downlevel_capabilities().flags;
+        let best = batch::BatchDrawMode::best(features, downlevel);
+        match wanted {
+            Some(mode) if mode.is_supported(features, downlevel) => mode,
+            Some(mode) => {
+                log::warn!("{} draws aren't supported here, using {}", mode, best);
+                best
+            }
+            None => best,
+        }
+    }
+
+    // Starts the timings over, so they only ever cover one mode
+    fn set_batch_draw(&mut self, mode: batch::BatchDrawMode) {
+        self.batch_draw = mode;
+        self.batch_draw_times = frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN);
+        if let Some(terrain) = &mut self.terrain {
+            terrain.set_draw_mode(mode);
+        }
+    }
+
+    /*
+    *   Flips the depth buffer around, see camera::DepthDirection. The projection changes with the
+    *   camera, but every pipeline that tests depth bakes its compare function in and the sky,
+    *   the MSAA depth resolve, depth of field & the outlines all have to know which way is far,
+    *   so it's the same rebuild as a new surface format.
+    */
+    fn set_depth_direction(&mut self, depth: camera::DepthDirection) {
+        if depth == self.camera.depth {
+            return;
+        }
+        self.camera.depth = depth;
+        self.recreate_surface_pipelines();
+        log::info!("depth: {}", depth);
+    }
+
+    // The bias & clamps for the mip bias demo's right half, kept within SamplerLod::clamped
+    fn set_mip_lod(&mut self, lod: texture::SamplerLod) {
+        self.mip_bias_demo.set_lod(&self.ctx.device, &self.ctx.queue, lod);
+        let lod = self.mip_bias_demo.lod();
+        log::info!("mip bias: {:+.1}, levels {} to {}", lod.bias, lod.min_clamp, lod.max_clamp);
+    }
+
+    // Whether the HUD model's pass clears the frame under it or draws over it
+    fn set_clear_color_enabled(&mut self, enabled: bool) {
+        self.clear_color_enabled = enabled;
+        log::info!("hud model color: {}", if enabled { "cleared" } else { "loaded" });
+    }
+
+    // Whether the HUD model's pass clears the scene's depth or tests against it
+    fn set_clear_depth_enabled(&mut self, enabled: bool) {
+        self.clear_depth_enabled = enabled;
+        log::info!("hud model depth: {}", if enabled { "cleared" } else { "loaded" });
+    }
+
+    // Average time to record the terrain's draws in microseconds, None before it's been drawn
+    fn batch_draw_time(&self) -> Option<f32> {
+        let times = self.batch_draw_times.as_slice();
+        (!times.is_empty()).then(|| times.iter().sum::<f32>() / times.len() as f32 * 1000.0)
+    }
+
+    // Records when an input arrived, the latency gets logged once the next frame is presented
+    fn mark_input_event(&mut self) {
+        self.latency.mark();
+    }
+
+    // Blocking read back of a gpu buffer. WASM can't block, use GpuContext::read_buffer_async there.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_buffer(&self, buffer: &wgpu::Buffer, size: wgpu::BufferAddress) -> Vec<u8> {
+        pollster::block_on(self.ctx.read_buffer_async(buffer, size))
+    }
+
+    // Bytes of buffers & textures currently alive, see gpu_memory.rs
+    fn gpu_memory_estimate(&self) -> u64 {
+        gpu_memory::GpuMemory::current().total()
+    }
+
+    // Buffers & textures currently alive, to check that swapping things out frees the old ones
+    fn resource_counts(&self) -> gpu_memory::ResourceCounts {
+        gpu_memory::ResourceCounts::current()
+    }
+
+    // How many lights fit, counting the flashlight
+    fn light_capacity(&self) -> usize {
+        self.lights.capacity()
+    }
+
+    // Reallocates the lights buffer & rebuilds the scene pipelines around the new array length
+    fn set_light_capacity(&mut self, capacity: usize) -> Result<(), String> {
+        if capacity == self.lights.capacity() {
+            return Ok(());
+        }
+        self.lights.set_capacity(&self.ctx.device, capacity)?;
+        self.recreate_surface_pipelines();
+        log::info!("room for {} lights", self.lights.capacity());
+        Ok(())
+    }
+
+    /*
+    *   How many buffers the camera & lights uniforms go round, see uniform_ring.rs. Logs the
+    *   frame times so far and starts the histories again, so the HUD & F10 show them with the
+    *   new size on their own.
+    */
+    fn set_uniform_ring_size(&mut self, slots: usize) -> Result<(), String> {
+        if !(1..=uniform_ring::UniformRing::MAX_SLOTS).contains(&slots) {
+            return Err(format!(
+                "the uniform ring needs 1 to {} slots, not {}",
+                uniform_ring::UniformRing::MAX_SLOTS,
+                slots
+            ));
+        }
+        let average = |history: &[f32]| history.iter().sum::<f32>() / history.len().max(1) as f32;
+        let mut before = format!("cpu {:.2}ms", average(self.frame_time_history()));
+        if !self.gpu_frame_time_history().is_empty() {
+            before += &format!(", gpu {:.2}ms", average(self.gpu_frame_time_history()));
+        }
+        log::info!(
+            "uniform ring: {} slots, was {} ({} over the last {} frames)",
+            slots,
+            self.camera_binding.ring().slots(),
+            before,
+            self.frame_time_history().len()
+        );
+        self.camera_binding.set_ring_slots(&self.ctx.device, slots);
+        self.lights.set_ring_slots(&self.ctx.device, slots);
+        self.cpu_frame_times = frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN);
+        self.gpu_frame_times = frame_history::FrameHistory::new(Self::FRAME_HISTORY_LEN);
+        Ok(())
+    }
+
+    // The slot of the uniform ring the next frame binds
+    fn uniform_ring_slot(&self) -> usize {
+        self.camera_binding.ring().current()
+    }
+
+    // What's on screen as a scene that can be saved, leaving out the moving instances
+    #[cfg(not(target_arch = "wasm32"))]
+    fn scene(&self) -> scene::Scene {
+        scene::Scene {
+            camera: scene::SceneCamera::from_camera(&self.camera),
+            material: self.material,
+            model: self.model_path().map(|path| path.to_path_buf()),
+            objects: self.instances[..self.instances.len() - Self::MOVING_INSTANCES].to_vec(),
+            sun: self.lights.sun,
+            ambient: self.lights.ambient,
+            lights: self.lights.lights.clone(),
+        }
+    }
+
+    /*
+    *   Replaces the current scene. The model gets loaded first, that's the part that can fail,
+    *   and a failure leaves everything as it was. The instance buffers are sized for the
+    *   number of instances, so they're made again to fit.
+    */
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply_scene(&mut self, scene: scene::Scene) -> Result<(), String> {
+        if self.loading.is_some() {
+            return Err("still loading the last one".into());
+        }
+        // One of the models already there, or a new one to cycle through
+        let index = match self.models.iter().position(|model| model.path == scene.model) {
+            Some(index) => index,
+            None => {
+                let model = match &scene.model {
+                    Some(path) => model::Model::from_file(path.clone(), model::MeshData::load_obj(path)?),
+                    None => model::Model::spheres(
+                        model::Mesh::uv_sphere_data("Sphere High", 32, 16),
+                        model::Mesh::uv_sphere_data("Sphere Low", 8, 6),
+                    ),
+                };
+                self.models.push(model);
+                self.models.len() - 1
+            }
+        };
+        self.show_model(index);
+
+        self.camera_tween = None;
+        scene.camera.apply(&mut self.camera);
+        self.sync_fly_camera();
+        self.camera_binding.update(&self.ctx.queue, &self.camera);
+
+        self.material = scene.material;
+        self.scene_pipeline(self.material);
+
+        // Make room for all of the scene's lights, plus the flashlight if it's on
+        let needed = scene.lights.len() + self.lights.flashlight.is_some() as usize;
+        if needed > self.light_capacity() {
+            self.set_light_capacity(needed)?;
+        }
+        self.lights.sun = scene.sun;
+        self.lights.ambient = scene.ambient;
+        self.lights.lights = scene.lights;
+        self.lights.update(&self.ctx.queue);
+
+        // The follower, bouncing ball & hopping sphere stay at the end
+        let moving = self.instances.split_off(self.instances.len() - Self::MOVING_INSTANCES);
+        self.instances = scene.objects;
+        self.instances.extend(moving);
+        self.selected = None;
+        self.gizmo.end_drag();
+        self.model.set_capacity(&self.ctx.device, self.instances.len());
+        self.portal.view.set_capacity(&self.ctx.device, self.instances.len());
+        if let Some(velocity) = &mut self.velocity {
+            velocity.set_capacity(&self.ctx.device, self.instances.len());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_scene(&self, path: &std::path::Path) {
+        match self.scene().save(path) {
+            Ok(()) => log::info!("saved the scene to {}", path.display()),
+            Err(e) => log::error!("couldn't save the scene: {}", e),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_scene(&mut self, path: &std::path::Path) {
+        match scene::Scene::load(path).and_then(|scene| self.apply_scene(scene)) {
+            Ok(()) => log::info!("loaded the scene from {}", path.display()),
+            Err(e) => log::error!("couldn't load the scene: {}", e),
+        }
+    }
+
+    // The fly camera keeps its own yaw & pitch, which have to match the camera's direction
+    // whenever something else turns it
+    fn sync_fly_camera(&mut self) {
+        self.fly_camera = camera::FlyCamera::new(&self.camera, self.fly_camera.speed, self.fly_camera.sensitivity);
+    }
+
+    // The ray from the camera through the cursor. None without a cursor in the window (or a
+    // captured one, which is hidden).
+    fn cursor_ray(&self) -> Option<camera::Ray> {
+        let position = self.cursor_position.filter(|_| !self.cursor_captured)?;
+        Some(self.camera.screen_ray(position, self.size))
+    }
+
+    // Where the cursor points on the horizontal plane at `plane_y`. None without a cursor ray,
+    // or when it points along or away from the plane.
+    fn mouse_world_on_plane(&self, plane_y: f32) -> Option<cgmath::Vector3<f32>> {
+        let point = self.cursor_ray()?.intersect_horizontal_plane(plane_y)?;
+        Some(cgmath::EuclideanSpace::to_vec(point))
+    }
+
+    // Remembers the current view in `slot` (0 based), and writes all of them to disk on native
+    fn save_camera_bookmark(&mut self, slot: usize) {
+        self.camera_bookmarks.set(slot, bookmarks::CameraBookmark::from_camera(&self.camera));
+        log::info!("camera bookmark {} saved", slot + 1);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = self.camera_bookmarks.save(std::path::Path::new(bookmarks::CameraBookmarks::FILE)) {
+            log::error!("{}", e);
+        }
+    }
+
+    // Goes back to the view saved in `slot`, gliding over with `tween`
+    fn goto_camera_bookmark(&mut self, slot: usize, tween: bool) {
+        let Some(bookmark) = self.camera_bookmarks.get(slot) else {
+            log::warn!("no camera bookmark {} yet, ctrl + numpad {} saves one", slot + 1, slot + 1);
+            return;
+        };
+        if tween {
+            let from = bookmarks::CameraBookmark::from_camera(&self.camera);
+            self.camera_tween = Some(bookmarks::CameraTween::new(from, bookmark, Self::BOOKMARK_TWEEN));
+        } else {
+            self.camera_tween = None;
+            bookmark.apply(&mut self.camera);
+            self.camera_binding.update(&self.ctx.queue, &self.camera);
+            self.sync_fly_camera();
+        }
+        log::info!("camera bookmark {}", slot + 1);
+    }
+
+    /*
+    *   Everything worth knowing when something looks wrong, as one block in the log: the
+    *   surface, the adapter & what the device got, the pipelines & gpu resources that are
+    *   around, then where the camera is and what the lights are doing. Only logs, so it's the
+    *   same on the web where it ends up in the browser console.
+    */
+    fn dump_state(&self) {
+        use std::fmt::Write;
+
+        let mut dump = String::from("state dump\n");
+        // Writing to a String can't fail
+        let mut line = |args: std::fmt::Arguments| {
+            let _ = writeln!(dump, "  {}", args);
+        };
+        let config = &self.config;
+        line(format_args!(
+            "surface: {}x{} {:?}, {:?}, {:?} alpha, view formats {:?}",
+            config.width, config.height, config.format, config.present_mode, config.alpha_mode, config.view_formats
+        ));
+        let caps = self.surface_capabilities();
+        line(format_args!(
+            "surface supports: formats {:?}, present modes {:?}, alpha modes {:?}",
+            caps.formats, caps.present_modes, caps.alpha_modes
+        ));
+        line(format_args!(
+            "offscreen: {:?} (asked for {:?}){}",
+            self.post_chain.format(),
+            self.offscreen_format,
+            match self.post_chain.tonemap() {
+                Some(_) => ", resolved then tonemapped to the surface's format",
+                None if self.post_chain.always_runs() => ", converted to the surface's format",
+                None => "",
+            }
+        ));
+        line(format_args!(
+            "tonemapping: {}",
+            match self.post_chain.tonemap() {
+                Some(tonemap) => tonemap.curve().to_string(),
+                None => "off, the offscreen format isn't HDR".to_string(),
+            }
+        ));
+        line(format_args!("frame pacing: {}", self.frame_pacer.mode()));
+        line(format_args!(
+            "frame: {}{}",
+            self.stepper.frame(),
+            if self.stepper.frozen() { ", frozen" } else { "" }
+        ));
+        if let Some(model) = self.models.get(self.current_model) {
+            line(format_args!("model: {} ({} of {})", model.name, self.current_model + 1, self.models.len()));
+        }
+        line(format_args!(
+            "terrain indices: {}{}",
+            self.terrain_indices,
+            self.terrain
+                .as_ref()
+                .map_or(String::new(), |terrain| format!(", {} of them", terrain.chunks().index_count()))
+        ));
+        line(format_args!("terrain shading: {}", self.terrain_shading_info()));
+        line(format_args!(
+            "batch draws: {}{}",
+            self.batch_draw,
+            self.batch_draw_time()
+                .map_or(String::new(), |us| format!(", terrain recorded in {:.1}µs", us))
+        ));
+        let info = self.ctx.adapter.get_info();
+        line(format_args!(
+            "adapter: {} ({:?} on {:?}), vendor {:#06x}, device {:#06x}, driver {} {}",
+            info.name, info.device_type, info.backend, info.vendor, info.device, info.driver, info.driver_info
+        ));
+        line(format_args!("features: {:?}", self.features));
+        let limits = &self.limits;
+        line(format_args!(
+            "limits: 2d textures {}, bind groups {}, uniform binding {} bytes, storage binding {} bytes, \
+            buffer {} bytes, vertex buffers {}, storage buffers per stage {}, workgroup invocations {}",
+            limits.max_texture_dimension_2d,
+            limits.max_bind_groups,
+            limits.max_uniform_buffer_binding_size,
+            limits.max_storage_buffer_binding_size,
+            limits.max_buffer_size,
+            limits.max_vertex_buffers,
+            limits.max_storage_buffers_per_shader_stage,
+            limits.max_compute_invocations_per_workgroup
+        ));
+
+        let stats = self.ctx.pipelines.stats();
+        let mut variants = self.render_pipelines.keys().map(|defines| format!("{:?}", defines)).collect::<Vec<_>>();
+        variants.sort();
+        line(format_args!(
+            "pipelines: {} alive, {} shader modules ({} hits, {} misses), scene variants {}",
+            stats.live,
+            stats.shaders,
+            stats.hits,
+            stats.misses,
+            variants.join(", ")
+        ));
+        let extra_meshes = [
+            ("terrain", self.terrain.is_some()),
+            ("skinned", self.skinned.is_some()),
+            ("morphed", self.morphed.is_some()),
+        ];
+        let loaded = extra_meshes.iter().filter(|(_, loaded)| *loaded).map(|(name, _)| *name).collect::<Vec<_>>();
+        line(format_args!(
+            "meshes: {} ({:?} & its low detail version, physics cube{}{}), {} instances, {} physics cubes",
+            3 + loaded.len(),
+            self.model.high_detail().name,
+            if loaded.is_empty() { "" } else { ", " },
+            loaded.join(", "),
+            self.instances.len(),
+            self.physics.world.bodies.len()
+        ));
+        let targets = [
+            ("msaa", self.msaa.is_some()),
+            ("velocity", self.velocity.is_some()),
+            ("taa history", self.taa.is_some()),
+            ("noise", self.noise.is_some()),
+        ];
+        let targets = targets.iter().filter(|(_, used)| *used).map(|(name, _)| *name).collect::<Vec<_>>();
+        line(format_args!(
+            "textures: procedural, depth, post chain{}{}, {} in flight to load",
+            if targets.is_empty() { "" } else { ", " },
+            targets.join(", "),
+            self.loading.as_ref().map_or(0, |loading| loading.progress.total() - loading.progress.loaded())
+        ));
+        let model_textures = self
+            .model_textures
+            .textures
+            .iter()
+            .map(|(slot, texture)| format!("{:?} ({:?})", slot, texture.texture.format()))
+            .collect::<Vec<_>>();
+        line(format_args!(
+            "model textures: {}",
+            if model_textures.is_empty() { "none".to_string() } else { model_textures.join(", ") }
+        ));
+        line(format_args!(
+            "gpu memory: {} bytes, {}, {}",
+            self.gpu_memory_estimate(),
+            gpu_memory::GpuMemory::current(),
+            self.resource_counts()
+        ));
+        line(format_args!(
+            "anti-aliasing: {}, material: {}, polygon mode: {:?}",
+            self.aa_mode, self.material.name, self.polygon_mode
+        ));
+        line(format_args!(
+            "transparency: {} with {}",
+            if self.transparency.enabled { "on" } else { "off" },
+            self.transparency.method()
+        ));
+        line(format_args!(
+            "particles: {}, {} blending, {} alive",
+            if self.particles.enabled { "on" } else { "off" },
+            self.particles.blend_mode,
+            self.particles.count()
+        ));
+        line(format_args!("overdraw view: {}", if self.overdraw.enabled { "on" } else { "off" }));
+        line(format_args!(
+            "uniform ring: slot {} of {}",
+            self.uniform_ring_slot() + 1,
+            self.camera_binding.ring().slots()
+        ));
+        let lod = self.mip_bias_demo.lod();
+        line(format_args!(
+            "mip bias demo: {}, bias {:+.1}, levels {} to {}",
+            if self.mip_bias_demo.enabled { "on" } else { "off" },
+            lod.bias,
+            lod.min_clamp,
+            lod.max_clamp
+        ));
+        let portal_camera = &self.portal.view.camera;
+        line(format_args!(
+            "portal: {}, eye {:?}, looking at {:?}",
+            if self.portal.enabled { "on" } else { "off" },
+            portal_camera.eye,
+            portal_camera.target
+        ));
+        line(format_args!(
+            "leaf: {}, back tint {}",
+            if self.leaf.enabled { "on" } else { "off" },
+            if self.leaf.material.tint_back_faces { "on" } else { "off" }
+        ));
+        let bands = self.audio.bands().map(|band| format!("{:.2}", band));
+        line(format_args!(
+            "audio bars: {}, synthetic beat, bands [{}]",
+            if self.audio.enabled { "on" } else { "off" },
+            bands.join(", ")
+        ));
+        line(format_args!(
+            "hud model: {}, color {}, depth {}",
+            if self.hud_model.enabled { "on" } else { "off" },
+            if self.clear_color_enabled { "cleared" } else { "loaded" },
+            if self.clear_depth_enabled { "cleared" } else { "loaded" }
+        ));
+        line(format_args!(
+            "screen space reflections: {}, {} steps, thickness {}",
+            if self.ssr.enabled { "on" } else { "off" },
+            self.ssr.steps(),
+            self.ssr.thickness()
+        ));
+        line(format_args!(
+            "depth: {}, z-fighting demo {}",
+            self.camera.depth,
+            if self.zfighting.enabled { "on" } else { "off" }
+        ));
+        line(format_args!(
+            "foliage: {}, {} (asked for {}), sample mask {:#b}",
+            if self.foliage.enabled { "on" } else { "off" },
+            self.foliage.current_cutout(),
+            self.foliage.cutout,
+            self.foliage.sample_mask
+        ));
+        line(format_args!(
+            "debug lines: {} of {} last frame, depth test {}, shapes {}",
+            self.debug_lines.line_count(),
+            self.debug_lines.line_capacity(),
+            if self.debug_lines.depth_test { "on" } else { "off" },
+            if self.debug_shapes { "on" } else { "off" }
+        ));
+        line(format_args!(
+            "gizmo: {}, {}, {}",
+            if self.gizmo.enabled { "on" } else { "off" },
+            self.gizmo.mode,
+            match self.selected {
+                Some(index) => format!("object {} selected", index),
+                None => "nothing selected".to_string(),
+            }
+        ));
+        line(format_args!(
+            "color grading: {}{}",
+            self.color_grading,
+            if self.color_grading.is_neutral() { " (off)" } else { "" }
+        ));
+
+        let camera = &self.camera;
+        line(format_args!(
+            "camera: eye {:?}, target {:?}, fovy {}, near {}, far {}, aspect {:.3}",
+            camera.eye,
+            camera.target,
+            camera.fovy,
+            camera.znear,
+            camera.zfar,
+            camera.aspect
+        ));
+        line(format_args!(
+            "cursor: {}, grab {:?}, {}",
+            if self.cursor_captured { "captured" } else { "free" },
+            self.cursor_grab,
+            if self.cursor_visible { "visible" } else { "hidden" }
+        ));
+        let lights = &self.lights;
+        line(format_args!(
+            "sun: direction {:?}, color {:?}",
+            lights.sun.direction, lights.sun.color
+        ));
+        line(format_args!(
+            "ambient: sky {:?}, ground {:?}, hemisphere {}",
+            lights.ambient.sky_color,
+            lights.ambient.ground_color,
+            lights.ambient.is_hemisphere()
+        ));
+        line(format_args!("fog: {:?}", lights.fog.mode()));
+        line(format_args!(
+            "lights: {} of {}, flashlight {}",
+            lights.count(),
+            lights.capacity(),
+            if lights.flashlight.is_some() { "on" } else { "off" }
+        ));
+        for (i, light) in lights.lights.iter().enumerate() {
+            line(format_args!(
+                "  {}: {:?} at {:?}, color {:?} x {}, range {}",
+                i,
+                light.light_type(),
+                light.position,
+                light.color,
+                light.intensity,
+                light.range
+            ));
+        }
+        log::info!("{}", dump.trim_end());
+    }
+
+    /*
+    *   The last frame's depth buffer as distances from the camera, row by row from the top
+    *   left. With MSAA the depth only makes it to depth_texture when post processing needs it,
+    *   so it gets resolved again here to be sure.
+    */
+    #[cfg(not(target_arch = "wasm32"))]
+    fn capture_depth(&self) -> Result<Vec<f32>, String> {
+        if self.loading.is_some() {
+            return Err("the scene hasn't been drawn yet".to_string());
+        }
+        if let Some(msaa) = &self.msaa {
+            let mut encoder = self.ctx.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Depth Capture Resolve Encoder"),
+            });
+            msaa.resolve_depth(&mut encoder, &self.depth_texture.view);
+            self.ctx.queue.submit(std::iter::once(encoder.finish()));
+        }
+        let size = wgpu::Extent3d {
+            width: self.config.width,
+            height: self.config.height,
+            depth_or_array_layers: 1,
+        };
+        let depths = pollster::block_on(depth_capture::read_depth(&self.ctx, &self.depth_texture, size));
+        Ok(depths
+            .into_iter()
+            .map(|depth| depth_capture::linearize(depth, self.camera.znear, self.camera.zfar, self.camera.depth))
+            .collect())
+    }
+
+    // Saves the depth buffer as a grayscale PNG in the working directory
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_depth_png(&self) {
+        let distances = match self.capture_depth() {
+            Ok(distances) => distances,
+            Err(e) => {
+                log::warn!("couldn't capture the depth buffer: {}", e);
+                return;
+            }
+        };
+        let pixels = depth_capture::to_grayscale(&distances, self.camera.zfar);
+        let path = "depth.png";
+        match image::save_buffer(path, &pixels, self.config.width, self.config.height, image::ColorType::L8) {
+            Ok(()) => log::info!("saved the depth buffer to {}", path),
+            Err(e) => log::error!("couldn't save {}: {}", path, e),
+        }
+    }
+
+    // Reads the high detail mesh's vertices back from the gpu and logs them, handy for checking
+    // what a compute shader actually wrote
+    #[cfg(not(target_arch = "wasm32"))]
+    fn dump_vertices(&self) {
+        let mesh = self.model.high_detail();
+        let vertex_size = std::mem::size_of::<model::ModelVertex>();
+        let bytes = self.read_buffer(&mesh.vertex_buffer, (mesh.vertices.len() * vertex_size) as wgpu::BufferAddress);
+        // The Vec<u8> isn't guaranteed to be aligned for ModelVertex, so read each one unaligned
+        let vertices = bytes
+            .chunks_exact(vertex_size)
+            .map(bytemuck::pod_read_unaligned::<model::ModelVertex>)
+            .collect::<Vec<_>>();
+        log::info!("{} vertices read back from {:?}", vertices.len(), mesh.name);
+        for (i, vertex) in vertices.iter().enumerate() {
+            log::info!("  {}: {:?}", i, vertex);
+        }
+    }
+
+    /*
+    *   Builds the terrain from a noise heightmap made on the gpu. Reading the heights back has
+    *   to block, which the web can't do, so there (and without compute shaders) the heightmap
+    *   is made of sine waves on the cpu instead.
+    */
+    fn generate_terrain(&mut self) {
+        const HEIGHTMAP_SIZE: u32 = 257;
+        let params = noise::NoiseParams {
+            frequency: 3.0,
+            ..Default::default()
+        };
+        let heightmap = match &self.noise {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(noise) => pollster::block_on(terrain::Heightmap::from_noise(
+                &self.ctx,
+                noise,
+                HEIGHTMAP_SIZE,
+                HEIGHTMAP_SIZE,
+                &params,
+            )),
+            _ => {
+                let heights = (0..HEIGHTMAP_SIZE * HEIGHTMAP_SIZE)
+                    .map(|i| {
+                        let x = (i % HEIGHTMAP_SIZE) as f32 / HEIGHTMAP_SIZE as f32 * params.frequency;
+                        let z = (i / HEIGHTMAP_SIZE) as f32 / HEIGHTMAP_SIZE as f32 * params.frequency;
+                        0.5 * (x * std::f32::consts::TAU).sin() * (z * std::f32::consts::TAU).cos()
+                    })
+                    .collect();
+                terrain::Heightmap::from_heights(HEIGHTMAP_SIZE, HEIGHTMAP_SIZE, heights)
+            }
+        };
+        // Sunk below the spheres so the hills don't swallow them
+        let transform = instance::Transform::from_position(cgmath::Vector3::new(0.0, -6.0, 0.0));
+        self.terrain = Some(terrain::Terrain::new(
+            &self.ctx.device,
+            &heightmap,
+            &terrain::TerrainSettings {
+                indices: self.terrain_indices,
+                ..Default::default()
+            },
+            transform,
+            self.batch_draw,
+        ));
+        if let Some(terrain) = &self.terrain {
+            let (min, max) = terrain.height_range();
+            self.colormap.set_range(&self.ctx.queue, min, max);
+            self.splat.set_height_range(&self.ctx.queue, min, max);
+        }
+    }
+
+    // Starts recording every frame to a video at `path`. Native only.
+    fn start_recording(&mut self, path: impl AsRef<std::path::Path>) {
+        if cfg!(target_arch = "wasm32") {
+            log::warn!("Recording isn't supported on the web");
+            return;
+        }
+        self.stop_recording();
+        match recording::Recorder::start(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.config,
+            &self.screen.bind_group_layout,
+            &self.post_chain,
+            path.as_ref(),
+            recording::Recorder::DEFAULT_FPS,
+        ) {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(e) => log::error!("couldn't start recording: {}", e),
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.stop();
+        }
+    }
+
+    // Debug & demo hotkeys. Returns true if the key was used.
+    fn handle_key(&mut self, key: VirtualKeyCode) -> bool {
+        // Ctrl + numpad saves a camera bookmark, the key alone goes back to it (shift cuts
+        // straight there)
+        if let Some(slot) = bookmarks::CameraBookmarks::slot_for_key(key) {
+            if self.modifiers.ctrl() {
+                self.save_camera_bookmark(slot);
+            } else {
+                self.goto_camera_bookmark(slot, !self.modifiers.shift());
+            }
+            return true;
+        }
+        match key {
+            // Show/hide the double sided leaf
+            VirtualKeyCode::N if self.modifiers.ctrl() => {
+                self.leaf.enabled = !self.leaf.enabled;
+                if self.leaf.enabled {
+                    self.scene_pipeline(self.leaf.material);
+                }
+                log::info!("leaf: {}", if self.leaf.enabled { "on" } else { "off" });
+                true
+            }
+            // Tint the leaf's back or leave it the same as the front
+            VirtualKeyCode::N if self.modifiers.shift() => {
+                self.leaf.material.tint_back_faces = !self.leaf.material.tint_back_faces;
+                if self.leaf.enabled {
+                    self.scene_pipeline(self.leaf.material);
+                }
+                log::info!("leaf back tint: {}", if self.leaf.material.tint_back_faces { "on" } else { "off" });
+                true
+            }
+            // Toggle the hedgehog normals view
+            VirtualKeyCode::N => {
+                self.normal_lines.enabled = !self.normal_lines.enabled;
+                true
+            }
+            // Run exactly one frame, freezing first if it isn't already
+            VirtualKeyCode::Period if self.modifiers.ctrl() => {
+                if !self.stepper.frozen() {
+                    self.set_frozen(true);
+                }
+                self.stepper.step();
+                true
+            }
+            // Raise/lower the lowest mip level the demo's right half can sample
+            VirtualKeyCode::Comma | VirtualKeyCode::Period if self.mip_bias_demo.enabled && self.modifiers.shift() => {
+                let mut lod = self.mip_bias_demo.lod();
+                lod.min_clamp += if key == VirtualKeyCode::Comma { -1.0 } else { 1.0 };
+                self.set_mip_lod(lod);
+                true
+            }
+            // Sharper/blurrier mip levels on the demo's right half
+            VirtualKeyCode::Comma | VirtualKeyCode::Period if self.mip_bias_demo.enabled => {
+                let mut lod = self.mip_bias_demo.lod();
+                lod.bias += if key == VirtualKeyCode::Comma { -0.5 } else { 0.5 };
+                self.set_mip_lod(lod);
+                true
+            }
+            // Shrink/grow the normal lines
+            VirtualKeyCode::Comma | VirtualKeyCode::Period if self.normal_lines.enabled => {
+                let step = if key == VirtualKeyCode::Comma { -0.05 } else { 0.05 };
+                let length = self.normal_lines.length() + step;
+                self.normal_lines.set_length(&self.ctx.queue, length);
+                true
+            }
+            // More detailed logging, round & round from errors only to everything
+            VirtualKeyCode::F2 => {
+                let level = logging::bump_level();
+                // Warn so it shows at every level but error
+                log::warn!("log level: {}", level);
+                true
+            }
+            // Title bar & border on/off. Some platforms take the border out of the window's inner
+            // size and only sometimes send a Resized for it, so we check for ourselves.
+            #[cfg(not(target_arch = "wasm32"))]
+            VirtualKeyCode::F3 => {
+                self.window.set_decorations(!self.window.is_decorated());
+                let size = self.window.inner_size();
+                if size != self.size {
+                    self.resize(size);
+                }
+                true
+            }
+            // Keep the window above all the others, or not
+            #[cfg(not(target_arch = "wasm32"))]
+            VirtualKeyCode::F4 => {
+                self.always_on_top = !self.always_on_top;
+                self.window.set_window_level(window_level(self.always_on_top));
+                log::info!("always on top: {}", self.always_on_top);
+                true
+            }
+            // The mip bias demo over the bottom of the frame
+            VirtualKeyCode::F7 if self.modifiers.ctrl() => {
+                self.mip_bias_demo.enabled = !self.mip_bias_demo.enabled;
+                log::info!("mip bias demo: {}", if self.mip_bias_demo.enabled { "on" } else { "off" });
+                true
+            }
+            // Center the window on whichever monitor it's (mostly) on
+            #[cfg(not(target_arch = "wasm32"))]
+            VirtualKeyCode::F7 => {
+                if let Some(monitor) = self.window.current_monitor() {
+                    let (origin, area) = (monitor.position(), monitor.size());
+                    // Outer size, so the title bar & border are centered too
+                    let size = self.window.outer_size();
+                    let x = origin.x + (area.width as i32 - size.width as i32) / 2;
+                    let y = origin.y + (area.height as i32 - size.height as i32) / 2;
+                    self.window.set_outer_position(winit::dpi::PhysicalPosition::new(x, y));
+                }
+                true
+            }
+            // Even frame pacing on/off, the jitter gets logged either way (off at debug level)
+            VirtualKeyCode::F8 if self.modifiers.shift() => {
+                let mode = match self.frame_pacer.mode() {
+                    frame_pacing::FramePacing::Off => self.paced,
+                    _ => frame_pacing::FramePacing::Off,
+                };
+                self.set_frame_pacing(mode);
+                true
+            }
+            // Interpolate the bouncing ball between steps or not, to see the stutter it fixes
+            VirtualKeyCode::F8 => {
+                self.interpolate = !self.interpolate;
+                log::info!("interpolation: {}", self.interpolate);
+                true
+            }
+            // Clear a panel behind the HUD, or let the scene show through again
+            VirtualKeyCode::F10 if self.modifiers.ctrl() => {
+                self.hud_panel = !self.hud_panel;
+                log::info!("hud panel: {}", if self.hud_panel { "on" } else { "off" });
+                true
+            }
+            // Frame times & so on, drawn over the scene
+            VirtualKeyCode::F10 if self.modifiers.shift() => {
+                self.hud = !self.hud;
+                true
+            }
+            // Sums up the recent frame times
+            VirtualKeyCode::F10 => {
+                let to_times = |history: &[f32]| {
+                    let samples = history
+                        .iter()
+                        .map(|&ms| instant::Duration::from_secs_f32(ms / 1000.0))
+                        .collect::<Vec<_>>();
+                    bench::FrameTimes::from_samples(&samples)
+                };
+                match to_times(self.frame_time_history()) {
+                    Some(cpu) => log::info!("last {} frames, cpu: {}", self.frame_time_history().len(), cpu),
+                    None => log::info!("no frames drawn yet"),
+                }
+                if let Some(gpu) = to_times(self.gpu_frame_time_history()) {
+                    log::info!("last {} timed frames, gpu: {}", self.gpu_frame_time_history().len(), gpu);
+                }
+                true
+            }
+            // Tearing test, see how it looks with each --present-mode
+            VirtualKeyCode::F12 => {
+                self.tearing_test.set_enabled(!self.tearing_test.enabled());
+                log::info!(
+                    "tearing test: {} ({:?}, {} pixels/s)",
+                    self.tearing_test.enabled(),
+                    self.config.present_mode,
+                    self.tearing_test.speed()
+                );
+                true
+            }
+            // 2d tilemap over the scene
+            VirtualKeyCode::F11 => {
+                self.tilemap.enabled = !self.tilemap.enabled;
+                true
+            }
+            // Boxes bouncing off each other, run on the fixed steps like the ball
+            VirtualKeyCode::F9 => {
+                self.physics.enabled = !self.physics.enabled;
+                true
+            }
+            // The spark fountain, and which blend mode it's drawn with
+            VirtualKeyCode::F6 if self.modifiers.shift() => {
+                self.particles.enabled = !self.particles.enabled;
+                log::info!(
+                    "particles: {}, {} blending",
+                    if self.particles.enabled { "on" } else { "off" },
+                    self.particles.blend_mode
+                );
+                true
+            }
+            VirtualKeyCode::F6 if self.modifiers.ctrl() => {
+                self.particles.blend_mode = self.particles.blend_mode.next();
+                log::info!("particle blend mode: {}", self.particles.blend_mode);
+                true
+            }
+            // Straight vs premultiplied alpha sprites
+            VirtualKeyCode::F6 => {
+                self.alpha_demo.enabled = !self.alpha_demo.enabled;
+                true
+            }
+            // Scene as a wireframe, for the devices that can draw one
+            VirtualKeyCode::F5 => {
+                if !self.features.contains(wgpu::Features::POLYGON_MODE_LINE) {
+                    log::warn!("wireframe needs POLYGON_MODE_LINE, which this device doesn't have");
+                    return true;
+                }
+                self.polygon_mode = match self.polygon_mode {
+                    wgpu::PolygonMode::Fill => wgpu::PolygonMode::Line,
+                    _ => wgpu::PolygonMode::Fill,
+                };
+                self.recreate_surface_pipelines();
+                true
+            }
+            // Swap the spheres between the textured & untextured material
+            VirtualKeyCode::F1 => {
+                self.material = if self.material.textured {
+                    material::Material::UNTEXTURED
+                } else {
+                    material::Material::TEXTURED
+                };
+                self.scene_pipeline(self.material);
+                log::info!("material: {}", self.material.name);
+                true
+            }
+            // Freeze the culling frustum where it is, or go back to the live camera
+            VirtualKeyCode::Key0 => {
+                self.frozen_frustum = match self.frozen_frustum {
+                    Some(_) => None,
+                    None => Some(camera::Frustum::from_camera(&self.camera)),
+                };
+                self.frustum_lines = self.frozen_frustum.map(|frustum| self.create_frustum_lines(&frustum));
+                log::info!("culling frustum {}", if self.frozen_frustum.is_some() { "frozen" } else { "live" });
+                true
+            }
+            VirtualKeyCode::L if self.modifiers.ctrl() => {
+                let thickness = if self.ssr.thickness() >= 2.0 { 0.125 } else { self.ssr.thickness() * 2.0 };
+                self.ssr.set_thickness(&self.ctx.queue, thickness);
+                log::info!("reflection thickness: {}", thickness);
+                true
+            }
+            // Toggle input latency measurement
+            VirtualKeyCode::L => {
+                self.latency.enabled = !self.latency.enabled;
+                log::info!("latency measurement: {}", self.latency.enabled);
+                true
+            }
+            // Save the depth buffer as an image
+            VirtualKeyCode::Backslash => {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        log::warn!("Can't block on a texture read back on the web, use depth_capture::read_depth");
+                    } else {
+                        self.save_depth_png();
+                    }
+                }
+                true
+            }
+            // Translucent panels, drawn with weighted blended OIT where it's supported
+            VirtualKeyCode::NumpadMultiply => {
+                self.transparency.enabled = !self.transparency.enabled;
+                log::info!(
+                    "transparent panels: {}, {}",
+                    if self.transparency.enabled { "on" } else { "off" },
+                    self.transparency.method()
+                );
+                true
+            }
+            VirtualKeyCode::NumpadDivide => {
+                let method = match self.transparency.method() {
+                    transparency::TransparencyMethod::WeightedBlended => transparency::TransparencyMethod::Sorted,
+                    transparency::TransparencyMethod::Sorted => transparency::TransparencyMethod::WeightedBlended,
+                };
+                if self.transparency.set_method(method) {
+                    log::info!("transparency: {}", method);
+                } else {
+                    log::warn!("{} isn't supported here, sticking with {}", method, self.transparency.method());
+                }
+                true
+            }
+            // Color grading: NumpadEnter picks what to change (shift resets everything),
+            // Numpad0/NumpadDecimal turn it down/up
+            VirtualKeyCode::NumpadEnter => {
+                if self.modifiers.shift() {
+                    self.color_grading.reset(&self.ctx.queue);
+                    log::info!("color grading reset");
+                } else {
+                    self.color_grading.selected = self.color_grading.selected.next();
+                    let selected = self.color_grading.selected;
+                    log::info!("color grading: adjusting {} ({:.2})", selected, self.color_grading.value(selected));
+                }
+                true
+            }
+            VirtualKeyCode::Numpad0 | VirtualKeyCode::NumpadDecimal => {
+                let selected = self.color_grading.selected;
+                let step = if key == VirtualKeyCode::Numpad0 { -selected.step() } else { selected.step() };
+                let value = self.color_grading.value(selected) + step;
+                self.color_grading.set_value(&self.ctx.queue, selected, value);
+                log::info!("color grading: {}", self.color_grading);
+                true
+            }
+            VirtualKeyCode::Insert => {
+                self.minimap.enabled = !self.minimap.enabled;
+                log::info!("minimap: {}", self.minimap.enabled);
+                true
+            }
+            VirtualKeyCode::Delete => {
+                let mut layout = self.minimap.layout();
+                layout.corner = match layout.corner {
+                    minimap::Corner::TopLeft => minimap::Corner::TopRight,
+                    minimap::Corner::TopRight => minimap::Corner::BottomRight,
+                    minimap::Corner::BottomRight => minimap::Corner::BottomLeft,
+                    minimap::Corner::BottomLeft => minimap::Corner::TopLeft,
+                };
+                self.minimap.set_layout(layout);
+                log::info!("minimap corner: {:?}", layout.corner);
+                true
+            }
+            // Show/hide the inspector windows
+            VirtualKeyCode::Grave if self.modifiers.ctrl() => {
+                self.inspector.enabled = !self.inspector.enabled;
+                log::info!("inspector: {}", if self.inspector.enabled { "on" } else { "off" });
+                true
+            }
+            // Up & down go through the inspector's rows
+            VirtualKeyCode::Up | VirtualKeyCode::Down if self.inspector.enabled => {
+                self.inspector.move_focus(if key == VirtualKeyCode::Up { -1 } else { 1 });
+                true
+            }
+            // Ctrl + left & right dock the focused row's window on that side
+            VirtualKeyCode::Left | VirtualKeyCode::Right if self.inspector.enabled && self.modifiers.ctrl() => {
+                let dock = if key == VirtualKeyCode::Left { inspector::Dock::Left } else { inspector::Dock::Right };
+                self.inspector.dock_focused(dock);
+                true
+            }
+            // Left & right change the focused row's value, a tenth as much with shift held
+            VirtualKeyCode::Left | VirtualKeyCode::Right if self.inspector.enabled => {
+                let step = if self.modifiers.shift() { 0.1 } else { 1.0 };
+                self.edit_inspector_field(if key == VirtualKeyCode::Left { -step } else { step });
+                true
+            }
+            // Log everything about the device & state, for bug reports
+            VirtualKeyCode::Grave => {
+                self.dump_state();
+                true
+            }
+            // Save the scene to scene.json, ' loads it back
+            VirtualKeyCode::Slash => {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        log::warn!("There's no file system to save a scene to on the web");
+                    } else {
+                        self.save_scene(std::path::Path::new("scene.json"));
+                    }
+                }
+                true
+            }
+            VirtualKeyCode::Apostrophe => {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        log::warn!("There's no file system to load a scene from on the web");
+                    } else {
+                        self.load_scene(std::path::Path::new("scene.json"));
+                    }
+                }
+                true
+            }
+            // Hold a cube in the corner of the view, over the finished frame
+            VirtualKeyCode::V if self.modifiers.shift() => {
+                self.hud_model.enabled = !self.hud_model.enabled;
+                log::info!("hud model: {}", if self.hud_model.enabled { "on" } else { "off" });
+                true
+            }
+            // Clear the depth under the HUD model or keep the scene's, to hide it behind things
+            VirtualKeyCode::V if self.modifiers.ctrl() => {
+                self.set_clear_depth_enabled(!self.clear_depth_enabled);
+                true
+            }
+            // Clear the color under the HUD model, leaving it on its own
+            VirtualKeyCode::B if self.modifiers.ctrl() => {
+                self.set_clear_color_enabled(!self.clear_color_enabled);
+                true
+            }
+            // Read the vertex buffer back from the gpu and log it
+            VirtualKeyCode::V => {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        log::warn!("Can't block on a buffer read back on the web, use GpuContext::read_buffer_async");
+                    } else {
+                        self.dump_vertices();
+                    }
+                }
+                true
+            }
+            // Freeze frame, or run continuously again
+            VirtualKeyCode::F if self.modifiers.ctrl() => {
+                self.set_frozen(!self.stepper.frozen());
+                true
+            }
+            // Point the camera at the model again, after flying off somewhere
+            VirtualKeyCode::F if self.modifiers.shift() => {
+                if self.models.is_empty() {
+                    log::info!("nothing to frame until the model has loaded");
+                } else {
+                    self.frame_model();
+                    log::info!("framed {}", self.models[self.current_model].name);
+                }
+                true
+            }
+            // Toggle depth of field
+            VirtualKeyCode::F => {
+                self.dof.enabled = !self.dof.enabled;
+                log::info!("depth of field: {}", self.dof.enabled);
+                true
+            }
+            // Move the focal distance closer/further
+            VirtualKeyCode::PageUp | VirtualKeyCode::PageDown if self.dof.enabled => {
+                let step = if key == VirtualKeyCode::PageUp { 0.25 } else { -0.25 };
+                let distance = self.dof.focal_distance() + step;
+                self.dof.set_focal_distance(&self.ctx.queue, distance);
+                log::info!("focal distance: {:.2}", self.dof.focal_distance());
+                true
+            }
+            // Open/close the aperture
+            VirtualKeyCode::Home | VirtualKeyCode::End if self.dof.enabled => {
+                let scale = if key == VirtualKeyCode::Home { 1.25 } else { 0.8 };
+                let aperture = self.dof.aperture() * scale;
+                self.dof.set_aperture(&self.ctx.queue, aperture);
+                log::info!("aperture: {:.2}", self.dof.aperture());
+                true
+            }
+            // Previous/next model, framed so it's all in view whatever size it is
+            VirtualKeyCode::LBracket | VirtualKeyCode::RBracket if self.modifiers.shift() => {
+                let count = self.models.len();
+                if count > 1 {
+                    let step = if key == VirtualKeyCode::LBracket { count - 1 } else { 1 };
+                    self.show_model((self.current_model + step) % count);
+                    self.frame_model();
+                    log::info!("model {} of {}: {}", self.current_model + 1, count, self.models[self.current_model].name);
+                } else {
+                    log::info!("there's only the one model, --model more than once loads more");
+                }
+                true
+            }
+            // Pull the LOD & cull distances in/push them out
+            VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                let scale = if key == VirtualKeyCode::LBracket { 0.8 } else { 1.25 };
+                self.model.low_detail_distance *= scale;
+                self.model.cull_distance *= scale;
+                log::info!(
+                    "lod distance: {:.1}, cull distance: {:.1}",
+                    self.model.low_detail_distance,
+                    self.model.cull_distance,
+                );
+                true
+            }
+            // Speed up/slow down time
+            VirtualKeyCode::Plus | VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd
+            | VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                let step = if matches!(key, VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract) {
+                    -Self::TIME_SCALE_STEP
+                } else {
+                    Self::TIME_SCALE_STEP
+                };
+                let (min, max) = Self::TIME_SCALE_RANGE;
+                self.time_scale = (self.time_scale + step).clamp(min, max);
+                log::info!("time scale: {:.2}", self.time_scale);
+                true
+            }
+            // Color the terrain by height: off, viridis, turbo
+            VirtualKeyCode::Y if self.modifiers.ctrl() => {
+                let colormapped = self.terrain_shading == terrain::TerrainShading::Colormap;
+                let colormap = match (colormapped, self.colormap.colormap()) {
+                    (false, _) => Some(colormap::Colormap::Viridis),
+                    (true, colormap::Colormap::Viridis) => Some(colormap::Colormap::Turbo),
+                    (true, colormap::Colormap::Turbo) => None,
+                };
+                self.set_terrain_colormap(colormap);
+                true
+            }
+            // The colormap's range over all of the terrain's heights, or just the upper half so
+            // the hilltops get the whole map & the valleys all look the same
+            VirtualKeyCode::Y if self.modifiers.shift() => {
+                match &self.terrain {
+                    Some(terrain) => {
+                        let (min, max) = terrain.height_range();
+                        let min = if self.colormap.range().0 == min { (min + max) / 2.0 } else { min };
+                        self.colormap.set_range(&self.ctx.queue, min, max);
+                        log::info!("terrain colormap range: {:.2} to {:.2}", min, max);
+                    }
+                    None => log::info!("terrain colormap range: fitted to the terrain, once there is one (O)"),
+                }
+                true
+            }
+            // Switch to the next surface format. The gradient shows until we're back at the
+            // preferred one.
+            VirtualKeyCode::Y => {
+                self.cycle_surface_format();
+                self.gradient.enabled = self.config.format != Self::preferred_surface_format(&self.surface_caps);
+                true
+            }
+            // How many samples the terrain splatting takes at glancing angles: 1, 2, 4, 8, 16
+            VirtualKeyCode::M if self.modifiers.ctrl() => {
+                let anisotropy = match self.splat.anisotropy() {
+                    splat::TerrainSplat::MAX_ANISOTROPY => 1,
+                    anisotropy => anisotropy * 2,
+                };
+                self.splat.set_anisotropy(&self.ctx.device, anisotropy);
+                let downlevel = self.ctx.adapter.get_downlevel_capabilities().flags;
+                if !downlevel.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING) {
+                    log::warn!("This adapter can't filter anisotropically, it's 1x whatever it's set to");
+                }
+                log::info!("terrain splatting anisotropy: {}x", anisotropy);
+                true
+            }
+            // Cycle through the anti-aliasing techniques
+            VirtualKeyCode::M => {
+                let mut mode = self.aa_mode.next();
+                // Skip past whatever the surface can't do
+                while !self.set_aa_mode(mode) {
+                    mode = mode.next();
+                }
+                true
+            }
+            // Store and/or resolve the MSAA samples, only matters with MSAA on
+            VirtualKeyCode::Semicolon => {
+                self.msaa_attachment = self.msaa_attachment.next();
+                log::info!("msaa attachment: {}", self.msaa_attachment);
+                if self.msaa.is_none() {
+                    log::info!("MSAA is off, turn it on with M to see the difference");
+                }
+                true
+            }
+            // Show another view of the scene on a TV screen behind the grid
+            VirtualKeyCode::P if self.modifiers.ctrl() => {
+                self.portal.enabled = !self.portal.enabled;
+                log::info!("portal: {}", if self.portal.enabled { "on" } else { "off" });
+                true
+            }
+            // Put the TV's camera where the main one is, keeping its own aspect & planes
+            VirtualKeyCode::P if self.modifiers.shift() => {
+                let camera = &mut self.portal.view.camera;
+                camera.eye = self.camera.eye;
+                camera.target = self.camera.target;
+                camera.up = self.camera.up;
+                log::info!("portal camera: eye {:?}, looking at {:?}", camera.eye, camera.target);
+                true
+            }
+            // Regenerate the procedural texture with more rings, wrapping back around
+            VirtualKeyCode::P => {
+                let frequency = if self.procedural.frequency() >= 16.0 { 1.0 } else { self.procedural.frequency() * 2.0 };
+                self.procedural.set_frequency(&self.ctx.device, &self.ctx.queue, frequency);
+                log::info!("procedural texture frequency: {}", frequency);
+                true
+            }
+            // Toggle screen space reflections
+            VirtualKeyCode::K if self.modifiers.shift() => {
+                self.ssr.enabled = !self.ssr.enabled;
+                log::info!("screen space reflections: {}", if self.ssr.enabled { "on" } else { "off" });
+                true
+            }
+            // Cycle the reflection ray-march steps
+            VirtualKeyCode::K if self.modifiers.ctrl() => {
+                let steps = match self.ssr.steps() {
+                    16 => 32,
+                    32 => 64,
+                    _ => 16,
+                };
+                self.ssr.set_steps(&self.ctx.queue, steps);
+                log::info!("reflection steps: {}", steps);
+                true
+            }
+            // Swap the procedural texture for animated noise clouds and back
+            VirtualKeyCode::K => {
+                if self.noise.is_some() {
+                    self.clouds = !self.clouds;
+                    if !self.clouds {
+                        self.procedural.generate(&self.ctx.device, &self.ctx.queue);
+                    }
+                    log::info!("noise clouds: {}", self.clouds);
+                } else {
+                    log::warn!("This adapter can't generate noise textures");
+                }
+                true
+            }
+            // Triangle list, strips joined by degenerate triangles or strips with primitive
+            // restart for the terrain
+            VirtualKeyCode::O if self.modifiers.ctrl() => {
+                self.set_terrain_indices(self.terrain_indices.next());
+                true
+            }
+            // Overdraw heatmap
+            VirtualKeyCode::O if self.modifiers.shift() => {
+                self.overdraw.enabled = !self.overdraw.enabled;
+                log::info!("overdraw view: {}", if self.overdraw.enabled { "on" } else { "off" });
+                true
+            }
+            // Show/hide the terrain
+            VirtualKeyCode::O => {
+                match &mut self.terrain {
+                    Some(terrain) => terrain.enabled = !terrain.enabled,
+                    None => self.generate_terrain(),
+                }
+                true
+            }
+            // Toggle a spotlight that follows the camera
+            VirtualKeyCode::X => {
+                if self.lights.flashlight.is_none() && self.lights.is_full() {
+                    // Every slot's taken, make one more rather than drop a light
+                    if let Err(e) = self.set_light_capacity(self.light_capacity() + 1) {
+                        log::warn!("No room for the flashlight: {}", e);
+                        return true;
+                    }
+                }
+                self.lights.flashlight = match self.lights.flashlight {
+                    Some(_) => None,
+                    None => Some(light::Lights::default_flashlight()),
+                };
+                // Has to go out now, update() only uploads while it's on
+                self.lights.update(&self.ctx.queue);
+                true
+            }
+            // Show/hide the bars pulsing to the synthetic beat
+            VirtualKeyCode::U if self.modifiers.ctrl() => {
+                self.audio.enabled = !self.audio.enabled;
+                if self.audio.enabled && self.audio_pipeline.is_none() {
+                    self.audio_pipeline = Some(self.create_audio_pipeline());
+                }
+                log::info!("audio bars: {}", if self.audio.enabled { "on" } else { "off" });
+                true
+            }
+            // One, two or three buffers for the camera & lights uniforms, round & round
+            VirtualKeyCode::U if self.modifiers.shift() => {
+                let slots = self.camera_binding.ring().slots() % 3 + 1;
+                if let Err(e) = self.set_uniform_ring_size(slots) {
+                    log::warn!("{}", e);
+                }
+                true
+            }
+            // Swing the sun around
+            VirtualKeyCode::U => {
+                self.lights.rotate_sun(&self.ctx.queue, cgmath::Deg(15.0).into());
+                true
+            }
+            // Darken or brighten the shadows
+            VirtualKeyCode::Q | VirtualKeyCode::E => {
+                let factor = if key == VirtualKeyCode::Q { 0.8 } else { 1.25 };
+                self.lights.ambient = self.lights.ambient.scaled(factor);
+                self.lights.update(&self.ctx.queue);
+                log::info!("Ambient sky color: {:?}", self.lights.ambient.sky_color);
+                true
+            }
+            // Show the world's axes, the physics boxes & so on with the debug lines
+            VirtualKeyCode::J if self.modifiers.ctrl() => {
+                self.debug_shapes = !self.debug_shapes;
+                log::info!("debug shapes: {}", if self.debug_shapes { "on" } else { "off" });
+                true
+            }
+            // Hide the debug lines behind the scene or draw them over it
+            VirtualKeyCode::J if self.modifiers.shift() => {
+                self.debug_lines.depth_test = !self.debug_lines.depth_test;
+                log::info!("debug lines depth test: {}", if self.debug_lines.depth_test { "on" } else { "off" });
+                true
+            }
+            VirtualKeyCode::J => {
+                let hemisphere = !self.lights.ambient.is_hemisphere();
+                self.lights.ambient = self.lights.ambient.with_hemisphere(hemisphere);
+                self.lights.update(&self.ctx.queue);
+                log::info!("Hemisphere ambient: {}", hemisphere);
+                true
+            }
+            // Reversed depth, and the squares that show what it's for
+            VirtualKeyCode::Z if self.modifiers.shift() => {
+                self.set_depth_direction(match self.camera.depth {
+                    camera::DepthDirection::Standard => camera::DepthDirection::Reversed,
+                    camera::DepthDirection::Reversed => camera::DepthDirection::Standard,
+                });
+                true
+            }
+            VirtualKeyCode::Z if self.modifiers.ctrl() => {
+                self.zfighting.enabled = !self.zfighting.enabled;
+                log::info!("z-fighting demo: {}", if self.zfighting.enabled { "on" } else { "off" });
+                true
+            }
+            VirtualKeyCode::Z => {
+                match &mut self.morphed {
+                    Some(morphed) => morphed.enabled = !morphed.enabled,
+                    None => log::warn!("Morph targets need storage buffers in vertex shaders, which this adapter doesn't have"),
+                }
+                true
+            }
+            VirtualKeyCode::B => {
+                match &mut self.skinned {
+                    Some(skinned) => skinned.enabled = !skinned.enabled,
+                    None => log::warn!("Skinning needs storage buffers in vertex shaders, which this adapter doesn't have"),
+                }
+                true
+            }
+            VirtualKeyCode::Key1 => {
+                match &mut self.motion_blur {
+                    Some(motion_blur) => motion_blur.enabled = !motion_blur.enabled,
+                    None => log::warn!("Motion blur needs a {:?} velocity buffer, which this adapter can't render to", velocity::VelocityBuffer::FORMAT),
+                }
+                true
+            }
+            VirtualKeyCode::Key2 => {
+                if let Some(motion_blur) = self.motion_blur.as_mut().filter(|motion_blur| motion_blur.enabled) {
+                    // 0.25 -> 0.5 -> ... -> 4 and around again
+                    let intensity = if motion_blur.intensity() >= 4.0 { 0.25 } else { motion_blur.intensity() * 2.0 };
+                    motion_blur.set_intensity(&self.ctx.queue, intensity);
+                    log::info!("motion blur intensity: {}", intensity);
+                }
+                true
+            }
+            // Procedural sky instead of the flat background. The fog follows along, so
+            // distant things fade into whichever is showing.
+            VirtualKeyCode::Key3 => {
+                self.sky.enabled = !self.sky.enabled;
+                self.lights.fog.color = if self.sky.enabled {
+                    self.sky.settings.horizon_color
+                } else {
+                    let background = Self::CLEAR_COLOR;
+                    [background.r as f32, background.g as f32, background.b as f32]
+                };
+                self.lights.update(&self.ctx.queue);
+                true
+            }
+            VirtualKeyCode::Key4 => {
+                let mode = self.lights.fog.mode().next();
+                self.lights.fog = self.lights.fog.with_mode(mode);
+                self.lights.update(&self.ctx.queue);
+                log::info!("fog: {:?}", mode);
+                true
+            }
+            // Pull the fog in closer or push it further out
+            VirtualKeyCode::Key5 | VirtualKeyCode::Key6 => {
+                let factor = if key == VirtualKeyCode::Key5 { 1.25 } else { 0.8 };
+                self.lights.fog = self.lights.fog.thicker(factor);
+                self.lights.update(&self.ctx.queue);
+                log::info!("fog: {:.1}..{:.1} (linear), density {:.3} (exponential)", self.lights.fog.start, self.lights.fog.end, self.lights.fog.density);
+                true
+            }
+            // Outline the animated instance using the stencil buffer
+            VirtualKeyCode::Key7 => {
+                self.outline.enabled = !self.outline.enabled;
+                true
+            }
+            VirtualKeyCode::Key8 => {
+                self.outline.method = self.outline.method.next();
+                log::info!("outline: {:?}", self.outline.method);
+                true
+            }
+            // 1 -> 2 -> 4 -> 8 and around again
+            VirtualKeyCode::Key9 => {
+                let thickness = if self.outline.thickness() >= 8.0 { 1.0 } else { self.outline.thickness() * 2.0 };
+                self.outline.set_thickness(&self.ctx.queue, thickness);
+                log::info!("outline thickness: {}", thickness);
+                true
+            }
+            // Stop clearing the frame and let things leave trails
+            VirtualKeyCode::Tab => {
+                self.clear_each_frame = !self.clear_each_frame;
+                if !self.clear_each_frame {
+                    self.trails.reset();
+                }
+                log::info!("clear each frame: {}", self.clear_each_frame);
+                true
+            }
+            VirtualKeyCode::T if self.modifiers.shift() => {
+                self.gizmo.enabled = !self.gizmo.enabled;
+                self.gizmo.end_drag();
+                log::info!(
+                    "gizmo: {}{}",
+                    if self.gizmo.enabled { "on" } else { "off" },
+                    if self.gizmo.enabled && self.selected.is_none() { ", click an object to select it" } else { "" }
+                );
+                true
+            }
+            VirtualKeyCode::T if self.modifiers.ctrl() => {
+                self.gizmo.mode = self.gizmo.mode.next();
+                self.gizmo.end_drag();
+                log::info!("gizmo: {}", self.gizmo.mode);
+                true
+            }
+            // Toggle the calibration test pattern
+            VirtualKeyCode::T => {
+                self.test_pattern.enabled = !self.test_pattern.enabled;
+                true
+            }
+            // The cursor's grab (none -> confined -> locked) & visibility on their own, without
+            // flying. Both go back to normal when it's captured & released.
+            VirtualKeyCode::C if self.modifiers.shift() => {
+                let mode = match self.cursor_grab {
+                    CursorGrabMode::None => CursorGrabMode::Confined,
+                    CursorGrabMode::Confined => CursorGrabMode::Locked,
+                    CursorGrabMode::Locked => CursorGrabMode::None,
+                };
+                if self.set_cursor_grab(mode) {
+                    log::info!("cursor grab: {:?}", self.cursor_grab);
+                }
+                true
+            }
+            VirtualKeyCode::C if self.modifiers.ctrl() => {
+                self.set_cursor_visible(!self.cursor_visible);
+                log::info!("cursor {}", if self.cursor_visible { "shown" } else { "hidden" });
+                true
+            }
+            // Capture the cursor and fly around
+            VirtualKeyCode::C => {
+                self.set_cursor_captured(!self.cursor_captured);
+                true
+            }
+            // Start/stop recording a video
+            VirtualKeyCode::R => {
+                if self.recorder.is_some() {
+                    self.stop_recording();
+                } else {
+                    self.start_recording("recording.mp4");
+                }
+                true
+            }
+            // Grass, rock & snow on the terrain by slope & height, or back to the texture
+            VirtualKeyCode::I if self.modifiers.ctrl() => {
+                let shading = if self.terrain_shading == terrain::TerrainShading::Splat {
+                    terrain::TerrainShading::Textured
+                } else {
+                    terrain::TerrainShading::Splat
+                };
+                self.set_terrain_shading(shading);
+                true
+            }
+            // Next way of drawing the terrain's chunks the device can do, logging how long the
+            // last one took to record
+            VirtualKeyCode::I if self.modifiers.shift() => {
+                let features = self.features;
+                let downlevel = self.ctx.adapter.get_downlevel_capabilities().flags;
+                let mut mode = self.batch_draw.next();
+                while !mode.is_supported(features, downlevel) {
+                    mode = mode.next();
+                }
+                if let Some(us) = self.batch_draw_time() {
+                    log::info!("{} terrain draws took {:.1}µs to record", self.batch_draw, us);
+                }
+                self.set_batch_draw(mode);
+                match &self.terrain {
+                    Some(terrain) => log::info!(
+                        "terrain draws: {}, {} calls for {} chunks",
+                        mode,
+                        terrain.chunks().draw_calls(),
+                        terrain.chunks().mesh_count()
+                    ),
+                    None => log::info!("terrain draws: {}, the terrain isn't there yet (O)", mode),
+                }
+                true
+            }
+            // Draw through draw_indexed_indirect with arguments written by the cpu
+            VirtualKeyCode::I => {
+                if lod::LodModel::supports_indirect(&self.ctx.adapter) {
+                    let enabled = !self.model.indirect();
+                    self.model.set_indirect(&self.ctx.device, enabled);
+                    log::info!("indirect drawing: {}", enabled);
+                } else {
+                    log::warn!("This adapter can't do indirect draws");
+                }
+                true
+            }
+            VirtualKeyCode::G if self.modifiers.shift() => {
+                self.foliage.enabled = !self.foliage.enabled;
+                log::info!("foliage: {}", if self.foliage.enabled { "on" } else { "off" });
+                true
+            }
+            VirtualKeyCode::G if self.modifiers.ctrl() => {
+                self.foliage.cutout = self.foliage.cutout.next();
+                self.recreate_surface_pipelines();
+                if self.foliage.current_cutout() == self.foliage.cutout {
+                    log::info!("foliage cutout: {}", self.foliage.cutout);
+                } else {
+                    log::info!("foliage cutout: {} needs MSAA, turn it on with M", self.foliage.cutout);
+                }
+                true
+            }
+            // Switch between tonemapping curves, with an HDR offscreen format
+            VirtualKeyCode::H if self.modifiers.ctrl() => {
+                self.set_tonemap_curve(self.tonemap_curve.next());
+                true
+            }
+            VirtualKeyCode::H if self.modifiers.shift() => {
+                self.foliage.sample_mask = foliage::Foliage::next_sample_mask(self.foliage.sample_mask);
+                self.recreate_surface_pipelines();
+                log::info!("foliage sample mask: {:#b}", self.foliage.sample_mask);
+                true
+            }
+            // Move frustum culling between the cpu & gpu
+            VirtualKeyCode::G => {
+                if gpu_cull::GpuCuller::is_supported(&self.ctx.adapter, &self.limits) {
+                    let enabled = !self.model.gpu_culling();
+                    self.model.set_gpu_culling(&self.ctx.device, enabled);
+                    log::info!("gpu culling: {}", enabled);
+                } else {
+                    log::warn!("This adapter can't run the gpu culling pass");
+                }
+                true
+            }
+            // Time culling a lot of instances on the cpu vs the gpu
+            VirtualKeyCode::H => {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        log::warn!("No gpu culling on the web");
+                    } else {
+                        let frustum = self
+                            .frozen_frustum
+                            .unwrap_or_else(|| camera::Frustum::from_camera(&self.camera));
+                        gpu_cull::compare_with_cpu(&self.ctx, &frustum, 200_000);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // See frame_pacing.rs. Off goes back to drawing whenever the window asks.
+    fn set_frame_pacing(&mut self, mode: frame_pacing::FramePacing) {
+        if mode != frame_pacing::FramePacing::Off {
+            self.paced = mode;
+        }
+        self.frame_pacer.set_mode(mode);
+        log::info!("frame pacing: {}", mode);
+    }
+
+    // While recording every frame covers the same amount of time, however long it took
+    fn frame_duration(&self, elapsed: instant::Duration) -> instant::Duration {
+        self.recorder.as_ref().map_or(elapsed, |recorder| recorder.frame_duration())
+    }
+
+    /*
+    *   Everything for one frame: the update with the time since the last one, as many fixed
+    *   steps as fit, and the render. Frozen (see frame_step.rs) it only runs for a single step,
+    *   with exactly one fixed step's time.
+    */
+    fn run_frame(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // Animations need to know how much time has passed since this window's last frame
+        let now = instant::Instant::now();
+        let elapsed = self.frame_duration(now - self.last_render_time);
+        self.last_render_time = now;
+        let dt = match self.stepper.next_frame(elapsed, self.timestep.step()) {
+            Some(dt) => dt,
+            None => return Ok(()),
+        };
+        self.update(dt);
+        // The simulation catches up with real time in fixed steps
+        for _ in 0..self.timestep.advance(dt) {
+            self.fixed_update(self.timestep.step());
+        }
+        if self.stepper.frozen() {
+            log::info!("frame {}, animation time {:.3}s", self.stepper.frame(), self.animation_time);
+        }
+        self.render(self.timestep.alpha())
+    }
+
+    // Freezes on the frame that's up, or carries on in real time from it
+    fn set_frozen(&mut self, frozen: bool) {
+        self.stepper.set_frozen(frozen);
+        // The time spent frozen isn't caught up on
+        self.last_render_time = instant::Instant::now();
+        log::info!(
+            "frozen: {}, at frame {}{}",
+            if frozen { "on" } else { "off" },
+            self.stepper.frame(),
+            if frozen { ", ctrl + . steps" } else { "" }
+        );
+    }
+
+    // Once a frame with the real time since the last one: loading, the camera & anything else
+    // that follows the user rather than the simulation
+    fn update(&mut self, dt: instant::Duration) {
+        self.update_loading();
+
+        // The camera moves in real time, pausing the animations shouldn't freeze it. A tween to a
+        // bookmark has it to itself until it's done.
+        if let Some(tween) = &mut self.camera_tween {
+            tween.step(dt).apply(&mut self.camera);
+            self.camera_binding.update(&self.ctx.queue, &self.camera);
+            if tween.finished() {
+                self.camera_tween = None;
+                self.sync_fly_camera();
+            }
+        } else if self.cursor_captured {
+            self.fly_camera.update_camera(&mut self.camera, dt);
+            self.camera_binding.update(&self.ctx.queue, &self.camera);
+        }
+        // The camera may have moved under a cursor that's stayed put
+        self.mouse_world = self.mouse_world_on_plane(Self::FOLLOWER_PLANE_Y);
+        self.tearing_test.update(&self.ctx.queue, dt);
+        // TAA moves the camera by a fraction of a pixel every frame. Nothing else gets the
+        // jitter, the velocity buffer & culling still see the camera where it really is.
+        if let Some(taa) = self.taa.as_mut().filter(|taa| taa.enabled) {
+            let jitter = taa.next_frame(&self.ctx.queue, self.size);
+            self.camera_binding.update_jittered(&self.ctx.queue, &self.camera, jitter);
+        }
+
+        // The flashlight is held by the camera
+        if let Some(flashlight) = &mut self.lights.flashlight {
+            flashlight.position = self.camera.eye.into();
+            flashlight.direction = cgmath::InnerSpace::normalize(self.camera.target - self.camera.eye).into();
+            self.lights.update(&self.ctx.queue);
+        }
+
+        if self.sky.enabled {
+            self.sky.update(&self.ctx.queue, &self.camera, self.lights.sun.direction);
+        }
+    }
+
+    // Moves the simulation forward by exactly one fixed step, see timestep.rs. Scaling the step
+    // here rather than in the animations means everything that moves agrees on how fast time
+    // passes. It doesn't touch vsync, we still render every frame.
+    fn fixed_update(&mut self, step: instant::Duration) {
+        let dt = step.as_secs_f32() * self.time_scale;
+        self.animation_time += dt;
+        self.bouncer.step(dt);
+        self.physics.step(dt);
+        self.particles.step(dt);
+    }
+
+    // Poses everything for the frame we're about to draw, `alpha` of the way from the second to
+    // last fixed step to the last one, and uploads it
+    fn prepare_frame(&mut self, alpha: f32) {
+        // The animations are all functions of time, so the pose between two steps is the pose
+        // at a time between them
+        let step = self.timestep.step().as_secs_f32() * self.time_scale;
+        self.frame_time = (self.animation_time - (1.0 - alpha) * step).max(0.0);
+        let bouncer = if self.interpolate {
+            self.bouncer.transform.at(alpha)
+        } else {
+            self.bouncer.transform.current
+        };
+        let bouncer_index = self.instances.len() - 2;
+        self.instances[bouncer_index] = bouncer;
+        // The follower stays where it was while the cursor's somewhere it can't follow
+        if let Some(position) = self.mouse_world {
+            let follower_index = self.instances.len() - Self::MOVING_INSTANCES;
+            self.instances[follower_index].position = position;
+        }
+        self.physics.update(&self.ctx.queue, if self.interpolate { alpha } else { 1.0 });
+        if self.tilemap.enabled {
+            // Drift back & forth over the whole map, there's more of it than fits on the screen
+            let t = self.frame_time;
+            let room = self.tilemap.pixel_size() - cgmath::Vector2::new(self.size.width as f32, self.size.height as f32);
+            self.tilemap.scroll = cgmath::Vector2::new(
+                (0.5 - 0.5 * (t * 0.3).cos()) * room.x.max(0.0),
+                (0.5 - 0.5 * (t * 0.2).cos()) * room.y.max(0.0),
+            );
+            tilemap::animate_demo_water(&mut self.tilemap, t);
+        }
+        self.tilemap.update(&self.ctx.queue, self.size);
+        if let Some(animated) = self.instances.last_mut() {
+            *animated = self.animation.sample(self.frame_time);
+        }
+        if let (true, Some(animated)) = (self.outline.enabled, self.instances.last()) {
+            self.outline.update(&self.ctx.queue, animated);
+        }
+        if let Some(skinned) = &self.skinned {
+            skinned.update(&self.ctx.queue, self.frame_time);
+        }
+        if self.leaf.enabled {
+            self.leaf.update(&self.ctx.queue, self.frame_time);
+        }
+        self.audio.update(&self.ctx.queue, self.frame_time);
+        if self.hud_model.enabled {
+            self.hud_model.update(&self.ctx.queue, &self.camera, self.frame_time);
+        }
+        // Sphere -> cube -> sphere, with a stretch at a different pace on top
+        if let Some(morphed) = self.morphed.as_mut().filter(|morphed| morphed.enabled) {
+            let t = self.frame_time;
+            morphed.set_weight("cube", 0.5 - 0.5 * (t * 1.5).cos());
+            morphed.set_weight("stretch", (t * 0.7).sin().max(0.0) * 0.5);
+            morphed.update(&self.ctx.queue);
+        }
+
+        if let Some(velocity) = &mut self.velocity {
+            velocity.update(
+                &self.ctx.queue,
+                // Its depth buffer is its own, and always standard
+                self.camera.build_standard_view_projection_matrix(),
+                &self.instances,
+                self.terrain.as_ref(),
+            );
+        }
+
+        self.minimap.update(&self.ctx.device, &self.ctx.queue, &self.camera, &self.instances);
+        if self.ssr.enabled {
+            self.ssr.update(&self.ctx.queue, &self.camera);
+        }
+        self.gizmo.update(&self.ctx.queue, &self.camera, self.selected.map(|index| &self.instances[index]));
+        if self.debug_shapes {
+            self.queue_debug_shapes();
+        }
+        self.debug_lines.prepare(&self.ctx.device, &self.ctx.queue);
+        // The inspector has the frame times & the camera too, and it'd be under the HUD
+        if self.hud && !self.inspector.enabled {
+            let text = self.hud_text();
+            if self.hud_panel {
+                let size = self.font.measure(&text, 1.0);
+                let rect = region_clear::ClearRect {
+                    x: 8 - Self::HUD_PANEL_PADDING,
+                    y: 8 - Self::HUD_PANEL_PADDING,
+                    width: size.x.ceil() as u32 + 2 * Self::HUD_PANEL_PADDING,
+                    height: size.y.ceil() as u32 + 2 * Self::HUD_PANEL_PADDING,
+                };
+                self.clear_region(rect, Self::HUD_PANEL_COLOR);
+            }
+            self.font.draw_text(&text, cgmath::Vector2::new(8.0, 8.0), 1.0);
+        }
+        if self.inspector.enabled {
+            self.draw_inspector();
+        }
+        if self.mip_bias_demo.enabled {
+            let lod = self.mip_bias_demo.lod();
+            let y = self.size.height as f32 - 24.0;
+            self.font.draw_text("no bias", cgmath::Vector2::new(8.0, y), 1.0);
+            let text = format!("bias {:+.1}, levels {} to {}", lod.bias, lod.min_clamp, lod.max_clamp);
+            self.font.draw_text(&text, cgmath::Vector2::new(self.size.width as f32 * 0.5 + 8.0, y), 1.0);
+        }
+        self.font.prepare(&self.ctx.device, &self.ctx.queue);
+        self.region_clear.prepare(&self.ctx.device, &self.ctx.queue, self.size);
+        self.transparency.update(&self.ctx.queue, self.camera.eye);
+        self.particles.update(&self.ctx.queue, &self.camera);
+        self.zfighting.update(&self.ctx.queue, &self.camera);
+
+        // Sort the instances into LOD levels by how far they are from the camera
+        let frustum = self
+            .frozen_frustum
+            .unwrap_or_else(|| camera::Frustum::from_camera(&self.camera));
+        if let Some(counts) = self.model.update(&self.ctx.queue, self.camera.eye, &frustum, &self.instances) {
+            log::info!("lod: {} high, {} low, {} culled", counts.high, counts.low, counts.culled);
+        }
+        if self.portal.enabled {
+            self.portal.update(&self.ctx.queue, &self.instances);
+        }
+    }
+
+    // Collects whatever finished loading since last frame, and swaps the scene in once it's all here
+    fn update_loading(&mut self) {
+        let loading = match &mut self.loading {
+            Some(loading) => loading,
+            None => return,
+        };
+        for (i, mesh) in loading.meshes.try_iter() {
+            loading.loaded[i] = Some(mesh);
+        }
+        loading.screen.set_progress(&self.ctx.queue, loading.progress.fraction());
+        if loading.loaded.iter().any(Option::is_none) {
+            return;
+        }
+
+        log::info!("loaded {} of {} resources", loading.progress.loaded(), loading.progress.total());
+        let loading = self.loading.take().unwrap();
+        let from_files = !loading.model_paths.is_empty();
+        let mut meshes = loading.loaded.into_iter().map(Option::unwrap);
+        self.models = if !from_files {
+            vec![model::Model::spheres(meshes.next().unwrap(), meshes.next().unwrap())]
+        } else {
+            loading.model_paths.into_iter().zip(meshes).map(|(path, mesh)| model::Model::from_file(path, mesh)).collect()
+        };
+        self.show_model(0);
+        // The starting view is set up around the spheres, who knows how big anything else is
+        if from_files {
+            self.frame_model();
+        }
+    }
+
+    // Where the model being shown came from, None for the spheres (or before anything loaded)
+    fn model_path(&self) -> Option<&std::path::Path> {
+        self.models.get(self.current_model)?.path.as_deref()
+    }
+
+    // Uploads one of the models in place of the last one, along with its textures
+    fn show_model(&mut self, index: usize) {
+        self.current_model = index;
+        let (high, low) = self.models[index].upload(&self.ctx.device);
+        self.set_meshes(high, low);
+        self.load_model_textures();
+    }
+
+    /*
+    *   Points the camera at the model on every one of the grid's instances (the moving ones are
+    *   left out), far enough back to see all of them. Each instance's box is grown into a cube
+    *   around a ball that holds it, however it's turned, which is a bit more room than needed.
+    */
+    fn frame_model(&mut self) {
+        use cgmath::{ElementWise, InnerSpace};
+        let bounds = self.models[self.current_model].bounds();
+        let center = bounds.center();
+        let radius = (bounds.max - bounds.min).magnitude() * 0.5;
+        let statics = &self.instances[..self.instances.len() - Self::MOVING_INSTANCES];
+        let framed = statics
+            .iter()
+            .map(|transform| {
+                let scale = transform.scale.x.max(transform.scale.y).max(transform.scale.z);
+                let world_center = transform.position + transform.rotation * center.mul_element_wise(transform.scale);
+                physics::Aabb::from_center(world_center, cgmath::Vector3::new(1.0, 1.0, 1.0) * radius * scale)
+            })
+            .reduce(|a, b| {
+                physics::Aabb::new(
+                    cgmath::Vector3::new(a.min.x.min(b.min.x), a.min.y.min(b.min.y), a.min.z.min(b.min.z)),
+                    cgmath::Vector3::new(a.max.x.max(b.max.x), a.max.y.max(b.max.y), a.max.z.max(b.max.z)),
+                )
+            })
+            .unwrap_or(bounds);
+        self.camera_tween = None;
+        self.camera.frame_bounds(framed.min, framed.max);
+        self.sync_fly_camera();
+        self.camera_binding.update(&self.ctx.queue, &self.camera);
+    }
+
+    // (Re)loads the textures of the model's materials, for a new model or a new device
+    fn load_model_textures(&mut self) {
+        self.model_textures = match self.model_path() {
+            Some(path) => material::MaterialTextures::load_for_obj(&self.ctx.device, &self.ctx.queue, path),
+            None => Default::default(),
+        };
+        self.model_bind_group = self.model_textures.bind_group(
+            &self.ctx.device,
+            &self.procedural.bind_group_layout,
+            material::TextureSlot::Diffuse,
+        );
+    }
+
+    // Swaps in the model's meshes, and the normal lines that go with them
+    fn set_meshes(&mut self, high: model::Mesh, low: model::Mesh) {
+        let before = self.resource_counts();
+        self.model.set_meshes(&self.ctx.device, high, low);
+        self.normal_lines = self.normal_lines.recreate(
+            &self.ctx.device,
+            &self.ctx.pipelines,
+            &self.ctx.queue,
+            self.post_chain.format(),
+            self.aa_mode.sample_count(),
+            self.camera.depth,
+            &self.screen.bind_group_layout,
+            &self.camera_binding.bind_group_layout,
+            &[self.model.high_detail()],
+        );
+        // The old meshes should be gone, so about the same as before
+        log::debug!("swapped the model's meshes, {} before, {} after", before, self.resource_counts());
+    }
+
+    // Just the progress bar, straight to the surface
+    fn render_loading_screen(&self, loading: &SceneLoad) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self.ctx.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Loading Screen Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Loading Screen Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_bind_group(0, &self.screen.bind_group, &[]);
+            loading.screen.draw(&mut render_pass);
+        }
+        self.ctx.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+
+    // `alpha` is how far into the next fixed step we are, see prepare_frame
+    // Cpu frame times in milliseconds, oldest first, see cpu_frame_times
+    fn frame_time_history(&self) -> &[f32] {
+        self.cpu_frame_times.as_slice()
+    }
+
+    // Same for the gpu, empty if the device can't time frames
+    fn gpu_frame_time_history(&self) -> &[f32] {
+        self.gpu_frame_times.as_slice()
+    }
+
+    // Clears `rect` of this frame (in pixels from the top left) to `color` and leaves the rest
+    // alone. It goes over the finished frame, under the text. Queue it every frame it should
+    // be there, see region_clear.rs.
+    fn clear_region(&mut self, rect: region_clear::ClearRect, color: wgpu::Color) {
+        self.region_clear.clear(rect, color);
+    }
+
+    // What the HUD shows, a line each
+    fn hud_text(&self) -> String {
+        let average = |history: &[f32]| history.iter().sum::<f32>() / history.len().max(1) as f32;
+        let cpu = average(self.frame_time_history());
+        let mut text = format!("cpu {:.2}ms ({:.0} fps)", cpu, 1000.0 / cpu.max(0.001));
+        if !self.gpu_frame_time_history().is_empty() {
+            text += &format!(", gpu {:.2}ms", average(self.gpu_frame_time_history()));
+        }
+        let eye = self.camera.eye;
+        text += &format!("\ncamera {:.1} {:.1} {:.1}, fovy {}", eye.x, eye.y, eye.z, self.camera.fovy);
+        text += &format!("\n{} instances, {}", self.instances.len(), self.aa_mode);
+        let slots = self.camera_binding.ring().slots();
+        if slots > 1 {
+            text += &format!("\nuniform slot {} of {}", self.uniform_ring_slot() + 1, slots);
+        }
+        if let Some(cursor) = self.mouse_world {
+            text += &format!("\ncursor on the ground at {:.1} {:.1}", cursor.x, cursor.z);
+        }
+        text
+    }
+
+    // Queues the inspector's windows, see inspector.rs
+    fn draw_inspector(&mut self) {
+        let line_height = self.font.measure("X", 1.0).y.ceil() as u32;
+        let focused = self.inspector.focused();
+        for (window, rect) in self.inspector.layout(self.size, line_height) {
+            self.clear_region(rect, Self::HUD_PANEL_COLOR);
+            let x = (rect.x + inspector::Inspector::PADDING) as f32;
+            let mut y = (rect.y + inspector::Inspector::PADDING) as f32;
+            self.font.draw_text(window.title(), cgmath::Vector2::new(x, y), 1.0);
+            for &field in window.fields() {
+                y += line_height as f32;
+                let marker = if field == focused { ">" } else { " " };
+                let text = format!("{} {}: {}", marker, field, self.inspector_value(field));
+                self.font.draw_text(&text, cgmath::Vector2::new(x, y), 1.0);
+            }
+            if window == inspector::Window::Stats {
+                let stats = |history: &[f32]| {
+                    let average = history.iter().sum::<f32>() / history.len().max(1) as f32;
+                    (average, history.iter().copied().fold(0.0, f32::max))
+                };
+                let (cpu, cpu_max) = stats(self.frame_time_history());
+                let text = format!("  cpu {:.2}ms, worst {:.2}ms", cpu, cpu_max);
+                self.font.draw_text(&text, cgmath::Vector2::new(x, y + line_height as f32), 1.0);
+                let text = match self.gpu_frame_time_history() {
+                    [] => "  gpu can't be timed".to_string(),
+                    history => format!("  gpu {:.2}ms, worst {:.2}ms", stats(history).0, stats(history).1),
+                };
+                self.font.draw_text(&text, cgmath::Vector2::new(x, y + 2.0 * line_height as f32), 1.0);
+
+                // The cpu times, scaled so 60fps is halfway up unless something took longer
+                let padding = inspector::Inspector::PADDING;
+                let graph = region_clear::ClearRect {
+                    x: rect.x + padding,
+                    y: rect.y + rect.height - padding - inspector::Inspector::GRAPH_HEIGHT,
+                    width: rect.width - 2 * padding,
+                    height: inspector::Inspector::GRAPH_HEIGHT,
+                };
+                let top = cpu_max.max(2000.0 / 60.0);
+                for bar in inspector::graph(self.frame_time_history(), graph, top) {
+                    self.clear_region(bar, Self::INSPECTOR_GRAPH_COLOR);
+                }
+            }
+        }
+    }
+
+    // What the inspector shows on a field's row
+    fn inspector_value(&self, field: inspector::Field) -> String {
+        use inspector::Field;
+        let selected = self.selected.map(|index| self.instances[index].position);
+        let eye = self.camera.eye;
+        match field {
+            Field::Selected => match self.selected {
+                Some(index) => format!("{} of {}", index, self.instances.len()),
+                None => "none".to_string(),
+            },
+            Field::PositionX => selected.map_or("-".to_string(), |position| format!("{:.2}", position.x)),
+            Field::PositionY => selected.map_or("-".to_string(), |position| format!("{:.2}", position.y)),
+            Field::PositionZ => selected.map_or("-".to_string(), |position| format!("{:.2}", position.z)),
+            Field::Material => self.material.name.to_string(),
+            Field::Ambient => {
+                let [r, g, b] = self.lights.ambient.sky_color;
+                format!("{:.2} {:.2} {:.2}", r, g, b)
+            }
+            Field::Hemisphere => (if self.lights.ambient.is_hemisphere() { "on" } else { "off" }).to_string(),
+            Field::SunAngle => {
+                let [x, _, z] = self.lights.sun.direction;
+                format!("{:.0} degrees", z.atan2(x).to_degrees())
+            }
+            Field::EyeX => format!("{:.2}", eye.x),
+            Field::EyeY => format!("{:.2}", eye.y),
+            Field::EyeZ => format!("{:.2}", eye.z),
+            Field::Fovy => format!("{:.1} degrees", self.camera.fovy),
+        }
+    }
+
+    // Changes the focused field by `steps`, negative for left, and uploads it right away
+    fn edit_inspector_field(&mut self, steps: f32) {
+        use inspector::Field;
+        let field = self.inspector.focused();
+        match field {
+            Field::Selected => {
+                let count = self.instances.len() as isize;
+                let index = match self.selected {
+                    Some(index) => (index as isize + steps.signum() as isize).rem_euclid(count),
+                    None => 0,
+                };
+                self.selected = Some(index as usize);
+            }
+            Field::PositionX | Field::PositionY | Field::PositionZ => {
+                // Goes out with the rest of the instances in prepare_frame
+                if let Some(index) = self.selected {
+                    let position = &mut self.instances[index].position;
+                    let axis = match field {
+                        Field::PositionX => &mut position.x,
+                        Field::PositionY => &mut position.y,
+                        _ => &mut position.z,
+                    };
+                    *axis += 0.1 * steps;
+                }
+            }
+            Field::Material => {
+                self.material = if self.material.textured {
+                    material::Material::UNTEXTURED
+                } else {
+                    material::Material::TEXTURED
+                };
+                self.scene_pipeline(self.material);
+            }
+            Field::Ambient => {
+                self.lights.ambient = self.lights.ambient.scaled(1.25f32.powf(steps));
+                self.lights.update(&self.ctx.queue);
+            }
+            Field::Hemisphere => {
+                let hemisphere = !self.lights.ambient.is_hemisphere();
+                self.lights.ambient = self.lights.ambient.with_hemisphere(hemisphere);
+                self.lights.update(&self.ctx.queue);
+            }
+            Field::SunAngle => self.lights.rotate_sun(&self.ctx.queue, cgmath::Deg(15.0 * steps).into()),
+            Field::EyeX | Field::EyeY | Field::EyeZ | Field::Fovy => {
+                match field {
+                    Field::EyeX => self.camera.eye.x += 0.5 * steps,
+                    Field::EyeY => self.camera.eye.y += 0.5 * steps,
+                    Field::EyeZ => self.camera.eye.z += 0.5 * steps,
+                    _ => self.camera.fovy = (self.camera.fovy + 5.0 * steps).clamp(10.0, 120.0),
+                }
+                // A tween would take it straight back
+                self.camera_tween = None;
+                self.camera_binding.update(&self.ctx.queue, &self.camera);
+                self.sync_fly_camera();
+            }
+        }
+    }
+
+    fn render(&mut self, alpha: f32) -> Result<(), wgpu::SurfaceError> {
+        if let Some(loading) = &self.loading {
+            return self.render_loading_screen(loading);
+        }
+        let start = instant::Instant::now();
+        let gpu_time = self.gpu_frame_timer.as_mut().and_then(|timer| timer.poll(&self.ctx.device));
+        if let Some(gpu_time) = gpu_time {
+            self.gpu_frame_times.push(gpu_time.as_secs_f32() * 1000.0);
+        }
+        if let Some(stats_log) = &mut self.stats_log {
+            stats_log.push(gpu_time);
+            if let Some(report) = stats_log.poll(gpu_memory::GpuMemory::current()) {
+                log::info!("{}", report);
+            }
+        }
+        self.resource_tracker.check();
+        self.prepare_frame(alpha);
+
+        // First we need to get a frame to render to
+        // the get_current_texture function will wait for the surface to provide a new SurfaceTexture that
+        // we will render to. We'll store this in output for later.
+        let output = self.surface.get_current_texture()?;
+
+        // view creates a TextureView with default settings. We need to do this because we want to control
+        // how the render code interacts with the texture.
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // We also need to create a CommandEncoder to create the actual commands to send to the gpu. Most
+        // modern graphics frameworks expect commands to be stored in a command buffer before being sent
+        // to the gpu. The encoder builds a command buffer that we can then send to the gpu.
+        let mut encoder = self.ctx.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+
+        if let Some(timer) = &mut self.gpu_frame_timer {
+            timer.begin(&mut encoder);
+        }
+        self.encode_frame(&mut encoder, &view);
+        if let Some(timer) = &mut self.gpu_frame_timer {
+            timer.end(&mut encoder);
+        }
+
+        // submit will accept anything that implements IntoIter
+        self.ctx.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = &mut self.gpu_frame_timer {
+            timer.after_submit();
+        }
+        // The gpu may still be reading this frame's uniforms, the next writes go to other slots
+        self.camera_binding.finish_frame();
+        self.lights.finish_frame();
+
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.capture(&self.ctx) {
+                log::error!("couldn't write the recorded frame, stopping: {}", e);
+                self.stop_recording();
+            }
+        }
+        output.present();
+        self.latency.presented();
+        self.cpu_frame_times.push(start.elapsed().as_secs_f32() * 1000.0);
+        self.surface_lost_frames = 0;
+
+        Ok(())
+    }
+
+    /*
+    *   Renders `frames` frames as fast as it can and reports how long they took. The frames go
+    *   to an offscreen texture instead of the surface, so vsync & the compositor stay out of the
+    *   numbers. Every frame advances by the same 60th of a second, so runs see the same scene.
+    *
+    *   We wait for the gpu after each frame. Otherwise the cpu would run ahead queueing frames
+    *   until wgpu makes it wait, and which frame that wait lands on is anyone's guess.
+    */
+    fn benchmark(&mut self, frames: u32) -> bench::BenchReport {
+        // Loading happens on other threads, measuring the loading screen would be pointless
+        while self.loading.is_some() {
+            self.update_loading();
+            std::thread::yield_now();
+        }
+
+        let target = texture::Texture::create_render_target(
+            &self.ctx.device,
+            &self.config,
+            self.config.format,
+            "Bench Target",
+        );
+        let timer = self
+            .features
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| bench::GpuTimer::new(&self.ctx));
+        let dt = instant::Duration::from_secs_f64(1.0 / 60.0);
+        let frames = frames.max(1);
+        let mut cpu_times = Vec::with_capacity(frames as usize);
+        let mut gpu_times = Vec::with_capacity(frames as usize);
+
+        for frame in 0..bench::WARMUP_FRAMES + frames {
+            let start = instant::Instant::now();
+            self.update(dt);
+            for _ in 0..self.timestep.advance(dt) {
+                self.fixed_update(self.timestep.step());
+            }
+            self.prepare_frame(self.timestep.alpha());
+            let mut encoder = self.ctx.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Bench Encoder"),
+            });
+            if let Some(timer) = &timer {
+                timer.begin(&mut encoder);
+            }
+            self.encode_frame(&mut encoder, &target.view);
+            if let Some(timer) = &timer {
+                timer.end(&mut encoder);
+            }
+            self.ctx.queue.submit(std::iter::once(encoder.finish()));
+            let cpu_time = start.elapsed();
+
+            let gpu_time = match &timer {
+                Some(timer) => Some(timer.read(&self.ctx)),
+                None => {
+                    self.ctx.device.poll(wgpu::Maintain::Wait);
+                    None
+                }
+            };
+            if frame >= bench::WARMUP_FRAMES {
+                cpu_times.push(cpu_time);
+                gpu_times.extend(gpu_time);
+            }
+        }
+
+        bench::BenchReport {
+            frames,
+            cpu: bench::FrameTimes::from_samples(&cpu_times).unwrap(),
+            gpu: bench::FrameTimes::from_samples(&gpu_times),
+        }
+    }
+
+    // Everything that goes into a frame, up to `view` being ready to show. While recording the
+    // frame is drawn to the recorder's target first and copied over at the end.
+    // The opaque scene as `view`'s camera sees it, drawn into `target`. Whatever samples the
+    // target can't be in it, see portal.rs.
+    fn render_to_texture(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &portal::PortalView,
+        target: &portal::PortalTarget,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Portal Pass"),
+            color_attachments: &[Some(target.color_attachment(wgpu::LoadOp::Clear(Self::CLEAR_COLOR)))],
+            depth_stencil_attachment: Some(target.depth_attachment(self.camera.depth.far())),
+        });
+        render_pass.set_pipeline(&self.render_pipelines[&self.material.defines()]);
+        render_pass.set_bind_group(0, &self.screen.bind_group, &[]);
+        render_pass.set_bind_group(1, view.camera_bind_group(), &[]);
+        render_pass.set_bind_group(2, self.model_bind_group.as_ref().unwrap_or(&self.procedural.bind_group), &[]);
+        render_pass.set_bind_group(3, self.lights.bind_group(), &[]);
+        view.draw_instances(&mut render_pass, self.model.high_detail());
+        render_pass.set_bind_group(2, &self.procedural.bind_group, &[]);
+        self.physics.draw(&mut render_pass);
+        if let Some(terrain) = &self.terrain {
+            render_pass.set_pipeline(&self.terrain_pipeline);
+            // Whatever the terrain pipeline was built for
+            let bind_group = match self.terrain_shading {
+                terrain::TerrainShading::Textured => &self.procedural.bind_group,
+                terrain::TerrainShading::Colormap => &self.colormap.bind_group,
+                terrain::TerrainShading::Splat => &self.splat.bind_group,
+            };
+            render_pass.set_bind_group(2, bind_group, &[]);
+            terrain.draw(&mut render_pass);
+        }
+    }
+
+    fn encode_frame(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.model.cull(encoder, &self.ctx.queue);
+        // Before the main pass, which samples what it draws
+        if self.portal.enabled {
+            self.render_to_texture(encoder, &self.portal.view, &self.portal.target);
+        }
+
+        // The clouds drift with the animations, so pausing time pauses them too
+        if let (true, Some(noise)) = (self.clouds, &self.noise) {
+            let params = noise::NoiseParams {
+                offset: [self.frame_time * 0.2, self.frame_time * 0.05],
+                ..Default::default()
+            };
+            noise.fill_texture(&self.ctx.device, encoder, &self.ctx.queue, &self.procedural.texture, &params);
+        }
+
+        // Extra block borrows encoder mutably (aka &mut self). We can't call encoder.finish() until
+        // we release that mutable borrow. The block tells rust to drop any variables within it when the
+        // code leaves that scope thus releasing the mutable borrow on encoder and allowing us to finish() it.
+        // While recording, the finished frame goes to the recorder's target and gets copied
+        // to the surface at the end
+        let output_view = match &self.recorder {
+            Some(recorder) => recorder.target_view(),
+            None => view,
+        };
+        // With any post effects on, the scene goes to an offscreen texture first
+        // FXAA goes last so it smooths whatever the other effects left behind, with the color
+        // grading just before it
+        // Motion blur goes first, the velocities line up with the scene as it was drawn. TAA
+        // goes even before that, the others should see the resolved image.
+        let mut effects: Vec<&dyn post::PostEffect> = Vec::with_capacity(8);
+        // Reflects the scene as it was lit, before anything blurs or grades it
+        effects.push(&self.ssr);
+        if let Some(taa) = &self.taa {
+            effects.push(taa);
+        }
+        if let Some(motion_blur) = &self.motion_blur {
+            effects.push(motion_blur);
+        }
+        effects.extend([&self.dof as &dyn post::PostEffect, &self.outline, &self.color_grading, &self.fxaa]);
+        // Replaces the picture, so after everything that would change it
+        effects.push(&self.overdraw);
+        let post_processing = self.post_chain.always_runs() || effects.iter().any(|effect| effect.enabled());
+        let scene_view = if post_processing {
+            self.post_chain.scene_target()
+        } else {
+            output_view
+        };
+
+        // With trails on the pass builds on the last frame, which it has to draw somewhere that
+        // keeps it around, see trails.rs. The first frame has nothing to build on yet.
+        let trails = !self.clear_each_frame;
+        let load = if trails && !self.trails.take_clear() {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(Self::CLEAR_COLOR)
+        };
+
+        // With MSAA the pass draws into the multisampled attachments, and the color gets
+        // resolved into scene_view, either when the pass ends or by a pass of our own after it
+        let (color_view, resolve_target, depth_view) = match &self.msaa {
+            Some(msaa) => (
+                msaa.color_view(),
+                self.msaa_attachment.resolve.then_some(scene_view),
+                msaa.depth_view(),
+            ),
+            None if trails => (self.trails.target_view(), None, &self.depth_texture.view),
+            None => (scene_view, None, &self.depth_texture.view),
+        };
+        // Trails build on the stored samples
+        let store = self.msaa.is_none() || self.msaa_attachment.store || trails;
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load,
+                        store,
+                    }
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.camera.depth.far()),
+                        store: true,
+                    }),
+                    // The outline marks pixels in the stencil, see outline.rs
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: true,
+                    }),
+                }),
+            });
+
+            if load == wgpu::LoadOp::Load {
+                self.trails.draw_fade(&mut render_pass);
+            }
+            render_pass.set_pipeline(&self.render_pipelines[&self.material.defines()]);
+            render_pass.set_bind_group(0, &self.screen.bind_group, &[]);
+            render_pass.set_bind_group(1, self.camera_binding.bind_group(), &[]);
+            // Untextured materials don't read it, but it's part of the layout all the same
+            render_pass.set_bind_group(2, self.model_bind_group.as_ref().unwrap_or(&self.procedural.bind_group), &[]);
+            render_pass.set_bind_group(3, self.lights.bind_group(), &[]);
+            self.model.draw(&mut render_pass);
+            render_pass.set_bind_group(2, &self.procedural.bind_group, &[]);
+            self.physics.draw(&mut render_pass);
+            self.ssr.draw_floor(&mut render_pass);
+            if let Some(terrain) = &self.terrain {
+                render_pass.set_pipeline(&self.terrain_pipeline);
+                // Whatever the terrain pipeline was built for
+                let bind_group = match self.terrain_shading {
+                    terrain::TerrainShading::Textured => &self.procedural.bind_group,
+                    terrain::TerrainShading::Colormap => &self.colormap.bind_group,
+                    terrain::TerrainShading::Splat => &self.splat.bind_group,
+                };
+                render_pass.set_bind_group(2, bind_group, &[]);
+                let start = instant::Instant::now();
+                terrain.draw(&mut render_pass);
+                if terrain.enabled {
+                    self.batch_draw_times.push(start.elapsed().as_secs_f32() * 1000.0);
+                }
+                // The colormap's & splatting's groups don't fit anything else's layout
+                render_pass.set_bind_group(2, &self.procedural.bind_group, &[]);
+            }
+            if self.portal.enabled {
+                render_pass.set_pipeline(&self.render_pipelines[&material::Material::TEXTURED.defines()]);
+                self.portal.draw_screen(&mut render_pass);
+                render_pass.set_bind_group(2, &self.procedural.bind_group, &[]);
+            }
+            if self.leaf.enabled {
+                render_pass.set_pipeline(&self.render_pipelines[&self.leaf.material.defines()]);
+                self.leaf.draw(&mut render_pass);
+            }
+            if let (true, Some(pipeline)) = (self.audio.enabled, &self.audio_pipeline) {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(2, &self.audio.bind_group, &[]);
+                self.audio.draw(&mut render_pass);
+                render_pass.set_bind_group(2, &self.procedural.bind_group, &[]);
+            }
+            if let (Some(skinned), Some(pipeline)) = (&self.skinned, &self.skinned_pipeline) {
+                if skinned.enabled {
+                    render_pass.set_pipeline(pipeline);
+                    skinned.draw(&mut render_pass);
+                }
+            }
+            if let (Some(morphed), Some(pipeline)) = (&self.morphed, &self.morphed_pipeline) {
+                if morphed.enabled {
+                    render_pass.set_pipeline(pipeline);
+                    morphed.draw(&mut render_pass);
+                }
+            }
+            // Writes depth like the rest of the opaque stuff, so before the sky
+            self.foliage.draw(&mut render_pass);
+            self.sky.draw(&mut render_pass);
+            // The sky has its own group 0, the rest want the screen back
+            render_pass.set_bind_group(0, &self.screen.bind_group, &[]);
+            // After the sky, which would paint over the parts sticking out into the background
+            self.outline.draw(&mut render_pass, self.model.high_detail());
+            self.zfighting.draw(&mut render_pass);
+
+            // The normal lines are built from the high detail mesh, so only show them on
+            // the instances drawn with it
+            if self.normal_lines.enabled {
+                let instances = self.model.bind_high_detail_instances(&mut render_pass);
+                self.normal_lines.draw(&mut render_pass, instances);
+            }
+            if let Some(frustum_lines) = &self.frustum_lines {
+                frustum_lines.draw(&mut render_pass);
+            }
+            self.debug_lines.draw(&mut render_pass);
+            // Last, it's drawn over whatever's already there
+            self.gizmo.draw(&mut render_pass);
+            self.gradient.draw(&mut render_pass, self.size);
+            self.latency.draw(&mut render_pass, self.size);
+        }
+
+        if let (Some(msaa), false) = (&self.msaa, self.msaa_attachment.resolve) {
+            msaa.resolve_color(encoder, scene_view);
+        }
+
+        if trails && self.msaa.is_none() {
+            self.trails.blit(
+                &self.ctx.device,
+                encoder,
+                &self.screen,
+                &self.post_chain,
+                &self.depth_texture,
+                scene_view,
+            );
+        }
+
+        // The transparent panels & particles test against the regular depth texture, which the
+        // MSAA pass didn't touch. The effects read it too, one resolve does for all of them.
+        let mut depth_resolved = false;
+        if self.transparency.enabled || self.particles.enabled {
+            if let Some(msaa) = &self.msaa {
+                msaa.resolve_depth(encoder, &self.depth_texture.view);
+                depth_resolved = true;
+            }
+            self.transparency.render(
+                encoder,
+                &self.screen.bind_group,
+                self.camera_binding.bind_group(),
+                scene_view,
+                &self.depth_texture.view,
+            );
+            self.particles.render(
+                encoder,
+                &self.screen.bind_group,
+                self.camera_binding.bind_group(),
+                scene_view,
+                &self.depth_texture.view,
+            );
+        }
+
+        let motion_blur = self.motion_blur.as_ref().is_some_and(|motion_blur| motion_blur.enabled);
+        let taa = self.taa.as_ref().filter(|taa| taa.enabled);
+        if let (true, Some(velocity)) = (motion_blur || taa.is_some(), &self.velocity) {
+            velocity.render(encoder, self.model.high_detail(), self.terrain.as_ref());
+        }
+        if let (Some(taa), Some(velocity)) = (taa, &self.velocity) {
+            taa.resolve(&self.ctx.device, encoder, self.post_chain.scene_target(), velocity);
+        }
+
+        if post::PostEffect::enabled(&self.outline) {
+            self.outline.render_mask(
+                encoder,
+                &self.screen.bind_group,
+                self.camera_binding.bind_group(),
+                self.model.high_detail(),
+            );
+        }
+
+        if self.ssr.enabled {
+            self.ssr.render_surfaces(
+                encoder,
+                &self.screen.bind_group,
+                self.camera_binding.bind_group(),
+                &self.model,
+                &self.physics,
+                self.terrain.as_ref(),
+            );
+        }
+
+        if self.overdraw.enabled {
+            self.overdraw.render_counts(
+                encoder,
+                &self.screen.bind_group,
+                self.camera_binding.bind_group(),
+                &self.model,
+                &self.physics,
+                self.terrain.as_ref(),
+            );
+        }
+
+        if post_processing {
+            // The effects read the regular depth texture, which the MSAA pass didn't touch
+            if let (Some(msaa), false) = (&self.msaa, depth_resolved) {
+                msaa.resolve_depth(encoder, &self.depth_texture.view);
+                depth_resolved = true;
+            }
+            self.post_chain.run(
+                &self.ctx.device,
+                encoder,
+                &self.screen,
+                &self.depth_texture,
+                &effects,
+                output_view,
+            );
+        }
+
+        // Its own pass over the finished frame, with color & depth each loaded or cleared. Testing
+        // against the scene needs the MSAA pass's depth in the regular depth texture.
+        if self.hud_model.enabled {
+            if let (Some(msaa), false, false) = (&self.msaa, self.clear_depth_enabled, depth_resolved) {
+                msaa.resolve_depth(encoder, &self.depth_texture.view);
+            }
+            let color_load = if self.clear_color_enabled {
+                wgpu::LoadOp::Clear(Self::CLEAR_COLOR)
+            } else {
+                wgpu::LoadOp::Load
+            };
+            let depth_load = if self.clear_depth_enabled {
+                wgpu::LoadOp::Clear(self.camera.depth.far())
+            } else {
+                wgpu::LoadOp::Load
+            };
+            self.hud_model.render(encoder, output_view, &self.depth_texture.view, color_load, depth_load);
+        }
+
+        self.test_pattern.render(encoder, &self.screen, output_view);
+        self.tearing_test.render(encoder, &self.screen, output_view);
+        self.alpha_demo.render(encoder, &self.screen, output_view);
+        self.mip_bias_demo.render(encoder, &self.screen, output_view);
+        self.tilemap.render(encoder, &self.screen, output_view);
+        self.minimap.render(encoder, output_view, self.size);
+        self.region_clear.render(encoder, output_view);
+        self.font.render(encoder, &self.screen, output_view);
+        if self.cursor_captured {
+            self.crosshair.render(encoder, &self.screen, output_view);
+        }
+
+        if let Some(recorder) = &self.recorder {
+            recorder.blit(&self.ctx.device, encoder, &self.screen, &self.post_chain, &self.depth_texture, view);
+        }
     }
 }
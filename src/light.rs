@@ -0,0 +1,404 @@
+use cgmath::prelude::*;
+
+use crate::fog::Fog;
+use crate::uniform_ring::UniformRing;
+
+/*
+*   The scene's lights. There's one directional light, the sun, plus as many others as there's
+*   capacity for (MAX_LIGHTS to start with). The sun is so far away that its light arrives from
+*   the same direction everywhere and is just as bright everywhere, so all it needs is a
+*   direction and a color.
+*
+*   Point lights have a position instead and the direction to them changes across the scene.
+*   They also get dimmer with distance: the same light spreads over a sphere whose area grows
+*   with the distance squared, so the brightness falls off as 1 / distance². That never quite
+*   reaches 0, so every light also has a range it's faded out to 0 at, which lets the shader
+*   skip lights that are too far away to matter.
+*
+*   Spotlights are point lights that only shine inside a cone. Inside the inner angle they're
+*   at full brightness, past the outer angle they're dark, and they fade smoothly in between.
+*
+*   Light that has bounced around the scene so many times it comes from everywhere is faked
+*   with an ambient term, so the sides facing away from every light aren't pitch black. The
+*   hemisphere version blends between a sky color for surfaces facing up and a ground color
+*   for surfaces facing down, which reads a lot more like an outdoor scene than a flat gray.
+*
+*   Everything goes into a single uniform buffer, bound at @group(3) by the scene pipeline.
+*   That's the scene globals group: wgpu only guarantees 4 bind groups (and WebGL has no more),
+*   so anything else the whole scene shares should go in here too rather than a group of its own.
+*   The fog settings are one of those, see fog.rs.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DirectionalLight {
+    // The direction the light travels in, so (0, -1, 0) shines straight down
+    pub direction: [f32; 3],
+    // A vec3 in a uniform is aligned to 16 bytes
+    _padding: u32,
+    pub color: [f32; 3],
+    _padding2: u32,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            direction,
+            _padding: 0,
+            color,
+            _padding2: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Ambient {
+    pub sky_color: [f32; 3],
+    // 0 uses sky_color everywhere, anything else blends towards ground_color facing down
+    hemisphere: u32,
+    pub ground_color: [f32; 3],
+    _padding: u32,
+}
+
+impl Ambient {
+    pub fn flat(color: [f32; 3]) -> Self {
+        Self::hemisphere(color, color).with_hemisphere(false)
+    }
+
+    pub fn hemisphere(sky_color: [f32; 3], ground_color: [f32; 3]) -> Self {
+        Self {
+            sky_color,
+            hemisphere: 1,
+            ground_color,
+            _padding: 0,
+        }
+    }
+
+    pub fn with_hemisphere(mut self, hemisphere: bool) -> Self {
+        self.hemisphere = hemisphere as u32;
+        self
+    }
+
+    pub fn is_hemisphere(&self) -> bool {
+        self.hemisphere != 0
+    }
+
+    // Scales both colors, to brighten or darken the shadows
+    pub fn scaled(mut self, factor: f32) -> Self {
+        for channel in self.sky_color.iter_mut().chain(self.ground_color.iter_mut()) {
+            *channel *= factor;
+        }
+        self
+    }
+}
+
+impl Default for Ambient {
+    // A subtle neutral gray, a bit darker from below
+    fn default() -> Self {
+        Self::hemisphere([0.1, 0.1, 0.1], [0.05, 0.05, 0.05])
+    }
+}
+
+// Which of the fields a Light uses depends on its type, the shader switches on light_type
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightType {
+    // direction, color & intensity
+    Directional = 0,
+    // position, range, color & intensity
+    Point = 1,
+    // everything
+    Spot = 2,
+}
+
+// The floats fill in the space the vec3s would leave as padding
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Light {
+    pub position: [f32; 3],
+    // Distance the light has faded out completely at
+    pub range: f32,
+    pub color: [f32; 3],
+    // Brightness at 1 unit away
+    pub intensity: f32,
+    // Where a directional light or spotlight points
+    pub direction: [f32; 3],
+    light_type: u32,
+    // Cosines of the spotlight's cone angles, so the shader can compare them with a dot product
+    inner_cutoff: f32,
+    outer_cutoff: f32,
+    _padding: [u32; 2],
+}
+
+impl Light {
+    pub const DEFAULT_RANGE: f32 = 10.0;
+
+    pub fn point(position: [f32; 3], color: [f32; 3], intensity: f32, range: f32) -> Self {
+        Self {
+            position,
+            range,
+            color,
+            intensity,
+            direction: [0.0, -1.0, 0.0],
+            light_type: LightType::Point as u32,
+            inner_cutoff: -1.0,
+            outer_cutoff: -1.0,
+            _padding: [0; 2],
+        }
+    }
+
+    pub fn directional(direction: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            direction,
+            light_type: LightType::Directional as u32,
+            ..Self::point([0.0; 3], color, intensity, f32::MAX)
+        }
+    }
+
+    // Turns a point light into a spotlight. The angles are measured from the direction to the
+    // edge of the cone, so they're half the cone's full width.
+    pub fn with_cone(mut self, direction: [f32; 3], inner_angle: cgmath::Deg<f32>, outer_angle: cgmath::Deg<f32>) -> Self {
+        self.direction = direction;
+        self.light_type = LightType::Spot as u32;
+        self.inner_cutoff = inner_angle.cos();
+        // An outer angle inside the inner one would flip the fade around
+        self.outer_cutoff = cgmath::Deg(outer_angle.0.max(inner_angle.0)).cos();
+        self
+    }
+
+    pub fn light_type(&self) -> LightType {
+        match self.light_type {
+            0 => LightType::Directional,
+            1 => LightType::Point,
+            _ => LightType::Spot,
+        }
+    }
+
+    // The inner & outer angles with_cone was given, for spotlights
+    pub fn cone_angles(&self) -> Option<(cgmath::Deg<f32>, cgmath::Deg<f32>)> {
+        (self.light_type() == LightType::Spot)
+            .then(|| (cgmath::Deg::acos(self.inner_cutoff), cgmath::Deg::acos(self.outer_cutoff)))
+    }
+}
+
+/*
+*   How many lights (counting the flashlight) fit in the buffer to start with. The lights are
+*   an array in a uniform buffer, and uniform arrays need a fixed length, so the shader's
+*   MAX_LIGHTS gets swapped for the current capacity before it's compiled (see
+*   Lights::preprocess). Raising the capacity means a bigger buffer and new scene pipelines.
+*   It can go as high as fits in max_uniform_buffer_binding_size, 16KB on WebGL is about 250.
+*/
+pub const MAX_LIGHTS: usize = 8;
+
+// The start of the Lights struct in shader.wgsl, followed by `capacity` lights then the count
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsHeader {
+    sun: DirectionalLight,
+    ambient: Ambient,
+    fog: Fog,
+}
+
+pub struct Lights {
+    pub sun: DirectionalLight,
+    pub ambient: Ambient,
+    pub fog: Fog,
+    // Use add_light, which won't go past the capacity. Anything past it is ignored.
+    pub lights: Vec<Light>,
+    // A spotlight that follows the camera around, see State::update. Takes up one of the slots.
+    pub flashlight: Option<Light>,
+    capacity: usize,
+    // The buffers, one per frame in flight, see uniform_ring.rs
+    ring: UniformRing,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl Lights {
+    pub fn new(device: &wgpu::Device, sun: DirectionalLight, lights: Vec<Light>) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("lights_bind_group_layout"),
+        });
+        let header = LightsHeader {
+            sun,
+            ambient: Ambient::default(),
+            fog: Fog::default(),
+        };
+        let contents = Self::contents(&header, &lights, MAX_LIGHTS);
+        let ring = UniformRing::new(device, &bind_group_layout, "Lights Buffer", &contents, UniformRing::DEFAULT_SLOTS);
+
+        Self {
+            sun,
+            ambient: Ambient::default(),
+            fog: Fog::default(),
+            lights,
+            flashlight: None,
+            capacity: MAX_LIGHTS,
+            ring,
+            bind_group_layout,
+        }
+    }
+
+    fn buffer_size(capacity: usize) -> wgpu::BufferAddress {
+        // The count is padded out to 16 bytes, like the end of every uniform struct
+        (std::mem::size_of::<LightsHeader>() + capacity * std::mem::size_of::<Light>() + 16) as wgpu::BufferAddress
+    }
+
+    // Past the capacity the lights are dropped, the shader would read past the end of the array
+    fn contents(header: &LightsHeader, lights: &[Light], capacity: usize) -> Vec<u8> {
+        let lights = &lights[..lights.len().min(capacity)];
+        let mut contents = Vec::with_capacity(Self::buffer_size(capacity) as usize);
+        contents.extend_from_slice(bytemuck::bytes_of(header));
+        contents.extend_from_slice(bytemuck::cast_slice(lights));
+        // The unused slots are zeroed
+        contents.resize(Self::buffer_size(capacity) as usize - 16, 0);
+        contents.extend_from_slice(bytemuck::cast_slice(&[lights.len() as u32, 0, 0, 0]));
+        contents
+    }
+
+    // A dim sun, so the colored lamps hovering over the grid stand out
+    pub fn demo(device: &wgpu::Device) -> Self {
+        let range = Light::DEFAULT_RANGE;
+        Self::new(
+            device,
+            DirectionalLight::new([-0.5, -1.0, -0.75], [0.4, 0.38, 0.34]),
+            vec![
+                Light::point([0.0, 2.0, 0.0], [1.0, 0.6, 0.2], 4.0, range),
+                Light::point([-7.5, 1.5, -7.5], [1.0, 0.1, 0.1], 3.0, range),
+                Light::point([7.5, 1.5, -7.5], [0.1, 1.0, 0.2], 3.0, range),
+                Light::point([0.0, 1.5, 9.0], [0.2, 0.3, 1.0], 3.0, range),
+            ],
+        )
+    }
+
+    // A bright, narrow spotlight. Where it is & points gets filled in from the camera.
+    pub fn default_flashlight() -> Light {
+        Light::point([0.0; 3], [1.0, 0.97, 0.9], 12.0, 25.0).with_cone(
+            [0.0, 0.0, -1.0],
+            cgmath::Deg(10.0),
+            cgmath::Deg(17.0),
+        )
+    }
+
+    // Same lights, capacity & ring on a new device
+    pub fn recreate(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let mut lights = Self::new(device, self.sun, self.lights.clone());
+        lights.ambient = self.ambient;
+        lights.fog = self.fog;
+        lights.flashlight = self.flashlight;
+        if let Err(e) = lights.set_capacity(device, self.capacity) {
+            log::warn!("{}", e);
+        }
+        lights.set_ring_slots(device, self.ring.slots());
+        lights.update(queue);
+        lights
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // How many slots are taken, counting the flashlight
+    pub fn count(&self) -> usize {
+        self.lights.len() + self.flashlight.is_some() as usize
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count() >= self.capacity
+    }
+
+    // Call update afterwards to upload it
+    pub fn add_light(&mut self, light: Light) -> Result<(), String> {
+        if self.is_full() {
+            return Err(format!("there's only room for {} lights, see set_capacity", self.capacity));
+        }
+        self.lights.push(light);
+        Ok(())
+    }
+
+    /*
+    *   Makes room for a different number of lights, errors if the buffer would be too big for
+    *   the device. The scene pipelines were built for the old capacity and have to be built
+    *   again too, see preprocess.
+    */
+    pub fn set_capacity(&mut self, device: &wgpu::Device, capacity: usize) -> Result<(), String> {
+        let max_size = device.limits().max_uniform_buffer_binding_size as wgpu::BufferAddress;
+        if Self::buffer_size(capacity) > max_size {
+            let max_capacity = (max_size - Self::buffer_size(0)) / std::mem::size_of::<Light>() as wgpu::BufferAddress;
+            return Err(format!("{} lights won't fit in a uniform buffer, the most this device takes is {}", capacity, max_capacity));
+        }
+        // A zero length array isn't valid WGSL
+        let capacity = capacity.max(1);
+        if capacity == self.capacity {
+            return Ok(());
+        }
+        self.capacity = capacity;
+        let contents = Self::contents(&self.header(), &self.all_lights(), capacity);
+        self.ring.resize(device, &self.bind_group_layout, &contents, self.ring.slots());
+        if self.count() > capacity {
+            log::warn!("{} lights but only room for {} now, the rest are ignored", self.count(), capacity);
+        }
+        Ok(())
+    }
+
+    // Sizes the lights array in shader.wgsl (or a shader that includes it) for the capacity
+    pub fn preprocess(&self, source: &str) -> String {
+        let default = format!("const MAX_LIGHTS: u32 = {}u;", MAX_LIGHTS);
+        assert!(source.contains(&default), "the shader doesn't declare MAX_LIGHTS");
+        source.replace(&default, &format!("const MAX_LIGHTS: u32 = {}u;", self.capacity))
+    }
+
+    fn header(&self) -> LightsHeader {
+        LightsHeader {
+            sun: self.sun,
+            ambient: self.ambient,
+            fog: self.fog,
+        }
+    }
+
+    // The flashlight goes last, so it's the first one dropped when there isn't room
+    fn all_lights(&self) -> Vec<Light> {
+        self.lights.iter().chain(self.flashlight.as_ref()).copied().collect()
+    }
+
+    // The current slot's, see UniformRing
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        self.ring.bind_group()
+    }
+
+    pub fn ring(&self) -> &UniformRing {
+        &self.ring
+    }
+
+    // Starts again with `slots` buffers, all holding the lights as they are now
+    pub fn set_ring_slots(&mut self, device: &wgpu::Device, slots: usize) {
+        let contents = Self::contents(&self.header(), &self.all_lights(), self.capacity);
+        self.ring.resize(device, &self.bind_group_layout, &contents, slots);
+    }
+
+    pub fn finish_frame(&mut self) {
+        self.ring.finish_frame();
+    }
+
+    // Uploads any changes made to the lights
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let contents = Self::contents(&self.header(), &self.all_lights(), self.capacity);
+        self.ring.write(queue, &contents);
+    }
+
+    // Turns the sun around the vertical axis, like the time of day changing (sort of)
+    pub fn rotate_sun(&mut self, queue: &wgpu::Queue, angle: cgmath::Rad<f32>) {
+        let rotation = cgmath::Quaternion::from_angle_y(angle);
+        self.sun.direction = rotation.rotate_vector(self.sun.direction.into()).into();
+        self.update(queue);
+    }
+}
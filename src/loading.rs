@@ -0,0 +1,197 @@
+use std::rc::Rc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc, Arc,
+};
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::model;
+use crate::pipeline_cache::PipelineCache;
+use crate::post;
+
+/*
+*   Loading screen. Anything that loads in the background bumps a shared LoadProgress when
+*   it's queued and again when it's done, and the LoadingScreen draws a progress bar from it
+*   until everything has arrived. Loaders run on their own threads on native. WASM has no
+*   threads, there the loaders would be fetches the browser runs for us.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct LoadProgress {
+    loaded: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl LoadProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Call when queueing something to load
+    pub fn add_total(&self, count: usize) {
+        self.total.fetch_add(count, Ordering::SeqCst);
+    }
+
+    // Call when one of those things has loaded
+    pub fn finish_one(&self) {
+        self.loaded.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn loaded(&self) -> usize {
+        self.loaded.load(Ordering::SeqCst)
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    pub fn fraction(&self) -> f32 {
+        match self.total() {
+            0 => 1.0,
+            total => self.loaded() as f32 / total as f32,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.loaded() >= self.total()
+    }
+}
+
+/*
+*   Builds meshes in the background. Each job's result comes out of the returned channel
+*   tagged with the job's index, in whatever order they finish.
+*/
+pub type MeshJob = Box<dyn FnOnce() -> model::MeshData + Send>;
+
+pub fn spawn_mesh_jobs(jobs: Vec<MeshJob>, progress: &LoadProgress) -> mpsc::Receiver<(usize, model::MeshData)> {
+    let (sender, receiver) = mpsc::channel();
+    progress.add_total(jobs.len());
+    for (i, job) in jobs.into_iter().enumerate() {
+        let sender = sender.clone();
+        let progress = progress.clone();
+        let run = move || {
+            let mesh = job();
+            progress.finish_one();
+            // The State might have given up on the load already, that's fine
+            let _ = sender.send((i, mesh));
+        };
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                run();
+            } else {
+                std::thread::spawn(run);
+            }
+        }
+    }
+    receiver
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LoadingUniform {
+    background_color: [f32; 4],
+    bar_color: [f32; 4],
+    progress: f32,
+    _padding: [f32; 3],
+}
+
+pub struct LoadingScreen {
+    uniform: LoadingUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl LoadingScreen {
+    pub const DEFAULT_BACKGROUND_COLOR: [f32; 4] = [0.02, 0.02, 0.03, 1.0];
+    pub const DEFAULT_BAR_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let uniform = LoadingUniform {
+            background_color: Self::DEFAULT_BACKGROUND_COLOR,
+            bar_color: Self::DEFAULT_BAR_COLOR,
+            progress: 0.0,
+            _padding: [0.0; 3],
+        };
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Loading Screen Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("loading_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("loading_bind_group"),
+        });
+
+        // Nothing post about it, but it's the same kind of full screen pass
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Loading Screen",
+            include_str!("loading.wgsl"),
+            &[screen_layout, &bind_group_layout],
+            color_format,
+        );
+
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Same colors & progress on a new device
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let mut screen = Self::new(device, pipelines, screen_layout, color_format);
+        screen.uniform = self.uniform;
+        queue.write_buffer(&screen.buffer, 0, bytemuck::cast_slice(&[screen.uniform]));
+        screen
+    }
+
+    pub fn set_colors(&mut self, queue: &wgpu::Queue, background: [f32; 4], bar: [f32; 4]) {
+        self.uniform.background_color = background;
+        self.uniform.bar_color = bar;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn set_progress(&mut self, queue: &wgpu::Queue, progress: f32) {
+        self.uniform.progress = progress.clamp(0.0, 1.0);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Covers the whole target, expects the screen bind group at @group(0)
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,43 @@
+// Loading screen progress bar, see loading.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+struct LoadingUniform {
+    background_color: vec4<f32>,
+    bar_color: vec4<f32>,
+    // 0 to 1
+    progress: f32,
+};
+@group(1) @binding(0)
+var<uniform> loading: LoadingUniform;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // The bar is 60% of the screen wide and 24 pixels high, in the middle of the screen
+    let half_size = vec2<f32>(screen.resolution.x * 0.3, 12.0);
+    let offset = position.xy - screen.resolution * 0.5;
+    let outline = 2.0;
+
+    if (any(abs(offset) > half_size + outline)) {
+        return loading.background_color;
+    }
+    // A thin outline around the bar so the empty part is visible
+    if (any(abs(offset) > half_size)) {
+        return loading.bar_color;
+    }
+    let filled = (offset.x + half_size.x) / (half_size.x * 2.0) <= loading.progress;
+    return select(loading.background_color, loading.bar_color, filled);
+}
@@ -0,0 +1,307 @@
+use cgmath::prelude::*;
+
+use crate::camera::Frustum;
+use crate::gpu_cull::{CullTarget, GpuCuller};
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::{InstanceRaw, Transform};
+use crate::model;
+
+/*
+*   Distance based level of detail. A LodModel has a detailed and a cheap version of the same
+*   mesh. Every frame we measure how far each instance is from the camera and sort it into the
+*   instance buffer of the level it should be drawn with. Anything past cull_distance doesn't
+*   go into any buffer, so it isn't drawn at all.
+*
+*   Instances outside the camera's frustum are culled too, either here on the cpu or, with
+*   gpu culling turned on, by a compute pass over each level's instance buffer (see gpu_cull.rs).
+*/
+pub struct LodModel {
+    // Instances closer than this use the high detail mesh
+    pub low_detail_distance: f32,
+    // Instances further away than this are skipped entirely
+    pub cull_distance: f32,
+    high: model::Mesh,
+    low: model::Mesh,
+    // One instance buffer per level, each big enough to hold every instance
+    high_instances: Tracked<wgpu::Buffer>,
+    low_instances: Tracked<wgpu::Buffer>,
+    counts: LodCounts,
+    capacity: usize,
+    // Set while the draw arguments come from a buffer the cpu writes, rather than the draw call
+    cpu_indirect: Option<[Tracked<wgpu::Buffer>; 2]>,
+    // Set while frustum culling runs on the gpu, which writes the draw arguments itself
+    gpu_culling: Option<GpuCulling>,
+}
+
+struct GpuCulling {
+    culler: GpuCuller,
+    high: CullTarget,
+    low: CullTarget,
+}
+
+// With gpu culling on, high & low still include instances the gpu will cull, as the cpu
+// never finds out which ones those are
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LodCounts {
+    pub high: u32,
+    pub low: u32,
+    pub culled: u32,
+}
+
+impl LodModel {
+    pub const DEFAULT_LOW_DETAIL_DISTANCE: f32 = 12.0;
+    pub const DEFAULT_CULL_DISTANCE: f32 = 22.0;
+
+    pub fn new(device: &wgpu::Device, high: model::Mesh, low: model::Mesh, capacity: usize) -> Self {
+        let high_instances = Self::create_instance_buffer(device, "High Detail Instance Buffer", capacity, false);
+        let low_instances = Self::create_instance_buffer(device, "Low Detail Instance Buffer", capacity, false);
+        Self {
+            low_detail_distance: Self::DEFAULT_LOW_DETAIL_DISTANCE,
+            cull_distance: Self::DEFAULT_CULL_DISTANCE,
+            high,
+            low,
+            high_instances,
+            low_instances,
+            counts: LodCounts::default(),
+            capacity,
+            cpu_indirect: None,
+            gpu_culling: None,
+        }
+    }
+
+    // `storage` lets the culling compute shader read the buffer, which WebGL doesn't support
+    fn create_instance_buffer(device: &wgpu::Device, label: &str, capacity: usize, storage: bool) -> Tracked<wgpu::Buffer> {
+        let mut usage = wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST;
+        if storage {
+            usage |= wgpu::BufferUsages::STORAGE;
+        }
+        // wgpu zeroes new buffers for us, update() fills in whatever is actually visible
+        device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity.max(1) * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Same meshes, thresholds & culling mode on a new device
+    pub fn recreate(&self, device: &wgpu::Device) -> Self {
+        let mut lod = Self::new(device, self.high.recreate(device), self.low.recreate(device), self.capacity);
+        lod.low_detail_distance = self.low_detail_distance;
+        lod.cull_distance = self.cull_distance;
+        lod.set_indirect(device, self.indirect());
+        lod.set_gpu_culling(device, self.gpu_culling());
+        lod
+    }
+
+    // Indirect draws need INDIRECT_EXECUTION, which WebGL doesn't have
+    pub fn supports_indirect(adapter: &wgpu::Adapter) -> bool {
+        adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+    }
+
+    pub fn indirect(&self) -> bool {
+        self.cpu_indirect.is_some()
+    }
+
+    /*
+    *   Switches to drawing through draw_indexed_indirect with arguments we write from the cpu
+    *   every frame. On its own that doesn't buy us anything, but it's the same draw call the gpu
+    *   culling path uses, where a compute shader writes the arguments instead.
+    *
+    *   We always draw from first_instance 0. Anything else needs Features::INDIRECT_FIRST_INSTANCE.
+    */
+    pub fn set_indirect(&mut self, device: &wgpu::Device, enabled: bool) {
+        self.cpu_indirect = enabled.then(|| {
+            [&self.high, &self.low].map(|mesh| {
+                device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Indirect Buffer", mesh.name)),
+                    contents: Self::draw_args(mesh, 0).as_bytes(),
+                    usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+        });
+    }
+
+    fn draw_args(mesh: &model::Mesh, instance_count: u32) -> wgpu::util::DrawIndexedIndirect {
+        wgpu::util::DrawIndexedIndirect {
+            vertex_count: mesh.num_elements,
+            instance_count,
+            base_index: 0,
+            vertex_offset: 0,
+            base_instance: 0,
+        }
+    }
+
+    pub fn gpu_culling(&self) -> bool {
+        self.gpu_culling.is_some()
+    }
+
+    // Moves frustum culling between the cpu & gpu. Check GpuCuller::is_supported first.
+    pub fn set_gpu_culling(&mut self, device: &wgpu::Device, enabled: bool) {
+        if enabled == self.gpu_culling() {
+            return;
+        }
+        // The instance buffers need STORAGE usage for the compute shader to read them. Their
+        // contents get rewritten by the next update() anyway.
+        self.high_instances = Self::create_instance_buffer(device, "High Detail Instance Buffer", self.capacity, enabled);
+        self.low_instances = Self::create_instance_buffer(device, "Low Detail Instance Buffer", self.capacity, enabled);
+        self.gpu_culling = enabled.then(|| {
+            let culler = GpuCuller::new(device);
+            let high = culler.create_target(
+                device,
+                &self.high_instances,
+                self.capacity,
+                self.high.num_elements,
+                self.high.bounding_radius(),
+            );
+            let low = culler.create_target(
+                device,
+                &self.low_instances,
+                self.capacity,
+                self.low.num_elements,
+                self.low.bounding_radius(),
+            );
+            GpuCulling { culler, high, low }
+        });
+    }
+
+    // Swaps in new meshes, e.g. once the real ones have finished loading
+    pub fn set_meshes(&mut self, device: &wgpu::Device, high: model::Mesh, low: model::Mesh) {
+        self.high = high;
+        self.low = low;
+        // The culling targets know the old meshes' sizes, start them over
+        if self.gpu_culling() {
+            self.set_gpu_culling(device, false);
+            self.set_gpu_culling(device, true);
+        }
+    }
+
+    // Makes room for a different number of instances, e.g. after loading a scene
+    pub fn set_capacity(&mut self, device: &wgpu::Device, capacity: usize) {
+        self.capacity = capacity;
+        let gpu_culling = self.gpu_culling();
+        self.high_instances = Self::create_instance_buffer(device, "High Detail Instance Buffer", capacity, gpu_culling);
+        self.low_instances = Self::create_instance_buffer(device, "Low Detail Instance Buffer", capacity, gpu_culling);
+        // The culling targets still point at the old buffers
+        if gpu_culling {
+            self.set_gpu_culling(device, false);
+            self.set_gpu_culling(device, true);
+        }
+    }
+
+    // The detailed mesh, what the debug views look at
+    pub fn high_detail(&self) -> &model::Mesh {
+        &self.high
+    }
+
+    pub fn counts(&self) -> LodCounts {
+        self.counts
+    }
+
+    // Picks a level for every instance based on its distance to `eye` and uploads the
+    // instance buffers. Returns the new counts if they changed since the last call.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        eye: cgmath::Point3<f32>,
+        frustum: &Frustum,
+        instances: &[Transform],
+    ) -> Option<LodCounts> {
+        let mut high = Vec::with_capacity(instances.len());
+        let mut low = Vec::with_capacity(instances.len());
+        let mut culled = 0;
+        let high_radius = self.high.bounding_radius();
+        // Anything past capacity has nowhere to go, treat it as culled
+        for transform in instances.iter().take(self.capacity) {
+            let distance = eye.distance(cgmath::Point3::from_vec(transform.position));
+            // The gpu does the frustum test itself later on
+            let radius = high_radius * transform.scale.x.max(transform.scale.y).max(transform.scale.z);
+            let outside_frustum = self.gpu_culling.is_none() && !frustum.intersects_sphere(transform.position, radius);
+            if distance > self.cull_distance || outside_frustum {
+                culled += 1;
+            } else if distance > self.low_detail_distance {
+                low.push(transform.to_raw());
+            } else {
+                high.push(transform.to_raw());
+            }
+        }
+        culled += instances.len().saturating_sub(self.capacity) as u32;
+
+        queue.write_buffer(&self.high_instances, 0, bytemuck::cast_slice(&high));
+        queue.write_buffer(&self.low_instances, 0, bytemuck::cast_slice(&low));
+        if let Some(gpu) = &self.gpu_culling {
+            gpu.culler.update_frustum(queue, frustum);
+        }
+        if let Some([high_args, low_args]) = &self.cpu_indirect {
+            queue.write_buffer(high_args, 0, Self::draw_args(&self.high, high.len() as u32).as_bytes());
+            queue.write_buffer(low_args, 0, Self::draw_args(&self.low, low.len() as u32).as_bytes());
+        }
+
+        let counts = LodCounts {
+            high: high.len() as u32,
+            low: low.len() as u32,
+            culled,
+        };
+        let changed = counts != self.counts;
+        self.counts = counts;
+        changed.then_some(counts)
+    }
+
+    // Runs the gpu culling pass, if it's on. Has to go before the render pass that draws us.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue) {
+        if let Some(gpu) = &self.gpu_culling {
+            gpu.culler.cull(encoder, queue, &gpu.high, self.counts.high);
+            gpu.culler.cull(encoder, queue, &gpu.low, self.counts.low);
+        }
+    }
+
+    // Draws both levels. Expects a pipeline taking ModelVertex in slot 0 and InstanceRaw in slot 1.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(gpu) = &self.gpu_culling {
+            // How many instances made it is only known on the gpu, the indirect buffers hold it
+            for (mesh, target) in [(&self.high, &gpu.high), (&self.low, &gpu.low)] {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, target.instances.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed_indirect(&target.indirect, 0);
+            }
+            return;
+        }
+
+        if let Some([high_args, low_args]) = &self.cpu_indirect {
+            for (mesh, instances, args) in [
+                (&self.high, &self.high_instances, high_args),
+                (&self.low, &self.low_instances, low_args),
+            ] {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, instances.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed_indirect(args, 0);
+            }
+            return;
+        }
+
+        for (mesh, instances, count) in [
+            (&self.high, &self.high_instances, self.counts.high),
+            (&self.low, &self.low_instances, self.counts.low),
+        ] {
+            if count == 0 {
+                continue;
+            }
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instances.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, 0..count);
+        }
+    }
+
+    // Binds the high detail instances in slot 1 and returns how many there are, for debug
+    // views built from the high detail mesh
+    pub fn bind_high_detail_instances<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) -> std::ops::Range<u32> {
+        render_pass.set_vertex_buffer(1, self.high_instances.slice(..));
+        0..self.counts.high
+    }
+}
@@ -0,0 +1,55 @@
+/*
+*   The logger is set up to let everything through, and log's global max level does the
+*   actual filtering. That way the level can be changed while running (see bump_level), which
+*   the loggers' own filters can't do once they've been installed.
+*
+*   wgpu, wgpu's internals & naga are very chatty at info and below, so they're capped at warn
+*   unless the starting level is trace. Their warnings & errors always come through, those are
+*   usually the only explanation you get for a validation panic.
+*
+*   On native, RUST_LOG works the way it does with env_logger::init(), and overrides all of
+*   the above: its directives replace ours, and the level it asks for is used instead of
+*   RunConfig::log_level. The web has no environment, so there it's always the config.
+*/
+#[cfg(not(target_arch = "wasm32"))]
+const NOISY_MODULES: [&str; 4] = ["wgpu", "wgpu_core", "wgpu_hal", "naga"];
+
+pub fn init(level: log::LevelFilter) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            // console_log can't filter by module, so the noisy ones get the same level as the rest
+            console_log::init_with_level(log::Level::Trace).expect("Couldn't initialize logger");
+            log::set_max_level(level);
+        } else {
+            let noisy_level = if level == log::LevelFilter::Trace {
+                log::LevelFilter::Trace
+            } else {
+                log::LevelFilter::Warn
+            };
+            let mut builder = env_logger::Builder::new();
+            builder.filter_level(log::LevelFilter::Trace);
+            for module in NOISY_MODULES {
+                builder.filter_module(module, noisy_level);
+            }
+            let from_env = std::env::var("RUST_LOG").is_ok();
+            builder.parse_default_env().init();
+            // init() set the max level from the filters, RUST_LOG's if there was one
+            if !from_env {
+                log::set_max_level(level);
+            }
+        }
+    }
+}
+
+// One step more detailed, wrapping around from trace back to errors only
+pub fn bump_level() -> log::LevelFilter {
+    let level = match log::max_level() {
+        log::LevelFilter::Off | log::LevelFilter::Trace => log::LevelFilter::Error,
+        log::LevelFilter::Error => log::LevelFilter::Warn,
+        log::LevelFilter::Warn => log::LevelFilter::Info,
+        log::LevelFilter::Info => log::LevelFilter::Debug,
+        log::LevelFilter::Debug => log::LevelFilter::Trace,
+    };
+    log::set_max_level(level);
+    level
+}
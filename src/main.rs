@@ -1,12 +1,30 @@
-use learn_wgpu::run; 
+use learn_wgpu::{config::{ArgsError, RunConfig}, run_with_config};
 
 /*
 *   Since our run is async, main() will need some way to await the future. We coukld use a crate
 *   like tokio or async-std, but this example we'll go with the much more lightweight pollster. 
 */
 fn main() {
+    let config = match RunConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(help @ ArgsError::Help) => {
+            println!("{}", help);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
     // Don't use block_on inside of an async function if we plan to support WASM. Futures have to
     // be run using the browser's executor. If you try to bring your own your code will crash
     // when you encounter a future that doesn't execute immediately.
-    pollster::block_on(run());
+    //
+    // It only comes back if there's no gpu, which gets a message rather than a panic so scripts
+    // & CI can tell what happened.
+    if let Err(e) = pollster::block_on(run_with_config(config)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,270 @@
+use std::collections::BTreeSet;
+
+use crate::texture;
+
+/*
+*   Shader variants without copies of the shader. The WGSL gets run through a tiny preprocessor
+*   before it reaches create_shader_module: lines between `#ifdef NAME` and `#endif` are only
+*   kept when NAME is defined, `#ifndef` is the opposite, and `#else` flips between the two.
+*   They nest. Lines that get dropped are left blank, so naga's error messages still point at
+*   the right line of the original file.
+*
+*   Each Material says which features it uses, and that turns into its set of defines. Two
+*   materials with the same features get the same shader, so the compiled pipelines are kept
+*   in a map keyed by the defines (see State::scene_pipeline).
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefines(BTreeSet<&'static str>);
+
+impl ShaderDefines {
+    pub fn new(defines: &[&'static str]) -> Self {
+        Self(defines.iter().copied().collect())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    pub fn preprocess(&self, source: &str) -> String {
+        // Whether each enclosing block is being kept
+        let mut keeping = Vec::new();
+        let mut output = String::with_capacity(source.len());
+        for line in source.lines() {
+            let directive = line.trim();
+            let kept = keeping.iter().all(|&keep| keep);
+            if let Some(name) = directive.strip_prefix("#ifdef ") {
+                keeping.push(self.contains(name.trim()));
+            } else if let Some(name) = directive.strip_prefix("#ifndef ") {
+                keeping.push(!self.contains(name.trim()));
+            } else if directive == "#else" {
+                let keep = keeping.last_mut().expect("#else without #ifdef");
+                *keep = !*keep;
+            } else if directive == "#endif" {
+                keeping.pop().expect("#endif without #ifdef");
+            } else if kept {
+                output.push_str(line);
+            }
+            output.push('\n');
+        }
+        assert!(keeping.is_empty(), "#ifdef without #endif");
+        output
+    }
+}
+
+/*
+*   Double sided materials are for thin things with nothing between their two sides, leaves,
+*   cloth, paper. Back face culling is off so both sides get drawn, and since the mesh only
+*   has one normal per vertex, pointing out of the front, the back sides would be lit as if
+*   they faced the other way: dark when the light's on them and lit when it's behind. The
+*   fragment shader turns the normal around on back faces, so it always points at the viewer.
+*
+*   @builtin(front_facing) is how the fragment shader knows which side it's on. It's true on
+*   triangles whose corners go around the screen in the pipeline's front_face order (counter
+*   clockwise here), the same test culling uses, so it's about the winding as seen from the
+*   camera rather than the normals.
+*
+*   The back can be tinted too, for undersides that are paler than the top the way most
+*   leaves are.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Material {
+    pub name: &'static str,
+    // Samples the diffuse texture at group 2, otherwise it's a flat color
+    pub textured: bool,
+    // Draws & lights both sides, see above
+    pub double_sided: bool,
+    // Back faces of a double sided material get a color of their own on top of the texture
+    pub tint_back_faces: bool,
+}
+
+impl Material {
+    pub const TEXTURED: Material = Material {
+        name: "textured",
+        textured: true,
+        double_sided: false,
+        tint_back_faces: false,
+    };
+    pub const UNTEXTURED: Material = Material {
+        name: "untextured",
+        textured: false,
+        double_sided: false,
+        tint_back_faces: false,
+    };
+    // For the leaf, see leaf.rs
+    pub const LEAF: Material = Material {
+        name: "leaf",
+        textured: true,
+        double_sided: true,
+        tint_back_faces: true,
+    };
+
+    pub const ALL: [Material; 3] = [Material::TEXTURED, Material::UNTEXTURED, Material::LEAF];
+
+    // Looks a material up by name, how scene files refer to them
+    pub fn from_name(name: &str) -> Option<Material> {
+        Self::ALL.into_iter().find(|material| material.name == name)
+    }
+
+    pub fn defines(&self) -> ShaderDefines {
+        let mut defines = Vec::new();
+        if self.textured {
+            defines.push("TEXTURED");
+        }
+        if self.double_sided {
+            defines.push("DOUBLE_SIDED");
+            if self.tint_back_faces {
+                defines.push("BACK_TINT");
+            }
+        }
+        ShaderDefines::new(&defines)
+    }
+}
+
+/*
+*   The texture statements of a .mtl file, by what they're used for. That's the only place a
+*   texture's meaning comes from, and with it the format it has to be loaded in (see
+*   texture::TextureKind): colors are sRGB, everything else is plain numbers.
+*
+*   bump & map_Bump were meant for height maps, but it's where most exporters put the normal
+*   map these days, so they're taken as one.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureSlot {
+    Diffuse,
+    Specular,
+    Emissive,
+    Normal,
+    Roughness,
+    Metallic,
+    Opacity,
+    Displacement,
+}
+
+impl TextureSlot {
+    // The .mtl keyword a texture statement starts with
+    pub fn from_mtl(keyword: &str) -> Option<Self> {
+        match keyword {
+            "map_Kd" => Some(TextureSlot::Diffuse),
+            "map_Ks" => Some(TextureSlot::Specular),
+            "map_Ke" => Some(TextureSlot::Emissive),
+            "norm" | "map_Kn" | "bump" | "map_Bump" | "map_bump" => Some(TextureSlot::Normal),
+            "map_Pr" => Some(TextureSlot::Roughness),
+            "map_Pm" => Some(TextureSlot::Metallic),
+            "map_d" => Some(TextureSlot::Opacity),
+            "disp" => Some(TextureSlot::Displacement),
+            _ => None,
+        }
+    }
+
+    pub fn kind(self) -> texture::TextureKind {
+        match self {
+            TextureSlot::Diffuse | TextureSlot::Specular | TextureSlot::Emissive => texture::TextureKind::Color,
+            TextureSlot::Normal => texture::TextureKind::Normal,
+            TextureSlot::Roughness | TextureSlot::Metallic | TextureSlot::Opacity | TextureSlot::Displacement => {
+                texture::TextureKind::Linear
+            }
+        }
+    }
+}
+
+// The textures an .obj's materials use, each loaded in the format for its slot
+#[derive(Default)]
+pub struct MaterialTextures {
+    pub textures: Vec<(TextureSlot, texture::Texture)>,
+}
+
+impl MaterialTextures {
+    /*
+    *   Loads the textures from every material library (mtllib) `obj_path` names. The mesh is
+    *   a single one (see MeshData::from_obj), so the first texture for each slot wins whatever
+    *   material it's in. Files that are missing or can't be decoded get logged & skipped.
+    */
+    pub fn load_for_obj(device: &wgpu::Device, queue: &wgpu::Queue, obj_path: &std::path::Path) -> Self {
+        let mut textures: Vec<(TextureSlot, texture::Texture)> = Vec::new();
+        for (slot, path) in Self::texture_paths(obj_path) {
+            if textures.iter().any(|(loaded, _)| *loaded == slot) {
+                continue;
+            }
+            let label = format!("{:?} {}", slot, path.display());
+            let loaded = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| {
+                    texture::Texture::from_bytes(device, queue, &bytes, &label, slot.kind(), texture::Alpha::Straight)
+                        .map_err(|e| e.to_string())
+                });
+            match loaded {
+                Ok(texture) => {
+                    log::info!("loaded {} as {:?} ({:?})", path.display(), slot, slot.kind().format());
+                    textures.push((slot, texture));
+                }
+                Err(e) => log::warn!("couldn't load the {:?} texture {}: {}", slot, path.display(), e),
+            }
+        }
+        Self { textures }
+    }
+
+    // Every texture statement in the .obj's material libraries, with paths relative to the .mtl
+    fn texture_paths(obj_path: &std::path::Path) -> Vec<(TextureSlot, std::path::PathBuf)> {
+        let directory = |path: &std::path::Path| path.parent().unwrap_or(std::path::Path::new("")).to_path_buf();
+        let obj = match std::fs::read_to_string(obj_path) {
+            Ok(obj) => obj,
+            Err(e) => {
+                log::warn!("couldn't read {} for its materials: {}", obj_path.display(), e);
+                return Vec::new();
+            }
+        };
+        let libraries = obj
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("mtllib "))
+            .flat_map(str::split_whitespace)
+            .map(|name| directory(obj_path).join(name))
+            .collect::<Vec<_>>();
+
+        let mut paths = Vec::new();
+        for library in libraries {
+            let mtl = match std::fs::read_to_string(&library) {
+                Ok(mtl) => mtl,
+                Err(e) => {
+                    log::warn!("couldn't read the material library {}: {}", library.display(), e);
+                    continue;
+                }
+            };
+            for line in mtl.lines() {
+                let mut words = line.split_whitespace();
+                let slot = match words.next().and_then(TextureSlot::from_mtl) {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                // Options like -bm 1.0 come first, the file name is last
+                if let Some(name) = words.last() {
+                    paths.push((slot, directory(&library).join(name)));
+                }
+            }
+        }
+        paths
+    }
+
+    pub fn get(&self, slot: TextureSlot) -> Option<&texture::Texture> {
+        self.textures.iter().find(|(loaded, _)| *loaded == slot).map(|(_, texture)| texture)
+    }
+
+    // The slot's texture & its sampler at bindings 0 & 1, the same layout as the procedural
+    // texture's
+    pub fn bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, slot: TextureSlot) -> Option<wgpu::BindGroup> {
+        let texture = self.get(slot)?;
+        Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("material_texture_bind_group"),
+        }))
+    }
+}
@@ -0,0 +1,265 @@
+use std::rc::Rc;
+
+use cgmath::InnerSpace;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance;
+use crate::model::Vertex;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+
+/*
+*   A map of the scene from straight above, in a square in one corner of the screen. It follows
+*   the main camera around, with an arrow in the middle showing where the camera is looking.
+*
+*   The scene is simplified down to a dot per instance, drawn from the same transforms the main
+*   pass uses but with a camera uniform of its own (a camera::TopDownCamera). Everything goes
+*   in a pass of its own at the end of the frame, squeezed into the corner with set_viewport,
+*   so it doesn't get blurred or anti-aliased along with the scene.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MinimapLayout {
+    pub corner: Corner,
+    // Width & height of the map in pixels, shrunk to fit smaller windows
+    pub size: u32,
+    // Pixels between the map and the edges of the screen
+    pub margin: u32,
+    // World units from the camera to the edges of the map
+    pub extent: f32,
+}
+
+impl Default for MinimapLayout {
+    fn default() -> Self {
+        Self {
+            corner: Corner::TopRight,
+            size: 200,
+            margin: 16,
+            extent: 20.0,
+        }
+    }
+}
+
+impl MinimapLayout {
+    // x, y, width & height of the viewport in pixels, for a screen of `size`
+    pub fn viewport(&self, size: winit::dpi::PhysicalSize<u32>) -> [f32; 4] {
+        let margin = self.margin.min(size.width / 4).min(size.height / 4);
+        let fits = size.width.min(size.height).saturating_sub(margin * 2);
+        let side = self.size.min(fits).max(1);
+        let x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin,
+            Corner::TopRight | Corner::BottomRight => size.width.saturating_sub(side + margin),
+        };
+        let y = match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin,
+            Corner::BottomLeft | Corner::BottomRight => size.height.saturating_sub(side + margin),
+        };
+        [x as f32, y as f32, side as f32, side as f32]
+    }
+}
+
+// Has to match Minimap in minimap.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MinimapUniform {
+    view_proj: [[f32; 4]; 4],
+    // Where the main camera is on the xz plane, and which way it's looking
+    marker_position: [f32; 2],
+    marker_direction: [f32; 2],
+    // In world units, so they scale with the extent like everything else on the map
+    marker_size: f32,
+    min_icon_radius: f32,
+    _padding: [f32; 2],
+}
+
+pub struct Minimap {
+    pub enabled: bool,
+    layout: MinimapLayout,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // One InstanceRaw per instance, rewritten every frame
+    instance_buffer: Tracked<wgpu::Buffer>,
+    capacity: usize,
+    instance_count: u32,
+    background_pipeline: Rc<wgpu::RenderPipeline>,
+    icon_pipeline: Rc<wgpu::RenderPipeline>,
+    marker_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Minimap {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        layout: MinimapLayout,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Minimap Buffer"),
+            contents: bytemuck::cast_slice(&[<MinimapUniform as bytemuck::Zeroable>::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("minimap_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("minimap_bind_group"),
+        });
+
+        // Everything is flat & drawn back to front, so there's no depth buffer
+        let pipeline = |label, vertex_entry_point, vertex_buffers: &[wgpu::VertexBufferLayout]| {
+            pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label,
+                    shader: include_str!("minimap.wgsl"),
+                    bind_group_layouts: &[&bind_group_layout],
+                    vertex_entry_point,
+                    vertex_buffers,
+                    fragment_entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            )
+        };
+        let background_pipeline = pipeline("Minimap Background", "vs_background", &[]);
+        let icon_pipeline = pipeline("Minimap Icons", "vs_icon", &[instance::InstanceRaw::desc()]);
+        let marker_pipeline = pipeline("Minimap Marker", "vs_marker", &[]);
+
+        Self {
+            enabled: false,
+            layout,
+            buffer,
+            bind_group,
+            instance_buffer: Self::create_instance_buffer(device, 0),
+            capacity: 0,
+            instance_count: 0,
+            background_pipeline,
+            icon_pipeline,
+            marker_pipeline,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Minimap Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<instance::InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new surface format, same layout.
+    // The instances come back with the next update.
+    pub fn recreate(&self, device: &wgpu::Device, pipelines: &PipelineCache, color_format: wgpu::TextureFormat) -> Self {
+        let mut minimap = Self::new(device, pipelines, color_format, self.layout);
+        minimap.enabled = self.enabled;
+        minimap
+    }
+
+    pub fn layout(&self) -> MinimapLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: MinimapLayout) {
+        self.layout = layout;
+    }
+
+    // Call once per frame with the main camera & where everything is
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &camera::Camera,
+        instances: &[instance::Transform],
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let map_camera = camera::TopDownCamera {
+            center: camera.eye,
+            extent: self.layout.extent,
+            height: camera.eye.y.max(0.0) + 50.0,
+        };
+        // Straight up or down there's no direction to point in, so point up the map
+        let forward = cgmath::vec2(camera.target.x - camera.eye.x, camera.target.z - camera.eye.z);
+        let direction = if forward.magnitude2() > 1e-8 { forward.normalize() } else { cgmath::vec2(0.0, -1.0) };
+        let uniform = MinimapUniform {
+            view_proj: map_camera.build_view_projection_matrix().into(),
+            marker_position: [camera.eye.x, camera.eye.z],
+            marker_direction: direction.into(),
+            marker_size: self.layout.extent * 0.08,
+            // Anything smaller than a couple of pixels would flicker in & out
+            min_icon_radius: self.layout.extent * 0.015,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        if instances.len() > self.capacity {
+            self.capacity = instances.len();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        let raw = instances.iter().map(instance::Transform::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw));
+        self.instance_count = raw.len() as u32;
+    }
+
+    // Draws over whatever is in `output` already
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView, size: winit::dpi::PhysicalSize<u32>) {
+        if !self.enabled {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Minimap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        let [x, y, width, height] = self.layout.viewport(size);
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+
+        render_pass.set_pipeline(&self.background_pipeline);
+        render_pass.draw(0..6, 0..1);
+        if self.instance_count > 0 {
+            render_pass.set_pipeline(&self.icon_pipeline);
+            render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+            render_pass.draw(0..6, 0..self.instance_count);
+        }
+        render_pass.set_pipeline(&self.marker_pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,92 @@
+// The top down map in the corner, see minimap.rs. The viewport is the map's square, so clip
+// space covers just the map.
+
+struct Minimap {
+    view_proj: mat4x4<f32>,
+    marker_position: vec2<f32>,
+    marker_direction: vec2<f32>,
+    marker_size: f32,
+    min_icon_radius: f32,
+};
+@group(0) @binding(0)
+var<uniform> minimap: Minimap;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    // -1 to 1 across each icon, the fragment shader rounds them off. 0 for everything else.
+    @location(1) local: vec2<f32>,
+};
+
+// Two triangles covering -1 to 1
+fn quad_corner(index: u32) -> vec2<f32> {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    return corners[index];
+}
+
+@vertex
+fn vs_background(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(quad_corner(in_vertex_index), 0.0, 1.0);
+    out.color = vec4<f32>(0.05, 0.07, 0.1, 0.75);
+    out.local = vec2<f32>(0.0);
+    return out;
+}
+
+// A dot on the ground under each instance, about as wide as the instance
+@vertex
+fn vs_icon(@builtin(vertex_index) in_vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    let center = instance.model_matrix_3.xyz;
+    let radius = max(max(length(instance.model_matrix_0.xyz), length(instance.model_matrix_2.xyz)), minimap.min_icon_radius);
+    let corner = quad_corner(in_vertex_index);
+    let world_position = center + vec3<f32>(corner.x, 0.0, corner.y) * radius;
+
+    var out: VertexOutput;
+    out.clip_position = minimap.view_proj * vec4<f32>(world_position, 1.0);
+    // Higher up is brighter
+    let height = clamp(center.y * 0.2, 0.0, 1.0);
+    out.color = vec4<f32>(mix(vec3<f32>(0.3, 0.6, 0.9), vec3<f32>(0.8, 0.95, 1.0), height), 1.0);
+    out.local = corner;
+    return out;
+}
+
+// An arrow at the main camera pointing where it looks
+@vertex
+fn vs_marker(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let forward = minimap.marker_direction;
+    let right = vec2<f32>(-forward.y, forward.x);
+    var points = array<vec2<f32>, 3>(
+        forward,
+        -forward * 0.6 - right * 0.6,
+        -forward * 0.6 + right * 0.6,
+    );
+    let point = minimap.marker_position + points[in_vertex_index] * minimap.marker_size;
+
+    var out: VertexOutput;
+    out.clip_position = minimap.view_proj * vec4<f32>(point.x, 0.0, point.y, 1.0);
+    out.color = vec4<f32>(1.0, 0.45, 0.1, 1.0);
+    out.local = vec2<f32>(0.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (dot(in.local, in.local) > 1.0) {
+        discard;
+    }
+    return in.color;
+}
@@ -0,0 +1,239 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{screen, texture};
+
+/*
+*   Mip level selection, see texture::SamplerLod. A mipmapped checkerboard is drawn as a floor
+*   stretching off to the horizon across the bottom half of the finished frame, the way the
+*   distance squeezes more and more texels into each pixel is what picks the level.
+*
+*   The left half always samples without a bias at the default clamps, to compare against.
+*   The right half uses the texture's own SamplerLod: with a negative bias the checks stay
+*   crisp further out but break up into moiré, with a positive one they fade to grey early,
+*   even close up.
+*/
+pub struct MipBiasDemo {
+    pub enabled: bool,
+    texture: texture::Texture,
+    // Without a bias or clamps, for the left half
+    reference_sampler: wgpu::Sampler,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MipBiasUniform {
+    bias: f32,
+    _padding: [f32; 3],
+}
+
+impl MipBiasDemo {
+    // Texels across the checkerboard, and across each check
+    const SIZE: u32 = 256;
+    const CHECK_SIZE: u32 = 8;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture = texture::Texture::from_image_mipmapped(
+            device,
+            queue,
+            &Self::checkerboard(),
+            "Mip Bias Checkerboard",
+            texture::TextureKind::Color,
+        );
+        let reference_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mip Bias Buffer"),
+            contents: bytemuck::cast_slice(&[MipBiasUniform {
+                bias: texture.lod().bias,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("mip_bias_demo_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture, &reference_sampler, &buffer);
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Mip Bias Demo",
+                shader: include_str!("mip_bias_demo.wgsl"),
+                bind_group_layouts: &[screen_layout, &bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+
+        Self {
+            enabled: false,
+            texture,
+            reference_sampler,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Same demo on a (new) device or for a new surface format, with the same SamplerLod
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let mut demo = Self::new(device, pipelines, queue, screen_layout, color_format);
+        demo.enabled = self.enabled;
+        demo.set_lod(device, queue, self.lod());
+        demo
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        reference_sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(reference_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("mip_bias_demo_bind_group"),
+        })
+    }
+
+    // Black & white checks, the worst case for aliasing and the easiest to see blur on
+    fn checkerboard() -> image::DynamicImage {
+        let image = image::RgbaImage::from_fn(Self::SIZE, Self::SIZE, |x, y| {
+            if (x / Self::CHECK_SIZE + y / Self::CHECK_SIZE) % 2 == 0 {
+                image::Rgba([235, 235, 235, 255])
+            } else {
+                image::Rgba([20, 20, 20, 255])
+            }
+        });
+        image::DynamicImage::ImageRgba8(image)
+    }
+
+    pub fn lod(&self) -> texture::SamplerLod {
+        self.texture.lod()
+    }
+
+    // For the right half, clamped to a sensible range (see SamplerLod::clamped)
+    pub fn set_lod(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lod: texture::SamplerLod) {
+        self.texture.set_lod(device, lod);
+        // The old bind group still holds the old sampler
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.texture, &self.reference_sampler, &self.buffer);
+        let uniform = MipBiasUniform {
+            bias: self.texture.lod().bias,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Draws over the bottom half of whatever is in `output` already
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        if !self.enabled {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mip Bias Demo Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
@@ -0,0 +1,70 @@
+// A checkerboard floor over the bottom half of the screen, see mip_bias_demo.rs. The left half
+// is sampled without a bias, the right half with the texture's.
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_floor: texture_2d<f32>;
+// The texture's own sampler, with its clamps
+@group(1) @binding(1)
+var s_floor: sampler;
+@group(1) @binding(2)
+var s_reference: sampler;
+
+struct MipBias {
+    bias: f32,
+};
+@group(1) @binding(3)
+var<uniform> mip_bias: MipBias;
+
+// How high above the floor it's seen from and how zoomed in, in floor units
+const EYE_HEIGHT: f32 = 1.0;
+const FOCAL_LENGTH: f32 = 1.5;
+// Times the texture repeats per floor unit
+const TILING: f32 = 0.5;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // The bottom half of the screen, from the horizon down
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(-1.0, 0.0),
+    );
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(corners[in_vertex_index], 0.0, 1.0);
+    out.ndc = corners[in_vertex_index];
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Where the ray through this pixel meets the floor. The distance grows without end towards
+    // the horizon, the tiny minimum keeps the last row from dividing by zero.
+    let aspect = screen.resolution.x * screen.inv_resolution.y;
+    let distance = EYE_HEIGHT * FOCAL_LENGTH / max(-in.ndc.y, 1e-4);
+    let ground = vec2<f32>(in.ndc.x * aspect * distance / FOCAL_LENGTH, distance);
+    let uv = ground * TILING;
+
+    // Both sampled everywhere, derivatives only work outside of branches
+    let unbiased = textureSampleBias(t_floor, s_reference, uv, 0.0);
+    let biased = textureSampleBias(t_floor, s_floor, uv, mip_bias.bias);
+    let color = select(unbiased, biased, in.ndc.x > 0.0);
+
+    // A line down the middle between the two
+    let divider = abs(in.clip_position.x - screen.resolution.x * 0.5) < 1.0;
+    return select(color, vec4<f32>(1.0, 0.8, 0.2, 1.0), divider);
+}
@@ -0,0 +1,383 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::physics;
+
+// Anything that can be put in a vertex buffer describes its memory layout through desc()
+pub trait Vertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+}
+
+impl Vertex for ModelVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            // array_stride is how wide a vertex is. When the shader goes to read the next
+            // vertex it will skip over array_stride number of bytes.
+            array_stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+            // step_mode tells the pipeline whether each element of the array represents
+            // per-vertex data or per-instance data.
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+// Mesh geometry that hasn't been uploaded to the gpu yet. Unlike Mesh it's Send, so it can be
+// built on another thread.
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn upload(self, device: &wgpu::Device) -> Mesh {
+        Mesh::new(device, &self.name, self.vertices, self.indices)
+    }
+
+    pub fn load_obj(path: &std::path::Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let name = path.file_stem().map_or("Model".into(), |stem| stem.to_string_lossy());
+        Self::from_obj(&name, &source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /*
+    *   Just enough of the .obj format for a single mesh: positions, texture coordinates,
+    *   normals and polygon faces, which get split into triangle fans. Materials (loaded on
+    *   their own, see material::MaterialTextures), groups & everything else are skipped. Faces
+    *   without normals get the face's own, flat normal.
+    *
+    *   The mesh is centered & scaled to fit in the same radius 0.5 ball as the spheres it
+    *   replaces, models come in all sizes and the grid spacing doesn't.
+    */
+    pub fn from_obj(name: &str, source: &str) -> Result<Self, String> {
+        use cgmath::InnerSpace;
+
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        // Corners that use the same position, uv & normal share a vertex
+        let mut seen = std::collections::HashMap::new();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", line_number + 1, message);
+            let mut words = line.split_whitespace();
+            let keyword = words.next();
+            let mut floats = |count: usize| -> Result<Vec<f32>, String> {
+                let values = words
+                    .by_ref()
+                    .take(count)
+                    .map(|word| word.parse::<f32>().map_err(|_| error("not a number")))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.len() < count {
+                    return Err(error("too few numbers"));
+                }
+                Ok(values)
+            };
+            match keyword {
+                Some("v") => {
+                    let v = floats(3)?;
+                    positions.push([v[0], v[1], v[2]]);
+                }
+                Some("vt") => {
+                    let vt = floats(2)?;
+                    // .obj has v going up from the bottom, wgpu textures go down from the top
+                    tex_coords.push([vt[0], 1.0 - vt[1]]);
+                }
+                Some("vn") => {
+                    let n = floats(3)?;
+                    normals.push([n[0], n[1], n[2]]);
+                }
+                Some("f") => {
+                    // (position, uv, normal) indices, 0 based
+                    let corners = words
+                        .map(|corner| {
+                            let mut parts = corner.split('/');
+                            let position = obj_index(parts.next(), positions.len())?.ok_or("face without a position")?;
+                            let uv = obj_index(parts.next(), tex_coords.len())?;
+                            let normal = obj_index(parts.next(), normals.len())?;
+                            Ok((position, uv, normal))
+                        })
+                        .collect::<Result<Vec<_>, String>>()
+                        .map_err(|e| error(&e))?;
+                    if corners.len() < 3 {
+                        return Err(error("face with fewer than 3 corners"));
+                    }
+
+                    let face_normal = {
+                        let [a, b, c] = [0, 1, 2].map(|i| cgmath::Vector3::from(positions[corners[i].0]));
+                        let n = (b - a).cross(c - a);
+                        if n.magnitude2() > 0.0 { n.normalize().into() } else { [0.0, 1.0, 0.0] }
+                    };
+                    let mut corner_indices = Vec::with_capacity(corners.len());
+                    for corner in corners {
+                        let index = *seen.entry(corner).or_insert_with(|| {
+                            let (position, uv, normal) = corner;
+                            vertices.push(ModelVertex {
+                                position: positions[position],
+                                tex_coords: uv.map_or([0.0, 0.0], |uv| tex_coords[uv]),
+                                normal: normal.map_or(face_normal, |normal| normals[normal]),
+                            });
+                            vertices.len() as u32 - 1
+                        });
+                        corner_indices.push(index);
+                    }
+                    for i in 1..corner_indices.len() - 1 {
+                        indices.extend_from_slice(&[corner_indices[0], corner_indices[i], corner_indices[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if indices.is_empty() {
+            return Err("no faces".to_string());
+        }
+
+        let (min, max) = vertices.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), v| {
+            ([0, 1, 2].map(|i| min[i].min(v.position[i])), [0, 1, 2].map(|i| max[i].max(v.position[i])))
+        });
+        let center = [0, 1, 2].map(|i| (min[i] + max[i]) * 0.5);
+        let radius = vertices
+            .iter()
+            .map(|v| (0..3).map(|i| (v.position[i] - center[i]).powi(2)).sum::<f32>().sqrt())
+            .fold(0.0, f32::max);
+        let scale = if radius > 0.0 { 0.5 / radius } else { 1.0 };
+        for v in &mut vertices {
+            v.position = [0, 1, 2].map(|i| (v.position[i] - center[i]) * scale);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            vertices,
+            indices,
+        })
+    }
+}
+
+// .obj indices start at 1, and negative ones count back from the last one so far
+fn obj_index(part: Option<&str>, count: usize) -> Result<Option<usize>, String> {
+    let part = match part {
+        Some(part) if !part.is_empty() => part,
+        _ => return Ok(None),
+    };
+    let index: i64 = part.parse().map_err(|_| format!("bad index {:?}", part))?;
+    let index = if index < 0 { count as i64 + index } else { index - 1 };
+    if index < 0 || index >= count as i64 {
+        return Err(format!("index {} out of range", part));
+    }
+    Ok(Some(index as usize))
+}
+
+// One of the models that can be shown, cycled with shift + [ & ]. Kept on the cpu, only the
+// one being shown is uploaded.
+#[derive(Clone, Debug)]
+pub struct Model {
+    pub name: String,
+    // Where it was loaded from, None for the spheres
+    pub path: Option<std::path::PathBuf>,
+    pub high: MeshData,
+    // Models from files have no low detail version, they use `high` for both
+    pub low: Option<MeshData>,
+}
+
+impl Model {
+    pub fn spheres(high: MeshData, low: MeshData) -> Self {
+        Self {
+            name: "Spheres".into(),
+            path: None,
+            high,
+            low: Some(low),
+        }
+    }
+
+    pub fn from_file(path: std::path::PathBuf, mesh: MeshData) -> Self {
+        Self {
+            name: mesh.name.clone(),
+            path: Some(path),
+            high: mesh,
+            low: None,
+        }
+    }
+
+    // Both detail levels, ready to draw
+    pub fn upload(&self, device: &wgpu::Device) -> (Mesh, Mesh) {
+        let low = self.low.as_ref().unwrap_or(&self.high);
+        (self.high.clone().upload(device), low.clone().upload(device))
+    }
+
+    // The box around the high detail mesh, in its own space
+    pub fn bounds(&self) -> physics::Aabb {
+        let (min, max) = self.high.vertices.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), v| {
+            ([0, 1, 2].map(|i| min[i].min(v.position[i])), [0, 1, 2].map(|i| max[i].max(v.position[i])))
+        });
+        physics::Aabb::new(min.into(), max.into())
+    }
+}
+
+pub struct Mesh {
+    pub name: String,
+    // We keep a copy of the geometry on the cpu so debug views (and anything else that
+    // wants to inspect it) don't have to read it back from the gpu, and so we can upload
+    // it again if the device is lost.
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub vertex_buffer: Tracked<wgpu::Buffer>,
+    pub index_buffer: Tracked<wgpu::Buffer>,
+    pub num_elements: u32,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, name: &str, vertices: Vec<ModelVertex>, indices: Vec<u32>) -> Self {
+        let vertex_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&vertices),
+            // COPY_SRC so the vertices can be read back for inspection
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+        });
+        let index_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            name: name.to_string(),
+            num_elements: indices.len() as u32,
+            vertices,
+            indices,
+            vertex_buffer,
+            index_buffer,
+        }
+    }
+
+    // Radius of the smallest sphere around the origin that holds the whole mesh, for culling
+    pub fn bounding_radius(&self) -> f32 {
+        self.vertices
+            .iter()
+            .map(|v| (v.position[0].powi(2) + v.position[1].powi(2) + v.position[2].powi(2)).sqrt())
+            .fold(0.0, f32::max)
+    }
+
+    // Uploads the cpu side copy of the mesh to a (new) device, used when the device is lost
+    pub fn recreate(&self, device: &wgpu::Device) -> Self {
+        Self::new(device, &self.name, self.vertices.clone(), self.indices.clone())
+    }
+
+    // A unit cube centered on the origin. Each face gets its own 4 vertices so the
+    // normals stay flat instead of being shared between faces.
+    pub fn cube(device: &wgpu::Device) -> Self {
+        // (normal, tangent u, tangent v) for each face
+        let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+            ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ];
+
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for (normal, u, v) in faces {
+            let base = vertices.len() as u32;
+            for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let position = [
+                    (normal[0] + u[0] * su + v[0] * sv) * 0.5,
+                    (normal[1] + u[1] * su + v[1] * sv) * 0.5,
+                    (normal[2] + u[2] * su + v[2] * sv) * 0.5,
+                ];
+                vertices.push(ModelVertex {
+                    position,
+                    tex_coords: [(su + 1.0) * 0.5, 1.0 - (sv + 1.0) * 0.5],
+                    normal,
+                });
+            }
+            // Counter clockwise winding, matching FrontFace::Ccw
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        Self::new(device, "Cube", vertices, indices)
+    }
+
+    // A unit square centered on the origin in the xy plane, facing +z, with the whole texture
+    // on it the right way up
+    pub fn quad(device: &wgpu::Device) -> Self {
+        let vertices = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)]
+            .map(|(x, y)| ModelVertex {
+                position: [x, y, 0.0],
+                tex_coords: [x + 0.5, 0.5 - y],
+                normal: [0.0, 0.0, 1.0],
+            })
+            .to_vec();
+        Self::new(device, "Quad", vertices, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    // A sphere of radius 0.5 made of `sectors` slices around the y axis and `stacks` rings from
+    // pole to pole. More of both means a rounder (and more expensive) sphere.
+    pub fn uv_sphere(device: &wgpu::Device, name: &str, sectors: u32, stacks: u32) -> Self {
+        Self::uv_sphere_data(name, sectors, stacks).upload(device)
+    }
+
+    // The cpu side of uv_sphere, doesn't need the device
+    pub fn uv_sphere_data(name: &str, sectors: u32, stacks: u32) -> MeshData {
+        let sectors = sectors.max(3);
+        let stacks = stacks.max(2);
+
+        let mut vertices = Vec::with_capacity(((sectors + 1) * (stacks + 1)) as usize);
+        for i in 0..=stacks {
+            let phi = std::f32::consts::PI * i as f32 / stacks as f32;
+            for j in 0..=sectors {
+                let theta = std::f32::consts::TAU * j as f32 / sectors as f32;
+                // On a sphere centered on the origin the normal is just the direction to the vertex
+                let normal = [phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin()];
+                vertices.push(ModelVertex {
+                    position: [normal[0] * 0.5, normal[1] * 0.5, normal[2] * 0.5],
+                    tex_coords: [j as f32 / sectors as f32, i as f32 / stacks as f32],
+                    normal,
+                });
+            }
+        }
+
+        let mut indices = Vec::with_capacity((sectors * stacks * 6) as usize);
+        for i in 0..stacks {
+            for j in 0..sectors {
+                let a = i * (sectors + 1) + j;
+                let b = a + sectors + 1;
+                // Counter clockwise when seen from outside the sphere
+                indices.extend_from_slice(&[a, a + 1, b + 1, a, b + 1, b]);
+            }
+        }
+
+        MeshData {
+            name: name.to_string(),
+            vertices,
+            indices,
+        }
+    }
+}
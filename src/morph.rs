@@ -0,0 +1,311 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::Transform;
+use crate::model;
+use crate::texture;
+
+/*
+*   Morph targets (blend shapes). A target is another version of the mesh stored as how far
+*   every vertex moves to get there, e.g. a smile or a blink for a face. The vertex shader adds
+*   each target's offsets scaled by that target's weight, so a weight of 0 is the base mesh, 1
+*   is fully the target, and several targets can be mixed at once.
+*
+*   The offsets of every target sit one after another in a storage buffer, the vertex shader
+*   finds its own by target * vertex_count + vertex_index. The weights change every frame, so
+*   they get a small uniform of their own.
+*
+*   glTF stores targets the same way: POSITION & NORMAL accessors per target holding deltas,
+*   plus default weights on the mesh. There's no glTF loader here yet, the demo generates its
+*   targets instead.
+*/
+#[derive(Clone, Debug)]
+pub struct MorphTarget {
+    pub name: String,
+    // One of each per vertex of the base mesh
+    pub position_deltas: Vec<[f32; 3]>,
+    pub normal_deltas: Vec<[f32; 3]>,
+}
+
+// vec3s in a storage buffer are aligned to 16 bytes, same as in a uniform
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MorphDelta {
+    position: [f32; 3],
+    _padding: u32,
+    normal: [f32; 3],
+    _padding2: u32,
+}
+
+pub const MAX_MORPH_TARGETS: usize = 8;
+
+// Has to match MorphWeights in morph.wgsl. The weights are packed 4 to a vec4 because every
+// element of a uniform array takes up 16 bytes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MorphUniform {
+    weights: [[f32; 4]; MAX_MORPH_TARGETS / 4],
+    target_count: u32,
+    vertex_count: u32,
+    _padding: [u32; 2],
+}
+
+pub struct MorphedModel {
+    pub enabled: bool,
+    mesh: model::Mesh,
+    targets: Vec<MorphTarget>,
+    // One per target, changes get uploaded with update()
+    pub weights: Vec<f32>,
+    transform: Transform,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    weights_buffer: Tracked<wgpu::Buffer>,
+    // Takes the place of the texture's group in the scene pipeline, with the targets added
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl MorphedModel {
+    // Storage buffers in vertex shaders again, like skinning
+    // The deltas for every target sit in one storage buffer
+    pub fn is_supported(adapter: &wgpu::Adapter, limits: &wgpu::Limits) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && limits.max_storage_buffers_per_shader_stage >= 1
+    }
+
+    // The vertex shader goes after shader.wgsl, so it can reuse its structs & fragment shader
+    pub fn shader_source() -> String {
+        format!("{}\n{}", include_str!("shader.wgsl"), include_str!("morph.wgsl"))
+    }
+
+    /*
+    *   Targets that don't have a delta for every vertex are left out, and anything past
+    *   MAX_MORPH_TARGETS is ignored, so a mesh with more (or broken) targets still shows up
+    *   with the ones we can use. A mesh without any targets just draws as is.
+    */
+    pub fn new(
+        device: &wgpu::Device,
+        texture: &texture::Texture,
+        mesh: model::Mesh,
+        targets: Vec<MorphTarget>,
+        transform: Transform,
+    ) -> Self {
+        let vertex_count = mesh.vertices.len();
+        let mut targets: Vec<MorphTarget> = targets
+            .into_iter()
+            .filter(|target| {
+                let complete = target.position_deltas.len() == vertex_count && target.normal_deltas.len() == vertex_count;
+                if !complete {
+                    log::warn!("Morph target {:?} doesn't match the {} vertices of {:?}, skipping it", target.name, vertex_count, mesh.name);
+                }
+                complete
+            })
+            .collect();
+        if targets.len() > MAX_MORPH_TARGETS {
+            log::warn!("{:?} has {} morph targets, only the first {} are used", mesh.name, targets.len(), MAX_MORPH_TARGETS);
+            targets.truncate(MAX_MORPH_TARGETS);
+        }
+
+        let mut deltas = targets
+            .iter()
+            .flat_map(|target| target.position_deltas.iter().zip(&target.normal_deltas))
+            .map(|(position, normal)| MorphDelta {
+                position: *position,
+                _padding: 0,
+                normal: *normal,
+                _padding2: 0,
+            })
+            .collect::<Vec<_>>();
+        // Bindings can't be empty, the shader never reads this one without any targets
+        if deltas.is_empty() {
+            deltas.push(bytemuck::Zeroable::zeroed());
+        }
+        let deltas_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Morph Target Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let weights = vec![0.0; targets.len()];
+        let weights_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Morph Weights Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&[Self::to_uniform(&weights, vertex_count)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morphed Instance Buffer"),
+            contents: bytemuck::cast_slice(&[transform.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("morphed_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: deltas_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: weights_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("morphed_bind_group"),
+        });
+
+        Self {
+            enabled: false,
+            mesh,
+            targets,
+            weights,
+            transform,
+            instance_buffer,
+            weights_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /*
+    *   A sphere with two targets: "cube" pushes every vertex out onto the cube around it and
+    *   "stretch" pulls it up into an egg. The deltas are just target - base, which is also how
+    *   you'd make them from two poses of any mesh with the same vertices.
+    */
+    pub fn demo_blob(device: &wgpu::Device, texture: &texture::Texture, transform: Transform) -> Self {
+        let base = model::Mesh::uv_sphere_data("Morph Blob", 32, 16);
+        let delta = |target: [f32; 3], base: [f32; 3]| [target[0] - base[0], target[1] - base[1], target[2] - base[2]];
+
+        let (cube_positions, cube_normals) = base
+            .vertices
+            .iter()
+            .map(|v| {
+                let p = v.position;
+                let largest = p[0].abs().max(p[1].abs()).max(p[2].abs()).max(1e-6);
+                let on_cube = p.map(|x| x / largest * 0.5);
+                // The face the vertex lands on points along its largest axis
+                let axis = (0..3).max_by(|a, b| p[*a].abs().total_cmp(&p[*b].abs())).unwrap();
+                let mut face_normal = [0.0; 3];
+                face_normal[axis] = p[axis].signum();
+                (delta(on_cube, p), delta(face_normal, v.normal))
+            })
+            .unzip();
+        let cube = MorphTarget {
+            name: "cube".to_string(),
+            position_deltas: cube_positions,
+            normal_deltas: cube_normals,
+        };
+
+        // Twice as tall & a bit narrower. Stretching tilts the normals towards the middle, but
+        // not by enough to bother with here.
+        let stretch = MorphTarget {
+            name: "stretch".to_string(),
+            position_deltas: base.vertices.iter().map(|v| [-0.25 * v.position[0], v.position[1], -0.25 * v.position[2]]).collect(),
+            normal_deltas: vec![[0.0; 3]; base.vertices.len()],
+        };
+
+        Self::new(device, texture, base.upload(device), vec![cube, stretch], transform)
+    }
+
+    // Same model on a new device
+    pub fn recreate(&self, device: &wgpu::Device, queue: &wgpu::Queue, texture: &texture::Texture) -> Self {
+        let mut model = Self::new(
+            device,
+            texture,
+            self.mesh.recreate(device),
+            self.targets.clone(),
+            self.transform,
+        );
+        model.enabled = self.enabled;
+        model.weights = self.weights.clone();
+        model.update(queue);
+        model
+    }
+
+    fn to_uniform(weights: &[f32], vertex_count: usize) -> MorphUniform {
+        let mut uniform = MorphUniform {
+            weights: [[0.0; 4]; MAX_MORPH_TARGETS / 4],
+            target_count: weights.len().min(MAX_MORPH_TARGETS) as u32,
+            vertex_count: vertex_count as u32,
+            _padding: [0; 2],
+        };
+        for (i, weight) in weights.iter().take(MAX_MORPH_TARGETS).enumerate() {
+            uniform.weights[i / 4][i % 4] = *weight;
+        }
+        uniform
+    }
+
+    pub fn target_names(&self) -> impl Iterator<Item = &str> {
+        self.targets.iter().map(|target| target.name.as_str())
+    }
+
+    // Sets a target's weight by name, does nothing if the mesh doesn't have that target
+    pub fn set_weight(&mut self, name: &str, weight: f32) {
+        if let Some(i) = self.targets.iter().position(|target| target.name == name) {
+            self.weights[i] = weight;
+        }
+    }
+
+    // Uploads the weights
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let uniform = Self::to_uniform(&self.weights, self.mesh.vertices.len());
+        queue.write_buffer(&self.weights_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Expects the morph pipeline, with the camera, screen & lights already bound
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.mesh.num_elements, 0, 0..1);
+    }
+}
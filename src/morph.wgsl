@@ -0,0 +1,55 @@
+// Needs shader.wgsl in front, see MorphedModel::shader_source. The fragment shader is fs_main.
+
+struct MorphDelta {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+};
+// Every target's deltas, one target after the other
+@group(2) @binding(2)
+var<storage, read> morph_deltas: array<MorphDelta>;
+
+struct MorphWeights {
+    // 4 weights to a vec4
+    weights: array<vec4<f32>, 2>,
+    target_count: u32,
+    vertex_count: u32,
+};
+@group(2) @binding(3)
+var<uniform> morph: MorphWeights;
+
+@vertex
+fn vs_morphed(
+    model: VertexInput,
+    instance: InstanceInput,
+    // Which vertex of the mesh this is, the index buffer's value rather than the draw order
+    @builtin(vertex_index) vertex_index: u32,
+) -> VertexOutput {
+    var position = model.position;
+    var normal = model.normal;
+    for (var i = 0u; i < morph.target_count; i += 1u) {
+        let weight = morph.weights[i / 4u][i % 4u];
+        let delta = morph_deltas[i * morph.vertex_count + vertex_index];
+        position += delta.position * weight;
+        normal += delta.normal * weight;
+    }
+
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    // Blended normals aren't unit length anymore, fs_main normalizes them anyway
+    out.world_normal = normal_matrix * normal;
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
@@ -0,0 +1,173 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::PipelineCache;
+use crate::{post, velocity};
+
+/*
+*   Motion blur. Every pixel averages the scene color along the line it moved along since the
+*   last frame, read from the velocity buffer. A real camera's shutter stays open for a while, so
+*   anything that moves while it's open gets smeared over the distance it covered, which is
+*   what this imitates. Things that don't move relative to the camera stay sharp.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionBlurUniform {
+    intensity: f32,
+    max_length: f32,
+    samples: u32,
+    _padding: u32,
+}
+
+pub struct MotionBlur {
+    pub enabled: bool,
+    uniform: MotionBlurUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl MotionBlur {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+        velocity: &velocity::VelocityBuffer,
+    ) -> Self {
+        let uniform = MotionBlurUniform {
+            intensity: 1.0,
+            max_length: 0.05,
+            samples: 12,
+            _padding: 0,
+        };
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Motion Blur Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("motion_blur_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer, velocity);
+
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Motion Blur",
+            include_str!("motion_blur.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &bind_group_layout],
+            color_format,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        velocity: &velocity::VelocityBuffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&velocity.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&velocity.texture.sampler),
+                },
+            ],
+            label: Some("motion_blur_bind_group"),
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device, keeping the current settings
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        color_format: wgpu::TextureFormat,
+        velocity: &velocity::VelocityBuffer,
+    ) -> Self {
+        let mut motion_blur = Self::new(device, pipelines, screen_layout, post_chain, color_format, velocity);
+        motion_blur.enabled = self.enabled;
+        motion_blur.set_intensity(queue, self.uniform.intensity);
+        motion_blur
+    }
+
+    // The velocity buffer gets a new texture when the window is resized
+    pub fn set_velocity(&mut self, device: &wgpu::Device, velocity: &velocity::VelocityBuffer) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.buffer, velocity);
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.uniform.intensity
+    }
+
+    // 0 turns the blur off, more than 1 exaggerates it
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.uniform.intensity = intensity.max(0.0);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+impl post::PostEffect for MotionBlur {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,55 @@
+// Per-object motion blur post effect, see motion_blur.rs
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+
+struct MotionBlurUniform {
+    // Scales the velocity, 1 blurs over the whole distance moved in the last frame
+    intensity: f32,
+    // Longest blur, as a fraction of the screen
+    max_length: f32,
+    samples: u32,
+};
+@group(2) @binding(0)
+var<uniform> motion_blur: MotionBlurUniform;
+@group(2) @binding(1)
+var t_velocity: texture_2d<f32>;
+@group(2) @binding(2)
+var s_velocity: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var velocity = textureSample(t_velocity, s_velocity, in.uv).xy * motion_blur.intensity;
+    // Really fast things would smear across the whole screen otherwise
+    let length = length(velocity);
+    if (length > motion_blur.max_length) {
+        velocity *= motion_blur.max_length / length;
+    }
+
+    // Average the color along the path the pixel took, centered on where it is now
+    let samples = max(motion_blur.samples, 2u);
+    var color = vec4<f32>(0.0);
+    for (var i = 0u; i < samples; i += 1u) {
+        let t = f32(i) / f32(samples - 1u) - 0.5;
+        color += textureSample(t_color, s_color, in.uv - velocity * t);
+    }
+    return color / f32(samples);
+}
@@ -0,0 +1,246 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::texture;
+
+/*
+*   Fractal noise on the gpu. noise.wgsl has the noise functions themselves (value noise,
+*   perlin noise and fbm), written so any shader can use them by pasting the file in front of
+*   its own source. The NoiseGenerator is one such shader: a compute pass that fills either a
+*   storage texture (to sample, e.g. clouds) or a storage buffer (to read back on the cpu,
+*   e.g. a terrain heightmap) with noise.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct NoiseParams {
+    // How many lattice cells of the first octave fit across the output
+    pub frequency: f32,
+    // Scales the result, which comes out roughly in -amplitude..amplitude
+    pub amplitude: f32,
+    pub octaves: u32,
+    // Frequency multiplier from one octave to the next
+    pub lacunarity: f32,
+    // Amplitude multiplier from one octave to the next
+    pub gain: f32,
+    pub seed: u32,
+    // Moves the noise around, in lattice cells
+    pub offset: [f32; 2],
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            frequency: 4.0,
+            amplitude: 1.0,
+            octaves: 5,
+            lacunarity: 2.0,
+            gain: 0.5,
+            seed: 0,
+            offset: [0.0, 0.0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct NoiseUniform {
+    frequency: f32,
+    amplitude: f32,
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32,
+    seed: u32,
+    offset: [f32; 2],
+    size: [u32; 2],
+    _padding: [u32; 2],
+}
+
+impl NoiseUniform {
+    fn new(params: &NoiseParams, width: u32, height: u32) -> Self {
+        Self {
+            frequency: params.frequency,
+            amplitude: params.amplitude,
+            octaves: params.octaves,
+            lacunarity: params.lacunarity,
+            gain: params.gain,
+            seed: params.seed,
+            offset: params.offset,
+            size: [width, height],
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub struct NoiseGenerator {
+    texture_pipeline: wgpu::ComputePipeline,
+    buffer_pipeline: wgpu::ComputePipeline,
+    params_buffer: Tracked<wgpu::Buffer>,
+    params_bind_group: wgpu::BindGroup,
+    texture_layout: wgpu::BindGroupLayout,
+    buffer_layout: wgpu::BindGroupLayout,
+}
+
+impl NoiseGenerator {
+    const WORKGROUP_SIZE: u32 = 8;
+
+    // The buffer output only needs compute shaders, the texture output needs storage textures too
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        texture::Texture::storage_supported(adapter)
+    }
+
+    // noise.wgsl followed by the shader that uses it
+    pub fn shader_source(source: &str) -> String {
+        format!("{}\n{}", include_str!("noise.wgsl"), source)
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("noise_params_bind_group_layout"),
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: texture::Texture::STORAGE_FORMAT,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+            label: Some("noise_texture_bind_group_layout"),
+        });
+        let buffer_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("noise_buffer_bind_group_layout"),
+        });
+
+        let params_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Noise Params Buffer"),
+            contents: bytemuck::cast_slice(&[NoiseUniform::new(&NoiseParams::default(), 1, 1)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+            label: Some("noise_params_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Noise Shader"),
+            source: wgpu::ShaderSource::Wgsl(Self::shader_source(include_str!("noise_gen.wgsl")).into()),
+        });
+        let create_pipeline = |name: &str, output_layout: &wgpu::BindGroupLayout, entry_point: &str| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&format!("{} Pipeline Layout", name)),
+                bind_group_layouts: &[&params_layout, output_layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(&format!("{} Pipeline", name)),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let texture_pipeline = create_pipeline("Noise Texture", &texture_layout, "cs_texture");
+        let buffer_pipeline = create_pipeline("Noise Buffer", &buffer_layout, "cs_buffer");
+
+        Self {
+            texture_pipeline,
+            buffer_pipeline,
+            params_buffer,
+            params_bind_group,
+            texture_layout,
+            buffer_layout,
+        }
+    }
+
+    /*
+    *   Queues filling `texture` (made with Texture::create_storage_texture) with noise, mapped
+    *   from -1..1 to 0..1 in every color channel. The params go through a single uniform buffer
+    *   that's written when the encoder is submitted, so only fill one thing per submit.
+    */
+    pub fn fill_texture(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        texture: &texture::Texture,
+        params: &NoiseParams,
+    ) {
+        let size = texture.texture.size();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            }],
+            label: Some("noise_texture_bind_group"),
+        });
+        self.dispatch(encoder, queue, &self.texture_pipeline, &bind_group, params, size.width, size.height);
+    }
+
+    // Same as fill_texture but writes width * height f32s into a STORAGE buffer, row by row
+    #[allow(clippy::too_many_arguments)]
+    pub fn fill_buffer(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+        params: &NoiseParams,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.buffer_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("noise_buffer_bind_group"),
+        });
+        self.dispatch(encoder, queue, &self.buffer_pipeline, &bind_group, params, width, height);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        pipeline: &wgpu::ComputePipeline,
+        output: &wgpu::BindGroup,
+        params: &NoiseParams,
+        width: u32,
+        height: u32,
+    ) {
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[NoiseUniform::new(params, width, height)]));
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Noise Pass"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, &self.params_bind_group, &[]);
+        compute_pass.set_bind_group(1, output, &[]);
+        compute_pass.dispatch_workgroups(width.div_ceil(Self::WORKGROUP_SIZE), height.div_ceil(Self::WORKGROUP_SIZE), 1);
+    }
+}
@@ -0,0 +1,64 @@
+// Noise functions. WGSL has no includes, so shaders that need these get this file pasted in
+// front of them, see noise.rs
+
+// Integer hash (PCG), scrambles the bits well enough that neighbouring inputs look unrelated
+fn hash_u32(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random value in 0..1 for a lattice point
+fn random2(p: vec2<i32>, seed: u32) -> f32 {
+    let h = hash_u32(bitcast<u32>(p.x) ^ hash_u32(bitcast<u32>(p.y) ^ hash_u32(seed)));
+    return f32(h) / 4294967295.0;
+}
+
+// Smoother than smoothstep, so the noise has no visible creases along the lattice lines
+fn fade(t: vec2<f32>) -> vec2<f32> {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// Random values at the lattice points, blended in between. Returns 0..1.
+fn value_noise(p: vec2<f32>, seed: u32) -> f32 {
+    let i = vec2<i32>(floor(p));
+    let u = fade(fract(p));
+    let a = random2(i, seed);
+    let b = random2(i + vec2<i32>(1, 0), seed);
+    let c = random2(i + vec2<i32>(0, 1), seed);
+    let d = random2(i + vec2<i32>(1, 1), seed);
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+fn lattice_gradient(p: vec2<i32>, seed: u32) -> vec2<f32> {
+    let angle = random2(p, seed) * 6.2831853;
+    return vec2<f32>(cos(angle), sin(angle));
+}
+
+// Perlin (gradient) noise: random slopes at the lattice points rather than random values,
+// which gets rid of value noise's blocky look. Returns roughly -0.7..0.7.
+fn perlin_noise(p: vec2<f32>, seed: u32) -> f32 {
+    let i = vec2<i32>(floor(p));
+    let f = fract(p);
+    let u = fade(f);
+    let a = dot(lattice_gradient(i, seed), f);
+    let b = dot(lattice_gradient(i + vec2<i32>(1, 0), seed), f - vec2<f32>(1.0, 0.0));
+    let c = dot(lattice_gradient(i + vec2<i32>(0, 1), seed), f - vec2<f32>(0.0, 1.0));
+    let d = dot(lattice_gradient(i + vec2<i32>(1, 1), seed), f - vec2<f32>(1.0, 1.0));
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+// Fractal noise: several octaves of perlin noise, each one `lacunarity` times the frequency
+// and `gain` times the amplitude of the one before, so big shapes get smaller details on top
+fn fbm(p: vec2<f32>, octaves: u32, lacunarity: f32, gain: f32, seed: u32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var i = 0u; i < octaves; i += 1u) {
+        // A different seed per octave keeps them from lining up at the origin
+        sum += amplitude * perlin_noise(p * frequency, seed + i);
+        frequency *= lacunarity;
+        amplitude *= gain;
+    }
+    return sum;
+}
@@ -0,0 +1,48 @@
+// Fills a texture or a buffer with fractal noise, see noise.rs. Needs noise.wgsl in front of it.
+
+struct NoiseParams {
+    frequency: f32,
+    amplitude: f32,
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32,
+    seed: u32,
+    // Scrolls the noise, e.g. to animate it
+    offset: vec2<f32>,
+    // Size of the output in texels/values
+    size: vec2<u32>,
+};
+@group(0) @binding(0)
+var<uniform> params: NoiseParams;
+
+// Each entry point only uses one of these, and its pipeline only binds that one
+@group(1) @binding(0)
+var output_texture: texture_storage_2d<rgba8unorm, write>;
+@group(1) @binding(1)
+var<storage, read_write> output_buffer: array<f32>;
+
+fn noise_at(id: vec2<u32>) -> f32 {
+    // `frequency` lattice cells across the whole output
+    let uv = (vec2<f32>(id) + 0.5) / vec2<f32>(params.size);
+    let p = uv * params.frequency + params.offset;
+    return params.amplitude * fbm(p, params.octaves, params.lacunarity, params.gain, params.seed);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_texture(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.size.x || id.y >= params.size.y) {
+        return;
+    }
+    // A unorm texture can't hold negative values, map -1..1 to 0..1
+    let value = clamp(0.5 + 0.5 * noise_at(id.xy), 0.0, 1.0);
+    textureStore(output_texture, vec2<i32>(id.xy), vec4<f32>(value, value, value, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_buffer(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.size.x || id.y >= params.size.y) {
+        return;
+    }
+    // Row by row, like an image
+    output_buffer[id.y * params.size.x + id.x] = noise_at(id.xy);
+}
@@ -0,0 +1,63 @@
+// Draws each vertex normal as a short line ("hedgehog" debug view)
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct NormalLinesUniform {
+    color: vec4<f32>,
+    length: f32,
+};
+@group(2) @binding(0)
+var<uniform> lines: NormalLinesUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    // 0.0 for the start of the line, 1.0 for the tip
+    @location(2) extent: f32,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    // Offset in world space so the lines all come out the same length whatever the scale
+    let world_position = (model_matrix * vec4<f32>(in.position, 1.0)).xyz;
+    let world_normal = normalize(normal_matrix * in.normal);
+    return camera.view_proj * vec4<f32>(world_position + world_normal * lines.length * in.extent, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return lines.color;
+}
@@ -0,0 +1,503 @@
+use std::rc::Rc;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::{self, Transform};
+use crate::model::{self, Vertex};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::post;
+use crate::texture;
+
+/*
+*   Outlines around the selected object, three ways. There's no picking yet, so State selects
+*   the animated instance. Whatever's selected gets handed to update every frame.
+*
+*   Stencil: the depth texture has 8 bits of stencil next to the depth (see
+*   Texture::DEPTH_FORMAT), one small counter per pixel that pipelines can test against and
+*   write to while drawing. It takes two draws:
+*
+*   1. Draw the object writing STENCIL_REF into the stencil wherever it covers the screen.
+*      It's already been drawn in color, so this one leaves the color alone.
+*   2. Draw it again a bit bigger in a flat color, but only where the stencil ISN'T
+*      STENCIL_REF. Everything over the object itself gets thrown away, which leaves the rim
+*      that sticks out past its edges.
+*
+*   Both of those are just a different wgpu::StencilFaceState, which decides what happens to a
+*   fragment of a front or back facing triangle:
+*
+*       compare        how the reference value (set_stencil_reference) is compared with the
+*                      value in the stencil buffer. The fragment is thrown away if it fails.
+*       fail_op        what to do to the stored value when the stencil test fails
+*       depth_fail_op  ... when the stencil test passes but the depth test fails
+*       pass_op        ... when both pass. Replace writes the reference value.
+*
+*   plus read_mask & write_mask on the StencilState, which pick which of the 8 bits get
+*   compared and written. Keep leaves the stored value alone, which is what every other
+*   pipeline in the main pass does with StencilState::default(). The main pass clears the
+*   stencil to 0 every frame. The bigger version has every vertex pushed away from the mesh's
+*   origin, which works for roughly round meshes centered on their origin like the demo's.
+*
+*   Inverted hull: draw the mesh once more with every vertex pushed out along its normal, in
+*   a flat color and culling the front faces instead of the back ones. What's left is the
+*   inside of a slightly bigger shell, which the object covers up except around its edges.
+*   It's one draw with no stencil, but hard edged meshes crack open at the corners where a
+*   vertex has a different normal for each face.
+*
+*   Edge detection: a post effect. The selected object gets drawn into a mask of its own, and
+*   a Sobel filter (the difference between the pixels on either side, weighted towards the
+*   middle row) finds where the mask goes from 0 to 1, i.e. the silhouette. The same filter
+*   over the depth buffer finds creases inside the object where one part of it is in front of
+*   another. The width is in pixels, so it's the same on screen however far away the object is.
+*   The mask isn't depth tested, so the outline shows through whatever is in front.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutlineMethod {
+    Stencil,
+    InvertedHull,
+    EdgeDetection,
+}
+
+impl OutlineMethod {
+    pub fn next(self) -> Self {
+        match self {
+            OutlineMethod::Stencil => OutlineMethod::InvertedHull,
+            OutlineMethod::InvertedHull => OutlineMethod::EdgeDetection,
+            OutlineMethod::EdgeDetection => OutlineMethod::Stencil,
+        }
+    }
+}
+
+// Has to match OutlineUniform in outline.wgsl & outline_edges.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    // How far past the mesh the stencil & hull outlines reach, in the mesh's own units
+    width: f32,
+    // How wide the edge detection's lines are, in pixels
+    thickness: f32,
+    // To turn the depth buffer back into distances for the crease detection
+    znear: f32,
+    zfar: f32,
+    // 1 when the depth buffer is reversed, see camera::DepthDirection
+    reverse_z: u32,
+    _padding: [u32; 3],
+}
+
+pub struct Outline {
+    pub enabled: bool,
+    pub method: OutlineMethod,
+    uniform: OutlineUniform,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // Where the outlined object is, see update
+    instance_buffer: Tracked<wgpu::Buffer>,
+    mask_pipeline: Rc<wgpu::RenderPipeline>,
+    outline_pipeline: Rc<wgpu::RenderPipeline>,
+    hull_pipeline: Rc<wgpu::RenderPipeline>,
+    // Edge detection's half
+    selection_mask: texture::Texture,
+    selection_pipeline: Rc<wgpu::RenderPipeline>,
+    edge_layout: wgpu::BindGroupLayout,
+    edge_bind_group: wgpu::BindGroup,
+    edge_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Outline {
+    // Any value other than the cleared 0 would do
+    pub const STENCIL_REF: u32 = 1;
+    pub const DEFAULT_COLOR: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+    const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    // `sample_count` is the main pass', the stencil & hull outlines are drawn in it
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        camera: &camera::Camera,
+    ) -> Self {
+        let uniform = OutlineUniform {
+            color: Self::DEFAULT_COLOR,
+            width: 0.06,
+            thickness: 2.0,
+            znear: camera.znear,
+            zfar: camera.zfar,
+            reverse_z: (camera.depth == camera::DepthDirection::Reversed) as u32,
+            _padding: [0; 3],
+        };
+        let uniform_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Instance Buffer"),
+            contents: bytemuck::cast_slice(&[Transform::default().to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry],
+            label: Some("outline_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("outline_bind_group"),
+        });
+
+        let layouts = [screen_layout, camera_layout, &bind_group_layout];
+
+        // Pass 1: mark the object's pixels, whatever's in the stencil buffer already
+        let mark = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
+        // Marks the hidden parts of the object too. Testing against the depth the scene
+        // pipeline left behind would z-fight, the two vertex shaders don't do their math in
+        // quite the same order. The outline pass is depth tested anyway.
+        let mask_pipeline = Self::create_pipeline(
+            device,
+            pipelines,
+            &layouts,
+            ("vs_mask", "fs_main"),
+            sample_count,
+            post_chain.format(),
+            // Stencil only, the object itself was drawn by the scene pipeline
+            wgpu::ColorWrites::empty(),
+            wgpu::Face::Back,
+            Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState {
+                    front: mark,
+                    back: mark,
+                    read_mask: 0xff,
+                    write_mask: 0xff,
+                },
+                bias: wgpu::DepthBiasState::default(),
+            }),
+        );
+
+        // Pass 2: only draw where pass 1 didn't, and leave the stencil as it is
+        let outside = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::NotEqual,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        };
+        let outline_pipeline = Self::create_pipeline(
+            device,
+            pipelines,
+            &layouts,
+            ("vs_outline", "fs_main"),
+            sample_count,
+            post_chain.format(),
+            wgpu::ColorWrites::ALL,
+            wgpu::Face::Back,
+            Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: camera.depth.compare(wgpu::CompareFunction::LessEqual),
+                stencil: wgpu::StencilState {
+                    front: outside,
+                    back: outside,
+                    read_mask: 0xff,
+                    write_mask: 0x00,
+                },
+                bias: wgpu::DepthBiasState::default(),
+            }),
+        );
+
+        // Just the inside of the shell, written to the depth buffer like any other geometry
+        let hull_pipeline = Self::create_pipeline(
+            device,
+            pipelines,
+            &layouts,
+            ("vs_hull", "fs_main"),
+            sample_count,
+            post_chain.format(),
+            wgpu::ColorWrites::ALL,
+            wgpu::Face::Front,
+            Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: camera.depth.compare(wgpu::CompareFunction::Less),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+        );
+
+        // The mask gets its own pass, no depth & no multisampling
+        let selection_pipeline = Self::create_pipeline(
+            device,
+            pipelines,
+            &layouts,
+            ("vs_mask", "fs_selection"),
+            1,
+            Self::MASK_FORMAT,
+            wgpu::ColorWrites::ALL,
+            wgpu::Face::Back,
+            None,
+        );
+        let selection_mask = texture::Texture::create_render_target(device, config, Self::MASK_FORMAT, "outline_selection_mask");
+
+        let edge_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry,
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("outline_edges_bind_group_layout"),
+        });
+        let edge_bind_group = Self::create_edge_bind_group(device, &edge_layout, &uniform_buffer, &selection_mask);
+        let edge_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Outline Edges",
+            include_str!("outline_edges.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &edge_layout],
+            post_chain.format(),
+        );
+
+        Self {
+            enabled: false,
+            method: OutlineMethod::Stencil,
+            uniform,
+            uniform_buffer,
+            bind_group,
+            instance_buffer,
+            mask_pipeline,
+            outline_pipeline,
+            hull_pipeline,
+            selection_mask,
+            selection_pipeline,
+            edge_layout,
+            edge_bind_group,
+            edge_pipeline,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_pipeline(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        (vs_entry_point, fs_entry_point): (&str, &str),
+        sample_count: u32,
+        format: wgpu::TextureFormat,
+        write_mask: wgpu::ColorWrites,
+        cull_mode: wgpu::Face,
+        depth_stencil: Option<wgpu::DepthStencilState>,
+    ) -> Rc<wgpu::RenderPipeline> {
+        pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: &format!("Outline ({})", vs_entry_point),
+                shader: include_str!("outline.wgsl"),
+                bind_group_layouts,
+                vertex_entry_point: vs_entry_point,
+                vertex_buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                fragment_entry_point: fs_entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask,
+                })],
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(cull_mode),
+                    ..Default::default()
+                },
+                depth_stencil,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        )
+    }
+
+    fn create_edge_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        selection_mask: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&selection_mask.view),
+                },
+            ],
+            label: Some("outline_edges_bind_group"),
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device, keeping the current settings
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        camera: &camera::Camera,
+    ) -> Self {
+        let mut outline = Self::new(
+            device,
+            pipelines,
+            config,
+            sample_count,
+            screen_layout,
+            camera_layout,
+            post_chain,
+            camera,
+        );
+        outline.enabled = self.enabled;
+        outline.method = self.method;
+        // The camera's depth direction might not be the same as before
+        outline.uniform = OutlineUniform { reverse_z: outline.uniform.reverse_z, ..self.uniform };
+        outline.write_uniform(queue);
+        outline
+    }
+
+    // The selection mask has to match the screen
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.selection_mask = texture::Texture::create_render_target(device, config, Self::MASK_FORMAT, "outline_selection_mask");
+        self.edge_bind_group = Self::create_edge_bind_group(device, &self.edge_layout, &self.uniform_buffer, &self.selection_mask);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: [f32; 4]) {
+        self.uniform.color = color;
+        self.write_uniform(queue);
+    }
+
+    pub fn thickness(&self) -> f32 {
+        self.uniform.thickness
+    }
+
+    // Scales both kinds of width together, `thickness` is the edge detection's in pixels
+    pub fn set_thickness(&mut self, queue: &wgpu::Queue, thickness: f32) {
+        let thickness = thickness.max(0.0);
+        self.uniform.width = 0.03 * thickness;
+        self.uniform.thickness = thickness;
+        self.write_uniform(queue);
+    }
+
+    // Moves the outline to wherever the selected object is now
+    pub fn update(&self, queue: &wgpu::Queue, transform: &Transform) {
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&[transform.to_raw()]));
+    }
+
+    fn bind_mesh<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a model::Mesh) {
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    /*
+    *   The stencil & hull outlines. Expects the screen & camera bind groups to already be set
+    *   at @group(0) & @group(1), and `mesh` to have been drawn at the transform given to update.
+    *   Call it after everything the outline should be able to go in front of.
+    */
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a model::Mesh) {
+        if !self.enabled {
+            return;
+        }
+        let pipelines = match self.method {
+            OutlineMethod::Stencil => {
+                render_pass.set_stencil_reference(Self::STENCIL_REF);
+                vec![&self.mask_pipeline, &self.outline_pipeline]
+            }
+            OutlineMethod::InvertedHull => vec![&self.hull_pipeline],
+            OutlineMethod::EdgeDetection => return,
+        };
+        self.bind_mesh(render_pass, mesh);
+        for pipeline in pipelines {
+            render_pass.set_pipeline(pipeline);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+        }
+    }
+
+    // Edge detection's mask, needs to happen before the post chain runs
+    pub fn render_mask(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        screen_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        mesh: &model::Mesh,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Selection Mask Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.selection_mask.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.selection_pipeline);
+        render_pass.set_bind_group(0, screen_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        self.bind_mesh(&mut render_pass, mesh);
+        render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+    }
+}
+
+impl post::PostEffect for Outline {
+    fn enabled(&self) -> bool {
+        self.enabled && self.method == OutlineMethod::EdgeDetection
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.edge_pipeline);
+        render_pass.set_bind_group(2, &self.edge_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,80 @@
+// Stencil & inverted hull outlines around the selected object, see outline.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    width: f32,
+    thickness: f32,
+    znear: f32,
+    zfar: f32,
+    reverse_z: u32,
+};
+@group(2) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+fn to_clip(position: vec3<f32>, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+}
+
+// The mesh as it is, for marking its pixels in the stencil buffer or the selection mask
+@vertex
+fn vs_mask(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return to_clip(model.position, instance);
+}
+
+// Every vertex pushed straight out from the mesh's origin, so the copy sticks out past the
+// original all the way around, even where the normals split at hard edges
+@vertex
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let size = max(length(model.position), 0.001);
+    return to_clip(model.position * (1.0 + outline.width / size), instance);
+}
+
+// The mesh pushed out along its normals, a shell around the original
+@vertex
+fn vs_hull(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return to_clip(model.position + normalize(model.normal) * outline.width, instance);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
+
+// 1 wherever the selected object is
+@fragment
+fn fs_selection() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
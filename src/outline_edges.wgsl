@@ -0,0 +1,94 @@
+// Sobel edge detection outline post effect, see outline.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+@group(1) @binding(2)
+var t_depth: texture_depth_2d;
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    width: f32,
+    thickness: f32,
+    znear: f32,
+    zfar: f32,
+    reverse_z: u32,
+};
+@group(2) @binding(0)
+var<uniform> outline: OutlineUniform;
+@group(2) @binding(1)
+var t_mask: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+fn linearize_depth(depth: f32) -> f32 {
+    // Reversed depth is znear / distance, with nothing past it (0) counting as zfar
+    if outline.reverse_z != 0u {
+        return min(outline.znear / max(depth, 1e-30), outline.zfar);
+    }
+    return outline.znear * outline.zfar / (outline.zfar - depth * (outline.zfar - outline.znear));
+}
+
+// Mask & distance at a pixel, clamped to the screen
+fn sample_at(pixel: vec2<i32>) -> vec2<f32> {
+    let clamped = clamp(pixel, vec2<i32>(0), vec2<i32>(screen.resolution) - vec2<i32>(1));
+    let mask = textureLoad(t_mask, clamped, 0).r;
+    let distance = linearize_depth(textureLoad(t_depth, clamped, 0));
+    return vec2<f32>(mask, distance);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_color, s_color, in.uv);
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let step = max(i32(round(outline.thickness)), 1);
+
+    // The 3x3 Sobel kernels, one for each direction
+    //     -1 0 1        -1 -2 -1
+    //     -2 0 2         0  0  0
+    //     -1 0 1         1  2  1
+    var gx = vec2<f32>(0.0);
+    var gy = vec2<f32>(0.0);
+    var selected = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let s = sample_at(pixel + vec2<i32>(x, y) * step);
+            let weight_x = f32(x) * select(1.0, 2.0, y == 0);
+            let weight_y = f32(y) * select(1.0, 2.0, x == 0);
+            gx += s * weight_x;
+            gy += s * weight_y;
+            selected = max(selected, s.x);
+        }
+    }
+    let gradient = sqrt(gx * gx + gy * gy);
+
+    // Where the mask changes is the silhouette. A jump in distance of more than a few percent
+    // is a crease, but only on the selected object itself.
+    let center = sample_at(pixel);
+    let silhouette = clamp(gradient.x, 0.0, 1.0);
+    let crease = smoothstep(0.05, 0.1, gradient.y / center.y) * selected;
+    let edge = max(silhouette, crease);
+    return vec4<f32>(mix(color.rgb, outline.color.rgb, edge * outline.color.a), color.a);
+}
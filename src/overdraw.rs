@@ -0,0 +1,202 @@
+use std::rc::Rc;
+
+use crate::batch::MeshTopology;
+use crate::instance;
+use crate::lod;
+use crate::model::{self, Vertex};
+use crate::physics;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::post;
+use crate::terrain;
+use crate::texture;
+
+/*
+*   A heatmap of how many times every pixel gets shaded, for finding out where the fragment
+*   shader's time goes.
+*
+*   The scene's opaque geometry gets drawn a second time into a single channel float target
+*   with depth testing off, every fragment adding 1 through an additive blend. With no depth
+*   test nothing gets thrown away, so what's left in a pixel is every triangle that covers it,
+*   hidden ones included. That's the worst case: the real pass only shades the hidden ones
+*   when they're drawn before whatever's in front of them. Back faces are culled like they are
+*   in the scene.
+*
+*   Then a post effect replaces the picture with the counts on a ramp, blue for once through
+*   green & yellow to red at MAX_LAYERS (overdraw_heatmap.wgsl) and black where nothing was
+*   drawn. The skinned & morphed demos have vertex layouts of their own and aren't counted.
+*/
+pub struct Overdraw {
+    pub enabled: bool,
+    counts: texture::Texture,
+    count_pipeline: Rc<wgpu::RenderPipeline>,
+    // For the terrain when its chunks are strips
+    strip_count_pipeline: Rc<wgpu::RenderPipeline>,
+    heatmap_layout: wgpu::BindGroupLayout,
+    heatmap_bind_group: wgpu::BindGroup,
+    heatmap_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Overdraw {
+    // Half floats count exactly up to 2048 and can be blended everywhere, unlike R32Float
+    const COUNT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+    ) -> Self {
+        let count_pipeline = |topology: MeshTopology| {
+            pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label: "Overdraw Count",
+                    shader: include_str!("overdraw.wgsl"),
+                    bind_group_layouts: &[screen_layout, camera_layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                    fragment_entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Self::COUNT_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrites::RED,
+                    })],
+                    primitive: wgpu::PrimitiveState {
+                        topology: topology.primitive(),
+                        strip_index_format: topology.strip_index_format(),
+                        cull_mode: Some(wgpu::Face::Back),
+                        ..Default::default()
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            )
+        };
+        let counts = texture::Texture::create_render_target(device, config, Self::COUNT_FORMAT, "overdraw_counts");
+
+        let heatmap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            }],
+            label: Some("overdraw_heatmap_bind_group_layout"),
+        });
+        let heatmap_bind_group = Self::create_heatmap_bind_group(device, &heatmap_layout, &counts);
+        let heatmap_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Overdraw Heatmap",
+            include_str!("overdraw_heatmap.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &heatmap_layout],
+            post_chain.format(),
+        );
+
+        Self {
+            enabled: false,
+            counts,
+            count_pipeline: count_pipeline(MeshTopology::TriangleList),
+            strip_count_pipeline: count_pipeline(MeshTopology::TriangleStrip),
+            heatmap_layout,
+            heatmap_bind_group,
+            heatmap_pipeline,
+        }
+    }
+
+    fn create_heatmap_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        counts: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&counts.view),
+            }],
+            label: Some("overdraw_heatmap_bind_group"),
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new post chain format
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+    ) -> Self {
+        let mut overdraw = Self::new(device, pipelines, config, screen_layout, camera_layout, post_chain);
+        overdraw.enabled = self.enabled;
+        overdraw
+    }
+
+    // The counts have to match the screen
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.counts = texture::Texture::create_render_target(device, config, Self::COUNT_FORMAT, "overdraw_counts");
+        self.heatmap_bind_group = Self::create_heatmap_bind_group(device, &self.heatmap_layout, &self.counts);
+    }
+
+    // Counts the layers of whatever the main pass drew, needs to happen before the post chain runs
+    pub fn render_counts(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        screen_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        model: &lod::LodModel,
+        physics: &physics::PhysicsDemo,
+        terrain: Option<&terrain::Terrain>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overdraw Count Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.counts.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.count_pipeline);
+        render_pass.set_bind_group(0, screen_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        model.draw(&mut render_pass);
+        physics.draw(&mut render_pass);
+        if let Some(terrain) = terrain {
+            if terrain.topology() == MeshTopology::TriangleStrip {
+                render_pass.set_pipeline(&self.strip_count_pipeline);
+            }
+            terrain.draw(&mut render_pass);
+        }
+    }
+}
+
+impl post::PostEffect for Overdraw {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.heatmap_pipeline);
+        render_pass.set_bind_group(2, &self.heatmap_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,41 @@
+// Overdraw heatmap post effect, see overdraw.rs
+
+// @group(0) is the screen uniform & @group(1) the scene, the heatmap replaces it outright
+
+@group(2) @binding(0)
+var t_counts: texture_2d<f32>;
+
+// Where the ramp tops out, anything drawn this many times or more is red
+const MAX_LAYERS: f32 = 8.0;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    return out;
+}
+
+// Blue -> cyan -> green -> yellow -> red
+fn ramp(t: f32) -> vec3<f32> {
+    let r = clamp(t * 4.0 - 2.0, 0.0, 1.0);
+    let g = clamp(min(t * 4.0, 4.0 - t * 4.0), 0.0, 1.0);
+    let b = clamp(2.0 - t * 4.0, 0.0, 1.0);
+    return vec3<f32>(r, g, b);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let layers = textureLoad(t_counts, vec2<i32>(in.clip_position.xy), 0).r;
+    // Nothing drawn at all stays black, one layer is the start of the ramp
+    if layers < 0.5 {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    return vec4<f32>(ramp((layers - 1.0) / (MAX_LAYERS - 1.0)), 1.0);
+}
@@ -0,0 +1,324 @@
+use std::rc::Rc;
+
+use cgmath::InnerSpace;
+
+use crate::blend::BlendMode;
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   A fountain of sparks, to show off the blend modes (see blend.rs). The particles are simulated
+*   on the cpu with the other fixed steps and drawn as camera facing quads, one instance each,
+*   after the opaque scene. They're depth tested against it so the spheres hide them, but only
+*   write depth themselves in BlendMode::Opaque.
+*
+*   There's a pipeline for every blend mode, all built up front through the PipelineCache, so
+*   switching between them is just picking another one. Additive is the default, the sparks
+*   pile up into a bright core where they're thick. The modes that care about the order get
+*   the particles sorted back to front.
+*/
+struct Particle {
+    position: cgmath::Vector3<f32>,
+    velocity: cgmath::Vector3<f32>,
+    // Seconds since it was spawned, it's gone at LIFETIME
+    age: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleRaw {
+    // xyz & the half size
+    position: [f32; 4],
+    // Straight alpha
+    color: [f32; 4],
+}
+
+impl ParticleRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Has to match BillboardUniform in particles.wgsl. Which way the quads' sides point, from the
+// camera.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BillboardUniform {
+    right: [f32; 4],
+    up: [f32; 4],
+}
+
+pub struct Particles {
+    pub enabled: bool,
+    pub blend_mode: BlendMode,
+    particles: Vec<Particle>,
+    // How many have been spawned, ever. Seeds each new one's direction.
+    spawned: u32,
+    // Time owed to the spawner, a particle is spawned for every SPAWN_INTERVAL of it
+    spawn_time: f32,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    // Only updated while enabled, how many particles the buffer holds
+    instance_count: u32,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // One per blend mode, in BlendMode::ALL's order
+    pipelines: [Rc<wgpu::RenderPipeline>; 5],
+}
+
+impl Particles {
+    const MAX_PARTICLES: usize = 256;
+    const LIFETIME: f32 = 2.5;
+    const SPAWN_INTERVAL: f32 = Self::LIFETIME / Self::MAX_PARTICLES as f32;
+    const GRAVITY: f32 = -4.0;
+    const SIZE: f32 = 0.08;
+    const ORIGIN: cgmath::Vector3<f32> = cgmath::Vector3::new(-2.5, 0.5, 2.0);
+    // Hot at the start, cooling off & fading out
+    const START_COLOR: [f32; 4] = [1.0, 0.8, 0.3, 1.0];
+    const END_COLOR: [f32; 4] = [0.8, 0.1, 0.0, 0.0];
+
+    // `color_format` is the main pass', the particles go on top of the finished scene
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let instance_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Instance Buffer"),
+            size: (Self::MAX_PARTICLES * std::mem::size_of::<ParticleRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Billboard Buffer"),
+            contents: bytemuck::cast_slice(&[BillboardUniform {
+                right: [1.0, 0.0, 0.0, 0.0],
+                up: [0.0, 1.0, 0.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("particle_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("particle_bind_group"),
+        });
+
+        let pipelines = BlendMode::ALL.map(|mode| {
+            pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label: "Particles",
+                    shader: include_str!("particles.wgsl"),
+                    bind_group_layouts: &[screen_layout, camera_layout, &layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[ParticleRaw::desc()],
+                    fragment_entry_point: if mode.premultiplied() { "fs_premultiplied" } else { "fs_main" },
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: mode.blend_state(),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: texture::Texture::DEPTH_FORMAT,
+                        depth_write_enabled: mode.writes_depth(),
+                        depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            )
+        });
+
+        Self {
+            enabled: false,
+            blend_mode: BlendMode::Additive,
+            particles: Vec::with_capacity(Self::MAX_PARTICLES),
+            spawned: 0,
+            spawn_time: 0.0,
+            instance_buffer,
+            instance_count: 0,
+            uniform_buffer,
+            bind_group,
+            pipelines,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new color format. The particles in
+    // the air start over.
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let mut particles = Self::new(device, pipelines, color_format, screen_layout, camera_layout, depth);
+        particles.enabled = self.enabled;
+        particles.blend_mode = self.blend_mode;
+        particles
+    }
+
+    pub fn count(&self) -> usize {
+        self.particles.len()
+    }
+
+    // Moves the fountain on by `dt` seconds of simulation time
+    pub fn step(&mut self, dt: f32) {
+        if !self.enabled {
+            return;
+        }
+        for particle in &mut self.particles {
+            particle.age += dt;
+            particle.velocity.y += Self::GRAVITY * dt;
+            particle.position += particle.velocity * dt;
+        }
+        self.particles.retain(|particle| particle.age < Self::LIFETIME);
+
+        self.spawn_time += dt;
+        while self.spawn_time >= Self::SPAWN_INTERVAL {
+            self.spawn_time -= Self::SPAWN_INTERVAL;
+            if self.particles.len() < Self::MAX_PARTICLES {
+                let particle = self.spawn();
+                self.particles.push(particle);
+            }
+        }
+    }
+
+    // Up & out in a cone. No rng here, the golden ratio spreads the directions around evenly
+    // and a hash of the count varies the speed.
+    fn spawn(&mut self) -> Particle {
+        self.spawned = self.spawned.wrapping_add(1);
+        let golden = 0.618_034;
+        let angle = (self.spawned as f32 * golden).fract() * std::f32::consts::TAU;
+        let hash = self.spawned.wrapping_mul(0x9e37_79b9) >> 8;
+        let random = hash as f32 / (1u32 << 24) as f32;
+        let spread = 0.6 + 0.6 * random;
+        let speed = 3.5 + 1.5 * (1.0 - random);
+        Particle {
+            position: Self::ORIGIN,
+            velocity: cgmath::Vector3::new(angle.cos() * spread, speed, angle.sin() * spread),
+            age: 0.0,
+        }
+    }
+
+    // Call once per frame, uploads where the particles are & which way the quads face
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &camera::Camera) {
+        if !self.enabled {
+            return;
+        }
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BillboardUniform {
+                right: right.extend(0.0).into(),
+                up: up.extend(0.0).into(),
+            }]),
+        );
+
+        let mut order = self.particles.iter().collect::<Vec<_>>();
+        if matches!(self.blend_mode, BlendMode::AlphaBlend | BlendMode::PremultipliedAlpha) {
+            // Farthest first
+            let eye = cgmath::Vector3::new(camera.eye.x, camera.eye.y, camera.eye.z);
+            let distance = |particle: &Particle| (particle.position - eye).magnitude2();
+            order.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        }
+        let raw = order.into_iter().map(Particle::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw));
+        self.instance_count = raw.len() as u32;
+    }
+
+    /*
+    *   Draws the particles over `output`, which holds the finished opaque scene, depth tested
+    *   against `depth` (the scene's, single sampled).
+    */
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        screen_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+    ) {
+        if !self.enabled || self.instance_count == 0 {
+            return;
+        }
+        let pipeline = &self.pipelines[BlendMode::ALL.iter().position(|mode| *mode == self.blend_mode).unwrap()];
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Particle Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+            }),
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, screen_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instance_count);
+    }
+}
+
+impl Particle {
+    fn to_raw(&self) -> ParticleRaw {
+        let t = self.age / Particles::LIFETIME;
+        let mut color = [0.0; 4];
+        for (i, channel) in color.iter_mut().enumerate() {
+            *channel = Particles::START_COLOR[i] + (Particles::END_COLOR[i] - Particles::START_COLOR[i]) * t;
+        }
+        ParticleRaw {
+            position: self.position.extend(Particles::SIZE * (1.0 - 0.5 * t)).into(),
+            color,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Camera facing discs for the particles, see particles.rs. fs_main outputs straight alpha and
+// fs_premultiplied the color already multiplied by it, for the blend modes that want that.
+
+// @group(0) is the screen uniform, which the particles don't need
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// The camera's right & up, in world space
+struct BillboardUniform {
+    right: vec4<f32>,
+    up: vec4<f32>,
+};
+@group(2) @binding(0)
+var<uniform> billboard: BillboardUniform;
+
+struct ParticleInput {
+    // xyz & the half size
+    @location(5) position: vec4<f32>,
+    // Straight alpha
+    @location(6) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    // -1..1 across the quad
+    @location(1) corner: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, particle: ParticleInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    let offset = (billboard.right.xyz * corner.x + billboard.up.xyz * corner.y) * particle.position.w;
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(particle.position.xyz + offset, 1.0);
+    out.color = particle.color;
+    out.corner = corner;
+    return out;
+}
+
+// A soft edged disc, nothing outside of it so the opaque mode isn't squares
+fn coverage(in: VertexOutput) -> f32 {
+    let distance = length(in.corner);
+    if distance > 1.0 {
+        discard;
+    }
+    return in.color.a * (1.0 - smoothstep(0.3, 1.0, distance));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color.rgb, coverage(in));
+}
+
+@fragment
+fn fs_premultiplied(in: VertexOutput) -> @location(0) vec4<f32> {
+    let alpha = coverage(in);
+    return vec4<f32>(in.color.rgb * alpha, alpha);
+}
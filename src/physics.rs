@@ -0,0 +1,336 @@
+use cgmath::Vector3;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::{InterpolatedTransform, Transform};
+use crate::model;
+
+// An axis aligned box, stored by its two far corners
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_center(center: Vector3<f32>, half_extents: Vector3<f32>) -> Self {
+        Self::new(center - half_extents, center + half_extents)
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    // Boxes that only touch don't count, there's nothing to push apart
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] < other.max[axis] && other.min[axis] < self.max[axis])
+    }
+
+    /*
+    *   How far the two boxes would have to move apart on each axis to stop overlapping, or None
+    *   if they don't. Only the smallest of the three is needed to separate them, moving along
+    *   any other axis would push them further than necessary.
+    */
+    pub fn overlap(&self, other: &Aabb) -> Option<Vector3<f32>> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let min = Vector3::new(
+            self.max.x.min(other.max.x),
+            self.max.y.min(other.max.y),
+            self.max.z.min(other.max.z),
+        );
+        let max = Vector3::new(
+            self.min.x.max(other.min.x),
+            self.min.y.max(other.min.y),
+            self.min.z.max(other.min.z),
+        );
+        Some(min - max)
+    }
+}
+
+/*
+*   Something the world moves around. Bodies never rotate, that way their box stays axis aligned
+*   and is exactly the shape that gets drawn.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct Body {
+    pub transform: InterpolatedTransform,
+    pub half_extents: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+}
+
+impl Body {
+    // A box of the given size, drawn with the unit cube scaled to match
+    pub fn new(position: Vector3<f32>, size: Vector3<f32>, velocity: Vector3<f32>) -> Self {
+        let transform = Transform {
+            position,
+            scale: size,
+            ..Default::default()
+        };
+        Self {
+            transform: InterpolatedTransform::new(transform),
+            half_extents: size * 0.5,
+            velocity,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_center(self.transform.current.position, self.half_extents)
+    }
+}
+
+/*
+*   Boxes flying around inside a bigger box, bouncing off its walls and off each other. Every
+*   fixed step moves each body by its velocity, then looks at every pair for overlaps. Overlapping
+*   pairs get pushed apart along the axis they overlap the least on, half the distance each, and
+*   whichever of them is still heading into the other has that part of its velocity reflected.
+*
+*   Reflecting instead of exchanging momentum isn't physically right, but nothing ever speeds up
+*   or slows down, so the demo keeps going forever. Checking every pair is O(n^2), fine for a few
+*   bodies, anything bigger would want a broad phase (sort & sweep, a grid...) first.
+*
+*   Being a fixed step simulation, bodies only know where they were at the last two steps and
+*   are drawn between them, see InterpolatedTransform.
+*/
+#[derive(Clone, Debug)]
+pub struct World {
+    pub bodies: Vec<Body>,
+    pub bounds: Aabb,
+}
+
+impl World {
+    pub fn new(bounds: Aabb) -> Self {
+        Self {
+            bodies: Vec::new(),
+            bounds,
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        // Everything starts the step where it ended the last one
+        let mut next = self
+            .bodies
+            .iter()
+            .map(|body| body.transform.current)
+            .collect::<Vec<_>>();
+        for (body, transform) in self.bodies.iter_mut().zip(&mut next) {
+            transform.position += body.velocity * dt;
+            // Walls first. Unlike the other bodies they don't move, so the body takes all of the
+            // correction.
+            for axis in 0..3 {
+                let low = self.bounds.min[axis] + body.half_extents[axis];
+                let high = self.bounds.max[axis] - body.half_extents[axis];
+                if transform.position[axis] < low {
+                    transform.position[axis] = 2.0 * low - transform.position[axis];
+                    body.velocity[axis] = body.velocity[axis].abs();
+                } else if transform.position[axis] > high {
+                    transform.position[axis] = 2.0 * high - transform.position[axis];
+                    body.velocity[axis] = -body.velocity[axis].abs();
+                }
+            }
+        }
+
+        for i in 0..self.bodies.len() {
+            for j in i + 1..self.bodies.len() {
+                let a = Aabb::from_center(next[i].position, self.bodies[i].half_extents);
+                let b = Aabb::from_center(next[j].position, self.bodies[j].half_extents);
+                let overlap = match a.overlap(&b) {
+                    Some(overlap) => overlap,
+                    None => continue,
+                };
+                let axis = (0..3)
+                    .min_by(|&x, &y| overlap[x].total_cmp(&overlap[y]))
+                    .unwrap();
+                // Which way b is from a along that axis
+                let direction = if b.center()[axis] >= a.center()[axis] { 1.0 } else { -1.0 };
+                next[i].position[axis] -= direction * overlap[axis] * 0.5;
+                next[j].position[axis] += direction * overlap[axis] * 0.5;
+
+                let (head, tail) = self.bodies.split_at_mut(j);
+                let (a, b) = (&mut head[i], &mut tail[0]);
+                if a.velocity[axis] * direction > 0.0 {
+                    a.velocity[axis] = -a.velocity[axis];
+                }
+                if b.velocity[axis] * direction < 0.0 {
+                    b.velocity[axis] = -b.velocity[axis];
+                }
+            }
+        }
+
+        for (body, transform) in self.bodies.iter_mut().zip(next) {
+            body.transform.push(transform);
+        }
+    }
+}
+
+/*
+*   A handful of cubes bouncing around above the grid of spheres, the World above plus what it
+*   takes to draw it. They use the scene's pipeline and whatever material it's on.
+*/
+pub struct PhysicsDemo {
+    pub enabled: bool,
+    pub world: World,
+    mesh: model::Mesh,
+    instance_buffer: Tracked<wgpu::Buffer>,
+}
+
+impl PhysicsDemo {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bounds = Aabb::new(Vector3::new(-14.0, 2.0, -14.0), Vector3::new(14.0, 9.0, 14.0));
+        let mut world = World::new(bounds);
+        // (position, size, velocity)
+        let cubes = [
+            ([-8.0, 4.0, -6.0], [1.0, 1.0, 1.0], [7.0, 2.0, 5.0]),
+            ([6.0, 5.0, -4.0], [1.5, 1.5, 1.5], [-5.0, -3.0, 6.0]),
+            ([0.0, 3.0, 8.0], [2.0, 1.0, 1.0], [4.0, 1.5, -7.0]),
+            ([-4.0, 7.0, 4.0], [1.0, 2.0, 1.0], [-6.0, -2.5, -3.0]),
+            ([10.0, 6.0, 10.0], [1.0, 1.0, 2.0], [-3.0, 4.0, -5.0]),
+            ([-11.0, 5.0, 9.0], [1.25, 1.25, 1.25], [8.0, -1.0, -2.0]),
+            ([3.0, 4.0, -11.0], [0.75, 0.75, 0.75], [-2.0, 3.0, 9.0]),
+            ([9.0, 3.0, -10.0], [1.0, 1.0, 1.0], [-7.0, 2.0, 3.0]),
+        ];
+        for (position, size, velocity) in cubes {
+            world.bodies.push(Body::new(position.into(), size.into(), velocity.into()));
+        }
+        Self::with_world(device, model::Mesh::cube(device), world)
+    }
+
+    fn with_world(device: &wgpu::Device, mesh: model::Mesh, world: World) -> Self {
+        let instances = world
+            .bodies
+            .iter()
+            .map(|body| body.transform.current.to_raw())
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Physics Instance Buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            enabled: false,
+            world,
+            mesh,
+            instance_buffer,
+        }
+    }
+
+    // The same cubes, in the same places, on a new device
+    pub fn recreate(&self, device: &wgpu::Device) -> Self {
+        Self {
+            enabled: self.enabled,
+            ..Self::with_world(device, self.mesh.recreate(device), self.world.clone())
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        if self.enabled {
+            self.world.step(dt);
+        }
+    }
+
+    // Uploads where the cubes are `alpha` of the way from the last step but one to the last
+    pub fn update(&self, queue: &wgpu::Queue, alpha: f32) {
+        if !self.enabled {
+            return;
+        }
+        let instances = self
+            .world
+            .bodies
+            .iter()
+            .map(|body| body.transform.at(alpha).to_raw())
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+    }
+
+    // Expects the scene pipeline to be set, same as LodModel::draw
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.mesh.num_elements, 0, 0..self.world.bodies.len() as u32);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(center: Vector3<f32>) -> Aabb {
+        Aabb::from_center(center, Vector3::new(0.5, 0.5, 0.5))
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((0..3).all(|axis| (a[axis] - b[axis]).abs() < 1e-5), "{:?} isn't {:?}", a, b);
+    }
+
+    // A world big enough that the walls stay out of it, with still bodies
+    fn world(bodies: &[(Vector3<f32>, Vector3<f32>)]) -> World {
+        let mut world = World::new(Aabb::new(Vector3::new(-50.0, -50.0, -50.0), Vector3::new(50.0, 50.0, 50.0)));
+        world.bodies = bodies
+            .iter()
+            .map(|&(position, size)| Body::new(position, size, Vector3::new(0.0, 0.0, 0.0)))
+            .collect();
+        world
+    }
+
+    #[test]
+    fn overlap_on_each_axis() {
+        let a = unit_box(Vector3::new(0.0, 0.0, 0.0));
+        let b = unit_box(Vector3::new(0.8, 0.5, -0.1));
+        assert_close(a.overlap(&b).unwrap(), Vector3::new(0.2, 0.5, 0.9));
+        assert_eq!(b.overlap(&a), a.overlap(&b));
+    }
+
+    #[test]
+    fn touching_isnt_overlapping() {
+        let a = unit_box(Vector3::new(0.0, 0.0, 0.0));
+        for center in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 1.0)] {
+            let b = unit_box(center);
+            assert!(!a.intersects(&b));
+            assert_eq!(a.overlap(&b), None);
+        }
+        assert_eq!(a.overlap(&unit_box(Vector3::new(3.0, 0.0, 0.0))), None);
+    }
+
+    #[test]
+    fn contained_box_overlaps_by_its_size() {
+        let outer = Aabb::from_center(Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 2.0, 2.0));
+        let inner = Aabb::from_center(Vector3::new(0.5, -0.5, 0.0), Vector3::new(0.5, 0.2, 0.5));
+        assert!(outer.intersects(&inner));
+        assert_close(outer.overlap(&inner).unwrap(), Vector3::new(1.0, 0.4, 1.0));
+        assert_close(inner.overlap(&outer).unwrap(), Vector3::new(1.0, 0.4, 1.0));
+    }
+
+    #[test]
+    fn pushed_apart_along_the_least_overlap() {
+        // 0.2 on x, 0.6 on y & 1 on z, so they go apart on x, half each
+        let mut world = world(&[
+            (Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)),
+            (Vector3::new(0.8, 0.4, 0.0), Vector3::new(1.0, 1.0, 1.0)),
+        ]);
+        world.step(0.1);
+        assert_close(world.bodies[0].transform.current.position, Vector3::new(-0.1, 0.0, 0.0));
+        assert_close(world.bodies[1].transform.current.position, Vector3::new(0.9, 0.4, 0.0));
+    }
+
+    #[test]
+    fn contained_body_pushed_along_its_thinnest_side() {
+        // The inner box is thinnest on y, that's the least overlap. It's below the outer
+        // box's center so it gets pushed down, not out of the box in one step.
+        let mut world = world(&[
+            (Vector3::new(0.0, 0.0, 0.0), Vector3::new(4.0, 4.0, 4.0)),
+            (Vector3::new(0.0, -0.5, 0.0), Vector3::new(1.0, 0.4, 1.0)),
+        ]);
+        world.step(0.1);
+        assert_close(world.bodies[0].transform.current.position, Vector3::new(0.0, 0.2, 0.0));
+        assert_close(world.bodies[1].transform.current.position, Vector3::new(0.0, -0.7, 0.0));
+    }
+}
@@ -0,0 +1,308 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+/*
+*   Building a render pipeline is one of the slowest things you can ask wgpu to do, the driver
+*   compiles the shaders for the exact state the pipeline is going to run with. This keeps
+*   every pipeline that's still in use, and when something asks for one that's identical to an
+*   existing pipeline it gets that one back instead of a new copy.
+*
+*   Pipelines are handed out as Rc's and the cache itself only keeps Weak's, so it never keeps
+*   a pipeline alive by itself. Once everything that was using one drops it, the next request
+*   builds it again. Shader modules are shared between pipelines with the same source, and
+*   live as long as the pipelines built from them: each cached pipeline holds on to its module,
+*   the cache only has Weak's of them too. Sources get made up on the fly (the light capacity
+*   & the shader defines go into them), so keeping every module ever compiled would grow
+*   without end.
+*
+*   It lives in the GpuContext (everything it holds belongs to the device) and uses interior
+*   mutability so it can be shared like the device is. Compute pipelines aren't cached, there
+*   are only a few and they're only built once.
+*/
+pub struct RenderPipelineDesc<'a> {
+    // Only shows up in debuggers & error messages, pipelines that differ only in their label
+    // are the same pipeline as far as the cache is concerned
+    pub label: &'a str,
+    // WGSL source, both stages come from the same module
+    pub shader: &'a str,
+    pub bind_group_layouts: &'a [&'a wgpu::BindGroupLayout],
+    pub vertex_entry_point: &'a str,
+    pub vertex_buffers: &'a [wgpu::VertexBufferLayout<'a>],
+    pub fragment_entry_point: &'a str,
+    pub targets: &'a [Option<wgpu::ColorTargetState>],
+    pub primitive: wgpu::PrimitiveState,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    pub multisample: wgpu::MultisampleState,
+}
+
+/*
+*   Everything that can make two pipelines behave differently, taken from a RenderPipelineDesc:
+*
+*   - the shader, as a hash of its source. Two sources would need to collide on all 64 bits
+*     to be mixed up.
+*   - the bind group layouts, by their wgpu ids. Layouts don't compare by their entries, so
+*     pipelines only match when they were described with the very same layout objects.
+*   - the entry points, vertex buffer layouts, color targets, primitive, depth/stencil &
+*     multisample state, compared field by field.
+*
+*   The label is left out on purpose, see RenderPipelineDesc.
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    shader: u64,
+    bind_group_layouts: Vec<wgpu::Id>,
+    vertex_entry_point: String,
+    vertex_buffers: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    fragment_entry_point: String,
+    targets: Vec<Option<wgpu::ColorTargetState>>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState,
+}
+
+impl PipelineKey {
+    fn new(desc: &RenderPipelineDesc) -> Self {
+        Self {
+            shader: hash_source(desc.shader),
+            bind_group_layouts: desc.bind_group_layouts.iter().map(|layout| layout.global_id()).collect(),
+            vertex_entry_point: desc.vertex_entry_point.to_owned(),
+            vertex_buffers: desc
+                .vertex_buffers
+                .iter()
+                .map(|buffer| (buffer.array_stride, buffer.step_mode, buffer.attributes.to_vec()))
+                .collect(),
+            fragment_entry_point: desc.fragment_entry_point.to_owned(),
+            targets: desc.targets.to_vec(),
+            primitive: desc.primitive,
+            depth_stencil: desc.depth_stencil.clone(),
+            multisample: desc.multisample,
+        }
+    }
+}
+
+fn hash_source(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PipelineCacheStats {
+    // Requests answered with a pipeline that already existed
+    pub hits: u32,
+    // Requests that had to build a new pipeline
+    pub misses: u32,
+    // Pipelines that are alive right now
+    pub live: usize,
+    // Shader modules the live pipelines (or ones not pruned yet) were built from
+    pub shaders: usize,
+}
+
+struct CachedPipeline {
+    pipeline: Weak<wgpu::RenderPipeline>,
+    // Keeps the module around for other pipelines with the same source, until this one's pruned
+    _shader: Rc<wgpu::ShaderModule>,
+}
+
+#[derive(Default)]
+pub struct PipelineCache {
+    shaders: RefCell<HashMap<u64, Weak<wgpu::ShaderModule>>>,
+    pipelines: RefCell<HashMap<PipelineKey, CachedPipeline>>,
+    hits: Cell<u32>,
+    misses: Cell<u32>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn render_pipeline(&self, device: &wgpu::Device, desc: &RenderPipelineDesc) -> Rc<wgpu::RenderPipeline> {
+        let key = PipelineKey::new(desc);
+        if let Some(pipeline) = self.pipelines.borrow().get(&key).and_then(|cached| cached.pipeline.upgrade()) {
+            self.hits.set(self.hits.get() + 1);
+            return pipeline;
+        }
+        self.misses.set(self.misses.get() + 1);
+
+        let shader = self.shader(device, desc.label, desc.shader, key.shader);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", desc.label)),
+            bind_group_layouts: desc.bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = Rc::new(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{} Pipeline", desc.label)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: desc.vertex_entry_point,
+                buffers: desc.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: desc.fragment_entry_point,
+                targets: desc.targets,
+            }),
+            primitive: desc.primitive,
+            depth_stencil: desc.depth_stencil.clone(),
+            multisample: desc.multisample,
+            multiview: None,
+        }));
+
+        let mut pipelines = self.pipelines.borrow_mut();
+        // Forget the pipelines nobody's using anymore while we're here, and with them the
+        // modules no live pipeline was built from
+        pipelines.retain(|_, cached| cached.pipeline.strong_count() > 0);
+        pipelines.insert(
+            key,
+            CachedPipeline {
+                pipeline: Rc::downgrade(&pipeline),
+                _shader: shader,
+            },
+        );
+        self.shaders.borrow_mut().retain(|_, shader| shader.strong_count() > 0);
+        pipeline
+    }
+
+    fn shader(&self, device: &wgpu::Device, label: &str, source: &str, hash: u64) -> Rc<wgpu::ShaderModule> {
+        let mut shaders = self.shaders.borrow_mut();
+        if let Some(shader) = shaders.get(&hash).and_then(Weak::upgrade) {
+            return shader;
+        }
+        let shader = Rc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&format!("{} Shader", label)),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }));
+        shaders.insert(hash, Rc::downgrade(&shader));
+        shader
+    }
+
+    pub fn stats(&self) -> PipelineCacheStats {
+        PipelineCacheStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            live: self.pipelines.borrow().values().filter(|cached| cached.pipeline.strong_count() > 0).count(),
+            shaders: self.shaders.borrow().values().filter(|shader| shader.strong_count() > 0).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::ShaderDefines;
+
+    const SOURCE: &str = "@vertex fn vs_main() {}";
+    const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+
+    fn desc<'a>(
+        shader: &'a str,
+        vertex_buffers: &'a [wgpu::VertexBufferLayout<'a>],
+        targets: &'a [Option<wgpu::ColorTargetState>],
+    ) -> RenderPipelineDesc<'a> {
+        RenderPipelineDesc {
+            label: "Test",
+            shader,
+            // Layouts need a device, see the key's doc for how they're compared
+            bind_group_layouts: &[],
+            vertex_entry_point: "vs_main",
+            vertex_buffers,
+            fragment_entry_point: "fs_main",
+            targets,
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        }
+    }
+
+    fn vertex_buffers(stride: wgpu::BufferAddress) -> [wgpu::VertexBufferLayout<'static>; 1] {
+        [wgpu::VertexBufferLayout {
+            array_stride: stride,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }]
+    }
+
+    fn color_targets(format: wgpu::TextureFormat) -> [Option<wgpu::ColorTargetState>; 1] {
+        [Some(format.into())]
+    }
+
+    #[test]
+    fn source_hash_follows_the_source() {
+        assert_eq!(hash_source(SOURCE), hash_source(&["@vertex fn ", "vs_main() {}"].concat()));
+        assert_ne!(hash_source(SOURCE), hash_source("@vertex fn vs_main() { }"));
+        // Every define combination is a source of its own
+        let source = "#ifdef A\nconst A: u32 = 1u;\n#endif\n";
+        let a = ShaderDefines::new(&["A"]).preprocess(source);
+        let none = ShaderDefines::default().preprocess(source);
+        assert_ne!(hash_source(&a), hash_source(&none));
+        assert_eq!(hash_source(&a), hash_source(&ShaderDefines::new(&["A"]).preprocess(source)));
+    }
+
+    #[test]
+    fn same_desc_same_key() {
+        let (buffers, targets) = (vertex_buffers(12), color_targets(wgpu::TextureFormat::Bgra8UnormSrgb));
+        let a = PipelineKey::new(&desc(SOURCE, &buffers, &targets));
+        let b = PipelineKey::new(&RenderPipelineDesc {
+            label: "Another Label",
+            ..desc(&["@vertex fn ", "vs_main() {}"].concat(), &buffers, &targets)
+        });
+        assert_eq!(a, b);
+        let mut keys = std::collections::HashSet::new();
+        keys.insert(a);
+        assert!(!keys.insert(b));
+    }
+
+    #[test]
+    fn every_field_counts() {
+        let (buffers, targets) = (vertex_buffers(12), color_targets(wgpu::TextureFormat::Bgra8UnormSrgb));
+        let key = PipelineKey::new(&desc(SOURCE, &buffers, &targets));
+        let other_buffers = vertex_buffers(16);
+        let other_targets = color_targets(wgpu::TextureFormat::Rgba16Float);
+        let others = [
+            desc("@vertex fn vs_main() { }", &buffers, &targets),
+            desc(SOURCE, &other_buffers, &targets),
+            desc(SOURCE, &buffers, &other_targets),
+            desc(SOURCE, &[], &targets),
+            RenderPipelineDesc {
+                vertex_entry_point: "vs_other",
+                ..desc(SOURCE, &buffers, &targets)
+            },
+            RenderPipelineDesc {
+                fragment_entry_point: "fs_other",
+                ..desc(SOURCE, &buffers, &targets)
+            },
+            RenderPipelineDesc {
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                ..desc(SOURCE, &buffers, &targets)
+            },
+            RenderPipelineDesc {
+                multisample: wgpu::MultisampleState {
+                    count: 4,
+                    ..Default::default()
+                },
+                ..desc(SOURCE, &buffers, &targets)
+            },
+            RenderPipelineDesc {
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                ..desc(SOURCE, &buffers, &targets)
+            },
+        ];
+        for (i, other) in others.iter().enumerate() {
+            assert_ne!(PipelineKey::new(other), key, "variation {}", i);
+        }
+    }
+}
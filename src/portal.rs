@@ -0,0 +1,302 @@
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::{InstanceRaw, Transform};
+use crate::model;
+use crate::texture;
+use crate::uniform_ring::UniformRing;
+
+/*
+*   Render to texture. The scene gets drawn a second time, from another camera, into a texture
+*   of its own, which is then a material like any other on something in the main scene. Here
+*   that's a TV screen showing what a security camera up in a corner sees, the same goes for
+*   mirrors & portals with the camera placed to match.
+*
+*   The portal's pass comes first in the frame's encoder and wgpu makes sure it's done before
+*   the main pass samples the texture. It draws with the scene pipelines as they are, so the
+*   target has the main pass's format & sample count: with MSAA on it draws into multisampled
+*   color & depth of its own and resolves into the texture.
+*
+*   The screen is left out of the portal's pass. A texture can't be drawn into and sampled in
+*   the same pass, and a screen that could see itself would need itself drawn first, forever.
+*   Screens that should see each other (or a mirror facing a mirror) would sample a copy from
+*   the frame before instead.
+*/
+pub struct Portal {
+    pub enabled: bool,
+    pub view: PortalView,
+    pub target: PortalTarget,
+    // Where the screen is in the scene, a unit quad before the scale
+    pub screen: Transform,
+    screen_mesh: model::Mesh,
+    screen_instance: Tracked<wgpu::Buffer>,
+}
+
+/*
+*   Where a portal is rendered from: its camera, and the scene's instances as that camera sees
+*   them. All of them, the main camera's LOD & culling would leave holes wherever the two
+*   cameras see different things.
+*/
+pub struct PortalView {
+    pub camera: camera::Camera,
+    camera_binding: camera::CameraBinding,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    instance_count: u32,
+    capacity: usize,
+}
+
+// What a portal is rendered into, and what the screen samples
+pub struct PortalTarget {
+    pub texture: texture::Texture,
+    // The texture as a material at @group(2)
+    pub bind_group: wgpu::BindGroup,
+    // Drawn into & resolved from with MSAA
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView,
+    // Kept alive for the views
+    _msaa: Option<Tracked<wgpu::Texture>>,
+    _depth: Tracked<wgpu::Texture>,
+}
+
+impl Portal {
+    // 16:9, small enough to not cost much and big enough for a screen that fills a third of
+    // the window
+    pub const WIDTH: u32 = 512;
+    pub const HEIGHT: u32 = 288;
+
+    // `color_format` & `sample_count` are the scene pipelines', `texture_layout` their @group(2)
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        texture_layout: &wgpu::BindGroupLayout,
+        depth: camera::DepthDirection,
+        capacity: usize,
+    ) -> Self {
+        // Up in a corner of the grid, looking down across it
+        let camera = camera::Camera {
+            eye: (14.0, 8.0, 14.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: Self::WIDTH as f32 / Self::HEIGHT as f32,
+            fovy: 60.0,
+            znear: 0.1,
+            zfar: 100.0,
+            depth,
+        };
+        // Behind the grid, facing the starting camera
+        let screen = Transform {
+            position: cgmath::Vector3::new(0.0, 4.0, -16.0),
+            scale: cgmath::Vector3::new(6.4, 3.6, 1.0),
+            ..Default::default()
+        };
+        Self::with_camera(device, color_format, sample_count, texture_layout, camera, screen, capacity)
+    }
+
+    fn with_camera(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        texture_layout: &wgpu::BindGroupLayout,
+        camera: camera::Camera,
+        screen: Transform,
+        capacity: usize,
+    ) -> Self {
+        let screen_instance = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Portal Screen Instance Buffer"),
+            contents: bytemuck::cast_slice(&[screen.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            enabled: false,
+            view: PortalView::new(device, camera, capacity),
+            target: PortalTarget::new(device, color_format, sample_count, texture_layout),
+            screen,
+            screen_mesh: model::Mesh::quad(device),
+            screen_instance,
+        }
+    }
+
+    // On a (new) device, or for a new format, sample count or depth direction
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        texture_layout: &wgpu::BindGroupLayout,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let camera = camera::Camera {
+            depth,
+            ..self.view.camera
+        };
+        let mut portal = Self::with_camera(
+            device,
+            color_format,
+            sample_count,
+            texture_layout,
+            camera,
+            self.screen,
+            self.view.capacity,
+        );
+        portal.enabled = self.enabled;
+        portal
+    }
+
+    // Call once per frame with where everything is now
+    pub fn update(&mut self, queue: &wgpu::Queue, instances: &[Transform]) {
+        self.view.update(queue, instances);
+        queue.write_buffer(&self.screen_instance, 0, bytemuck::cast_slice(&[self.screen.to_raw()]));
+    }
+
+    // Expects the textured scene pipeline to be set, and binds its own @group(2)
+    pub fn draw_screen<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_bind_group(2, &self.target.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.screen_mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.screen_instance.slice(..));
+        render_pass.set_index_buffer(self.screen_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.screen_mesh.num_elements, 0, 0..1);
+    }
+}
+
+impl PortalView {
+    pub fn new(device: &wgpu::Device, camera: camera::Camera, capacity: usize) -> Self {
+        let camera_binding = camera::CameraBinding::new(device, &camera, UniformRing::DEFAULT_SLOTS);
+        Self {
+            camera,
+            camera_binding,
+            instance_buffer: Self::create_instance_buffer(device, capacity),
+            instance_count: 0,
+            capacity,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Portal Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Room for a different number of instances
+    pub fn set_capacity(&mut self, device: &wgpu::Device, capacity: usize) {
+        self.instance_buffer = Self::create_instance_buffer(device, capacity);
+        self.instance_count = 0;
+        self.capacity = capacity;
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, instances: &[Transform]) {
+        self.camera_binding.update(queue, &self.camera);
+        let raw = instances.iter().take(self.capacity).map(Transform::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw));
+        self.instance_count = raw.len() as u32;
+    }
+
+    // For @group(1) of the scene pipelines
+    pub fn camera_bind_group(&self) -> &wgpu::BindGroup {
+        self.camera_binding.bind_group()
+    }
+
+    // Every instance with `mesh`, expects the scene pipeline to be set
+    pub fn draw_instances<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a model::Mesh) {
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..mesh.num_elements, 0, 0..self.instance_count);
+    }
+}
+
+impl PortalTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        texture_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let texture = texture::Texture::create_render_target_sized(
+            device,
+            Portal::WIDTH,
+            Portal::HEIGHT,
+            color_format,
+            "portal_texture",
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("portal_bind_group"),
+        });
+
+        let attachment = |label: &str, format: wgpu::TextureFormat| {
+            device.create_tracked_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: Portal::WIDTH,
+                    height: Portal::HEIGHT,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+        };
+        let msaa = (sample_count > 1).then(|| attachment("portal_msaa_texture", color_format));
+        let depth = attachment("portal_depth_texture", texture::Texture::DEPTH_FORMAT);
+
+        Self {
+            texture,
+            bind_group,
+            msaa_view: msaa.as_ref().map(|msaa| msaa.create_view(&wgpu::TextureViewDescriptor::default())),
+            depth_view: depth.create_view(&wgpu::TextureViewDescriptor::default()),
+            _msaa: msaa,
+            _depth: depth,
+        }
+    }
+
+    // Straight into the texture, or resolved into it from the multisampled one
+    pub fn color_attachment(&self, load: wgpu::LoadOp<wgpu::Color>) -> wgpu::RenderPassColorAttachment<'_> {
+        match &self.msaa_view {
+            Some(msaa_view) => wgpu::RenderPassColorAttachment {
+                view: msaa_view,
+                resolve_target: Some(&self.texture.view),
+                // Only the resolved colors are needed afterwards
+                ops: wgpu::Operations { load, store: false },
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: &self.texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            },
+        }
+    }
+
+    // Nothing reads the depth after the pass, it doesn't need keeping
+    pub fn depth_attachment(&self, clear: f32) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+        wgpu::RenderPassDepthStencilAttachment {
+            view: &self.depth_view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(clear),
+                store: false,
+            }),
+            stencil_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(0),
+                store: false,
+            }),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::rc::Rc;
+
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{screen, texture, tonemap};
+
+// What an offscreen target has to support: being drawn to, then read with a filtering sampler
+const TARGET_USAGES: wgpu::TextureUsages =
+    wgpu::TextureUsages::RENDER_ATTACHMENT.union(wgpu::TextureUsages::TEXTURE_BINDING);
+
+/*
+*   Post processing chain. When any effect is enabled the scene is drawn into an offscreen
+*   texture instead of the surface, then each enabled effect runs as a full screen pass that
+*   reads the previous result and writes the next one. The two offscreen targets are used
+*   ping-pong style, and the last effect writes straight to the surface.
+*
+*   The targets can be in a format of their own, e.g. Rgba16Float to keep values above 1.0
+*   around for HDR effects. Everything drawn into them (the scene pipelines, the MSAA & trails
+*   attachments, the effects) has to be built for format() then, and since the last effect
+*   can't write to the surface any more, the scene always goes through the chain and a final
+*   blit converts the result to the surface's format. With an HDR format that last pass
+*   tonemaps too, after every effect & the MSAA resolve (see tonemap.rs for why it's last).
+*
+*   Every effect pipeline uses the same bind group layout for the first two groups:
+*
+*       @group(0) the screen uniform (see screen.rs)
+*       @group(1) @binding(0) the previous pass' color    texture_2d<f32>
+*                 @binding(1) a linear clamping sampler    sampler
+*                 @binding(2) the scene's depth buffer     texture_depth_2d
+*
+*   and is free to use @group(2) and up for its own settings.
+*/
+pub trait PostEffect {
+    fn enabled(&self) -> bool;
+
+    // Groups 0 & 1 are already set, the effect sets its pipeline & anything else it needs
+    // and draws a full screen triangle.
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>);
+}
+
+pub struct PostChain {
+    pub input_layout: wgpu::BindGroupLayout,
+    targets: [texture::Texture; 2],
+    format: wgpu::TextureFormat,
+    // Copies the result to the surface when the targets are in another format
+    present: Option<Present>,
+}
+
+enum Present {
+    Blit(Rc<wgpu::RenderPipeline>),
+    Tonemap(tonemap::Tonemap),
+}
+
+impl PostChain {
+    pub fn is_format_supported(adapter: &wgpu::Adapter, format: wgpu::TextureFormat) -> bool {
+        let features = adapter.get_texture_format_features(format);
+        features.allowed_usages.contains(TARGET_USAGES)
+            && features.flags.contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+    }
+
+    // The format asked for if the adapter can use it for the targets, the surface's otherwise
+    pub fn pick_format(
+        adapter: &wgpu::Adapter,
+        config: &wgpu::SurfaceConfiguration,
+        requested: Option<wgpu::TextureFormat>,
+    ) -> wgpu::TextureFormat {
+        match requested {
+            Some(format) if Self::is_format_supported(adapter, format) => format,
+            Some(format) => {
+                log::warn!(
+                    "{:?} can't be rendered to & sampled on this adapter, using {:?} offscreen",
+                    format,
+                    config.format
+                );
+                config.format
+            }
+            None => config.format,
+        }
+    }
+
+    // `format` should have passed is_format_supported, see pick_format. `curve` only matters
+    // when it's an HDR format.
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        curve: tonemap::TonemapCurve,
+    ) -> Self {
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // Depth textures can't be filtered, effects read them with textureLoad (see
+                // texture::DepthSampling for the samplers that do work with them)
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_input_bind_group_layout"),
+        });
+
+        let targets = Self::create_targets(device, config, format);
+        let present = if tonemap::Tonemap::is_hdr(format) {
+            Some(Present::Tonemap(tonemap::Tonemap::new(
+                device,
+                pipelines,
+                screen_layout,
+                &input_layout,
+                config.format,
+                curve,
+            )))
+        } else {
+            (format != config.format).then(|| {
+                Present::Blit(create_effect_pipeline(
+                    device,
+                    pipelines,
+                    "Post Present",
+                    include_str!("blit.wgsl"),
+                    &[screen_layout, &input_layout],
+                    config.format,
+                ))
+            })
+        };
+
+        Self {
+            input_layout,
+            targets,
+            format,
+            present,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> [texture::Texture; 2] {
+        [
+            texture::Texture::create_render_target(device, config, format, "post_target_a"),
+            texture::Texture::create_render_target(device, config, format, "post_target_b"),
+        ]
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = Self::create_targets(device, config, self.format);
+    }
+
+    // Every pipeline drawing into the targets needs this as its color format
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // When the targets aren't in the surface's format the scene has to be drawn into them even
+    // with every effect off, run converts it
+    pub fn always_runs(&self) -> bool {
+        self.present.is_some()
+    }
+
+    // The tonemapping in the last pass, only there with an HDR format
+    pub fn tonemap(&self) -> Option<&tonemap::Tonemap> {
+        match &self.present {
+            Some(Present::Tonemap(tonemap)) => Some(tonemap),
+            _ => None,
+        }
+    }
+
+    pub fn tonemap_mut(&mut self) -> Option<&mut tonemap::Tonemap> {
+        match &mut self.present {
+            Some(Present::Tonemap(tonemap)) => Some(tonemap),
+            _ => None,
+        }
+    }
+
+    // Where the scene should be drawn when any effect is enabled (or always_runs)
+    pub fn scene_target(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        screen: &screen::Screen,
+        depth_texture: &texture::Texture,
+        effects: &[&dyn PostEffect],
+        output: &wgpu::TextureView,
+    ) {
+        let effects = effects.iter().filter(|effect| effect.enabled()).collect::<Vec<_>>();
+        let depth_view = depth_texture.depth_only_view();
+        // The present blit is one more pass at the end
+        let passes = effects.len() + self.present.is_some() as usize;
+        for i in 0..passes {
+            let input = &self.targets[i % 2];
+            let target = if i + 1 == passes {
+                output
+            } else {
+                &self.targets[(i + 1) % 2].view
+            };
+
+            // The input alternates between the two targets, so the bind group is made per pass
+            let input_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.input_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&input.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&input.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&depth_view),
+                    },
+                ],
+                label: Some("post_input_bind_group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Process Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // Every pixel gets overwritten by the full screen triangle
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_bind_group(0, &screen.bind_group, &[]);
+            render_pass.set_bind_group(1, &input_bind_group, &[]);
+            if let Some(effect) = effects.get(i) {
+                effect.draw(&mut render_pass);
+            } else {
+                match &self.present {
+                    Some(Present::Blit(pipeline)) => {
+                        render_pass.set_pipeline(pipeline);
+                        render_pass.draw(0..3, 0..1);
+                    }
+                    Some(Present::Tonemap(tonemap)) => tonemap.draw(&mut render_pass),
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+// Builds a full screen pipeline for a post effect. The shader needs a vs_main that takes the
+// vertex index and a fs_main, see dof.wgsl for an example.
+pub fn create_effect_pipeline(
+    device: &wgpu::Device,
+    pipelines: &PipelineCache,
+    label: &str,
+    shader: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    format: wgpu::TextureFormat,
+) -> Rc<wgpu::RenderPipeline> {
+    pipelines.render_pipeline(
+        device,
+        &RenderPipelineDesc {
+            label,
+            shader,
+            bind_group_layouts,
+            vertex_entry_point: "vs_main",
+            vertex_buffers: &[],
+            fragment_entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        },
+    )
+}
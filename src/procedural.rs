@@ -0,0 +1,226 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::texture;
+
+/*
+*   A texture generated on the gpu. A compute shader writes every texel through a storage
+*   texture binding, then the render pass samples it like any other texture. Writing and
+*   sampling happen in different passes, wgpu makes sure the compute pass has finished
+*   before the render pass reads the result.
+*
+*   Storage textures need compute shaders and a format that allows STORAGE_BINDING, which
+*   WebGL doesn't have. There the texture is filled with a flat color from the cpu instead.
+*/
+pub struct ProceduralTexture {
+    pub texture: texture::Texture,
+    // What the render pipeline binds to sample the texture
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    generator: Option<Generator>,
+    params: PatternParams,
+}
+
+struct Generator {
+    pipeline: wgpu::ComputePipeline,
+    params_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PatternParams {
+    frequency: f32,
+    _padding: [f32; 3],
+}
+
+impl ProceduralTexture {
+    pub const SIZE: u32 = 256;
+    const WORKGROUP_SIZE: u32 = 8;
+    const FALLBACK_COLOR: [u8; 4] = [200, 200, 200, 255];
+
+    pub fn new(device: &wgpu::Device, adapter: &wgpu::Adapter, queue: &wgpu::Queue) -> Self {
+        Self::with_frequency(device, adapter, queue, 4.0)
+    }
+
+    fn with_frequency(device: &wgpu::Device, adapter: &wgpu::Adapter, queue: &wgpu::Queue, frequency: f32) -> Self {
+        let supported = texture::Texture::storage_supported(adapter);
+        if !supported {
+            log::warn!("This adapter can't write storage textures, the procedural texture will be blank");
+        }
+        let texture = texture::Texture::create_storage_texture(device, Self::SIZE, Self::SIZE, supported, "procedural_texture");
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("procedural_texture_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("procedural_texture_bind_group"),
+        });
+
+        let params = PatternParams {
+            frequency,
+            _padding: [0.0; 3],
+        };
+        let generator = supported.then(|| Generator::new(device, &texture, params));
+        let procedural = Self {
+            texture,
+            bind_group_layout,
+            bind_group,
+            generator,
+            params,
+        };
+        procedural.generate(device, queue);
+        procedural
+    }
+
+    // Same pattern on a new device
+    pub fn recreate(&self, device: &wgpu::Device, adapter: &wgpu::Adapter, queue: &wgpu::Queue) -> Self {
+        Self::with_frequency(device, adapter, queue, self.params.frequency)
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.params.frequency
+    }
+
+    // Changes the pattern and generates it again
+    pub fn set_frequency(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, frequency: f32) {
+        self.params.frequency = frequency;
+        self.generate(device, queue);
+    }
+
+    // Runs the compute shader over the whole texture, or fills it from the cpu without one
+    pub fn generate(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let generator = match &self.generator {
+            Some(generator) => generator,
+            None => {
+                let texels = Self::FALLBACK_COLOR.repeat((Self::SIZE * Self::SIZE) as usize);
+                queue.write_texture(
+                    self.texture.texture.as_image_copy(),
+                    &texels,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: std::num::NonZeroU32::new(4 * Self::SIZE),
+                        rows_per_image: std::num::NonZeroU32::new(Self::SIZE),
+                    },
+                    self.texture.texture.size(),
+                );
+                return;
+            }
+        };
+
+        queue.write_buffer(&generator.params_buffer, 0, bytemuck::cast_slice(&[self.params]));
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Procedural Texture Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Procedural Texture Pass"),
+            });
+            compute_pass.set_pipeline(&generator.pipeline);
+            compute_pass.set_bind_group(0, &generator.bind_group, &[]);
+            // One invocation per texel
+            let groups = Self::SIZE.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups, groups, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+impl Generator {
+    fn new(device: &wgpu::Device, texture: &texture::Texture, params: PatternParams) -> Self {
+        let params_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Procedural Params Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // The format has to match the one in the shader's texture_storage_2d type
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: texture::Texture::STORAGE_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("procedural_generator_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+            ],
+            label: Some("procedural_generator_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Procedural Texture Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("procedural.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Procedural Texture Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Procedural Texture Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        Self {
+            pipeline,
+            params_buffer,
+            bind_group,
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// Writes a procedural pattern into a storage texture, see procedural.rs
+
+struct PatternParams {
+    // How many rings fit between the middle and the edge
+    frequency: f32,
+};
+@group(0) @binding(0)
+var<uniform> params: PatternParams;
+// Storage textures have their format in the type, and can only be written to
+@group(0) @binding(1)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(output));
+    // The last workgroups hang over the edge when the size isn't a multiple of 8
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    // Rings around the middle on top of a colored gradient
+    let rings = 0.5 + 0.5 * cos(length(uv - 0.5) * 2.0 * params.frequency * 6.2831853);
+    let gradient = vec3<f32>(uv.x, uv.y, 1.0 - uv.x);
+    let color = mix(gradient * 0.35, gradient, rings);
+    textureStore(output, vec2<i32>(id.xy), vec4<f32>(color, 1.0));
+}
@@ -0,0 +1,207 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+
+use crate::context::GpuContext;
+use crate::pipeline_cache::PipelineCache;
+use crate::{post, screen, texture};
+
+/*
+*   Records the rendered frames to a video. While recording, the frame is drawn into an
+*   offscreen target instead of the surface (we can't copy out of a surface texture), then
+*   blitted to the surface so the window still shows it. After the frame is submitted we read
+*   the target back and hand the pixels to ffmpeg, which encodes them as they come in.
+*
+*   If ffmpeg can't be started we write every frame out as a numbered PNG instead, which
+*   ffmpeg (or anything else) can turn into a video later.
+*
+*   Reading back every frame stalls the gpu, so the recording runs at a fixed timestep: each
+*   frame advances the animations by exactly 1 / fps seconds however long it took to render.
+*/
+pub struct Recorder {
+    target: texture::Texture,
+    blit_pipeline: Rc<wgpu::RenderPipeline>,
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    fps: u32,
+    sink: FrameSink,
+    frames: u32,
+}
+
+enum FrameSink {
+    Ffmpeg(Child),
+    Png(PathBuf),
+}
+
+impl Recorder {
+    pub const DEFAULT_FPS: u32 = 30;
+
+    pub fn start(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        path: &Path,
+        fps: u32,
+    ) -> std::io::Result<Self> {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let sink = match Self::spawn_ffmpeg(path, size, fps) {
+            Ok(child) => {
+                log::info!("recording to {:?} through ffmpeg", path);
+                FrameSink::Ffmpeg(child)
+            }
+            Err(e) => {
+                // recording.mp4 -> recording_frames/
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("recording");
+                let dir = path.with_file_name(format!("{}_frames", stem));
+                log::warn!("couldn't start ffmpeg ({}), writing PNG frames to {:?} instead", e, dir);
+                std::fs::create_dir_all(&dir)?;
+                FrameSink::Png(dir)
+            }
+        };
+
+        let target = texture::Texture::create_render_target(device, config, config.format, "recording_target");
+        let blit_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Blit",
+            include_str!("blit.wgsl"),
+            &[screen_layout, &post_chain.input_layout],
+            config.format,
+        );
+
+        Ok(Self {
+            target,
+            blit_pipeline,
+            format: config.format,
+            size,
+            fps: fps.max(1),
+            sink,
+            frames: 0,
+        })
+    }
+
+    fn spawn_ffmpeg(path: &Path, size: wgpu::Extent3d, fps: u32) -> std::io::Result<Child> {
+        Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", size.width, size.height)])
+            .args(["-r", &fps.to_string()])
+            .args(["-i", "-"])
+            // yuv420p so the result plays pretty much anywhere
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+    }
+
+    // How much time each recorded frame represents
+    pub fn frame_duration(&self) -> instant::Duration {
+        instant::Duration::from_secs_f64(1.0 / self.fps as f64)
+    }
+
+    // The video size is fixed once ffmpeg has started, so a resize ends the recording
+    pub fn matches_size(&self, config: &wgpu::SurfaceConfiguration) -> bool {
+        self.size.width == config.width && self.size.height == config.height
+    }
+
+    // Draw the frame here instead of the surface
+    pub fn target_view(&self) -> &wgpu::TextureView {
+        &self.target.view
+    }
+
+    // Copies the recorded frame onto the surface so it still shows up in the window
+    pub fn blit(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        screen: &screen::Screen,
+        post_chain: &post::PostChain,
+        depth_texture: &texture::Texture,
+        output: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &post_chain.input_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.target.sampler),
+                },
+                // The blit doesn't read depth, but the layout wants one
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.depth_only_view()),
+                },
+            ],
+            label: Some("blit_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.blit_pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Reads the frame that was just submitted back from the gpu and writes it out
+    pub fn capture(&mut self, ctx: &GpuContext) -> std::io::Result<()> {
+        let mut pixels = pollster::block_on(ctx.read_texture_async(&self.target.texture, self.format, self.size));
+
+        // Surfaces are often BGRA, ffmpeg & PNGs want RGBA
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        match &mut self.sink {
+            FrameSink::Ffmpeg(child) => {
+                let stdin = child.stdin.as_mut().expect("ffmpeg stdin is piped");
+                stdin.write_all(&pixels)?;
+            }
+            FrameSink::Png(dir) => {
+                let path = dir.join(format!("frame_{:05}.png", self.frames));
+                image::save_buffer(&path, &pixels, self.size.width, self.size.height, image::ColorType::Rgba8)
+                    .map_err(std::io::Error::other)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Closes ffmpeg's input and waits for it to finish writing the file
+    pub fn stop(self) {
+        match self.sink {
+            FrameSink::Ffmpeg(mut child) => {
+                drop(child.stdin.take());
+                match child.wait() {
+                    Ok(status) if status.success() => log::info!("recorded {} frames", self.frames),
+                    Ok(status) => log::error!("ffmpeg exited with {}", status),
+                    Err(e) => log::error!("couldn't wait for ffmpeg: {}", e),
+                }
+            }
+            FrameSink::Png(dir) => log::info!("recorded {} frames to {:?}", self.frames, dir),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+
+// A rectangle of the surface in pixels, from its top left corner
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClearRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ClearRect {
+    // Cut down to the part that's on a surface of `size`, None when that's nothing. A scissor
+    // rect reaching past the edge of the target fails validation.
+    pub fn clamp(self, size: winit::dpi::PhysicalSize<u32>) -> Option<Self> {
+        let x = self.x.min(size.width);
+        let y = self.y.min(size.height);
+        let width = self.width.min(size.width - x);
+        let height = self.height.min(size.height - y);
+        (width > 0 && height > 0).then_some(Self { x, y, width, height })
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClearInstance {
+    color: [f32; 4],
+}
+
+impl ClearInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ClearInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x4,
+            }],
+        }
+    }
+}
+
+/*
+*   Clears rectangles of the finished frame to a color, for a panel behind the HUD or the
+*   halves of a split screen.
+*
+*   A render pass can only clear its attachments whole. LoadOp::Clear happens when the pass
+*   begins, before there's a scissor rect to limit it, so every pixel goes. To clear part of
+*   the frame the pass has to Load what's there instead, and draw over the part with the
+*   clear color: here a triangle over the whole screen, cut down to the rectangle by the
+*   scissor rect, with blending off so it replaces what was there alpha & all.
+*
+*   The two don't cost the same. An attachment clear is close to free, a lot of gpus just
+*   mark the memory as cleared without writing it, and it lets tiled gpus skip loading the
+*   old contents. The drawn clear is a regular draw: every pixel in the rectangle runs the
+*   fragment shader & gets written, and the pass has to load the rest. Fine for a panel, but
+*   when the whole target gets cleared anyway LoadOp::Clear is the way.
+*
+*   Like the font, nothing's kept between frames. Every clear queued since the last prepare
+*   gets uploaded by it and drawn by render, in the order they were queued.
+*/
+pub struct RegionClear {
+    // Queued since the last prepare
+    pending: Vec<(ClearRect, [f32; 4])>,
+    // What the last prepare uploaded, in the same order as their colors in the buffer
+    rects: Vec<ClearRect>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    capacity: usize,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl RegionClear {
+    pub fn new(device: &wgpu::Device, pipelines: &PipelineCache, color_format: wgpu::TextureFormat) -> Self {
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Region Clear",
+                shader: include_str!("region_clear.wgsl"),
+                bind_group_layouts: &[],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[ClearInstance::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // A clear replaces what's there, it doesn't blend with it
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+        let capacity = 4;
+        Self {
+            pending: Vec::new(),
+            rects: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, capacity),
+            capacity,
+            pipeline,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Region Clear Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<ClearInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new surface format. Clears come
+    // back with the next frame's.
+    pub fn recreate(&self, device: &wgpu::Device, pipelines: &PipelineCache, color_format: wgpu::TextureFormat) -> Self {
+        Self::new(device, pipelines, color_format)
+    }
+
+    // Queues `rect` to be cleared to `color` this frame
+    pub fn clear(&mut self, rect: ClearRect, color: wgpu::Color) {
+        let color = [color.r, color.g, color.b, color.a].map(|c| c as f32);
+        self.pending.push((rect, color));
+    }
+
+    // Uploads everything queued since the last prepare for render to draw, clamped to a
+    // surface of `size`. Call once per frame.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, size: winit::dpi::PhysicalSize<u32>) {
+        self.rects.clear();
+        let mut colors = Vec::with_capacity(self.pending.len());
+        for (rect, color) in self.pending.drain(..) {
+            if let Some(rect) = rect.clamp(size) {
+                self.rects.push(rect);
+                colors.push(ClearInstance { color });
+            }
+        }
+        if colors.len() > self.capacity {
+            self.capacity = colors.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&colors));
+    }
+
+    // Clears the rectangles in `output`, keeping everything around them
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if self.rects.is_empty() {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Region Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Not Clear, that would take the whole frame with it
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        for (i, rect) in self.rects.iter().enumerate() {
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            render_pass.draw(0..3, i as u32..i as u32 + 1);
+        }
+    }
+}
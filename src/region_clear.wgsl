@@ -0,0 +1,23 @@
+// A full screen triangle in one flat color, see region_clear.rs. The scissor rect decides
+// which part of the screen it clears.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, @location(0) color: vec4<f32>) -> VertexOutput {
+    // One triangle big enough to cover the whole screen: (-1,-1), (3,-1), (-1,3)
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,367 @@
+use std::path::{Path, PathBuf};
+
+use crate::json::{array, field, number, numbers, string, vector, Json};
+use crate::light::{Ambient, DirectionalLight, Light, LightType};
+use crate::{camera, instance, material};
+
+/*
+*   Everything needed to set the demo back up the way it was: where the camera is, which
+*   material the spheres use, the model they're drawn with, where each of them sits and the
+*   lights. Saved as JSON (see json.rs) so scenes can be written & tweaked by hand.
+*
+*   Assets are stored as paths, never their contents. A relative path is relative to the scene
+*   file, so a scene can be moved together with its models, and saving writes them that way
+*   whatever directory the demo was run from. No model means the spheres. The
+*   textures are all generated, so there's nothing to reference for those yet.
+*
+*   The bouncing ball, hopping sphere & physics cubes are animated and not part of a scene.
+*/
+pub struct Scene {
+    pub camera: SceneCamera,
+    pub material: material::Material,
+    pub model: Option<PathBuf>,
+    pub objects: Vec<instance::Transform>,
+    pub sun: DirectionalLight,
+    pub ambient: Ambient,
+    pub lights: Vec<Light>,
+}
+
+// The parts of camera::Camera that aren't just the window's aspect ratio
+#[derive(Copy, Clone, Debug)]
+pub struct SceneCamera {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl SceneCamera {
+    pub fn from_camera(camera: &camera::Camera) -> Self {
+        Self {
+            eye: camera.eye,
+            target: camera.target,
+            fovy: camera.fovy,
+            znear: camera.znear,
+            zfar: camera.zfar,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut camera::Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.fovy = self.fovy;
+        camera.znear = self.znear;
+        camera.zfar = self.zfar;
+    }
+}
+
+impl Scene {
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let directory = path.parent().unwrap_or(Path::new(""));
+        std::fs::write(path, format!("{}\n", self.to_json(directory)))
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+    }
+
+    // Also checks the assets are there, so a broken scene fails here rather than halfway through
+    // being applied
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let scene = Json::parse(&source)
+            .and_then(|json| Self::from_json(&json, directory))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some(model) = &scene.model {
+            if !model.is_file() {
+                return Err(format!("{}: model {} doesn't exist", path.display(), model.display()));
+            }
+        }
+        Ok(scene)
+    }
+
+    // Asset paths get written relative to `directory`, where the scene's going
+    pub fn to_json(&self, directory: &Path) -> Json {
+        let camera = &self.camera;
+        Json::Object(vec![
+            (
+                "camera".into(),
+                Json::Object(vec![
+                    ("eye".into(), vector(camera.eye)),
+                    ("target".into(), vector(camera.target)),
+                    ("fovy".into(), camera.fovy.into()),
+                    ("znear".into(), camera.znear.into()),
+                    ("zfar".into(), camera.zfar.into()),
+                ]),
+            ),
+            ("material".into(), self.material.name.into()),
+            (
+                "model".into(),
+                self.model.as_ref().map_or(Json::Null, |model| {
+                    relative_to(model, directory).to_string_lossy().as_ref().into()
+                }),
+            ),
+            ("objects".into(), Json::Array(self.objects.iter().map(transform_to_json).collect())),
+            (
+                "sun".into(),
+                Json::Object(vec![
+                    ("direction".into(), self.sun.direction.into()),
+                    ("color".into(), self.sun.color.into()),
+                ]),
+            ),
+            (
+                "ambient".into(),
+                Json::Object(vec![
+                    ("sky_color".into(), self.ambient.sky_color.into()),
+                    ("ground_color".into(), self.ambient.ground_color.into()),
+                    ("hemisphere".into(), self.ambient.is_hemisphere().into()),
+                ]),
+            ),
+            ("lights".into(), Json::Array(self.lights.iter().map(light_to_json).collect())),
+        ])
+    }
+
+    // Relative asset paths get resolved against `directory`
+    pub fn from_json(json: &Json, directory: &Path) -> Result<Self, String> {
+        let camera = field(json, "camera")?;
+        let camera = SceneCamera {
+            eye: numbers::<3>(camera, "eye")?.into(),
+            target: numbers::<3>(camera, "target")?.into(),
+            fovy: number(camera, "fovy")?,
+            znear: number(camera, "znear")?,
+            zfar: number(camera, "zfar")?,
+        };
+        let material = string(json, "material")?;
+        let material = material::Material::from_name(material).ok_or_else(|| {
+            let names = material::Material::ALL.map(|material| material.name);
+            format!("unknown material {:?}, expected one of {:?}", material, names)
+        })?;
+        let model = match field(json, "model")? {
+            Json::Null => None,
+            model => {
+                let model = model.as_str().ok_or("\"model\" should be a path or null")?;
+                Some(directory.join(model))
+            }
+        };
+        let objects = array(json, "objects")?
+            .iter()
+            .enumerate()
+            .map(|(i, object)| transform_from_json(object).map_err(|e| format!("object {}: {}", i, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sun = field(json, "sun")?;
+        let sun = DirectionalLight::new(numbers(sun, "direction")?, numbers(sun, "color")?);
+        let ambient = field(json, "ambient")?;
+        let hemisphere = field(ambient, "hemisphere")?
+            .as_bool()
+            .ok_or("\"hemisphere\" should be true or false")?;
+        let ambient = Ambient::hemisphere(numbers(ambient, "sky_color")?, numbers(ambient, "ground_color")?)
+            .with_hemisphere(hemisphere);
+        let lights = array(json, "lights")?
+            .iter()
+            .enumerate()
+            .map(|(i, light)| light_from_json(light).map_err(|e| format!("light {}: {}", i, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            camera,
+            material,
+            model,
+            objects,
+            sun,
+            ambient,
+            lights,
+        })
+    }
+}
+
+// `path` as seen from `directory`, both relative to the working directory or absolute. Goes up
+// with .. as far as it needs to. Across drives on Windows it can't, and a directory that goes
+// up itself can't be undone without knowing what's up there, so those stay absolute.
+fn relative_to(path: &Path, directory: &Path) -> PathBuf {
+    let absolute = |path: &Path| match std::env::current_dir() {
+        Ok(current) => current.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    let (path, directory) = (absolute(path), absolute(directory));
+    let mut path_components = path.components().peekable();
+    let mut directory_components = directory.components().peekable();
+    // Drop the part they share
+    while let (Some(a), Some(b)) = (path_components.peek(), directory_components.peek()) {
+        if a != b {
+            break;
+        }
+        path_components.next();
+        directory_components.next();
+    }
+    let directory_components: Vec<_> = directory_components.collect();
+    let unreachable = matches!(
+        path_components.peek(),
+        Some(std::path::Component::Prefix(_) | std::path::Component::RootDir)
+    ) || directory_components.contains(&std::path::Component::ParentDir);
+    if unreachable {
+        return path;
+    }
+    directory_components
+        .iter()
+        .map(|_| std::path::Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
+
+fn transform_to_json(transform: &instance::Transform) -> Json {
+    let rotation = transform.rotation;
+    Json::Object(vec![
+        ("position".into(), vector(transform.position)),
+        // x, y, z, w like most tools write them
+        ("rotation".into(), [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s].into()),
+        ("scale".into(), vector(transform.scale)),
+    ])
+}
+
+fn transform_from_json(json: &Json) -> Result<instance::Transform, String> {
+    let [x, y, z, w] = numbers(json, "rotation")?;
+    Ok(instance::Transform {
+        position: numbers::<3>(json, "position")?.into(),
+        rotation: cgmath::Quaternion::new(w, x, y, z),
+        scale: numbers::<3>(json, "scale")?.into(),
+    })
+}
+
+fn light_to_json(light: &Light) -> Json {
+    let light_type = match light.light_type() {
+        LightType::Directional => "directional",
+        LightType::Point => "point",
+        LightType::Spot => "spot",
+    };
+    let mut members = vec![
+        ("type".into(), light_type.into()),
+        ("position".into(), light.position.into()),
+        ("range".into(), light.range.into()),
+        ("color".into(), light.color.into()),
+        ("intensity".into(), light.intensity.into()),
+        ("direction".into(), light.direction.into()),
+    ];
+    if let Some((inner, outer)) = light.cone_angles() {
+        members.push(("cone".into(), [inner.0, outer.0].into()));
+    }
+    Json::Object(members)
+}
+
+fn light_from_json(json: &Json) -> Result<Light, String> {
+    let color = numbers(json, "color")?;
+    let intensity = number(json, "intensity")?;
+    match string(json, "type")? {
+        "directional" => Ok(Light::directional(numbers(json, "direction")?, color, intensity)),
+        "point" => Ok(Light::point(numbers(json, "position")?, color, intensity, number(json, "range")?)),
+        "spot" => {
+            let [inner, outer] = numbers(json, "cone")?;
+            Ok(Light::point(numbers(json, "position")?, color, intensity, number(json, "range")?).with_cone(
+                numbers(json, "direction")?,
+                cgmath::Deg(inner),
+                cgmath::Deg(outer),
+            ))
+        }
+        other => Err(format!("unknown light type {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_to_goes_up_and_down() {
+        assert_eq!(relative_to(Path::new("assets/x.obj"), Path::new("")), Path::new("assets/x.obj"));
+        assert_eq!(relative_to(Path::new("assets/x.obj"), Path::new("scenes")), Path::new("../assets/x.obj"));
+        assert_eq!(relative_to(Path::new("scenes/a/x.obj"), Path::new("scenes")), Path::new("a/x.obj"));
+        assert_eq!(relative_to(Path::new("x.obj"), Path::new("scenes/a")), Path::new("../../x.obj"));
+    }
+
+    #[test]
+    fn relative_to_keeps_what_it_cant_reach_absolute() {
+        let current = std::env::current_dir().unwrap();
+        assert_eq!(relative_to(Path::new("x.obj"), Path::new("../scenes")), current.join("x.obj"));
+    }
+
+    fn scene(model: Option<PathBuf>) -> Scene {
+        Scene {
+            camera: SceneCamera {
+                eye: (0.0, 1.0, 2.0).into(),
+                target: (0.0, 0.0, 0.0).into(),
+                fovy: 45.0,
+                znear: 0.1,
+                zfar: 100.0,
+            },
+            material: material::Material::ALL[0],
+            model,
+            objects: Vec::new(),
+            sun: DirectionalLight::new([0.0, -1.0, 0.0], [1.0; 3]),
+            ambient: Ambient::hemisphere([0.1; 3], [0.05; 3]),
+            lights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut scene = scene(Some(PathBuf::from("assets/x.obj")));
+        scene.material = *material::Material::ALL.last().unwrap();
+        scene.objects = vec![
+            instance::Transform::default(),
+            instance::Transform {
+                position: (1.0, -2.0, 3.5).into(),
+                rotation: cgmath::Quaternion::new(0.5, 0.5, 0.5, 0.5),
+                scale: (2.0, 1.0, 0.25).into(),
+            },
+        ];
+        scene.ambient = scene.ambient.with_hemisphere(false);
+        scene.lights = vec![
+            Light::point([1.0, 2.0, 3.0], [1.0, 0.5, 0.25], 2.0, 10.0),
+            Light::point([0.0, 4.0, 0.0], [1.0; 3], 1.0, 8.0).with_cone(
+                [0.0, -1.0, 0.0],
+                cgmath::Deg(20.0),
+                cgmath::Deg(30.0),
+            ),
+            Light::directional([0.0, -1.0, 1.0], [0.5; 3], 0.75),
+        ];
+
+        let printed = scene.to_json(Path::new("")).to_string();
+        let loaded = Scene::from_json(&Json::parse(&printed).unwrap(), Path::new("")).unwrap();
+        assert_eq!(loaded.to_json(Path::new("")).to_string(), printed);
+        assert_eq!(loaded.model, scene.model);
+        assert_eq!(loaded.material.name, scene.material.name);
+        assert_eq!(loaded.objects.len(), 2);
+        assert_eq!(loaded.objects[1].position, scene.objects[1].position);
+        assert_eq!(loaded.objects[1].rotation, scene.objects[1].rotation);
+        assert_eq!(loaded.lights.len(), 3);
+        assert!(!loaded.ambient.is_hemisphere());
+    }
+
+    #[test]
+    fn unknown_material() {
+        let mut json = scene(None).to_json(Path::new(""));
+        if let Json::Object(members) = &mut json {
+            members.iter_mut().find(|(key, _)| key == "material").unwrap().1 = "velvet".into();
+        }
+        let error = Scene::from_json(&json, Path::new("")).err().unwrap();
+        assert!(error.starts_with("unknown material \"velvet\""), "{}", error);
+    }
+
+    #[test]
+    fn saved_model_loads_from_another_directory() {
+        let root = std::env::temp_dir().join(format!("learn-wgpu-scene-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("assets")).unwrap();
+        std::fs::create_dir_all(root.join("scenes")).unwrap();
+        let model = root.join("assets/x.obj");
+        std::fs::write(&model, "").unwrap();
+
+        let scene = scene(Some(model.clone()));
+        let path = root.join("scenes/a.json");
+        scene.save(&path).unwrap();
+        let json = Json::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(string(&json, "model").unwrap(), "../assets/x.obj");
+        let loaded = Scene::load(&path).unwrap();
+        assert_eq!(loaded.model.unwrap().canonicalize().unwrap(), model.canonicalize().unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
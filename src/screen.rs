@@ -0,0 +1,107 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+
+/*
+*   Lots of effects need to know how big the surface is (UV computation from
+*   @builtin(position), screen-space effects, pixel sized overlays...). Rather than
+*   recomputing it in a bunch of places, we upload it once into a uniform buffer and
+*   re-upload it whenever the window is resized.
+*
+*   Every pipeline binds this at @group(0) so shaders can always rely on it being there:
+*
+*       struct ScreenUniform {
+*           resolution: vec2<f32>,
+*           inv_resolution: vec2<f32>,
+*       };
+*       @group(0) @binding(0)
+*       var<uniform> screen: ScreenUniform;
+*/
+
+// We need this for Rust to store our data correctly for the shaders
+#[repr(C)]
+// This is so we can store this in a buffer
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ScreenUniform {
+    // Width & height of the surface in pixels
+    pub resolution: [f32; 2],
+    // 1 / resolution, so shaders can multiply instead of divide
+    pub inv_resolution: [f32; 2],
+}
+
+impl ScreenUniform {
+    pub fn new(size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let mut uniform = Self {
+            resolution: [0.0; 2],
+            inv_resolution: [0.0; 2],
+        };
+        uniform.update(size);
+        uniform
+    }
+
+    pub fn update(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        // The surface is never configured with a size of 0, but guard against it anyway
+        // so we never hand the shaders an infinity.
+        let width = size.width.max(1) as f32;
+        let height = size.height.max(1) as f32;
+        self.resolution = [width, height];
+        self.inv_resolution = [1.0 / width, 1.0 / height];
+    }
+}
+
+// Owns the gpu side of the ScreenUniform: the buffer, and the bind group that every
+// pipeline shares at @group(0).
+pub struct Screen {
+    pub uniform: ScreenUniform,
+    pub buffer: Tracked<wgpu::Buffer>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Screen {
+    pub fn new(device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let uniform = ScreenUniform::new(size);
+
+        // COPY_DST lets us write to the buffer with queue.write_buffer when we resize
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Screen Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                // Both stages get to see it, vertex shaders need it for pixel sized geometry
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("screen_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("screen_bind_group"),
+        });
+
+        Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    // Called from State::resize so the shaders always see the current surface size
+    pub fn resize(&mut self, queue: &wgpu::Queue, size: winit::dpi::PhysicalSize<u32>) {
+        self.uniform.update(size);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
@@ -0,0 +1,323 @@
+// Shared by every pipeline at @group(0), see screen.rs
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Generated by a compute shader, see procedural.rs. The #ifdef's are handled by
+// ShaderDefines::preprocess, see material.rs.
+#ifdef TEXTURED
+@group(2) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(2) @binding(1)
+var s_diffuse: sampler;
+#endif
+// In place of the textures, colors by height. See colormap.rs.
+#ifdef COLORMAP
+@group(2) @binding(0)
+var t_colormap: texture_2d<f32>;
+@group(2) @binding(1)
+var s_colormap: sampler;
+struct ColormapRange {
+    min: f32,
+    max: f32,
+};
+@group(2) @binding(2)
+var<uniform> colormap_range: ColormapRange;
+#endif
+// Or grass, rock & snow by slope & height, see splat.rs
+#ifdef SPLAT
+const SPLAT_GRASS: i32 = 0;
+const SPLAT_ROCK: i32 = 1;
+const SPLAT_SNOW: i32 = 2;
+@group(2) @binding(0)
+var t_splat: texture_2d_array<f32>;
+@group(2) @binding(1)
+var s_splat: sampler;
+struct SplatSettings {
+    min_height: f32,
+    max_height: f32,
+    snow_line: f32,
+    rock_slope: f32,
+    blend: f32,
+    tiling: f32,
+};
+@group(2) @binding(2)
+var<uniform> splat: SplatSettings;
+
+fn splat_color(world_position: vec3<f32>, world_normal: vec3<f32>) -> vec3<f32> {
+    // Tiled across the world, the terrain's uvs would stretch one copy over all of it
+    let uv = world_position.xz / splat.tiling;
+    let grass = textureSample(t_splat, s_splat, uv, SPLAT_GRASS).rgb;
+    let rock = textureSample(t_splat, s_splat, uv, SPLAT_ROCK).rgb;
+    let snow = textureSample(t_splat, s_splat, uv, SPLAT_SNOW).rgb;
+
+    // 1 on flat ground, heading for 0 as it steepens
+    let flatness = normalize(world_normal).y;
+    let rock_weight = 1.0 - smoothstep(splat.rock_slope - splat.blend, splat.rock_slope + splat.blend, flatness);
+    let height = (world_position.y - splat.min_height) / (splat.max_height - splat.min_height);
+    // Snow doesn't stay on the steepest rock
+    let snow_weight = smoothstep(splat.snow_line - splat.blend, splat.snow_line + splat.blend, height)
+        * (1.0 - 0.6 * rock_weight);
+    return mix(mix(grass, rock, rock_weight), snow, snow_weight);
+}
+#endif
+// The loudness of each frequency band, with the textures, see audio.rs
+#ifdef AUDIO
+const AUDIO_BANDS: u32 = 8u;
+struct AudioBands {
+    bands: array<vec4<f32>, 2>,
+};
+@group(2) @binding(2)
+var<uniform> audio: AudioBands;
+
+fn audio_band(index: u32) -> f32 {
+    let band = index % AUDIO_BANDS;
+    return audio.bands[band / 4u][band % 4u];
+}
+#endif
+
+// See light.rs
+struct DirectionalLight {
+    direction: vec3<f32>,
+    color: vec3<f32>,
+};
+struct Ambient {
+    sky_color: vec3<f32>,
+    hemisphere: u32,
+    ground_color: vec3<f32>,
+};
+// See fog.rs
+const FOG_OFF: u32 = 0u;
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL_SQUARED: u32 = 2u;
+struct Fog {
+    color: vec3<f32>,
+    mode: u32,
+    density: f32,
+    start: f32,
+    end: f32,
+};
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    direction: vec3<f32>,
+    light_type: u32,
+    // Cosines of the cone angles
+    inner_cutoff: f32,
+    outer_cutoff: f32,
+};
+// Replaced with the lights' capacity before compiling, see Lights::preprocess
+const MAX_LIGHTS: u32 = 8u;
+struct Lights {
+    sun: DirectionalLight,
+    ambient: Ambient,
+    fog: Fog,
+    lights: array<Light, MAX_LIGHTS>,
+    light_count: u32,
+};
+@group(3) @binding(0)
+var<uniform> lights: Lights;
+
+// Vertex shader
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+    // @builtin(position) is the pixel coordinate of the fragment in the fragment shader
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+#ifdef AUDIO
+    @location(3) band: f32,
+#endif
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.world_normal = normal_matrix * model.normal;
+#ifdef AUDIO
+    // Each instance is a band, stretched up from its bottom by how loud it is. The bars are
+    // boxes, which keep their normals when they're stretched along an axis.
+    let band = audio_band(instance_index);
+    out.band = band;
+    var position = model.position;
+    position.y = (position.y + 0.5) * (1.0 + band * 4.0) - 0.5;
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+#else
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+#endif
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// Fragment shader
+
+// Inverse square falloff, faded smoothly to 0 at the light's range so there's no visible edge
+// where it stops (the same window function as glTF's KHR_lights_punctual)
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    // Keep it from blowing up right next to the light
+    return window * window / max(distance * distance, 0.01);
+}
+
+// 1 inside the spotlight's inner cone, 0 outside the outer one, smooth in between
+fn spot_cone(light: Light, to_light: vec3<f32>) -> f32 {
+    let cos_angle = dot(-to_light, normalize(light.direction));
+    return smoothstep(light.outer_cutoff, light.inner_cutoff, cos_angle);
+}
+
+// Light coming from everywhere at once. With the hemisphere on it's the sky color facing up,
+// the ground color facing down & a blend of the two on the way around.
+fn ambient_light(normal: vec3<f32>) -> vec3<f32> {
+    let ambient = lights.ambient;
+    let up = select(1.0, normal.y * 0.5 + 0.5, ambient.hemisphere != 0u);
+    return mix(ambient.ground_color, ambient.sky_color, up);
+}
+
+// How much of the fog color covers something this far from the camera, 0 is none
+fn fog_amount(distance: f32) -> f32 {
+    let fog = lights.fog;
+    if (fog.mode == FOG_LINEAR) {
+        return clamp((distance - fog.start) / (fog.end - fog.start), 0.0, 1.0);
+    }
+    if (fog.mode == FOG_EXPONENTIAL_SQUARED) {
+        let thickness = fog.density * distance;
+        return 1.0 - exp(-thickness * thickness);
+    }
+    return 0.0;
+}
+
+// Blinn-Phong lighting from one light. `to_light` points from the surface towards the light.
+fn shade(base_color: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, to_light: vec3<f32>, light_color: vec3<f32>) -> vec3<f32> {
+    let diffuse = max(dot(normal, to_light), 0.0);
+    let half_dir = normalize(view_dir + to_light);
+    // No highlights on the side facing away from the light
+    let specular = select(0.0, pow(max(dot(normal, half_dir), 0.0), 32.0), diffuse > 0.0);
+    return light_color * (base_color * diffuse + vec3<f32>(0.3) * specular);
+}
+
+// Paler & yellower, like the underside of a leaf
+const BACK_TINT: vec3<f32> = vec3<f32>(0.75, 0.9, 0.55);
+
+// front_facing is true when the triangle's corners go around counter clockwise on the screen,
+// so we're looking at its front. Culling leaves only those unless the material's double sided.
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+#ifdef COLORMAP
+    // The height picks the texel, clamped so everything outside the range gets an end's color
+    let value = (in.world_position.y - colormap_range.min) / (colormap_range.max - colormap_range.min);
+    let base_color = textureSample(t_colormap, s_colormap, vec2<f32>(clamp(value, 0.0, 1.0), 0.5)).rgb;
+#else
+#ifdef SPLAT
+    let base_color = splat_color(in.world_position, in.world_normal);
+#else
+#ifdef TEXTURED
+    let base_color = textureSample(t_diffuse, s_diffuse, in.tex_coords).rgb;
+#else
+    // Light grey, so the lighting is all there is to see
+    let base_color = vec3<f32>(0.8);
+#endif
+#endif
+#endif
+#ifdef DOUBLE_SIDED
+    // The normal points out of the front, on the back it has to be turned around to face us
+    // or the side we're looking at gets lit as if it were the other one
+    var normal = normalize(in.world_normal);
+    if (!front_facing) {
+        normal = -normal;
+    }
+#else
+    let normal = normalize(in.world_normal);
+#endif
+#ifdef BACK_TINT
+    let albedo = select(base_color * BACK_TINT, base_color, front_facing);
+#else
+    let albedo = base_color;
+#endif
+    let view_dir = normalize(camera.view_position.xyz - in.world_position);
+
+    // A little light everywhere so the shadowed sides aren't pitch black
+    var color = albedo * ambient_light(normal);
+    // The sun comes from the same direction everywhere, and doesn't fade with distance
+    color += shade(albedo, normal, view_dir, -normalize(lights.sun.direction), lights.sun.color);
+    // A bad count can't read past the end of the array
+    for (var i = 0u; i < min(lights.light_count, MAX_LIGHTS); i += 1u) {
+        let light = lights.lights[i];
+        var radiance = light.color * light.intensity;
+        var to_light: vec3<f32>;
+        if (light.light_type == LIGHT_DIRECTIONAL) {
+            to_light = -normalize(light.direction);
+        } else {
+            let offset = light.position - in.world_position;
+            let distance = length(offset);
+            // Out of range lights wouldn't add anything
+            if (distance >= light.range) {
+                continue;
+            }
+            to_light = offset / distance;
+            radiance *= attenuation(distance, light.range);
+            if (light.light_type == LIGHT_SPOT) {
+                radiance *= spot_cone(light, to_light);
+            }
+        }
+        color += shade(albedo, normal, view_dir, to_light, radiance);
+    }
+
+#ifdef AUDIO
+    // Loud bands glow, whatever light's on them
+    color += albedo * in.band;
+#endif
+
+    let distance = length(camera.view_position.xyz - in.world_position);
+    color = mix(color, lights.fog.color, fog_amount(distance));
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,402 @@
+use cgmath::prelude::*;
+
+use crate::animation::{AnimationTrack, PlaybackMode};
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::Transform;
+use crate::model::Vertex;
+use crate::texture;
+
+/*
+*   Skeletal animation, skinned on the gpu. A skeleton is a tree of joints (bones), each with
+*   a transform relative to its parent. Every vertex is attached to up to 4 joints with
+*   weights that add up to 1, and the vertex shader moves it by the weighted blend of those
+*   joints' matrices.
+*
+*   The matrix for a joint is its current global transform times its inverse bind matrix. The
+*   inverse bind matrix undoes where the joint was when the mesh was modelled (the bind pose),
+*   so a joint that hasn't moved leaves its vertices exactly where they were. The palette of
+*   joint matrices is recalculated on the cpu every frame and lives in a storage buffer, since
+*   a skeleton can have more joints than fit comfortably in a uniform.
+*
+*   This is the same data a glTF skin holds: a list of joint nodes, their parents, an inverse
+*   bind matrix per joint and JOINTS_0/WEIGHTS_0 vertex attributes. There's no glTF loader in
+*   here yet, so the demo builds a tentacle procedurally instead.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    // Indices into the joint palette, unused slots should have a weight of 0
+    pub joint_indices: [u32; 4],
+    pub joint_weights: [f32; 4],
+}
+
+impl Vertex for SkinnedVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        // The first 3 match ModelVertex, so the fragment shader doesn't know the difference
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x2,
+            2 => Float32x3,
+            3 => Uint32x4,
+            4 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SkinnedMeshData {
+    pub name: String,
+    pub vertices: Vec<SkinnedVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl SkinnedMeshData {
+    /*
+    *   An open tube standing on the origin, `height` tall, with the joints spread evenly along
+    *   it. Each ring of vertices is shared between the two nearest joints, weighted by how close
+    *   it is to each one, so it bends smoothly instead of kinking at the joints.
+    */
+    pub fn tube(name: &str, radius: f32, height: f32, sectors: u32, rings: u32, joint_count: u32) -> Self {
+        let sectors = sectors.max(3);
+        let rings = rings.max(1);
+        let segment = height / joint_count as f32;
+
+        let mut vertices = Vec::with_capacity(((sectors + 1) * (rings + 1)) as usize);
+        for i in 0..=rings {
+            let y = height * i as f32 / rings as f32;
+            // Joint a sits at the bottom of segment a, blend from the middle of one segment
+            // to the middle of the next
+            let along = (y / segment - 0.5).clamp(0.0, (joint_count - 1) as f32);
+            let a = (along.floor() as u32).min(joint_count.saturating_sub(2));
+            let t = along - a as f32;
+            let b = (a + 1).min(joint_count - 1);
+            for j in 0..=sectors {
+                let theta = std::f32::consts::TAU * j as f32 / sectors as f32;
+                let normal = [theta.cos(), 0.0, theta.sin()];
+                vertices.push(SkinnedVertex {
+                    position: [normal[0] * radius, y, normal[2] * radius],
+                    tex_coords: [j as f32 / sectors as f32, 1.0 - i as f32 / rings as f32],
+                    normal,
+                    joint_indices: [a, b, 0, 0],
+                    joint_weights: [1.0 - t, t, 0.0, 0.0],
+                });
+            }
+        }
+
+        let mut indices = Vec::with_capacity((sectors * rings * 6) as usize);
+        for i in 0..rings {
+            for j in 0..sectors {
+                let a = i * (sectors + 1) + j;
+                let b = a + sectors + 1;
+                // Counter clockwise seen from outside the tube
+                indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+            }
+        }
+
+        Self {
+            name: name.to_string(),
+            vertices,
+            indices,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    // Parents always come before their children
+    pub parent: Option<usize>,
+    // Relative to the parent, used for any joint the animation doesn't move
+    pub rest: Transform,
+    pub inverse_bind: cgmath::Matrix4<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Self {
+        for (i, joint) in joints.iter().enumerate() {
+            assert!(joint.parent.is_none_or(|parent| parent < i), "joint {} comes before its parent", i);
+        }
+        Self { joints }
+    }
+
+    // When the mesh was modelled around the rest pose, the inverse bind matrices are just the
+    // inverses of where the joints are in it
+    pub fn from_rest_pose(rest: Vec<(Option<usize>, Transform)>) -> Self {
+        let mut globals: Vec<cgmath::Matrix4<f32>> = Vec::with_capacity(rest.len());
+        let mut joints = Vec::with_capacity(rest.len());
+        for (parent, transform) in rest {
+            let global = parent.map_or(cgmath::Matrix4::identity(), |parent| globals[parent]) * transform.to_matrix();
+            globals.push(global);
+            joints.push(Joint {
+                parent,
+                rest: transform,
+                inverse_bind: global.invert().expect("joint transforms can't have a scale of 0"),
+            });
+        }
+        Self::new(joints)
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    // The palette for the shader, given every joint's transform relative to its parent
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<[[f32; 4]; 4]> {
+        let mut globals: Vec<cgmath::Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(pose) {
+            let parent = joint.parent.map_or(cgmath::Matrix4::identity(), |parent| globals[parent]);
+            globals.push(parent * local.to_matrix());
+        }
+        globals
+            .iter()
+            .zip(&self.joints)
+            .map(|(global, joint)| (global * joint.inverse_bind).into())
+            .collect()
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+}
+
+// One track per joint, joints without one stay in their rest pose
+#[derive(Clone, Debug)]
+pub struct SkinAnimation {
+    tracks: Vec<Option<AnimationTrack>>,
+}
+
+impl SkinAnimation {
+    pub fn new(tracks: Vec<Option<AnimationTrack>>) -> Self {
+        Self { tracks }
+    }
+
+    pub fn sample(&self, skeleton: &Skeleton, t: f32) -> Vec<Transform> {
+        skeleton
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| match self.tracks.get(i) {
+                Some(Some(track)) => track.sample(t),
+                _ => joint.rest,
+            })
+            .collect()
+    }
+}
+
+pub struct SkinnedModel {
+    pub enabled: bool,
+    mesh: SkinnedMeshData,
+    skeleton: Skeleton,
+    animation: SkinAnimation,
+    transform: Transform,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    index_buffer: Tracked<wgpu::Buffer>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    joint_buffer: Tracked<wgpu::Buffer>,
+    // Takes the place of the texture's group in the scene pipeline, with the joints added
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl SkinnedModel {
+    // WebGL can't read storage buffers at all, some downlevel backends can't in vertex shaders
+    // The joint matrices are a storage buffer read by the vertex shader
+    pub fn is_supported(adapter: &wgpu::Adapter, limits: &wgpu::Limits) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+            && limits.max_storage_buffers_per_shader_stage >= 1
+    }
+
+    // The vertex shader goes after shader.wgsl, so it can reuse its structs & fragment shader
+    pub fn shader_source() -> String {
+        format!("{}\n{}", include_str!("shader.wgsl"), include_str!("skinning.wgsl"))
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        texture: &texture::Texture,
+        mesh: SkinnedMeshData,
+        skeleton: Skeleton,
+        animation: SkinAnimation,
+        transform: Transform,
+    ) -> Self {
+        let vertex_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&mesh.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(&mesh.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skinned Instance Buffer"),
+            contents: bytemuck::cast_slice(&[transform.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let joint_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Joint Matrix Buffer"),
+            contents: bytemuck::cast_slice(&skeleton.joint_matrices(&skeleton.rest_pose())),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("skinned_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: joint_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("skinned_bind_group"),
+        });
+
+        Self {
+            enabled: false,
+            mesh,
+            skeleton,
+            animation,
+            transform,
+            vertex_buffer,
+            index_buffer,
+            instance_buffer,
+            joint_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    // A tentacle of 5 joints swaying back & forth, each a little behind the one below it
+    pub fn demo_tentacle(device: &wgpu::Device, texture: &texture::Texture, transform: Transform) -> Self {
+        const JOINTS: u32 = 5;
+        const SEGMENT: f32 = 0.8;
+        let mesh = SkinnedMeshData::tube("Tentacle", 0.25, SEGMENT * JOINTS as f32, 16, 40, JOINTS);
+
+        let rest = (0..JOINTS)
+            .map(|i| {
+                let parent = i.checked_sub(1).map(|parent| parent as usize);
+                // The root sits at the bottom, every other joint one segment up from its parent
+                let offset = if i == 0 { 0.0 } else { SEGMENT };
+                (parent, Transform::from_position(cgmath::Vector3::new(0.0, offset, 0.0)))
+            })
+            .collect::<Vec<_>>();
+        let tracks = rest
+            .iter()
+            .enumerate()
+            .map(|(i, (_, rest))| {
+                let delay = i as f32 * 0.2;
+                let keyframes = [0.0, 20.0, 0.0, -20.0, 0.0]
+                    .iter()
+                    .enumerate()
+                    .map(|(k, angle)| {
+                        let transform = Transform {
+                            rotation: cgmath::Quaternion::from_angle_z(cgmath::Deg(*angle)),
+                            ..*rest
+                        };
+                        (delay + k as f32 * 0.5, transform)
+                    })
+                    .collect();
+                Some(AnimationTrack::new(keyframes, PlaybackMode::Loop))
+            })
+            .collect();
+
+        Self::new(
+            device,
+            texture,
+            mesh,
+            Skeleton::from_rest_pose(rest),
+            SkinAnimation::new(tracks),
+            transform,
+        )
+    }
+
+    // Same model on a new device, back in its rest pose until the next update
+    pub fn recreate(&self, device: &wgpu::Device, texture: &texture::Texture) -> Self {
+        let mut model = Self::new(
+            device,
+            texture,
+            self.mesh.clone(),
+            self.skeleton.clone(),
+            self.animation.clone(),
+            self.transform,
+        );
+        model.enabled = self.enabled;
+        model
+    }
+
+    // Poses the skeleton at time `t` and uploads the new joint palette
+    pub fn update(&self, queue: &wgpu::Queue, t: f32) {
+        if !self.enabled {
+            return;
+        }
+        let pose = self.animation.sample(&self.skeleton, t);
+        queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&self.skeleton.joint_matrices(&pose)));
+    }
+
+    // Expects the skinned pipeline, with the camera, screen & lights already bound
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.mesh.indices.len() as u32, 0, 0..1);
+    }
+}
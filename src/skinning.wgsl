@@ -0,0 +1,45 @@
+// Needs shader.wgsl in front, see SkinnedModel::shader_source. The fragment shader is fs_main.
+
+// Takes the place of the model's texture bind group, with the joint palette added
+@group(2) @binding(2)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+struct SkinnedVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) joint_indices: vec4<u32>,
+    @location(4) joint_weights: vec4<f32>,
+};
+
+@vertex
+fn vs_skinned(
+    model: SkinnedVertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // The weights add up to 1, so blending the matrices blends where each joint would put the vertex
+    let skin = joint_matrices[model.joint_indices.x] * model.joint_weights.x
+        + joint_matrices[model.joint_indices.y] * model.joint_weights.y
+        + joint_matrices[model.joint_indices.z] * model.joint_weights.z
+        + joint_matrices[model.joint_indices.w] * model.joint_weights.w;
+
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    // Fine as long as the joints don't scale unevenly, like the instance's normal matrix
+    out.world_normal = normal_matrix * (skin * vec4<f32>(model.normal, 0.0)).xyz;
+    let world_position = model_matrix * skin * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
@@ -0,0 +1,197 @@
+use std::rc::Rc;
+
+use cgmath::SquareMatrix;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   A procedural sky, no cubemap needed. A full screen triangle sits on the far plane behind
+*   everything, and every pixel works out which way it's looking: the inverse view projection
+*   turns the pixel's position on the near & far planes back into world space, and the line
+*   between the two is the view ray. The ray's height picks a color between the horizon and
+*   the zenith, and the closer it points to the sun the more of the sun's color it gets.
+*
+*   It's drawn after the scene with depth testing but no depth writes. Only the pixels nothing
+*   else covered still have the cleared depth (1, or 0 with reversed depth), so those are the
+*   only ones it fills in.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct SkySettings {
+    pub horizon_color: [f32; 3],
+    pub zenith_color: [f32; 3],
+    pub sun_color: [f32; 3],
+    // Angle from the middle of the sun disc to its edge
+    pub sun_size: cgmath::Deg<f32>,
+}
+
+impl Default for SkySettings {
+    // A clear midday sky
+    fn default() -> Self {
+        Self {
+            horizon_color: [0.75, 0.85, 0.95],
+            zenith_color: [0.15, 0.35, 0.75],
+            sun_color: [1.0, 0.95, 0.8],
+            sun_size: cgmath::Deg(1.5),
+        }
+    }
+}
+
+// Has to match SkyUniform in sky.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    horizon_color: [f32; 3],
+    // Cosine of sun_size, so the shader can compare it with a dot product
+    sun_cutoff: f32,
+    zenith_color: [f32; 3],
+    _padding: u32,
+    sun_color: [f32; 3],
+    _padding2: u32,
+    // Towards the sun, the opposite of the way its light travels
+    sun_direction: [f32; 3],
+    _padding3: u32,
+}
+
+pub struct Sky {
+    pub enabled: bool,
+    // Uploaded along with the camera by update
+    pub settings: SkySettings,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Sky {
+    // Drawn in the main pass, so it takes that pass' sample count
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        settings: SkySettings,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: bytemuck::cast_slice(&[Self::to_uniform(&settings, cgmath::Matrix4::identity(), [0.0, 1.0, 0.0])]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("sky_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("sky_bind_group"),
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Sky",
+                shader: include_str!("sky.wgsl"),
+                bind_group_layouts: &[&bind_group_layout],
+                // The triangle goes wherever far is
+                vertex_entry_point: match depth {
+                    camera::DepthDirection::Standard => "vs_main",
+                    camera::DepthDirection::Reversed => "vs_reversed",
+                },
+                vertex_buffers: &[],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                // LessEqual, since the triangle is exactly as far away as the cleared depth
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: depth.compare(wgpu::CompareFunction::LessEqual),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        );
+
+        Self {
+            enabled: false,
+            settings,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Same sky for a new device, format, sample count or depth direction. It gets uploaded again on the next update.
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let mut sky = Self::new(device, pipelines, color_format, sample_count, depth, self.settings);
+        sky.enabled = self.enabled;
+        sky
+    }
+
+    fn to_uniform(settings: &SkySettings, inv_view_proj: cgmath::Matrix4<f32>, sun_direction: [f32; 3]) -> SkyUniform {
+        SkyUniform {
+            inv_view_proj: inv_view_proj.into(),
+            horizon_color: settings.horizon_color,
+            sun_cutoff: cgmath::Angle::cos(settings.sun_size),
+            zenith_color: settings.zenith_color,
+            _padding: 0,
+            sun_color: settings.sun_color,
+            _padding2: 0,
+            sun_direction,
+            _padding3: 0,
+        }
+    }
+
+    // `sun_direction` is the way the sun's light travels, like DirectionalLight::direction
+    pub fn update(&self, queue: &wgpu::Queue, camera: &camera::Camera, sun_direction: [f32; 3]) {
+        // The rays come out the same either way, the standard one has a far plane to unproject
+        let inv_view_proj = camera
+            .build_standard_view_projection_matrix()
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        let towards_sun = cgmath::InnerSpace::normalize(-cgmath::Vector3::from(sun_direction));
+        let uniform = Self::to_uniform(&self.settings, inv_view_proj, towards_sun.into());
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Call after the scene, it only fills in what's left
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,63 @@
+// Procedural sky gradient, see sky.rs
+
+struct SkyUniform {
+    inv_view_proj: mat4x4<f32>,
+    horizon_color: vec3<f32>,
+    sun_cutoff: f32,
+    zenith_color: vec3<f32>,
+    sun_color: vec3<f32>,
+    sun_direction: vec3<f32>,
+};
+@group(0) @binding(0)
+var<uniform> sky: SkyUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// One triangle big enough to cover the whole screen, right on the far plane
+fn full_screen(in_vertex_index: u32, far: f32) -> VertexOutput {
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, far, 1.0);
+    out.ndc = vec2<f32>(x, y);
+    return out;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    return full_screen(in_vertex_index, 1.0);
+}
+
+// For reversed depth, where far is 0
+@vertex
+fn vs_reversed(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    return full_screen(in_vertex_index, 0.0);
+}
+
+// Undoes the view projection for a point on the screen at the given depth
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = sky.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let ray = normalize(unproject(in.ndc, 1.0) - unproject(in.ndc, 0.0));
+
+    // Most of the change happens close to the horizon. Below it the horizon color darkens a
+    // little, which reads as haze over the ground.
+    let height = ray.y;
+    var color = mix(sky.horizon_color, sky.zenith_color, sqrt(max(height, 0.0)));
+    color *= 1.0 - 0.5 * clamp(-height * 4.0, 0.0, 1.0);
+
+    // A hard edged disc with a soft glow around it
+    let to_sun = dot(ray, sky.sun_direction);
+    let disc = smoothstep(sky.sun_cutoff - 0.0002, sky.sun_cutoff, to_sun);
+    let glow = pow(max(to_sun, 0.0), 64.0) * 0.4;
+    color += sky.sun_color * (disc + glow);
+
+    return vec4<f32>(color, 1.0);
+}
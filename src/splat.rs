@@ -0,0 +1,247 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::texture;
+
+/*
+*   Texture splatting for the terrain: grass on the flat ground, rock on the slopes and snow up
+*   high, blended where they meet. Splatting usually reads the weights from a splat map painted
+*   over the terrain, here they come from the surface itself in the shader. The world normal's
+*   y is 1 on flat ground and falls towards 0 as it steepens, past rock_slope it's rock. The
+*   height across the terrain's range (0 at the lowest point, 1 at the highest) past snow_line
+*   is snow, except where it's too steep for it to stay. Each threshold fades over `blend`
+*   either side, so there are no hard edges. Changing them is a uniform write, see
+*   set_settings.
+*
+*   The layers are one texture array, a layer each, so a single binding holds all three and
+*   the shader picks with an index. The terrain's own uvs stretch one texture across all of it,
+*   the layers are sampled with the world position's xz instead, repeating every `tiling` units.
+*
+*   Terrain is mostly seen at a glancing angle, where a texel squeezes into a sliver of a pixel
+*   and the gpu picks a small mip level to fit the narrow side, blurring the wide side along
+*   with it. Anisotropic filtering takes several samples along the squeezed direction instead,
+*   the ground stays sharp into the distance. Ctrl + M goes through how many.
+*/
+pub struct TerrainSplat {
+    settings: SplatSettings,
+    layers: texture::Texture,
+    buffer: Tracked<wgpu::Buffer>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+// Has to match SplatSettings in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SplatSettings {
+    // The terrain's lowest & highest points in world space, what snow_line is a fraction of
+    pub min_height: f32,
+    pub max_height: f32,
+    // How far up the height range the snow starts, 0..1
+    pub snow_line: f32,
+    // The normal's y below which it's rock, 1 would be everything but dead flat
+    pub rock_slope: f32,
+    // How far either side of a threshold the layers fade into each other
+    pub blend: f32,
+    // World units each layer covers before it repeats
+    pub tiling: f32,
+    _padding: [f32; 2],
+}
+
+impl Default for SplatSettings {
+    fn default() -> Self {
+        Self {
+            min_height: 0.0,
+            max_height: 1.0,
+            snow_line: 0.75,
+            rock_slope: 0.9,
+            blend: 0.04,
+            tiling: 4.0,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+impl TerrainSplat {
+    // Texels across each layer
+    const SIZE: u32 = 256;
+    // What anisotropy starts at, the most there is
+    pub const MAX_ANISOTROPY: u8 = 16;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, settings: SplatSettings) -> Self {
+        Self::with_anisotropy(device, queue, settings, Self::MAX_ANISOTROPY)
+    }
+
+    fn with_anisotropy(device: &wgpu::Device, queue: &wgpu::Queue, settings: SplatSettings, anisotropy: u8) -> Self {
+        let mut layers = texture::Texture::array_from_images_mipmapped(
+            device,
+            queue,
+            &[Self::grass(), Self::rock(), Self::snow()],
+            "Terrain Splat Layers",
+            texture::TextureKind::Color,
+        );
+        layers.set_anisotropy(device, anisotropy);
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Splat Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("terrain_splat_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &layers, &buffer);
+
+        Self {
+            settings,
+            layers,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    // Same settings & anisotropy on a (new) device
+    pub fn recreate(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::with_anisotropy(device, queue, self.settings, self.anisotropy())
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        layers: &texture::Texture,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&layers.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&layers.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("terrain_splat_bind_group"),
+        })
+    }
+
+    pub fn settings(&self) -> SplatSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: SplatSettings) {
+        self.settings = settings;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    // Fits snow_line to a terrain's heights, see terrain::Terrain::height_range
+    pub fn set_height_range(&mut self, queue: &wgpu::Queue, min: f32, max: f32) {
+        self.set_settings(
+            queue,
+            SplatSettings {
+                min_height: min,
+                // A flat terrain would divide by zero
+                max_height: max.max(min + 1e-3),
+                ..self.settings
+            },
+        );
+    }
+
+    pub fn anisotropy(&self) -> u8 {
+        self.layers.anisotropy()
+    }
+
+    // The sampler's part of the bind group, so that's made again too
+    pub fn set_anisotropy(&mut self, device: &wgpu::Device, anisotropy: u8) {
+        self.layers.set_anisotropy(device, anisotropy);
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.layers, &self.buffer);
+    }
+
+    // Each layer is a color blotched with a darker & lighter one by noise that tiles
+    fn layer(dark: [f32; 3], light: [f32; 3], scale: u32, seed: u32) -> image::DynamicImage {
+        let image = image::RgbaImage::from_fn(Self::SIZE, Self::SIZE, |x, y| {
+            let t = fractal_noise(x as f32 / Self::SIZE as f32, y as f32 / Self::SIZE as f32, scale, seed);
+            let channel = |i: usize| ((dark[i] + (light[i] - dark[i]) * t) * 255.0).round() as u8;
+            image::Rgba([channel(0), channel(1), channel(2), 255])
+        });
+        image::DynamicImage::ImageRgba8(image)
+    }
+
+    fn grass() -> image::DynamicImage {
+        Self::layer([0.13, 0.27, 0.07], [0.36, 0.52, 0.16], 8, 1)
+    }
+
+    fn rock() -> image::DynamicImage {
+        Self::layer([0.28, 0.26, 0.24], [0.58, 0.56, 0.52], 4, 2)
+    }
+
+    fn snow() -> image::DynamicImage {
+        Self::layer([0.78, 0.82, 0.88], [0.97, 0.97, 0.98], 4, 3)
+    }
+}
+
+// 0..1 from a hash of the lattice point, the same for the same point & seed
+fn lattice(x: u32, y: u32, seed: u32) -> f32 {
+    let mut h = x.wrapping_mul(0x8da6_b343) ^ y.wrapping_mul(0xd816_3841) ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+// Value noise over 0..1 with `period` cells each way, wrapping around so the texture tiles
+fn value_noise(u: f32, v: f32, period: u32, seed: u32) -> f32 {
+    let (x, y) = (u * period as f32, v * period as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    // Smoothstepped, so the cells don't show as creases
+    let fade = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (fx, fy) = (fade(x - x0), fade(y - y0));
+    let corner = |dx: u32, dy: u32| lattice((x0 as u32 + dx) % period, (y0 as u32 + dy) % period, seed);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * fx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * fx;
+    top + (bottom - top) * fy
+}
+
+// Four octaves of value_noise, each twice as fine & half as strong as the last
+fn fractal_noise(u: f32, v: f32, period: u32, seed: u32) -> f32 {
+    let (mut sum, mut weight, mut total) = (0.0, 1.0, 0.0);
+    for octave in 0..4 {
+        sum += value_noise(u, v, period << octave, seed + octave) * weight;
+        total += weight;
+        weight *= 0.5;
+    }
+    sum / total
+}
@@ -0,0 +1,454 @@
+use std::rc::Rc;
+
+use crate::batch::MeshTopology;
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance;
+use crate::lod;
+use crate::model::{self, Vertex};
+use crate::physics;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::post;
+use crate::terrain;
+use crate::texture;
+
+/*
+*   Screen space reflections, for shiny surfaces without a cubemap of the scene. Whatever's
+*   reflected has to be on the screen already, the effect only reuses pixels that were drawn.
+*
+*   The main pass only leaves colors & depth behind, so first a small G-buffer pass draws the
+*   scene again into `surfaces`: the world space normal in rgb, how reflective the surface is in
+*   a. The post effect (ssr.wgsl) then takes every reflective pixel, rebuilds where it is in
+*   the world from the depth buffer, reflects the view ray off the normal and marches along the
+*   reflection `steps` times until it's gone `max_distance`. Every step gets projected back
+*   onto the screen and compared with the depth buffer there: a step that's behind the surface
+*   the depth buffer saw, by less than `thickness`, has hit it. A few halvings of the last step
+*   find where exactly, and the color there gets mixed in by the reflectivity.
+*
+*   Thickness is the guess at how deep every surface is, the depth buffer only has their fronts.
+*   Too thin and rays slip between steps through things they should hit, too thick and rays
+*   passing behind an object hit it anyway, which smears it out sideways.
+*
+*   Rays that leave the screen, or point back at the camera, don't find anything and the pixel
+*   keeps its own color. The reflection fades out towards the edges of the screen and the end of
+*   the march so that cut off isn't a hard line.
+*
+*   The demo surface is a glossy floor under the spheres, which only gets drawn while this is
+*   on. The instances, physics boxes & terrain reflect a little too, the skinned, morphed &
+*   foliage demos aren't in the G-buffer and don't.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsrUniform {
+    // Standard depth, only the screen position & distance (w) of the steps are used
+    view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    // The view ray through a pixel is forward + right * x + up * y, x & y going from -1 to 1
+    // across the screen. Right & up are scaled by the field of view for that.
+    forward: [f32; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+    znear: f32,
+    zfar: f32,
+    // 1 when the depth buffer is reversed, see camera::DepthDirection
+    reverse_z: u32,
+    steps: u32,
+    thickness: f32,
+    max_distance: f32,
+    _padding: [f32; 2],
+}
+
+// How reflective one kind of surface is, written into the G-buffer's alpha
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SurfaceUniform {
+    reflectivity: f32,
+    _padding: [f32; 3],
+}
+
+pub struct Ssr {
+    pub enabled: bool,
+    uniform: SsrUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    // Normal & reflectivity of whatever's closest, cleared to not reflective
+    surfaces: texture::Texture,
+    // The G-buffer pass does its own depth test, like the velocity pass
+    depth: texture::Texture,
+    depth_direction: camera::DepthDirection,
+    surface_pipeline: Rc<wgpu::RenderPipeline>,
+    // For the terrain when its chunks are strips
+    strip_surface_pipeline: Rc<wgpu::RenderPipeline>,
+    // Kept alive for the bind groups
+    _surface_buffers: [Tracked<wgpu::Buffer>; 2],
+    objects_bind_group: wgpu::BindGroup,
+    floor_bind_group: wgpu::BindGroup,
+    floor: model::Mesh,
+    floor_instance: Tracked<wgpu::Buffer>,
+    effect_layout: wgpu::BindGroupLayout,
+    effect_bind_group: wgpu::BindGroup,
+    effect_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Ssr {
+    const SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const OBJECT_REFLECTIVITY: f32 = 0.15;
+    const FLOOR_REFLECTIVITY: f32 = 0.6;
+    // Right under the spheres in the grid, which are 0.5 across
+    const FLOOR_Y: f32 = -0.5;
+    const FLOOR_SIZE: f32 = 40.0;
+    pub const DEFAULT_STEPS: u32 = 32;
+    pub const DEFAULT_THICKNESS: f32 = 0.5;
+    const MAX_DISTANCE: f32 = 15.0;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        depth_direction: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        camera: &camera::Camera,
+    ) -> Self {
+        let surfaces = texture::Texture::create_render_target(device, config, Self::SURFACE_FORMAT, "ssr_surfaces");
+        let depth = texture::Texture::create_depth_texture(device, config, "ssr_depth_texture");
+
+        let surface_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("ssr_surface_bind_group_layout"),
+        });
+        let surface = |reflectivity: f32, label: &str| {
+            let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&[SurfaceUniform {
+                    reflectivity,
+                    _padding: [0.0; 3],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &surface_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+                label: Some(label),
+            });
+            (buffer, bind_group)
+        };
+        let (objects_buffer, objects_bind_group) = surface(Self::OBJECT_REFLECTIVITY, "SSR Objects Surface");
+        let (floor_buffer, floor_bind_group) = surface(Self::FLOOR_REFLECTIVITY, "SSR Floor Surface");
+
+        let surface_pipeline = |topology: MeshTopology| {
+            pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label: "SSR Surfaces",
+                    shader: include_str!("ssr_surfaces.wgsl"),
+                    bind_group_layouts: &[screen_layout, camera_layout, &surface_layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                    fragment_entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Self::SURFACE_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    primitive: wgpu::PrimitiveState {
+                        topology: topology.primitive(),
+                        strip_index_format: topology.strip_index_format(),
+                        cull_mode: Some(wgpu::Face::Back),
+                        ..Default::default()
+                    },
+                    // Drawn with the scene's camera, so its depth runs the same way
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: texture::Texture::DEPTH_FORMAT,
+                        depth_write_enabled: true,
+                        depth_compare: depth_direction.compare(wgpu::CompareFunction::Less),
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            )
+        };
+
+        // A square facing up, counter clockwise seen from above
+        let floor = model::Mesh::new(
+            device,
+            "Floor",
+            [(-0.5, 0.5, 0.0, 1.0), (0.5, 0.5, 1.0, 1.0), (0.5, -0.5, 1.0, 0.0), (-0.5, -0.5, 0.0, 0.0)]
+                .map(|(x, z, u, v)| model::ModelVertex {
+                    position: [x, 0.0, z],
+                    tex_coords: [u, v],
+                    normal: [0.0, 1.0, 0.0],
+                })
+                .to_vec(),
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        let floor_transform = instance::Transform {
+            position: cgmath::Vector3::new(0.0, Self::FLOOR_Y, 0.0),
+            scale: cgmath::Vector3::new(Self::FLOOR_SIZE, 1.0, Self::FLOOR_SIZE),
+            ..Default::default()
+        };
+        let floor_instance = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Floor Instance Buffer"),
+            contents: bytemuck::cast_slice(&[floor_transform.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let uniform = SsrUniform {
+            steps: Self::DEFAULT_STEPS,
+            thickness: Self::DEFAULT_THICKNESS,
+            max_distance: Self::MAX_DISTANCE,
+            ..Self::camera_uniform(camera)
+        };
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSR Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let effect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ssr_bind_group_layout"),
+        });
+        let effect_bind_group = Self::create_effect_bind_group(device, &effect_layout, &buffer, &surfaces);
+        let effect_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Screen Space Reflections",
+            include_str!("ssr.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &effect_layout],
+            post_chain.format(),
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            surfaces,
+            depth,
+            depth_direction,
+            surface_pipeline: surface_pipeline(MeshTopology::TriangleList),
+            strip_surface_pipeline: surface_pipeline(MeshTopology::TriangleStrip),
+            _surface_buffers: [objects_buffer, floor_buffer],
+            objects_bind_group,
+            floor_bind_group,
+            floor,
+            floor_instance,
+            effect_layout,
+            effect_bind_group,
+            effect_pipeline,
+        }
+    }
+
+    fn create_effect_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        surfaces: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&surfaces.view),
+                },
+            ],
+            label: Some("ssr_bind_group"),
+        })
+    }
+
+    // Rebuilds the gpu resources on a (new) device, for a new post chain format or depth
+    // direction, keeping the current settings
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        depth_direction: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        camera: &camera::Camera,
+    ) -> Self {
+        let mut ssr = Self::new(device, pipelines, config, depth_direction, screen_layout, camera_layout, post_chain, camera);
+        ssr.enabled = self.enabled;
+        ssr.uniform = SsrUniform { reverse_z: ssr.uniform.reverse_z, ..self.uniform };
+        ssr.write_uniform(queue);
+        ssr
+    }
+
+    // The G-buffer has to match the screen
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.surfaces = texture::Texture::create_render_target(device, config, Self::SURFACE_FORMAT, "ssr_surfaces");
+        self.depth = texture::Texture::create_depth_texture(device, config, "ssr_depth_texture");
+        self.effect_bind_group = Self::create_effect_bind_group(device, &self.effect_layout, &self.buffer, &self.surfaces);
+    }
+
+    // Everything in the uniform that comes from the camera, the rest zeroed
+    fn camera_uniform(camera: &camera::Camera) -> SsrUniform {
+        use cgmath::InnerSpace;
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        let half_height = (cgmath::Rad::from(cgmath::Deg(camera.fovy)).0 * 0.5).tan();
+        SsrUniform {
+            view_proj: camera.build_standard_view_projection_matrix().into(),
+            eye: camera.eye.to_homogeneous().into(),
+            forward: forward.extend(0.0).into(),
+            right: (right * half_height * camera.aspect).extend(0.0).into(),
+            up: (up * half_height).extend(0.0).into(),
+            znear: camera.znear,
+            zfar: camera.zfar,
+            reverse_z: (camera.depth == camera::DepthDirection::Reversed) as u32,
+            steps: 0,
+            thickness: 0.0,
+            max_distance: 0.0,
+            _padding: [0.0; 2],
+        }
+    }
+
+    // Call once per frame while enabled, the rays are rebuilt from where the camera is
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &camera::Camera) {
+        self.uniform = SsrUniform {
+            steps: self.uniform.steps,
+            thickness: self.uniform.thickness,
+            max_distance: self.uniform.max_distance,
+            ..Self::camera_uniform(camera)
+        };
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.uniform.steps
+    }
+
+    // More steps find thinner things & get closer to the hit before refining, at a linear cost
+    pub fn set_steps(&mut self, queue: &wgpu::Queue, steps: u32) {
+        self.uniform.steps = steps.max(1);
+        self.write_uniform(queue);
+    }
+
+    pub fn thickness(&self) -> f32 {
+        self.uniform.thickness
+    }
+
+    // How far behind a surface a step can be and still count as hitting it, in world units
+    pub fn set_thickness(&mut self, queue: &wgpu::Queue, thickness: f32) {
+        self.uniform.thickness = thickness.max(0.0);
+        self.write_uniform(queue);
+    }
+
+    // The floor goes in the main pass with the scene's own pipeline. Expects that and its bind
+    // groups to already be set.
+    pub fn draw_floor<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.floor.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.floor_instance.slice(..));
+        render_pass.set_index_buffer(self.floor.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.floor.num_elements, 0, 0..1);
+    }
+
+    // Fills in the G-buffer, needs to happen before the post chain runs
+    pub fn render_surfaces(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        screen_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        model: &lod::LodModel,
+        physics: &physics::PhysicsDemo,
+        terrain: Option<&terrain::Terrain>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSR Surface Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.surfaces.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_direction.far()),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.surface_pipeline);
+        render_pass.set_bind_group(0, screen_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.objects_bind_group, &[]);
+        model.draw(&mut render_pass);
+        physics.draw(&mut render_pass);
+        if let Some(terrain) = terrain.filter(|terrain| terrain.topology() == MeshTopology::TriangleStrip) {
+            render_pass.set_pipeline(&self.strip_surface_pipeline);
+            terrain.draw(&mut render_pass);
+            render_pass.set_pipeline(&self.surface_pipeline);
+        } else if let Some(terrain) = terrain {
+            terrain.draw(&mut render_pass);
+        }
+        render_pass.set_bind_group(2, &self.floor_bind_group, &[]);
+        self.draw_floor(&mut render_pass);
+    }
+}
+
+impl post::PostEffect for Ssr {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.effect_pipeline);
+        render_pass.set_bind_group(2, &self.effect_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,138 @@
+// Screen space reflections post effect, see ssr.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+@group(1) @binding(2)
+var t_depth: texture_depth_2d;
+
+struct SsrUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+    forward: vec4<f32>,
+    right: vec4<f32>,
+    up: vec4<f32>,
+    znear: f32,
+    zfar: f32,
+    reverse_z: u32,
+    steps: u32,
+    thickness: f32,
+    max_distance: f32,
+};
+@group(2) @binding(0)
+var<uniform> ssr: SsrUniform;
+// Normal in rgb, reflectivity in a
+@group(2) @binding(1)
+var t_surfaces: texture_2d<f32>;
+
+// How many times the step that hit gets halved to find where exactly
+const REFINE_STEPS: i32 = 4;
+// How close to the edge of the screen (in uv) the reflection starts fading out
+const EDGE_FADE: f32 = 0.1;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    // Texture coordinates have y pointing down
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+// Turns the non-linear [0, 1] depth buffer value back into a distance from the camera
+fn linearize_depth(depth: f32) -> f32 {
+    // Reversed depth is znear / distance, with nothing past it (0) counting as zfar
+    if ssr.reverse_z != 0u {
+        return min(ssr.znear / max(depth, 1e-30), ssr.zfar);
+    }
+    return ssr.znear * ssr.zfar / (ssr.zfar - depth * (ssr.zfar - ssr.znear));
+}
+
+fn scene_distance(uv: vec2<f32>) -> f32 {
+    let max_pixel = vec2<i32>(screen.resolution) - vec2<i32>(1, 1);
+    let coords = clamp(vec2<i32>(uv * screen.resolution), vec2<i32>(0, 0), max_pixel);
+    return linearize_depth(textureLoad(t_depth, coords, 0));
+}
+
+// Where a point in the world lands on the screen in xy, and how far in front of the camera it
+// is in z
+fn project(position: vec3<f32>) -> vec3<f32> {
+    let clip = ssr.view_proj * vec4<f32>(position, 1.0);
+    let ndc = clip.xy / clip.w;
+    return vec3<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5, clip.w);
+}
+
+// How far behind the surface the depth buffer saw a projected point is, negative in front
+fn behind(point: vec3<f32>) -> f32 {
+    return point.z - scene_distance(point.xy);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(t_color, s_color, in.uv);
+    let coords = vec2<i32>(in.clip_position.xy);
+    let surface = textureLoad(t_surfaces, coords, 0);
+    if surface.a <= 0.0 {
+        return scene;
+    }
+
+    // Back out into the world along the view ray, which is 1 long along the view direction
+    let ndc = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    let view_ray = ssr.forward.xyz + ssr.right.xyz * ndc.x + ssr.up.xyz * ndc.y;
+    let normal = normalize(surface.xyz);
+    let position = ssr.eye.xyz + view_ray * scene_distance(in.uv);
+    let direction = reflect(normalize(view_ray), normal);
+    // Towards the camera the reflection would be of things behind it, which aren't on screen
+    if dot(direction, ssr.forward.xyz) < -0.5 {
+        return scene;
+    }
+
+    let step = direction * (ssr.max_distance / f32(ssr.steps));
+    // Off the surface a little, or the first step finds the surface it started on
+    var point = position + normal * 0.02;
+    for (var i = 1u; i <= ssr.steps; i++) {
+        let previous = point;
+        point += step;
+        let projected = project(point);
+        // Left the screen or went behind the camera, nothing to reflect
+        if projected.z <= ssr.znear || any(projected.xy < vec2<f32>(0.0)) || any(projected.xy > vec2<f32>(1.0)) {
+            break;
+        }
+        let depth_behind = behind(projected);
+        if depth_behind > 0.0 && depth_behind < ssr.thickness {
+            // Somewhere between the last step & this one, halve it a few times
+            var front = previous;
+            var back = point;
+            for (var j = 0; j < REFINE_STEPS; j++) {
+                let middle = (front + back) * 0.5;
+                if behind(project(middle)) > 0.0 {
+                    back = middle;
+                } else {
+                    front = middle;
+                }
+            }
+            let hit = project(back).xy;
+            let edge = min(min(hit.x, 1.0 - hit.x), min(hit.y, 1.0 - hit.y));
+            let fade = smoothstep(0.0, EDGE_FADE, edge) * (1.0 - f32(i) / f32(ssr.steps));
+            let reflected = textureSampleLevel(t_color, s_color, hit, 0.0).rgb;
+            return vec4<f32>(mix(scene.rgb, reflected, surface.a * fade), scene.a);
+        }
+    }
+    return scene;
+}
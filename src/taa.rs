@@ -0,0 +1,297 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::PipelineCache;
+use crate::{post, texture, velocity};
+
+/*
+*   Temporal anti-aliasing. Every frame the projection gets nudged by a different fraction of a
+*   pixel, so over a few frames each pixel ends up sampling several points inside itself, same
+*   as MSAA does within a single frame. Blending every frame into a running history averages
+*   those samples together.
+*
+*   Things move though, so the history can't be read at the same pixel. The velocity buffer
+*   says where the surface under each pixel was last frame, and that's where the history gets
+*   read from ("reprojection"). Where that lands off the screen the history has nothing for
+*   this pixel and only the current frame is used.
+*
+*   Reprojection isn't perfect: something that was hidden last frame gets whatever was in front
+*   of it, and that smears into ghosts. The history gets clamped to the range of colors around
+*   the pixel in the current frame, which throws away anything that obviously doesn't belong.
+*
+*   The resolve pass writes into one of two history textures and reads the other, and they swap
+*   every frame. As a post effect it then just copies the newest history along the chain.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    // How much of the current frame goes into the result, 1 ignores the history
+    blend: f32,
+    _padding: [u32; 3],
+}
+
+pub struct Taa {
+    pub enabled: bool,
+    history: [texture::Texture; 2],
+    // The post chain's, the resolve reads from its scene target
+    format: wgpu::TextureFormat,
+    // The history written this frame, the other one holds last frame's
+    current: usize,
+    // False until there's a frame in the history that lines up with the screen
+    history_valid: bool,
+    frame: u32,
+    buffer: Tracked<wgpu::Buffer>,
+    resolve_layout: wgpu::BindGroupLayout,
+    resolve_pipeline: Rc<wgpu::RenderPipeline>,
+    output_layout: wgpu::BindGroupLayout,
+    output_bind_groups: [wgpu::BindGroup; 2],
+    output_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Taa {
+    // Share of the current frame once there's a history, lower is smoother but ghosts more
+    const BLEND: f32 = 0.1;
+    // Jitter offsets before the sequence repeats
+    const JITTER_SAMPLES: u32 = 8;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Buffer"),
+            contents: bytemuck::cast_slice(&[TaaUniform {
+                blend: 1.0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // This frame
+                texture_entry(0),
+                sampler_entry(1),
+                // Last frame's result
+                texture_entry(2),
+                // The velocity buffer
+                texture_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("taa_resolve_bind_group_layout"),
+        });
+        let output_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), sampler_entry(1)],
+            label: Some("taa_output_bind_group_layout"),
+        });
+
+        let resolve_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "TAA Resolve",
+            include_str!("taa.wgsl"),
+            &[&resolve_layout],
+            post_chain.format(),
+        );
+        let output_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "TAA Output",
+            include_str!("taa_output.wgsl"),
+            &[screen_layout, &post_chain.input_layout, &output_layout],
+            post_chain.format(),
+        );
+
+        let history = Self::create_history(device, config, post_chain.format());
+        let output_bind_groups = Self::create_output_bind_groups(device, &output_layout, &history);
+
+        Self {
+            enabled: false,
+            history,
+            format: post_chain.format(),
+            current: 0,
+            history_valid: false,
+            frame: 0,
+            buffer,
+            resolve_layout,
+            resolve_pipeline,
+            output_layout,
+            output_bind_groups,
+            output_pipeline,
+        }
+    }
+
+    fn create_history(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> [texture::Texture; 2] {
+        [
+            texture::Texture::create_render_target(device, config, format, "taa_history_a"),
+            texture::Texture::create_render_target(device, config, format, "taa_history_b"),
+        ]
+    }
+
+    fn create_output_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        history: &[texture::Texture; 2],
+    ) -> [wgpu::BindGroup; 2] {
+        history.each_ref().map(|texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+                label: Some("taa_output_bind_group"),
+            })
+        })
+    }
+
+    // The old history doesn't fit the new size, so it starts over
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.history = Self::create_history(device, config, self.format);
+        self.output_bind_groups = Self::create_output_bind_groups(device, &self.output_layout, &self.history);
+        self.reset();
+    }
+
+    // Forgets the history, e.g. after the camera jumped somewhere else or TAA was off for a while
+    pub fn reset(&mut self) {
+        self.history_valid = false;
+    }
+
+    // The i-th number of the Halton sequence in the given base: 1/2, 1/4, 3/4, 1/8... for base 2.
+    // Consecutive values spread out evenly over 0..1 without ever clumping together.
+    fn halton(mut index: u32, base: u32) -> f32 {
+        let mut result = 0.0;
+        let mut fraction = 1.0;
+        while index > 0 {
+            fraction /= base as f32;
+            result += fraction * (index % base) as f32;
+            index /= base;
+        }
+        result
+    }
+
+    /*
+    *   Call once per frame before drawing, it swaps the histories around and returns this
+    *   frame's offset in clip space. Halton(2, 3) gives points spread over the pixel, which get
+    *   shifted to -0.5..0.5 pixels around its center. A pixel is 2 / width wide in clip space
+    *   since that goes from -1 to 1.
+    */
+    pub fn next_frame(&mut self, queue: &wgpu::Queue, size: winit::dpi::PhysicalSize<u32>) -> [f32; 2] {
+        self.current = 1 - self.current;
+        let blend = if self.history_valid { Self::BLEND } else { 1.0 };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[TaaUniform { blend, _padding: [0; 3] }]));
+        self.history_valid = true;
+
+        self.frame = self.frame % Self::JITTER_SAMPLES + 1;
+        let x = Self::halton(self.frame, 2) - 0.5;
+        let y = Self::halton(self.frame, 3) - 0.5;
+        [x * 2.0 / size.width.max(1) as f32, y * 2.0 / size.height.max(1) as f32]
+    }
+
+    /*
+    *   Blends `scene` (this frame, drawn with the jittered camera) into the history. Run it
+    *   after the velocity pass and before the post chain, which then picks the result up.
+    */
+    pub fn resolve(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::TextureView,
+        velocity: &velocity::VelocityBuffer,
+    ) {
+        let previous = 1 - self.current;
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.resolve_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(scene),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.history[previous].sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.history[previous].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&velocity.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("taa_resolve_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("TAA Resolve Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.history[self.current].view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.resolve_pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl post::PostEffect for Taa {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // The chain's input is the unresolved scene, what goes on is this frame's history
+    fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.output_pipeline);
+        render_pass.set_bind_group(2, &self.output_bind_groups[self.current], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,61 @@
+// Temporal anti-aliasing resolve, see taa.rs
+
+@group(0) @binding(0)
+var t_current: texture_2d<f32>;
+@group(0) @binding(1)
+var s_linear: sampler;
+@group(0) @binding(2)
+var t_history: texture_2d<f32>;
+@group(0) @binding(3)
+var t_velocity: texture_2d<f32>;
+
+struct TaaUniform {
+    blend: f32,
+};
+@group(0) @binding(4)
+var<uniform> taa: TaaUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let last_pixel = vec2<i32>(textureDimensions(t_current)) - vec2<i32>(1);
+    let current = textureLoad(t_current, pixel, 0).rgb;
+
+    // The range of colors in the 3x3 pixels around this one. History outside of it most likely
+    // belongs to something that isn't here anymore.
+    var lo = current;
+    var hi = current;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let neighbour = textureLoad(t_current, clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), last_pixel), 0).rgb;
+            lo = min(lo, neighbour);
+            hi = max(hi, neighbour);
+        }
+    }
+
+    // Where this pixel's surface was last frame
+    let velocity = textureLoad(t_velocity, pixel, 0).xy;
+    let previous_uv = in.uv - velocity;
+    let history = clamp(textureSample(t_history, s_linear, previous_uv).rgb, lo, hi);
+
+    // Off the screen last frame, so there's no history for it
+    let on_screen = all(previous_uv >= vec2<f32>(0.0)) && all(previous_uv <= vec2<f32>(1.0));
+    let blend = select(1.0, taa.blend, on_screen);
+    return vec4<f32>(mix(history, current, blend), 1.0);
+}
@@ -0,0 +1,172 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::PipelineCache;
+use crate::{post, screen};
+
+/*
+*   Tearing test for the present mode lesson: a white bar racing across a black screen. When a
+*   frame is swapped in while the display is halfway through showing the last one, the top of
+*   the screen has the bar in one place and the bottom in another, and it looks broken in two.
+*
+*   With Fifo (the default) that never happens. Immediate tears all the time, and Mailbox
+*   shouldn't, but renders frames that never get shown. Start with --present-mode to compare,
+*   a faster bar makes the tears easier to spot.
+*
+*   Like the test pattern it covers the whole screen and gets drawn after everything else.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TearingUniform {
+    // Seconds since the test was turned on
+    time: f32,
+    // Pixels per second
+    speed: f32,
+    // Pixels across
+    bar_width: f32,
+    _padding: f32,
+}
+
+pub struct TearingTest {
+    enabled: bool,
+    uniform: TearingUniform,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl TearingTest {
+    // Pixels per second when nothing else was asked for, a good 2-3 seconds across most screens
+    pub const DEFAULT_SPEED: f32 = 800.0;
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        speed: f32,
+    ) -> Self {
+        let uniform = TearingUniform {
+            time: 0.0,
+            speed,
+            bar_width: 64.0,
+            _padding: 0.0,
+        };
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tearing Test Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("tearing_test_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("tearing_test_bind_group"),
+        });
+
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Tearing Test",
+            include_str!("tearing_test.wgsl"),
+            &[screen_layout, &bind_group_layout],
+            color_format,
+        );
+
+        Self {
+            enabled: false,
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new surface format, the bar carries
+    // on from where it was
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let mut test = Self::new(device, pipelines, screen_layout, color_format, self.uniform.speed);
+        test.enabled = self.enabled;
+        test.uniform = self.uniform;
+        test.write_uniform(queue);
+        test
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // The bar starts over from the left edge every time it's turned on
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.uniform.time = 0.0;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.uniform.speed
+    }
+
+    pub fn set_speed(&mut self, queue: &wgpu::Queue, speed: f32) {
+        self.uniform.speed = speed.max(0.0);
+        self.write_uniform(queue);
+    }
+
+    // Moves the bar on by `dt` of real time. Pausing the animations doesn't stop it, a still bar
+    // can't tear.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: instant::Duration) {
+        if !self.enabled {
+            return;
+        }
+        self.uniform.time += dt.as_secs_f32();
+        self.write_uniform(queue);
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        if !self.enabled {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tearing Test Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,34 @@
+// A white bar scrolling across a black screen, see tearing_test.rs
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+struct TearingUniform {
+    time: f32,
+    speed: f32,
+    bar_width: f32,
+};
+@group(1) @binding(0)
+var<uniform> tearing: TearingUniform;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // The bar's left edge, wrapping around once it's gone off the right side. Counting the bar's
+    // width in the distance lets it slide in from the left rather than pop in.
+    let distance = screen.resolution.x + tearing.bar_width;
+    let left = (tearing.time * tearing.speed) % distance - tearing.bar_width;
+    let inside = position.x >= left && position.x < left + tearing.bar_width;
+    return vec4<f32>(vec3<f32>(select(0.0, 1.0, inside)), 1.0);
+}
@@ -0,0 +1,376 @@
+use cgmath::InnerSpace;
+
+use crate::batch::{BatchDrawMode, MeshBatch, MeshTopology};
+use crate::context::GpuContext;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::Transform;
+use crate::model;
+use crate::noise;
+
+/*
+*   Terrain built from a heightmap. The heightmap is a grid of heights in 0..1-ish, either
+*   generated with the NoiseGenerator and read back from the gpu, or loaded from a grayscale
+*   image. We lay a flat grid of vertices over it, push every vertex up by the height under
+*   it and work out the normals from the slope between the neighbouring heights.
+*
+*   Big grids are split into chunks of at most CHUNK_QUADS x CHUNK_QUADS quads, each its own
+*   mesh. That keeps the index ranges small and is what you'd cull or stream terrain by later
+*   on. They all share a pipeline, so they're packed into one MeshBatch and drawn in as few
+*   calls as the device allows (see batch.rs). The normals come from the heightmap rather than
+*   the chunk's own triangles, so they match up along the seams.
+*/
+pub struct Heightmap {
+    pub width: u32,
+    pub height: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn from_heights(width: u32, height: u32, heights: Vec<f32>) -> Self {
+        assert_eq!(heights.len(), (width * height) as usize, "heightmap size doesn't match its heights");
+        Self { width, height, heights }
+    }
+
+    // Black is 0, white is 1
+    pub fn from_image(bytes: &[u8]) -> image::ImageResult<Self> {
+        let image = image::load_from_memory(bytes)?.into_luma16();
+        let heights = image.pixels().map(|p| p.0[0] as f32 / u16::MAX as f32).collect();
+        Ok(Self::from_heights(image.width(), image.height(), heights))
+    }
+
+    // Fills a buffer with noise on the gpu and reads it back
+    pub async fn from_noise(
+        ctx: &GpuContext,
+        generator: &noise::NoiseGenerator,
+        width: u32,
+        height: u32,
+        params: &noise::NoiseParams,
+    ) -> Self {
+        let size = (width * height) as wgpu::BufferAddress * std::mem::size_of::<f32>() as wgpu::BufferAddress;
+        let buffer = ctx.device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Heightmap Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let mut encoder = ctx.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Heightmap Encoder"),
+        });
+        generator.fill_buffer(&ctx.device, &mut encoder, &ctx.queue, &buffer, width, height, params);
+        ctx.queue.submit(std::iter::once(encoder.finish()));
+
+        let bytes = ctx.read_buffer_async(&buffer, size).await;
+        Self::from_heights(width, height, bytemuck::pod_collect_to_vec(&bytes))
+    }
+
+    // Bilinear lookup, u & v in 0..1 across the whole map
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let y = v.clamp(0.0, 1.0) * (self.height - 1) as f32;
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x.fract(), y.fract());
+        let at = |x: u32, y: u32| self.heights[(y * self.width + x) as usize];
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * tx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * tx;
+        top + (bottom - top) * ty
+    }
+}
+
+/*
+*   How the chunks' grids get turned into triangles, for comparing the index buffer sizes.
+*
+*   List              two triangles per quad, 6 indices each.
+*   DegenerateStrips  one strip per row of quads, 2 indices per quad plus 2 to start the row.
+*                     Rows are joined by repeating the last index of one & the first of the
+*                     next, the triangles that makes in between have two corners in the same
+*                     place, no area, and the gpu throws them away. That's the trick from
+*                     before primitive restart, it works with any index format & any api.
+*   RestartStrips     the same rows, ended by MeshBatch::STRIP_RESTART instead. One index per
+*                     join rather than two, and nothing for the gpu to throw away.
+*
+*   Both strips take about a third of the list's indices, the vertices are the same in all of
+*   them. Don't expect them to draw much faster though: gpus keep recently shaded vertices in a
+*   cache, so a list doesn't run the vertex shader much more often than a strip does. What's
+*   saved is index memory & the bandwidth to read it.
+*/
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TerrainIndices {
+    #[default]
+    List,
+    DegenerateStrips,
+    RestartStrips,
+}
+
+impl TerrainIndices {
+    pub fn next(self) -> Self {
+        match self {
+            TerrainIndices::List => TerrainIndices::DegenerateStrips,
+            TerrainIndices::DegenerateStrips => TerrainIndices::RestartStrips,
+            TerrainIndices::RestartStrips => TerrainIndices::List,
+        }
+    }
+
+    pub fn topology(self) -> MeshTopology {
+        match self {
+            TerrainIndices::List => MeshTopology::TriangleList,
+            TerrainIndices::DegenerateStrips | TerrainIndices::RestartStrips => MeshTopology::TriangleStrip,
+        }
+    }
+}
+
+impl std::fmt::Display for TerrainIndices {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TerrainIndices::List => write!(f, "triangle list"),
+            TerrainIndices::DegenerateStrips => write!(f, "strips joined by degenerate triangles"),
+            TerrainIndices::RestartStrips => write!(f, "strips with primitive restart"),
+        }
+    }
+}
+
+// What colors the terrain. Each is a pipeline of its own with its own @group(2).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TerrainShading {
+    // The procedural texture, like the rest of the scene
+    #[default]
+    Textured,
+    // By height through a colormap, see colormap.rs
+    Colormap,
+    // Grass, rock & snow by slope & height, see splat.rs
+    Splat,
+}
+
+impl std::fmt::Display for TerrainShading {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TerrainShading::Textured => write!(f, "textured"),
+            TerrainShading::Colormap => write!(f, "colormap"),
+            TerrainShading::Splat => write!(f, "splatted"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TerrainSettings {
+    // Quads along each side of the grid
+    pub resolution: u32,
+    // Width & depth of the terrain in world units
+    pub size: f32,
+    // World units a height of 1 turns into
+    pub height_scale: f32,
+    // How many times the texture repeats across the terrain
+    pub texture_repeat: f32,
+    pub indices: TerrainIndices,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 256,
+            size: 80.0,
+            height_scale: 4.0,
+            texture_repeat: 8.0,
+            indices: TerrainIndices::List,
+        }
+    }
+}
+
+pub struct Terrain {
+    pub enabled: bool,
+    chunks: MeshBatch,
+    // What it was built with, the chunks' sizes come from it
+    settings: TerrainSettings,
+    // The terrain only has the one instance, but the pipeline wants an instance buffer
+    transform: Transform,
+    instance_buffer: Tracked<wgpu::Buffer>,
+}
+
+impl Terrain {
+    const CHUNK_QUADS: u32 = 64;
+
+    pub fn new(
+        device: &wgpu::Device,
+        heightmap: &Heightmap,
+        settings: &TerrainSettings,
+        transform: Transform,
+        draw_mode: BatchDrawMode,
+    ) -> Self {
+        let chunk_count = settings.resolution.div_ceil(Self::CHUNK_QUADS);
+        let chunks = (0..chunk_count)
+            .flat_map(|z| (0..chunk_count).map(move |x| (x, z)))
+            .map(|(x, z)| Self::chunk_data(heightmap, settings, x, z))
+            .collect();
+        let chunks = MeshBatch::new(device, "Terrain", chunks, 1, draw_mode, settings.indices.topology());
+        Self::from_chunks(device, chunks, *settings, transform)
+    }
+
+    fn from_chunks(device: &wgpu::Device, chunks: MeshBatch, settings: TerrainSettings, transform: Transform) -> Self {
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Instance Buffer"),
+            contents: bytemuck::cast_slice(&[transform.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self {
+            enabled: true,
+            chunks,
+            settings,
+            transform,
+            instance_buffer,
+        }
+    }
+
+    // Same terrain on a new device
+    pub fn recreate(&self, device: &wgpu::Device) -> Self {
+        let mut terrain = Self::from_chunks(device, self.chunks.recreate(device), self.settings, self.transform);
+        terrain.enabled = self.enabled;
+        terrain
+    }
+
+    // Where a chunk starts & how many quads it covers each way, the last one in each row may
+    // be smaller
+    fn chunk_quads(settings: &TerrainSettings, chunk_x: u32, chunk_z: u32) -> ((u32, u32), (u32, u32)) {
+        let resolution = settings.resolution.max(1);
+        let start = (chunk_x * Self::CHUNK_QUADS, chunk_z * Self::CHUNK_QUADS);
+        let quads = (
+            Self::CHUNK_QUADS.min(resolution - start.0),
+            Self::CHUNK_QUADS.min(resolution - start.1),
+        );
+        (start, quads)
+    }
+
+    fn chunk_data(heightmap: &Heightmap, settings: &TerrainSettings, chunk_x: u32, chunk_z: u32) -> model::MeshData {
+        let resolution = settings.resolution.max(1);
+        let (start, quads) = Self::chunk_quads(settings, chunk_x, chunk_z);
+
+        let height_at = |u: f32, v: f32| heightmap.sample(u, v) * settings.height_scale;
+        // Distance between neighbouring vertices, in uv & world units
+        let step_uv = 1.0 / resolution as f32;
+        let step_world = settings.size * step_uv;
+
+        let mut vertices = Vec::with_capacity(((quads.0 + 1) * (quads.1 + 1)) as usize);
+        for z in 0..=quads.1 {
+            for x in 0..=quads.0 {
+                let u = (start.0 + x) as f32 * step_uv;
+                let v = (start.1 + z) as f32 * step_uv;
+                // The slope in each direction from the heights on either side
+                let dx = (height_at(u + step_uv, v) - height_at(u - step_uv, v)) / (2.0 * step_world);
+                let dz = (height_at(u, v + step_uv) - height_at(u, v - step_uv)) / (2.0 * step_world);
+                let normal = cgmath::Vector3::new(-dx, 1.0, -dz).normalize();
+                vertices.push(model::ModelVertex {
+                    position: [(u - 0.5) * settings.size, height_at(u, v), (v - 0.5) * settings.size],
+                    tex_coords: [u * settings.texture_repeat, v * settings.texture_repeat],
+                    normal: normal.into(),
+                });
+            }
+        }
+
+        model::MeshData {
+            name: format!("Terrain Chunk {},{}", chunk_x, chunk_z),
+            vertices,
+            indices: Self::chunk_indices(quads, settings.indices),
+        }
+    }
+
+    // Counter clockwise seen from above, whichever way they're put together
+    fn chunk_indices(quads: (u32, u32), kind: TerrainIndices) -> Vec<u32> {
+        let row = quads.0 + 1;
+        if kind == TerrainIndices::List {
+            // Two triangles per quad
+            return (0..quads.1)
+                .flat_map(|z| (0..quads.0).map(move |x| (x, z)))
+                .flat_map(|(x, z)| {
+                    let top_left = z * row + x;
+                    let bottom_left = top_left + row;
+                    [top_left, bottom_left, top_left + 1, top_left + 1, bottom_left, bottom_left + 1]
+                })
+                .collect();
+        }
+
+        // Zig-zagging down the row, top then bottom. Each row has an even number of indices, so
+        // the next one starts out wound the right way round too.
+        let mut indices = Vec::with_capacity((quads.1 * (2 * row + 2)) as usize);
+        for z in 0..quads.1 {
+            if z > 0 {
+                match kind {
+                    TerrainIndices::DegenerateStrips => {
+                        let last = *indices.last().unwrap();
+                        indices.extend_from_slice(&[last, z * row]);
+                    }
+                    _ => indices.push(MeshBatch::STRIP_RESTART),
+                }
+            }
+            for x in 0..row {
+                let top = z * row + x;
+                indices.extend_from_slice(&[top, top + row]);
+            }
+        }
+        indices
+    }
+
+    // Puts the same chunks together a different way, the vertices stay as they are
+    pub fn set_indices(&mut self, device: &wgpu::Device, kind: TerrainIndices) {
+        let chunk_count = self.settings.resolution.div_ceil(Self::CHUNK_QUADS);
+        // Made row by row in new
+        let chunks = self
+            .chunks
+            .meshes()
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let (_, quads) = Self::chunk_quads(&self.settings, i as u32 % chunk_count, i as u32 / chunk_count);
+                model::MeshData {
+                    name: chunk.name.clone(),
+                    vertices: chunk.vertices.clone(),
+                    indices: Self::chunk_indices(quads, kind),
+                }
+            })
+            .collect();
+        self.settings.indices = kind;
+        self.chunks = MeshBatch::new(device, "Terrain", chunks, 1, self.chunks.mode, kind.topology());
+    }
+
+    pub fn indices(&self) -> TerrainIndices {
+        self.settings.indices
+    }
+
+    // What the pipelines drawing it have to be built for
+    pub fn topology(&self) -> MeshTopology {
+        self.chunks.topology()
+    }
+
+    // Expects the same bind groups as LodModel::draw, and its pipeline built for topology()
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        self.draw_chunks(render_pass);
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    // The lowest & highest points, as heights in world space
+    pub fn height_range(&self) -> (f32, f32) {
+        let heights = self.chunks.meshes().iter().flat_map(|chunk| chunk.vertices.iter()).map(|v| v.position[1]);
+        let (min, max) = heights.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| (min.min(h), max.max(h)));
+        let to_world = |height: f32| self.transform.position.y + height * self.transform.scale.y;
+        (to_world(min), to_world(max))
+    }
+
+    // Only binds & draws the chunks, for passes that bring their own instance data
+    pub fn draw_chunks<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.chunks.draw(render_pass);
+    }
+
+    pub fn chunks(&self) -> &MeshBatch {
+        &self.chunks
+    }
+
+    // Check BatchDrawMode::is_supported first
+    pub fn set_draw_mode(&mut self, mode: BatchDrawMode) {
+        self.chunks.mode = mode;
+    }
+}
@@ -0,0 +1,58 @@
+use std::rc::Rc;
+
+use crate::pipeline_cache::PipelineCache;
+use crate::{post, screen};
+
+/*
+*   Calibration test pattern: color bars, grayscale ramps and checkerboards, for checking gamma,
+*   colors and that pixels make it to the display 1:1. It gets drawn in its own pass after the
+*   post effects so nothing blurs it.
+*/
+pub struct TestPattern {
+    pub enabled: bool,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl TestPattern {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Test Pattern",
+            include_str!("test_pattern.wgsl"),
+            &[screen_layout],
+            color_format,
+        );
+        Self {
+            enabled: false,
+            pipeline,
+        }
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        if !self.enabled {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Test Pattern Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel gets overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,63 @@
+// Display calibration test pattern, see test_pattern.rs. Everything is worked out from the
+// pixel position so it stays sharp at any resolution.
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+// 75% color bars: white, yellow, cyan, green, magenta, red, blue
+fn color_bar(index: i32) -> vec3<f32> {
+    switch index {
+        case 0: { return vec3<f32>(0.75, 0.75, 0.75); }
+        case 1: { return vec3<f32>(0.75, 0.75, 0.0); }
+        case 2: { return vec3<f32>(0.0, 0.75, 0.75); }
+        case 3: { return vec3<f32>(0.0, 0.75, 0.0); }
+        case 4: { return vec3<f32>(0.75, 0.0, 0.75); }
+        case 5: { return vec3<f32>(0.75, 0.0, 0.0); }
+        default: { return vec3<f32>(0.0, 0.0, 0.75); }
+    }
+}
+
+// Alternating black & white squares `size` pixels across
+fn checker(pixel: vec2<f32>, size: f32) -> f32 {
+    let cell = vec2<i32>(floor(pixel / size));
+    return f32((cell.x + cell.y) & 1);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // position.xy is the pixel's center, e.g. (0.5, 0.5) for the top left pixel
+    let pixel = position.xy;
+    let uv = pixel * screen.inv_resolution;
+
+    // Top half: color bars
+    if (uv.y < 0.5) {
+        return vec4<f32>(color_bar(i32(uv.x * 7.0)), 1.0);
+    }
+
+    // Then a smooth grayscale ramp above a stepped one
+    if (uv.y < 0.625) {
+        return vec4<f32>(vec3<f32>(uv.x), 1.0);
+    }
+    if (uv.y < 0.75) {
+        return vec4<f32>(vec3<f32>(floor(uv.x * 11.0) / 10.0), 1.0);
+    }
+
+    // Bottom quarter: a 1 pixel checkerboard in the middle, any scaling or filtering between
+    // us and the display turns it into a grey smear. Bigger checkers on either side.
+    if (abs(uv.x - 0.5) < 0.125) {
+        return vec4<f32>(vec3<f32>(checker(pixel, 1.0)), 1.0);
+    }
+    return vec4<f32>(vec3<f32>(checker(pixel, 16.0)), 1.0);
+}
@@ -0,0 +1,788 @@
+use crate::blend::BlendMode;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+
+/*
+*   How a texture's color relates to its alpha.
+*
+*   Straight is how most image editors save PNGs: the color is the color of the surface and
+*   alpha says how much of it there is. Fully transparent pixels can hold any color, usually
+*   black. The trouble starts when the gpu filters between texels, halfway between an opaque
+*   orange texel and a transparent black one we get half transparent dark orange, which shows
+*   up as a dark fringe around soft edges.
+*
+*   Premultiplied stores the color already multiplied by alpha. Filtering then blends colors
+*   the way they actually cover the pixel, so there are no fringes, and transparent texels
+*   are black no matter what the file said. Use it for anything with soft or filtered edges
+*   (sprites, particles, text, ui) and blend with Alpha::blend_state, which picks the matching
+*   BlendState. Straight is fine for textures that are opaque or only ever fully in or out.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Alpha {
+    Straight,
+    Premultiplied,
+}
+
+impl Alpha {
+    // The blend mode that composites this kind of color correctly
+    pub fn blend_mode(self) -> BlendMode {
+        match self {
+            // src * a + dst * (1 - a)
+            Alpha::Straight => BlendMode::AlphaBlend,
+            // src + dst * (1 - a), the multiply by a already happened
+            Alpha::Premultiplied => BlendMode::PremultipliedAlpha,
+        }
+    }
+
+    pub fn blend_state(self) -> wgpu::BlendState {
+        self.blend_mode().blend_state().expect("both alpha blend modes blend")
+    }
+}
+
+/*
+*   What a texture's texels mean, which decides the format it's uploaded in.
+*
+*   Color     what the image shows is what it looks like: diffuse/albedo, emissive, sprites.
+*             Image files store color in sRGB, so it goes into an Rgba8UnormSrgb texture and
+*             the gpu decodes it to linear light whenever it's sampled.
+*   Normal    a direction per texel, xyz packed into 0..1 as rgb * 0.5 + 0.5
+*   Linear    any other data: roughness, metalness, specular, ambient occlusion, heights
+*
+*   The last two are numbers, not colors, and have to go into a plain Rgba8Unorm. The sRGB
+*   decode is a curve that pulls everything below 1 down, so a normal map loaded as Color
+*   comes out with its "flat" (0.5, 0.5, 1.0) turned into (0.21, 0.21, 1.0), the normals all
+*   lean off towards -x & -y and the lighting looks washed out & wrong. Nothing fails, which is
+*   why it's such a common mistake. Most image editors show normal maps as colors too, so
+*   there's nothing in the file that says which it is, it comes from how the texture's used
+*   (see material::TextureSlot).
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureKind {
+    Color,
+    Normal,
+    Linear,
+}
+
+impl TextureKind {
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureKind::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureKind::Normal | TextureKind::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/*
+*   The two ways a shader can sample a depth texture. Depth formats can't be filtered, a
+*   regular Linear sampler on one fails validation, so it's one of these:
+*
+*   Compare     a comparison sampler & textureSampleCompare(t, s, uv, reference). Each texel is
+*               compared with the reference and it's the 0 or 1 results that get filtered,
+*               which is what shadow maps want (percentage closer filtering for free). This one
+*               can be Linear.
+*   Raw         a NonFiltering sampler & textureSample(t, s, uv), for the depth value itself.
+*               Depth of field & fog want this to turn it back into a distance. Has to be
+*               Nearest. textureLoad reads the same values without needing a sampler at all,
+*               which is what the post effects do.
+*
+*   Either way the texture's bound as a texture_depth_2d through a view of only its depth
+*   (see depth_only_view), and needs TEXTURE_BINDING.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthSampling {
+    Compare,
+    Raw,
+}
+
+impl DepthSampling {
+    pub fn create_sampler(self, device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&self.sampler_desc())
+    }
+
+    fn sampler_desc(self) -> wgpu::SamplerDescriptor<'static> {
+        let (label, filter, compare) = match self {
+            DepthSampling::Compare => (
+                "Depth Compare Sampler",
+                wgpu::FilterMode::Linear,
+                Some(wgpu::CompareFunction::LessEqual),
+            ),
+            DepthSampling::Raw => ("Depth Sampler", wgpu::FilterMode::Nearest, None),
+        };
+        wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn sampler_binding_type(self) -> wgpu::SamplerBindingType {
+        match self {
+            DepthSampling::Compare => wgpu::SamplerBindingType::Comparison,
+            DepthSampling::Raw => wgpu::SamplerBindingType::NonFiltering,
+        }
+    }
+
+    // Bind group layout entries for a depth texture at `binding` and its sampler right after
+    pub fn layout_entries(self, binding: u32, visibility: wgpu::ShaderStages) -> [wgpu::BindGroupLayoutEntry; 2] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler(self.sampler_binding_type()),
+                count: None,
+            },
+        ]
+    }
+}
+
+/*
+*   Which mip level a texture gets sampled from. The gpu picks a level from how many texels
+*   fall in each pixel, the bias gets added to that, and the result is clamped between
+*   min_clamp & max_clamp. Each whole step of bias is one level, twice or half the texel size.
+*
+*   A negative bias samples bigger, more detailed levels than the gpu would: sharper in the
+*   distance, but it starts to shimmer and alias the way it would without mipmaps. A positive
+*   one samples smaller levels, blurrier but calmer. Raising min_clamp keeps the biggest levels
+*   from being used at all, lowering max_clamp the smallest.
+*
+*   The clamps are part of the sampler. The bias isn't, wgpu's samplers don't have one since
+*   not every backend can, so the shaders add it with textureSampleBias themselves (see
+*   mip_bias_demo.wgsl).
+*/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplerLod {
+    pub bias: f32,
+    pub min_clamp: f32,
+    pub max_clamp: f32,
+}
+
+impl SamplerLod {
+    // WGSL allows -16 to 15.99, but past a few levels either way it's all shimmer or one color
+    pub const MAX_BIAS: f32 = 4.0;
+
+    // The bias within MAX_BIAS, and clamps that don't cross or go below level 0
+    pub fn clamped(self) -> Self {
+        let min_clamp = self.min_clamp.max(0.0);
+        Self {
+            bias: self.bias.clamp(-Self::MAX_BIAS, Self::MAX_BIAS),
+            min_clamp,
+            max_clamp: self.max_clamp.max(min_clamp),
+        }
+    }
+}
+
+impl Default for SamplerLod {
+    fn default() -> Self {
+        Self {
+            bias: 0.0,
+            min_clamp: 0.0,
+            max_clamp: 32.0,
+        }
+    }
+}
+
+// Why Texture::from_rgba turned its texels down
+#[derive(Debug)]
+pub enum RgbaError {
+    // Empty, or over the device's limit either way
+    Size { width: u32, height: u32, max: u32 },
+    // Not width * height * 4 bytes
+    Length { width: u32, height: u32, len: usize },
+}
+
+impl std::fmt::Display for RgbaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RgbaError::Size { width, height, max } => {
+                write!(f, "a {}x{} texture won't do, it has to be 1 to {} texels each way", width, height, max)
+            }
+            RgbaError::Length { width, height, len } => write!(
+                f,
+                "{}x{} RGBA8 texels take {} bytes, got {}",
+                width,
+                height,
+                *width as usize * *height as usize * 4,
+                len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RgbaError {}
+
+pub struct Texture {
+    pub texture: Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    // What `sampler` was made from, set_lod makes a new one from it
+    sampler_desc: wgpu::SamplerDescriptor<'static>,
+    lod_bias: f32,
+}
+
+impl Texture {
+    // 24 bits (or more) of depth plus an 8 bit stencil buffer in the same texture, see outline.rs
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+    // RENDER_ATTACHMENT is required since we render to it, TEXTURE_BINDING lets later passes
+    // read the depth values back, with either kind of DepthSampling
+    pub const DEPTH_USAGES: wgpu::TextureUsages =
+        wgpu::TextureUsages::RENDER_ATTACHMENT.union(wgpu::TextureUsages::TEXTURE_BINDING);
+
+    // The depth texture needs to be the same size as the surface, so we recreate it whenever
+    // the window is resized
+
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: Self::DEPTH_USAGES,
+            view_formats: &[],
+        };
+        let texture = device.create_tracked_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // We don't strictly need a sampler for a depth texture, but if we ever want to
+        // render it we'll want a comparison sampler. depth_sampling makes either kind.
+        let sampler_desc = DepthSampling::Compare.sampler_desc();
+        let sampler = device.create_sampler(&sampler_desc);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_desc,
+            lod_bias: 0.0,
+        }
+    }
+
+    /*
+    *   A texture with both depth & stencil can only be bound for reading one of the two at a
+    *   time, so passes that read the depth need a view of just that part. The render passes
+    *   still attach `view`, which covers both.
+    */
+    pub fn depth_only_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        })
+    }
+
+    // A depth only view & a sampler to read it with, for the entries from
+    // DepthSampling::layout_entries
+    pub fn depth_sampling(&self, device: &wgpu::Device, sampling: DepthSampling) -> (wgpu::TextureView, wgpu::Sampler) {
+        (self.depth_only_view(), sampling.create_sampler(device))
+    }
+
+    /*
+    *   An offscreen color texture we can render into and then sample from in a later pass,
+    *   used by the post processing chain. It's the same size as the surface so it gets
+    *   recreated on resize just like the depth texture.
+    */
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        Self::create_render_target_sized(device, config.width, config.height, format, label)
+    }
+
+    // The same for a target that isn't the size of the surface, see portal.rs
+    pub fn create_render_target_sized(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_tracked_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // COPY_SRC so whatever was drawn can be read back, see recording.rs
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler_desc = wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        };
+        let sampler = device.create_sampler(&sampler_desc);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_desc,
+            lod_bias: 0.0,
+        }
+    }
+
+    pub const STORAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    // Whether compute shaders can write STORAGE_FORMAT textures on this adapter (not on WebGL)
+    pub fn storage_supported(adapter: &wgpu::Adapter) -> bool {
+        let compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let storage = adapter
+            .get_texture_format_features(Self::STORAGE_FORMAT)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::STORAGE_BINDING);
+        compute && storage
+    }
+
+    /*
+    *   A color texture a compute shader writes into (STORAGE_BINDING) and the render pass samples
+    *   from afterwards (TEXTURE_BINDING). Only some formats can be storage textures, Rgba8Unorm
+    *   is one of the few every WebGPU implementation has to support. Without `storage` it's
+    *   filled with queue.write_texture instead, for adapters that can't run compute shaders.
+    */
+    pub fn create_storage_texture(device: &wgpu::Device, width: u32, height: u32, storage: bool, label: &str) -> Self {
+        let usage = if storage {
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING
+        } else {
+            wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING
+        };
+        let texture = device.create_tracked_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::STORAGE_FORMAT,
+            usage,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Repeat so the pattern tiles across the mesh
+        let sampler_desc = wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        };
+        let sampler = device.create_sampler(&sampler_desc);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_desc,
+            lod_bias: 0.0,
+        }
+    }
+
+    // The biggest width or height a 2d texture can have on this device, only 2048 on WebGL2
+    pub fn max_texture_dimension(device: &wgpu::Device) -> u32 {
+        device.limits().max_texture_dimension_2d
+    }
+
+    // Decodes a png or jpeg and uploads it, see from_image
+    pub fn from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        kind: TextureKind,
+        alpha: Alpha,
+    ) -> image::ImageResult<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(Self::from_image(device, queue, &img, label, kind, alpha))
+    }
+
+    /*
+    *   Uploads an image as a texture we can sample in a shader, in the format for its `kind`
+    *   (sRGB for colors, see TextureKind). Too big ones get scaled down first, see
+    *   fit_to_device.
+    *
+    *   Images are assumed to have straight alpha, Alpha::Premultiplied converts them while
+    *   loading (see premultiply).
+    */
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: &str,
+        kind: TextureKind,
+        alpha: Alpha,
+    ) -> Self {
+        let mut rgba = Self::fit_to_device(device, img, label);
+        if alpha == Alpha::Premultiplied {
+            premultiply(&mut rgba, kind == TextureKind::Color);
+        }
+        let (width, height) = rgba.dimensions();
+        Self::upload_rgba(device, queue, &rgba, width, height, label, kind)
+    }
+
+    /*
+    *   Uploads texels that are already RGBA8, width * height of them row by row, without going
+    *   through an image: generated textures, test fixtures, pixels from somewhere else. They're
+    *   taken as sRGB colors with straight alpha, like from_image with TextureKind::Color. The
+    *   data has to be exactly the right length, and nothing gets scaled down to fit the device,
+    *   a texture too big for it is an error.
+    */
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Result<Self, RgbaError> {
+        let max = Self::max_texture_dimension(device);
+        if width == 0 || height == 0 || width > max || height > max {
+            return Err(RgbaError::Size { width, height, max });
+        }
+        let expected = width as usize * height as usize * 4;
+        if data.len() != expected {
+            return Err(RgbaError::Length {
+                width,
+                height,
+                len: data.len(),
+            });
+        }
+        Ok(Self::upload_rgba(device, queue, data, width, height, label, TextureKind::Color))
+    }
+
+    // The part of from_image & from_rgba after the texels are ready
+    fn upload_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        label: &str,
+        kind: TextureKind,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_tracked_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Color images are stored in sRGB, the gpu converts to linear when we sample
+            format: kind.format(),
+            // COPY_DST so we can write the pixels into it
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                // Unlike copies from a buffer, write_texture doesn't need the rows padded to
+                // COPY_BYTES_PER_ROW_ALIGNMENT, wgpu does that on its side, so tightly packed is fine
+                bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler_desc = wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        };
+        let sampler = device.create_sampler(&sampler_desc);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_desc,
+            lod_bias: 0.0,
+        }
+    }
+
+    /*
+    *   Like from_image, with every mip level down to 1x1 and a sampler that blends between
+    *   them, repeating so it can tile. Each level is the one before scaled down to half on
+    *   the cpu. The scaling averages the stored values, which for sRGB comes out a little
+    *   darker than averaging the light would, close enough for textures without fine high
+    *   contrast detail. Straight alpha only.
+    */
+    pub fn from_image_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: &str,
+        kind: TextureKind,
+    ) -> Self {
+        let level = Self::fit_to_device(device, img, label);
+        Self::create_mipmapped(device, queue, vec![level], label, kind, wgpu::TextureViewDimension::D2)
+    }
+
+    /*
+    *   A texture array with a layer for each image, mipmapped like from_image_mipmapped, for
+    *   shaders that pick between textures of the same size with a number (texture_2d_array &
+    *   an index). Layers that aren't the size of the first get scaled to it. Needs at least
+    *   one image.
+    */
+    pub fn array_from_images_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        label: &str,
+        kind: TextureKind,
+    ) -> Self {
+        assert!(!images.is_empty(), "a texture array needs at least one layer");
+        let first = Self::fit_to_device(device, &images[0], label);
+        let (width, height) = first.dimensions();
+        let layers = std::iter::once(first)
+            .chain(images[1..].iter().map(|img| {
+                let layer = Self::fit_to_device(device, img, label);
+                if layer.dimensions() == (width, height) {
+                    layer
+                } else {
+                    image::imageops::resize(&layer, width, height, image::imageops::FilterType::Triangle)
+                }
+            }))
+            .collect();
+        Self::create_mipmapped(device, queue, layers, label, kind, wgpu::TextureViewDimension::D2Array)
+    }
+
+    // Every layer has to be the same size
+    fn create_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut layers: Vec<image::RgbaImage>,
+        label: &str,
+        kind: TextureKind,
+        view_dimension: wgpu::TextureViewDimension,
+    ) -> Self {
+        let (width, height) = layers[0].dimensions();
+        // Halving down to 1 pixel along the longer side
+        let mip_level_count = u32::BITS - width.max(height).leading_zeros();
+
+        let texture = device.create_tracked_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layers.len() as u32,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: kind.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for mip_level in 0..mip_level_count {
+            for (layer, level) in layers.iter_mut().enumerate() {
+                if mip_level > 0 {
+                    let (width, height) = ((level.width() / 2).max(1), (level.height() / 2).max(1));
+                    *level = image::imageops::resize(&*level, width, height, image::imageops::FilterType::Triangle);
+                }
+                let (width, height) = level.dimensions();
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    level,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                        rows_per_image: std::num::NonZeroU32::new(height),
+                    },
+                    wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(view_dimension),
+            ..Default::default()
+        });
+        let lod = SamplerLod::default();
+        let sampler_desc = wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            // Blends the two nearest levels, or the switch from one to the next shows as a line
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: lod.min_clamp,
+            lod_max_clamp: lod.max_clamp,
+            ..Default::default()
+        };
+        let sampler = device.create_sampler(&sampler_desc);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            sampler_desc,
+            lod_bias: lod.bias,
+        }
+    }
+
+    /*
+    *   An image wider or taller than the device allows would fail validation when we create
+    *   the texture, so those get scaled down to fit first. resize() keeps the aspect ratio,
+    *   the longer side ends up at the limit.
+    */
+    fn fit_to_device(device: &wgpu::Device, img: &image::DynamicImage, label: &str) -> image::RgbaImage {
+        let max = Self::max_texture_dimension(device);
+        if img.width() > max || img.height() > max {
+            let downscaled = img.resize(max, max, image::imageops::FilterType::Triangle);
+            log::warn!(
+                "{:?} is {}x{}, over this device's {} pixel limit, downscaled to {}x{}",
+                label,
+                img.width(),
+                img.height(),
+                max,
+                downscaled.width(),
+                downscaled.height(),
+            );
+            downscaled.to_rgba8()
+        } else {
+            img.to_rgba8()
+        }
+    }
+
+    pub fn lod(&self) -> SamplerLod {
+        SamplerLod {
+            bias: self.lod_bias,
+            min_clamp: self.sampler_desc.lod_min_clamp,
+            max_clamp: self.sampler_desc.lod_max_clamp,
+        }
+    }
+
+    // Up to how many samples the sampler takes along the direction a texture is squeezed in
+    // when it's seen at a glancing angle, 1 for none. Has to be a power of two up to 16 and
+    // needs every filter linear, adapters without anisotropic filtering quietly take 1 (see
+    // wgpu::DownlevelFlags::ANISOTROPIC_FILTERING).
+    pub fn set_anisotropy(&mut self, device: &wgpu::Device, clamp: u8) {
+        self.sampler_desc.anisotropy_clamp = std::num::NonZeroU8::new(clamp).filter(|clamp| clamp.get() > 1);
+        self.sampler = device.create_sampler(&self.sampler_desc);
+    }
+
+    pub fn anisotropy(&self) -> u8 {
+        self.sampler_desc.anisotropy_clamp.map_or(1, |clamp| clamp.get())
+    }
+
+    // Makes a new sampler with `lod`'s clamps, after SamplerLod::clamped. Bind groups still
+    // point at the old one until they're made again, and the bias is up to the shaders.
+    pub fn set_lod(&mut self, device: &wgpu::Device, lod: SamplerLod) {
+        let lod = lod.clamped();
+        self.sampler_desc.lod_min_clamp = lod.min_clamp;
+        self.sampler_desc.lod_max_clamp = lod.max_clamp;
+        self.lod_bias = lod.bias;
+        self.sampler = device.create_sampler(&self.sampler_desc);
+    }
+}
+
+/*
+*   An sRGB texture's color has to be multiplied by alpha as linear light and then encoded
+*   again. Multiplying the sRGB values directly would make soft edges come out darker than
+*   they should once the gpu decodes them. Linear data gets multiplied as it is.
+*/
+fn premultiply(image: &mut image::RgbaImage, srgb: bool) {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.0;
+        for channel in &mut pixel.0[..3] {
+            let value = *channel as f32 / 255.0;
+            let value = if srgb { linear_to_srgb(srgb_to_linear(value) * alpha) } else { value * alpha };
+            *channel = (value * 255.0).round() as u8;
+        }
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
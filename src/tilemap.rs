@@ -0,0 +1,442 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::model::Vertex;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{screen, texture};
+
+/*
+*   A grid of tile indices, row by row from the top. None is an empty cell, nothing gets drawn
+*   there. This is the cpu side of a TileMap, the part that gets loaded & edited.
+*/
+#[derive(Clone, Debug)]
+pub struct TileMapData {
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<Option<u32>>,
+}
+
+impl TileMapData {
+    pub fn load_csv(path: &std::path::Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        Self::from_csv(&source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /*
+    *   One row of the map per line, tile indices separated by commas, the way most tilemap
+    *   editors export a layer. Negative indices (editors like -1) and blank cells are empty.
+    *   Every row has to be as long as the first, blank lines are skipped.
+    */
+    pub fn from_csv(source: &str) -> Result<Self, String> {
+        let mut width = None;
+        let mut tiles = Vec::new();
+        for (number, line) in source.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let row_start = tiles.len();
+            for cell in line.split(',').map(str::trim) {
+                let tile = match cell {
+                    "" => None,
+                    _ => {
+                        let index = cell
+                            .parse::<i64>()
+                            .map_err(|_| format!("line {}: {:?} isn't a tile index", number + 1, cell))?;
+                        u32::try_from(index).ok()
+                    }
+                };
+                tiles.push(tile);
+            }
+            let row_width = (tiles.len() - row_start) as u32;
+            match width {
+                None => width = Some(row_width),
+                Some(width) if width != row_width => {
+                    return Err(format!("line {}: {} tiles, the first row has {}", number + 1, row_width, width))
+                }
+                _ => {}
+            }
+        }
+        let width = width.ok_or("no tiles")?;
+        Ok(Self {
+            width,
+            height: tiles.len() as u32 / width,
+            tiles,
+        })
+    }
+
+    pub fn tile(&self, x: u32, y: u32) -> Option<u32> {
+        if x < self.width && y < self.height {
+            self.tiles[(y * self.width + x) as usize]
+        } else {
+            None
+        }
+    }
+}
+
+/*
+*   A texture split into a grid of equally sized tiles, numbered row by row from the top left.
+*   uv_region is where tile `index` is, in texture coordinates.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct TileSet {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl TileSet {
+    pub fn len(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Top left corner & size. None if the atlas doesn't have that many tiles.
+    pub fn uv_region(&self, index: u32) -> Option<[f32; 4]> {
+        if index >= self.len() {
+            return None;
+        }
+        let size = [1.0 / self.columns as f32, 1.0 / self.rows as f32];
+        let (column, row) = (index % self.columns, index / self.columns);
+        Some([column as f32 * size[0], row as f32 * size[1], size[0], size[1]])
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TileInstance {
+    // x, y, width & height in pixels from the top left of the screen
+    rect: [f32; 4],
+    // Same for the tile in the atlas, in texture coordinates
+    uv_region: [f32; 4],
+}
+
+impl Vertex for TileInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TileInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/*
+*   Draws a TileMapData in 2d over the finished frame, one instanced quad per tile, each
+*   sampling its own region of the tileset's atlas. `scroll` is the pixel of the map that ends
+*   up in the top left corner of the screen.
+*
+*   Only the tiles that land on the screen get an instance. Working out which ones those are is
+*   a couple of divisions since the tiles are a grid, so the list is rebuilt every frame rather
+*   than kept up to date. The instance buffer has room for the whole map so that never needs
+*   to grow.
+*/
+pub struct TileMap {
+    pub enabled: bool,
+    pub scroll: cgmath::Vector2<f32>,
+    // Pixels across each tile on the screen
+    pub tile_size: f32,
+    data: TileMapData,
+    tileset: TileSet,
+    atlas: image::DynamicImage,
+    instances: Vec<TileInstance>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    // Kept with its bind group, it's what the bind group points at
+    _texture: texture::Texture,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl TileMap {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        data: TileMapData,
+        atlas: image::DynamicImage,
+        tileset: TileSet,
+    ) -> Self {
+        let texture = texture::Texture::from_image(
+            device,
+            queue,
+            &atlas,
+            "Tile Atlas",
+            texture::TextureKind::Color,
+            texture::Alpha::Straight,
+        );
+        // Tiles are pixel art, and linear filtering would blend in the neighbouring tiles at
+        // the edges
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Tile Atlas Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("tilemap_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("tilemap_bind_group"),
+        });
+
+        let instance_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile Instance Buffer"),
+            size: (data.tiles.len().max(1) * std::mem::size_of::<TileInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Tilemap",
+                shader: include_str!("tilemap.wgsl"),
+                bind_group_layouts: &[screen_layout, &bind_group_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[TileInstance::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            },
+        );
+
+        Self {
+            enabled: false,
+            scroll: cgmath::Vector2::new(0.0, 0.0),
+            tile_size: 32.0,
+            data,
+            tileset,
+            atlas,
+            instances: Vec::new(),
+            instance_buffer,
+            _texture: texture,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new surface format, keeping the
+    // tiles as they've been edited and where the map is scrolled to
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        queue: &wgpu::Queue,
+        screen_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            enabled: self.enabled,
+            scroll: self.scroll,
+            tile_size: self.tile_size,
+            ..Self::new(
+                device,
+                pipelines,
+                queue,
+                screen_layout,
+                color_format,
+                self.data.clone(),
+                self.atlas.clone(),
+                self.tileset,
+            )
+        }
+    }
+
+    pub fn data(&self) -> &TileMapData {
+        &self.data
+    }
+
+    // Size of the whole map in pixels on the screen
+    pub fn pixel_size(&self) -> cgmath::Vector2<f32> {
+        cgmath::Vector2::new(self.data.width as f32, self.data.height as f32) * self.tile_size
+    }
+
+    // Changes one tile, None empties it. Tiles outside the map are ignored.
+    pub fn set_tile(&mut self, x: u32, y: u32, index: Option<u32>) {
+        if x < self.data.width && y < self.data.height {
+            self.data.tiles[(y * self.data.width + x) as usize] = index;
+        }
+    }
+
+    // Works out which tiles are on a `screen_size` screen and uploads them
+    pub fn update(&mut self, queue: &wgpu::Queue, screen_size: winit::dpi::PhysicalSize<u32>) {
+        self.instances.clear();
+        if !self.enabled {
+            return;
+        }
+        // The range of columns & rows that touch the screen, clamped to the map
+        let visible = |scroll: f32, screen: u32, tiles: u32| {
+            let first = (scroll / self.tile_size).floor().max(0.0) as u32;
+            let last = ((scroll + screen as f32) / self.tile_size).ceil().max(0.0) as u32;
+            first.min(tiles)..last.min(tiles)
+        };
+        let columns = visible(self.scroll.x, screen_size.width, self.data.width);
+        for y in visible(self.scroll.y, screen_size.height, self.data.height) {
+            for x in columns.clone() {
+                let region = match self.data.tile(x, y).and_then(|index| self.tileset.uv_region(index)) {
+                    Some(region) => region,
+                    None => continue,
+                };
+                self.instances.push(TileInstance {
+                    rect: [
+                        x as f32 * self.tile_size - self.scroll.x,
+                        y as f32 * self.tile_size - self.scroll.y,
+                        self.tile_size,
+                        self.tile_size,
+                    ],
+                    uv_region: region,
+                });
+            }
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.instances));
+    }
+
+    // Draws over whatever is in `output` already
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, screen: &screen::Screen, output: &wgpu::TextureView) {
+        if !self.enabled || self.instances.is_empty() {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tilemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instances.len() as u32);
+    }
+}
+
+/*
+*   The demo map: a lake, a walled courtyard and a couple of paths, with a corner left empty
+*   to show the scene behind. The tiles are drawn here rather than loaded, 8x8 pixels each in
+*   a 4x2 atlas. Water has two tiles, animate_demo_water flips between them.
+*/
+const GRASS: u32 = 0;
+const PATH: u32 = 1;
+const WATER: [u32; 2] = [2, 3];
+const STONE: u32 = 4;
+const TREE: u32 = 5;
+const FLOWERS: u32 = 6;
+const SAND: u32 = 7;
+
+pub const DEMO_TILESET: TileSet = TileSet { columns: 4, rows: 2 };
+const TILE_PIXELS: u32 = 8;
+
+pub fn demo_map() -> TileMapData {
+    TileMapData::from_csv(include_str!("tilemap.csv")).expect("the demo tilemap is broken")
+}
+
+pub fn demo_atlas() -> image::DynamicImage {
+    let (width, height) = (DEMO_TILESET.columns * TILE_PIXELS, DEMO_TILESET.rows * TILE_PIXELS);
+    let image = image::RgbaImage::from_fn(width, height, |px, py| {
+        let tile = (py / TILE_PIXELS) * DEMO_TILESET.columns + px / TILE_PIXELS;
+        let (x, y) = (px % TILE_PIXELS, py % TILE_PIXELS);
+        // Cheap per pixel noise so the flat colors aren't completely flat
+        let speckle = (x * 7 + y * 13 + tile * 5).is_multiple_of(11);
+        let grass = if speckle { [70, 150, 60] } else { [90, 170, 70] };
+        let [r, g, b] = match tile {
+            GRASS => grass,
+            PATH if speckle => [160, 130, 90],
+            PATH => [185, 155, 110],
+            // The wave lines move down a couple of pixels between the two frames
+            t if WATER.contains(&t) => {
+                let shift = if t == WATER[0] { 0 } else { 2 };
+                if (y + shift).is_multiple_of(4) && !(x + y / 4).is_multiple_of(4) {
+                    [130, 180, 240]
+                } else {
+                    [50, 110, 200]
+                }
+            }
+            STONE => {
+                // Bricks, every other row offset by half a brick
+                let mortar = y % 4 == 3 || (x + if y < 4 { 0 } else { 4 }) % 8 == 7;
+                if mortar {
+                    [90, 90, 95]
+                } else {
+                    [150, 150, 155]
+                }
+            }
+            TREE => {
+                let (dx, dy) = (x as f32 - 3.5, y as f32 - 3.5);
+                if dx * dx + dy * dy < 10.0 {
+                    [30, 95, 40]
+                } else {
+                    grass
+                }
+            }
+            FLOWERS => match (x, y) {
+                (1, 2) | (5, 5) => [230, 60, 70],
+                (5, 1) | (2, 6) => [245, 220, 60],
+                _ => grass,
+            },
+            SAND if speckle => [215, 200, 140],
+            SAND => [235, 220, 160],
+            // Anything the atlas doesn't have
+            _ => [255, 0, 255],
+        };
+        image::Rgba([r, g, b, 255])
+    });
+    image::DynamicImage::ImageRgba8(image)
+}
+
+// Shows the first water tile for half a second, then the second, and so on
+pub fn animate_demo_water(map: &mut TileMap, time: f32) {
+    let frame = WATER[(time * 2.0).rem_euclid(2.0) as usize];
+    let (width, height) = (map.data().width, map.data().height);
+    for y in 0..height {
+        for x in 0..width {
+            if map.data().tile(x, y).filter(|tile| WATER.contains(tile)).is_some() {
+                map.set_tile(x, y, Some(frame));
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// A tilemap drawn as one quad per visible tile, see tilemap.rs. Each instance brings its own
+// rectangle on the screen and its tile's region of the atlas.
+
+struct ScreenUniform {
+    resolution: vec2<f32>,
+    inv_resolution: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+
+@group(1) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var s_atlas: sampler;
+
+struct TileInstance {
+    // Top left corner & size in pixels, y goes down from the top of the screen
+    @location(0) rect: vec4<f32>,
+    // Top left corner & size in the atlas
+    @location(1) uv_region: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+    tile: TileInstance,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[in_vertex_index];
+    let pixel = tile.rect.xy + corner * tile.rect.zw;
+    let ndc = pixel * 2.0 * screen.inv_resolution - 1.0;
+
+    var out: VertexOutput;
+    // Pixels count down from the top, clip space counts up from the bottom
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    // Both count down, so the atlas needs no flip
+    out.tex_coords = tile.uv_region.xy + corner * tile.uv_region.zw;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_atlas, s_atlas, in.tex_coords);
+}
@@ -0,0 +1,97 @@
+use instant::Duration;
+
+/*
+*   Fixed timestep, the "fix your timestep" pattern. Rendering happens whenever the window
+*   asks for a frame, however long that took, but the simulation always moves forward in
+*   steps of exactly `step`. Real time piles up in an accumulator and each frame runs as many
+*   whole steps as fit in it (zero, one or several), carrying the rest over to the next frame.
+*   The same inputs give the same results whatever the frame rate.
+*
+*   What's left over after the steps is between 0 and 1 step, that fraction is alpha. The
+*   frame gets drawn `1 - alpha` steps behind the latest simulated state, interpolating
+*   between the last two steps, so motion stays smooth when the frame & step rates differ.
+*
+*   If a frame takes very long (a breakpoint, the window being dragged) catching up would
+*   take more steps than we can run in one frame, which makes the next frame slow too, and so
+*   on. Past MAX_STEPS we drop the backlog instead and the simulation falls behind real time.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    const MAX_STEPS: u32 = 8;
+
+    pub fn new(step: Duration) -> Self {
+        assert!(!step.is_zero(), "the fixed timestep can't be zero");
+        Self {
+            step,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    // Adds a frame's worth of real time and returns how many steps to run for it
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps > Self::MAX_STEPS {
+            log::debug!("{} fixed steps behind, skipping {}", steps, steps - Self::MAX_STEPS);
+            steps = Self::MAX_STEPS;
+        }
+        steps
+    }
+
+    // How far between the last step and the next one we are, 0..1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn carries_the_rest_over() {
+        let mut timestep = FixedTimestep::new(STEP);
+        assert_eq!(timestep.advance(Duration::from_millis(4)), 0);
+        assert!((timestep.alpha() - 0.4).abs() < 1e-6);
+        // The 4 left over make this one 2 steps with 1 to spare
+        assert_eq!(timestep.advance(Duration::from_millis(17)), 2);
+        assert!((timestep.alpha() - 0.1).abs() < 1e-6);
+        // Exactly a step leaves nothing over
+        assert_eq!(timestep.advance(Duration::from_millis(9)), 1);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    fn drops_the_backlog_after_a_stall() {
+        let mut timestep = FixedTimestep::new(STEP);
+        // A second's stall is 100 steps, only MAX_STEPS of them run and the rest are gone
+        assert_eq!(timestep.advance(Duration::from_millis(1005)), FixedTimestep::MAX_STEPS);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+        assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+    }
+
+    #[test]
+    fn alpha_stays_under_a_step() {
+        let mut timestep = FixedTimestep::new(STEP);
+        for millis in [0, 1, 3, 7, 9, 10, 11, 19, 20, 33, 250, 1000] {
+            timestep.advance(Duration::from_millis(millis));
+            let alpha = timestep.alpha();
+            assert!((0.0..1.0).contains(&alpha), "alpha {} after {}ms", alpha, millis);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::PipelineCache;
+use crate::{antialiasing, post};
+
+/*
+*   HDR output. With --hdr the scene is drawn into multisampled Rgba16Float, which keeps light
+*   brighter than 1.0 instead of clipping it. The samples get resolved into the post chain's
+*   single sample Rgba16Float targets, the effects run on those, and the chain's last pass
+*   tonemaps the result down to what the surface can show (see post.rs).
+*
+*   Tonemapping has to come after the resolve. A pixel on an edge is partly one surface and
+*   partly another, and the light reaching it is the mix of the two in proportion, so that's
+*   what the resolve should average: linear light, before anything bends it. Tonemapping is
+*   far from linear, tonemap(average) isn't average(tonemap) and the two come apart the most
+*   exactly where MSAA matters, on edges between something bright and something dark. Doing it
+*   per sample first also leaves the effects nothing but display values to work with, and a
+*   surface can't be multisampled anyway, so the resolve would need a pass of its own.
+*
+*   The catch is that a very bright sample dominates the average and a 1-in-4 edge comes out
+*   nearly as bright as a fully covered pixel, so the jaggies come back in the highlights after
+*   tonemapping. The custom resolve (MsaaAttachment::CUSTOM_RESOLVE) weighs bright samples down
+*   to soften that, it stays an average of linear values.
+*/
+
+// The curves that squeeze 0..infinity into 0..1, cycled with ctrl + H
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TonemapCurve {
+    // Cuts everything above 1.0 off, what the surface would do on its own
+    Clamp,
+    // x / (1 + x), never quite reaches white and desaturates the highlights
+    Reinhard,
+    // The filmic curve most games use, with a toe & a shoulder
+    #[default]
+    Aces,
+}
+
+impl TonemapCurve {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Clamp => Self::Reinhard,
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::Clamp,
+        }
+    }
+
+    // What the shader switches on
+    fn index(self) -> u32 {
+        match self {
+            Self::Clamp => 0,
+            Self::Reinhard => 1,
+            Self::Aces => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for TonemapCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Clamp => "clamp",
+            Self::Reinhard => "Reinhard",
+            Self::Aces => "ACES",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Has to match TonemapUniform in tonemap.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    curve: u32,
+    _padding: [u32; 3],
+}
+
+pub struct Tonemap {
+    curve: TonemapCurve,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Tonemap {
+    // What --hdr draws the scene into
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    // Formats that hold more than 0..1, the ones worth tonemapping
+    pub fn is_hdr(format: wgpu::TextureFormat) -> bool {
+        matches!(
+            format,
+            wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float | wgpu::TextureFormat::Rg11b10Float
+        )
+    }
+
+    // Whether the adapter can do all of --hdr: render to HDR_FORMAT, sample it in the post
+    // chain, and draw it multisampled with `sample_count` samples
+    pub fn is_supported(adapter: &wgpu::Adapter, sample_count: u32) -> bool {
+        post::PostChain::is_format_supported(adapter, Self::HDR_FORMAT)
+            && antialiasing::Msaa::is_supported(adapter, Self::HDR_FORMAT, sample_count)
+    }
+
+    // What HDR_FORMAT can be multisampled with here, for when 4x can't
+    pub fn supported_sample_counts(adapter: &wgpu::Adapter) -> Vec<u32> {
+        [2, 4, 8, 16]
+            .into_iter()
+            .filter(|count| antialiasing::Msaa::is_supported(adapter, Self::HDR_FORMAT, *count))
+            .collect()
+    }
+
+    // `input_layout` is the post chain's, `surface_format` what the result goes to
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        screen_layout: &wgpu::BindGroupLayout,
+        input_layout: &wgpu::BindGroupLayout,
+        surface_format: wgpu::TextureFormat,
+        curve: TonemapCurve,
+    ) -> Self {
+        let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Buffer"),
+            contents: bytemuck::cast_slice(&[TonemapUniform {
+                curve: curve.index(),
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("tonemap_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("tonemap_bind_group"),
+        });
+
+        let pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Tonemap",
+            include_str!("tonemap.wgsl"),
+            &[screen_layout, input_layout, &bind_group_layout],
+            surface_format,
+        );
+
+        Self {
+            curve,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn curve(&self) -> TonemapCurve {
+        self.curve
+    }
+
+    pub fn set_curve(&mut self, queue: &wgpu::Queue, curve: TonemapCurve) {
+        self.curve = curve;
+        let uniform = TonemapUniform {
+            curve: curve.index(),
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Groups 0 & 1 are already set, like for a post effect
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,62 @@
+// Tonemaps the resolved HDR scene onto the surface, see tonemap.rs
+
+// @group(0) is the screen uniform, which tonemapping doesn't need
+
+// Same layout as the post processing input, see post.rs
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+
+struct TonemapUniform {
+    // 0 clamps, 1 is Reinhard, 2 is ACES, see TonemapCurve
+    curve: u32,
+};
+@group(2) @binding(0)
+var<uniform> tonemap: TonemapUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    // Texture coordinates have y pointing down
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve, for linear sRGB
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_color, s_color, in.uv);
+    var color: vec3<f32>;
+    switch tonemap.curve {
+        case 1u: {
+            color = hdr.rgb / (1.0 + hdr.rgb);
+        }
+        case 2u: {
+            color = aces(hdr.rgb);
+        }
+        default: {
+            color = clamp(hdr.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    // Still linear, an sRGB surface encodes it on the way out
+    return vec4<f32>(color, hdr.a);
+}
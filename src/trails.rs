@@ -0,0 +1,219 @@
+use std::rc::Rc;
+
+use crate::gpu_memory::TrackedDevice;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::{post, screen, texture};
+
+/*
+*   Trails, from not clearing the frame. With LoadOp::Load the main pass starts from whatever
+*   the last frame left behind, so anything that moves smears across the screen. A full screen
+*   triangle blended over the old frame first fades it towards the background a little, which
+*   turns the smears into trails that die out instead of piling up forever.
+*
+*   The previous frame has to still be around for that to work. The surface texture isn't, it
+*   comes from a swapchain that hands out several textures in turn, so without MSAA the scene
+*   goes into a target of our own and gets copied to where it was going afterwards. With MSAA
+*   the multisampled color attachment already sticks around between frames.
+*
+*   The depth buffer is still cleared every frame: the old frame's depth would hide anything
+*   that moved behind where something else used to be.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FadeUniform {
+    color: [f32; 4],
+}
+
+pub struct Trails {
+    target: texture::Texture,
+    fade_bind_group: wgpu::BindGroup,
+    fade_pipeline: Rc<wgpu::RenderPipeline>,
+    blit_pipeline: Rc<wgpu::RenderPipeline>,
+    format: wgpu::TextureFormat,
+    // Set when there's no previous frame to build on, so the next one starts from scratch
+    needs_clear: bool,
+}
+
+impl Trails {
+    // How much of the way back to the background every frame, lower means longer trails
+    const FADE: f32 = 0.08;
+
+    // `sample_count` has to match the main pass' attachments, see antialiasing.rs
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        screen_layout: &wgpu::BindGroupLayout,
+        post_chain: &post::PostChain,
+        background: wgpu::Color,
+    ) -> Self {
+        // Drawn into by the main pass like the post chain's targets, so in the same format
+        let format = post_chain.format();
+        let target = texture::Texture::create_render_target(device, config, format, "trails_target");
+
+        let fade_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Trails Fade Buffer"),
+            contents: bytemuck::cast_slice(&[FadeUniform {
+                color: [background.r as f32, background.g as f32, background.b as f32, Self::FADE],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let fade_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("trails_fade_bind_group_layout"),
+        });
+        let fade_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &fade_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: fade_buffer.as_entire_binding(),
+            }],
+            label: Some("trails_fade_bind_group"),
+        });
+
+        let fade_pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Trails Fade",
+                shader: include_str!("trails.wgsl"),
+                bind_group_layouts: &[&fade_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // Mix the color in by its alpha, but leave the frame's own alpha alone
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                primitive: wgpu::PrimitiveState::default(),
+                // Drawn in the main pass before anything else, without touching the depth buffer
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        );
+
+        let blit_pipeline = post::create_effect_pipeline(
+            device,
+            pipelines,
+            "Trails Blit",
+            include_str!("blit.wgsl"),
+            &[screen_layout, &post_chain.input_layout],
+            format,
+        );
+
+        Self {
+            target,
+            fade_bind_group,
+            fade_pipeline,
+            blit_pipeline,
+            format,
+            needs_clear: true,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.target = texture::Texture::create_render_target(device, config, self.format, "trails_target");
+        self.needs_clear = true;
+    }
+
+    // Throws the old frame away, e.g. when trails get switched back on after a while
+    pub fn reset(&mut self) {
+        self.needs_clear = true;
+    }
+
+    // Whether this frame has to clear instead of building on the last one. Only true once.
+    pub fn take_clear(&mut self) -> bool {
+        std::mem::take(&mut self.needs_clear)
+    }
+
+    // Where the main pass draws without MSAA
+    pub fn target_view(&self) -> &wgpu::TextureView {
+        &self.target.view
+    }
+
+    // Call first thing in the main pass
+    pub fn draw_fade<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.fade_pipeline);
+        render_pass.set_bind_group(0, &self.fade_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Copies the accumulated frame to where the main pass would have drawn it
+    pub fn blit(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        screen: &screen::Screen,
+        post_chain: &post::PostChain,
+        depth_texture: &texture::Texture,
+        output: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &post_chain.input_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.target.sampler),
+                },
+                // Unused, it's only there to fill the post chain's layout
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.depth_only_view()),
+                },
+            ],
+            label: Some("trails_blit_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Trails Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.blit_pipeline);
+        render_pass.set_bind_group(0, &screen.bind_group, &[]);
+        render_pass.set_bind_group(1, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,22 @@
+// Pulls what's left of the previous frames a little closer to the background, see trails.rs
+
+struct FadeUniform {
+    // The background color, and how much of the way to move towards it every frame
+    color: vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> fade: FadeUniform;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen: (-1,-1), (3,-1), (-1,3)
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    // Alpha blending does the mixing: color * alpha + previous * (1 - alpha)
+    return fade.color;
+}
@@ -0,0 +1,499 @@
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, One, Rotation3};
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance;
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   Order independent transparency, weighted blended (McGuire & Bavoil 2013). Alpha blending
+*   only comes out right drawn back to front, and sorting whole objects can't fix two that cut
+*   through each other. Here every transparent fragment gets added into two targets in one
+*   pass, in any order:
+*
+*       accum      rgb * alpha & alpha, times a weight that favours what's close. Added up.
+*       revealage  how much of what's behind still shows, every fragment multiplies in
+*                  (1 - alpha). Starts at 1.
+*
+*   A full screen pass then divides the sums by each other to get an average color and blends
+*   it over the scene by 1 - revealage. The coverage is exact, the colors an estimate that gets
+*   worse the more opaque the layers are. For the light, see-through stuff it's meant for that
+*   looks fine from every angle.
+*
+*   It needs both targets in one pass with different blending for each, which not everything
+*   can do (DownlevelFlags::INDEPENDENT_BLEND). Without it the panels are sorted by distance
+*   and alpha blended, which goes wrong where they cut through each other.
+*
+*   The demo is a few colored panels stuck through each other above the spheres, drawn after
+*   the opaque scene and depth tested against it without writing depth.
+*/
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransparencyMethod {
+    WeightedBlended,
+    Sorted,
+}
+
+impl std::fmt::Display for TransparencyMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransparencyMethod::WeightedBlended => write!(f, "weighted blended OIT"),
+            TransparencyMethod::Sorted => write!(f, "sorted alpha blending"),
+        }
+    }
+}
+
+struct Panel {
+    transform: instance::Transform,
+    // Straight alpha
+    color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PanelRaw {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+}
+
+impl PanelRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        // Same locations as InstanceRaw's model matrix
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PanelRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// The targets & pipelines of the weighted blended method
+struct WeightedBlended {
+    accum: texture::Texture,
+    revealage: texture::Texture,
+    accumulate_pipeline: Rc<wgpu::RenderPipeline>,
+    composite_layout: wgpu::BindGroupLayout,
+    composite_bind_group: wgpu::BindGroup,
+    composite_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+pub struct Transparency {
+    pub enabled: bool,
+    method: TransparencyMethod,
+    // None where the adapter can't do it, see is_supported
+    weighted_blended: Option<WeightedBlended>,
+    panels: Vec<Panel>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    sorted_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Transparency {
+    // Half floats, the weighted sums go way past 1
+    const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    // Whether weighted blended OIT works here, it's sorted alpha blending otherwise
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        let renders_blended = |format| {
+            let features = adapter.get_texture_format_features(format);
+            features
+                .allowed_usages
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+                && features.flags.contains(wgpu::TextureFormatFeatureFlags::BLENDABLE)
+        };
+        adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::INDEPENDENT_BLEND)
+            && renders_blended(Self::ACCUM_FORMAT)
+            && renders_blended(Self::REVEALAGE_FORMAT)
+    }
+
+    // `color_format` is the main pass', the panels go on top of the finished scene
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        adapter: &wgpu::Adapter,
+        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let panels = Self::demo_panels();
+        let raw = panels.iter().map(Panel::to_raw).collect::<Vec<_>>();
+        let instance_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Transparency Panel Buffer"),
+            contents: bytemuck::cast_slice(&raw),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let panel_pipeline = |label, fragment_entry_point, targets: &[Option<wgpu::ColorTargetState>]| {
+            pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label,
+                    shader: include_str!("transparency.wgsl"),
+                    bind_group_layouts: &[screen_layout, camera_layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[PanelRaw::desc()],
+                    fragment_entry_point,
+                    targets,
+                    // Seen from both sides
+                    primitive: wgpu::PrimitiveState::default(),
+                    // Hidden behind the scene, but never hiding each other
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: texture::Texture::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            )
+        };
+
+        let sorted_pipeline = panel_pipeline(
+            "Transparency Sorted",
+            "fs_sorted",
+            &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        );
+
+        let weighted_blended = Self::is_supported(adapter).then(|| {
+            let accumulate_pipeline = panel_pipeline(
+                "Transparency Accumulate",
+                "fs_accumulate",
+                &[
+                    Some(wgpu::ColorTargetState {
+                        format: Self::ACCUM_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    // dst * (1 - alpha)
+                    Some(wgpu::ColorTargetState {
+                        format: Self::REVEALAGE_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::Zero,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::Zero,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                ],
+            );
+
+            let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            };
+            let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[texture_entry(0), texture_entry(1)],
+                label: Some("transparency_composite_bind_group_layout"),
+            });
+            let composite_pipeline = pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label: "Transparency Composite",
+                    shader: include_str!("transparency_composite.wgsl"),
+                    bind_group_layouts: &[&composite_layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[],
+                    fragment_entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            );
+
+            let (accum, revealage) = Self::create_targets(device, config);
+            let composite_bind_group = Self::create_composite_bind_group(device, &composite_layout, &accum, &revealage);
+            WeightedBlended {
+                accum,
+                revealage,
+                accumulate_pipeline,
+                composite_layout,
+                composite_bind_group,
+                composite_pipeline,
+            }
+        });
+
+        let method = if weighted_blended.is_some() {
+            TransparencyMethod::WeightedBlended
+        } else {
+            TransparencyMethod::Sorted
+        };
+        Self {
+            enabled: false,
+            method,
+            weighted_blended,
+            panels,
+            instance_buffer,
+            sorted_pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device or for a new color format. Keeps the method
+    // if it still works there.
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        adapter: &wgpu::Adapter,
+        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        depth: camera::DepthDirection,
+    ) -> Self {
+        let mut transparency = Self::new(device, pipelines, adapter, config, color_format, screen_layout, camera_layout, depth);
+        transparency.enabled = self.enabled;
+        transparency.set_method(self.method);
+        transparency
+    }
+
+    fn create_targets(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> (texture::Texture, texture::Texture) {
+        (
+            texture::Texture::create_render_target(device, config, Self::ACCUM_FORMAT, "transparency_accum"),
+            texture::Texture::create_render_target(device, config, Self::REVEALAGE_FORMAT, "transparency_revealage"),
+        )
+    }
+
+    fn create_composite_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        accum: &texture::Texture,
+        revealage: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage.view),
+                },
+            ],
+            label: Some("transparency_composite_bind_group"),
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        if let Some(weighted_blended) = &mut self.weighted_blended {
+            (weighted_blended.accum, weighted_blended.revealage) = Self::create_targets(device, config);
+            weighted_blended.composite_bind_group = Self::create_composite_bind_group(
+                device,
+                &weighted_blended.composite_layout,
+                &weighted_blended.accum,
+                &weighted_blended.revealage,
+            );
+        }
+    }
+
+    pub fn method(&self) -> TransparencyMethod {
+        self.method
+    }
+
+    // False if the adapter can't do weighted blended
+    pub fn set_method(&mut self, method: TransparencyMethod) -> bool {
+        if method == TransparencyMethod::WeightedBlended && self.weighted_blended.is_none() {
+            return false;
+        }
+        self.method = method;
+        true
+    }
+
+    // Three panels through each other and two more in front & behind, above the spheres
+    fn demo_panels() -> Vec<Panel> {
+        let center = cgmath::vec3(0.0, 2.5, 2.0);
+        let panel = |offset: cgmath::Vector3<f32>, rotation, size: f32, color| Panel {
+            transform: instance::Transform {
+                position: center + offset,
+                rotation,
+                scale: cgmath::vec3(size, size, size),
+            },
+            color,
+        };
+        let facing = cgmath::Quaternion::one();
+        let across = cgmath::Quaternion::from_angle_y(cgmath::Deg(90.0));
+        let flat = cgmath::Quaternion::from_angle_x(cgmath::Deg(90.0));
+        vec![
+            panel(cgmath::vec3(0.0, 0.0, 0.0), facing, 1.2, [1.0, 0.2, 0.2, 0.5]),
+            panel(cgmath::vec3(0.0, 0.0, 0.0), across, 1.2, [0.2, 1.0, 0.2, 0.5]),
+            panel(cgmath::vec3(0.0, 0.0, 0.0), flat, 1.2, [0.2, 0.4, 1.0, 0.5]),
+            panel(cgmath::vec3(0.3, 0.2, -1.0), facing, 0.8, [1.0, 0.9, 0.2, 0.4]),
+            panel(cgmath::vec3(-0.3, -0.2, 1.0), facing, 0.8, [0.9, 0.9, 0.9, 0.3]),
+        ]
+    }
+
+    // Call once per frame. Sorting only matters for the fallback, the weighted blended pass
+    // takes the panels in any order.
+    pub fn update(&self, queue: &wgpu::Queue, eye: cgmath::Point3<f32>) {
+        if !self.enabled || self.method != TransparencyMethod::Sorted {
+            return;
+        }
+        // Farthest first, by their centers
+        let mut order = self.panels.iter().collect::<Vec<_>>();
+        let distance = |panel: &Panel| (panel.transform.position - cgmath::vec3(eye.x, eye.y, eye.z)).magnitude2();
+        order.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        let raw = order.into_iter().map(Panel::to_raw).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw));
+    }
+
+    /*
+    *   Draws the panels over `output`, which holds the finished opaque scene, depth tested
+    *   against `depth` (the scene's, single sampled).
+    */
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        screen_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        // Kept as it is, the panels never write depth
+        let depth_attachment = wgpu::RenderPassDepthStencilAttachment {
+            view: depth,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true,
+            }),
+            stencil_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: true,
+            }),
+        };
+        let count = self.panels.len() as u32;
+
+        match (self.method, &self.weighted_blended) {
+            (TransparencyMethod::WeightedBlended, Some(weighted_blended)) => {
+                {
+                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Transparency Accumulate Pass"),
+                        color_attachments: &[
+                            Some(wgpu::RenderPassColorAttachment {
+                                view: &weighted_blended.accum.view,
+                                resolve_target: None,
+                                ops: wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                    store: true,
+                                },
+                            }),
+                            // Everything shows through until something's drawn
+                            Some(wgpu::RenderPassColorAttachment {
+                                view: &weighted_blended.revealage.view,
+                                resolve_target: None,
+                                ops: wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                                    store: true,
+                                },
+                            }),
+                        ],
+                        depth_stencil_attachment: Some(depth_attachment),
+                    });
+                    render_pass.set_pipeline(&weighted_blended.accumulate_pipeline);
+                    render_pass.set_bind_group(0, screen_bind_group, &[]);
+                    render_pass.set_bind_group(1, camera_bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+                    render_pass.draw(0..6, 0..count);
+                }
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Transparency Composite Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: output,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(&weighted_blended.composite_pipeline);
+                render_pass.set_bind_group(0, &weighted_blended.composite_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+            // Sorted by update
+            _ => {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Transparency Sorted Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: output,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(depth_attachment),
+                });
+                render_pass.set_pipeline(&self.sorted_pipeline);
+                render_pass.set_bind_group(0, screen_bind_group, &[]);
+                render_pass.set_bind_group(1, camera_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+                render_pass.draw(0..6, 0..count);
+            }
+        }
+    }
+}
+
+impl Panel {
+    fn to_raw(&self) -> PanelRaw {
+        PanelRaw {
+            model: self.transform.to_matrix().into(),
+            color: self.color,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Translucent panels, see transparency.rs. fs_accumulate is the weighted blended pass and
+// fs_sorted the plain alpha blended fallback. transparency_composite.wgsl puts the result of
+// the first over the scene.
+
+// @group(0) is the screen uniform, which the panels don't need
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view_pos: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct PanelInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    // Straight alpha
+    @location(9) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    // From the camera, for the weight
+    @location(1) distance: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32, panel: PanelInput) -> VertexOutput {
+    // A 2x2 square on the xy plane, two triangles
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let model_matrix = mat4x4<f32>(
+        panel.model_matrix_0,
+        panel.model_matrix_1,
+        panel.model_matrix_2,
+        panel.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(corners[in_vertex_index], 0.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.color = panel.color;
+    out.distance = distance(world_position.xyz, camera.view_pos.xyz);
+    return out;
+}
+
+struct AccumulateOutput {
+    // Premultiplied color & alpha, weighted, added up
+    @location(0) accum: vec4<f32>,
+    // Alpha, multiplied in as (1 - alpha): what's left showing through
+    @location(1) revealage: f32,
+};
+
+// McGuire & Bavoil's weight (eq. 10). Closer surfaces count for more, and the clamp keeps the
+// sums inside what a half float can hold.
+fn weight(view_distance: f32, alpha: f32) -> f32 {
+    return alpha * clamp(0.03 / (1e-5 + pow(view_distance / 200.0, 4.0)), 1e-2, 3e3);
+}
+
+@fragment
+fn fs_accumulate(in: VertexOutput) -> AccumulateOutput {
+    let alpha = in.color.a;
+    let w = weight(in.distance, alpha);
+    var out: AccumulateOutput;
+    out.accum = vec4<f32>(in.color.rgb * alpha, alpha) * w;
+    out.revealage = alpha;
+    return out;
+}
+
+@fragment
+fn fs_sorted(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,29 @@
+// Puts the weighted blended transparency over the scene, see transparency.rs
+
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // One triangle big enough to cover the whole screen
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let revealage = textureLoad(t_revealage, pixel, 0).r;
+    // Nothing transparent here, leave the scene alone
+    if revealage >= 1.0 {
+        discard;
+    }
+    let accum = textureLoad(t_accum, pixel, 0);
+    // The weighted average color, the weights cancel out
+    let average = accum.rgb / max(accum.a, 1e-5);
+    // Alpha blended over the scene with 1 - revealage as the coverage
+    return vec4<f32>(average, 1.0 - revealage);
+}
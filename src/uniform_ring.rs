@@ -0,0 +1,96 @@
+use crate::gpu_memory::{Tracked, TrackedDevice};
+
+/*
+*   A uniform buffer that's really several, used round & round a frame at a time. The first
+*   write after finish_frame moves on to the next slot, so what the gpu reads for a frame that's
+*   still in flight is never the buffer being written for the next one. Writes in the same frame
+*   go to the same slot, and a frame without writes stays on the slot it was on.
+*
+*   queue.write_buffer doesn't wait on the gpu either way, wgpu copies the data into staging
+*   memory of its own and queues the copy into the buffer. With one buffer though, that copy has
+*   to wait for the frame before it to finish reading, so the gpu can't start on a frame's
+*   uniforms while it's still drawing the last one. A slot per frame in flight takes that
+*   dependency away. More slots than frames in flight only cost memory.
+*
+*   Every slot gets the whole contents on each write, so switching to one that was last
+*   written a few frames ago never shows anything stale.
+*/
+pub struct UniformRing {
+    label: &'static str,
+    slots: Vec<(Tracked<wgpu::Buffer>, wgpu::BindGroup)>,
+    current: usize,
+    // The frame using `current` was submitted, the next write moves on
+    advance: bool,
+}
+
+impl UniformRing {
+    // One slot, a plain uniform buffer
+    pub const DEFAULT_SLOTS: usize = 1;
+    // Well past any sensible number of frames in flight
+    pub const MAX_SLOTS: usize = 8;
+
+    // `slots` buffers holding `contents`, bound at binding 0 of `layout`
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        label: &'static str,
+        contents: &[u8],
+        slots: usize,
+    ) -> Self {
+        let slots = (0..slots.max(1))
+            .map(|_| {
+                let buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some(label),
+                });
+                (buffer, bind_group)
+            })
+            .collect();
+        Self {
+            label,
+            slots,
+            current: 0,
+            advance: false,
+        }
+    }
+
+    // A new ring of `slots` buffers holding `contents`, starting again from the first
+    pub fn resize(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, contents: &[u8], slots: usize) {
+        *self = Self::new(device, layout, self.label, contents, slots);
+    }
+
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    // The slot the next frame binds
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.slots[self.current].1
+    }
+
+    // Moves on to the next slot first if the frame using this one has been submitted
+    pub fn write(&mut self, queue: &wgpu::Queue, contents: &[u8]) {
+        if std::mem::take(&mut self.advance) {
+            self.current = (self.current + 1) % self.slots.len();
+        }
+        queue.write_buffer(&self.slots[self.current].0, 0, contents);
+    }
+
+    // Call once the frame's commands are submitted
+    pub fn finish_frame(&mut self) {
+        self.advance = true;
+    }
+}
@@ -0,0 +1,289 @@
+use std::rc::Rc;
+
+use crate::batch::MeshTopology;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::instance::Transform;
+use crate::model::{self, Vertex};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::terrain;
+use crate::texture;
+
+/*
+*   A velocity buffer: for every pixel, how far the surface under it moved across the screen
+*   since the last frame, in texture coordinates. Effects like motion blur read it to know
+*   which way to smear each pixel.
+*
+*   It takes a pass of its own that draws the scene again with both this frame's and last
+*   frame's matrices, and stores the difference between the two screen positions. Both the
+*   camera and the objects moving show up in it. That means holding on to last frame's view
+*   projection and every instance's last transform, which is what `update` does.
+*
+*   Only the instances and the terrain are drawn into it. The background is left at 0, and
+*   the skinned & morphed models don't move around the scene so they're left out too.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct VelocityInstance {
+    model: [[f32; 4]; 4],
+    prev_model: [[f32; 4]; 4],
+}
+
+impl VelocityInstance {
+    fn new(current: &Transform, previous: &Transform) -> Self {
+        Self {
+            model: current.to_matrix().into(),
+            prev_model: previous.to_matrix().into(),
+        }
+    }
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        // Same locations as InstanceRaw's model matrix, followed by the previous one
+        const ATTRIBUTES: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+            10 => Float32x4,
+            11 => Float32x4,
+            12 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<VelocityInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct VelocityCamera {
+    view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+}
+
+pub struct VelocityBuffer {
+    // What effects sample, cleared to no motion
+    pub texture: texture::Texture,
+    // The velocity pass does its own depth test, so hidden surfaces don't leak their motion
+    depth: texture::Texture,
+    camera_buffer: Tracked<wgpu::Buffer>,
+    camera_bind_group: wgpu::BindGroup,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    instance_count: u32,
+    capacity: usize,
+    terrain_buffer: Tracked<wgpu::Buffer>,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    // For the terrain when its chunks are strips
+    strip_pipeline: Rc<wgpu::RenderPipeline>,
+    // Last frame's view projection & instance transforms
+    previous_view_proj: Option<cgmath::Matrix4<f32>>,
+    previous: Vec<Transform>,
+}
+
+impl VelocityBuffer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+    // Half float targets need EXT_color_buffer_float on WebGL
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        adapter
+            .get_texture_format_features(Self::FORMAT)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        capacity: usize,
+    ) -> Self {
+        let texture = texture::Texture::create_render_target(device, config, Self::FORMAT, "velocity_texture");
+        let depth = texture::Texture::create_depth_texture(device, config, "velocity_depth_texture");
+
+        let camera_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Velocity Camera Buffer"),
+            size: std::mem::size_of::<VelocityCamera>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("velocity_camera_bind_group_layout"),
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("velocity_camera_bind_group"),
+        });
+
+        let instance_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Velocity Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<VelocityInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let terrain_buffer = device.create_tracked_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Velocity Terrain Buffer"),
+            contents: bytemuck::cast_slice(&[VelocityInstance::new(&Transform::default(), &Transform::default())]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let pipeline = |topology: MeshTopology| {
+            pipelines.render_pipeline(
+                device,
+                &RenderPipelineDesc {
+                    label: "Velocity",
+                    shader: include_str!("velocity.wgsl"),
+                    bind_group_layouts: &[&camera_layout],
+                    vertex_entry_point: "vs_main",
+                    vertex_buffers: &[model::ModelVertex::desc(), VelocityInstance::desc()],
+                    fragment_entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Self::FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    primitive: wgpu::PrimitiveState {
+                        topology: topology.primitive(),
+                        strip_index_format: topology.strip_index_format(),
+                        cull_mode: Some(wgpu::Face::Back),
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: texture::Texture::DEPTH_FORMAT,
+                        depth_write_enabled: true,
+                        depth_compare: wgpu::CompareFunction::Less,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                },
+            )
+        };
+
+        Self {
+            texture,
+            depth,
+            camera_buffer,
+            camera_bind_group,
+            instance_buffer,
+            instance_count: 0,
+            capacity,
+            terrain_buffer,
+            pipeline: pipeline(MeshTopology::TriangleList),
+            strip_pipeline: pipeline(MeshTopology::TriangleStrip),
+            previous_view_proj: None,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.texture = texture::Texture::create_render_target(device, config, Self::FORMAT, "velocity_texture");
+        self.depth = texture::Texture::create_depth_texture(device, config, "velocity_depth_texture");
+    }
+
+    // Room for a different number of instances. Everything was just moved there rather than
+    // moving on its own, so last frame is forgotten and nothing gets blurred.
+    pub fn set_capacity(&mut self, device: &wgpu::Device, capacity: usize) {
+        self.instance_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Velocity Instance Buffer"),
+            size: (capacity.max(1) * std::mem::size_of::<VelocityInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.instance_count = 0;
+        self.capacity = capacity;
+        self.previous_view_proj = None;
+        self.previous.clear();
+    }
+
+    /*
+    *   Call once per frame with where everything is now. Instances are matched up with last
+    *   frame's by their index, new ones count as not having moved. Afterwards this frame
+    *   becomes the previous one.
+    */
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        view_proj: cgmath::Matrix4<f32>,
+        instances: &[Transform],
+        terrain: Option<&terrain::Terrain>,
+    ) {
+        let prev_view_proj = self.previous_view_proj.unwrap_or(view_proj);
+        let camera = VelocityCamera {
+            view_proj: view_proj.into(),
+            prev_view_proj: prev_view_proj.into(),
+        };
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+
+        let velocity_instances = instances
+            .iter()
+            .take(self.capacity)
+            .enumerate()
+            .map(|(i, current)| VelocityInstance::new(current, self.previous.get(i).unwrap_or(current)))
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&velocity_instances));
+        self.instance_count = velocity_instances.len() as u32;
+
+        // The terrain doesn't move, only the camera's motion shows up on it
+        if let Some(terrain) = terrain {
+            let transform = terrain.transform();
+            queue.write_buffer(&self.terrain_buffer, 0, bytemuck::cast_slice(&[VelocityInstance::new(&transform, &transform)]));
+        }
+
+        self.previous_view_proj = Some(view_proj);
+        self.previous.clear();
+        self.previous.extend_from_slice(instances);
+    }
+
+    // Draws `mesh` for every instance given to update, plus the terrain
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, mesh: &model::Mesh, terrain: Option<&terrain::Terrain>) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Velocity Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..mesh.num_elements, 0, 0..self.instance_count);
+
+        if let Some(terrain) = terrain.filter(|terrain| terrain.enabled) {
+            if terrain.topology() == MeshTopology::TriangleStrip {
+                render_pass.set_pipeline(&self.strip_pipeline);
+            }
+            render_pass.set_vertex_buffer(1, self.terrain_buffer.slice(..));
+            terrain.draw_chunks(&mut render_pass);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Screen space motion of every pixel since the last frame, see velocity.rs
+
+struct VelocityCamera {
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> cameras: VelocityCamera;
+
+// Only the position of the ModelVertex is needed
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) prev_model_matrix_0: vec4<f32>,
+    @location(10) prev_model_matrix_1: vec4<f32>,
+    @location(11) prev_model_matrix_2: vec4<f32>,
+    @location(12) prev_model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Where the vertex is now & was last frame. The divide by w has to wait for the fragment
+    // shader, after interpolation.
+    @location(0) current: vec4<f32>,
+    @location(1) previous: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let prev_model_matrix = mat4x4<f32>(
+        instance.prev_model_matrix_0,
+        instance.prev_model_matrix_1,
+        instance.prev_model_matrix_2,
+        instance.prev_model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = cameras.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.current = out.clip_position;
+    out.previous = cameras.prev_view_proj * prev_model_matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let current = in.current.xy / in.current.w;
+    let previous = in.previous.xy / in.previous.w;
+    // From clip space (-1..1, y up) to texture coordinates (0..1, y down)
+    let velocity = (current - previous) * vec2<f32>(0.5, -0.5);
+    return vec4<f32>(velocity, 0.0, 1.0);
+}
@@ -0,0 +1,185 @@
+use std::rc::Rc;
+
+use cgmath::InnerSpace;
+
+use crate::camera;
+use crate::gpu_memory::{Tracked, TrackedDevice};
+use crate::pipeline_cache::{PipelineCache, RenderPipelineDesc};
+use crate::texture;
+
+/*
+*   Shows off what reversed depth (camera::DepthDirection) is for. A row of squares goes across
+*   the screen, each one further away than the last, from 10 up to 90 units. Every square is a
+*   red one with a green one GAP behind it, both turned away from the camera so their depth
+*   changes across them.
+*
+*   The green one is drawn first. The red one only covers it where its depth comes out strictly
+*   closer, so wherever the depth buffer can't tell the two apart the green one shows through.
+*   With standard depth that's the far squares, which break up into green stripes & noise
+*   (z-fighting). With reversed depth in a float buffer they all stay red.
+*
+*   The squares follow the camera and get bigger with the distance, so they're always on screen
+*   and the same size there.
+*/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ZFightingVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl ZFightingVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x3,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ZFightingVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+pub struct ZFightingDemo {
+    pub enabled: bool,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl ZFightingDemo {
+    const DISTANCES: [f32; 5] = [10.0, 30.0, 50.0, 70.0, 90.0];
+    // How far behind the red squares the green ones are, in world units
+    const GAP: f32 = 0.002;
+    // How far each square is turned away from the camera
+    const TILT: cgmath::Deg<f32> = cgmath::Deg(60.0);
+    const FRONT_COLOR: [f32; 3] = [0.9, 0.1, 0.1];
+    const BACK_COLOR: [f32; 3] = [0.1, 0.8, 0.1];
+    // Two squares of two triangles each per distance
+    const VERTEX_COUNT: usize = Self::DISTANCES.len() * 2 * 6;
+
+    // Drawn in the main pass, so it takes that pass' sample count
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let vertex_buffer = device.create_tracked_buffer(&wgpu::BufferDescriptor {
+            label: Some("Z-Fighting Vertex Buffer"),
+            size: (Self::VERTEX_COUNT * std::mem::size_of::<ZFightingVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline = pipelines.render_pipeline(
+            device,
+            &RenderPipelineDesc {
+                label: "Z-Fighting",
+                shader: include_str!("zfighting.wgsl"),
+                bind_group_layouts: &[screen_layout, camera_layout],
+                vertex_entry_point: "vs_main",
+                vertex_buffers: &[ZFightingVertex::desc()],
+                fragment_entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                // Both sides, the squares are turned pretty far
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: depth.compare(wgpu::CompareFunction::Less),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            },
+        );
+
+        Self {
+            enabled: false,
+            vertex_buffer,
+            pipeline,
+        }
+    }
+
+    // Rebuilds the gpu resources on a (new) device, format, sample count or depth direction.
+    // The squares get uploaded again on the next update.
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &PipelineCache,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth: camera::DepthDirection,
+        screen_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let mut demo = Self::new(device, pipelines, color_format, sample_count, depth, screen_layout, camera_layout);
+        demo.enabled = self.enabled;
+        demo
+    }
+
+    // Call once per frame, puts the squares in front of the camera
+    pub fn update(&self, queue: &wgpu::Queue, camera: &camera::Camera) {
+        if !self.enabled {
+            return;
+        }
+        let eye = cgmath::Vector3::new(camera.eye.x, camera.eye.y, camera.eye.z);
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        // Half the screen's height at a distance of 1
+        let half_height = (cgmath::Rad::from(cgmath::Deg(camera.fovy)) * 0.5).0.tan();
+        let (sin, cos) = cgmath::Rad::from(Self::TILT).0.sin_cos();
+        let across = right * cos + forward * sin;
+
+        let count = Self::DISTANCES.len() as f32;
+        let mut vertices = Vec::with_capacity(Self::VERTEX_COUNT);
+        for (i, distance) in Self::DISTANCES.iter().enumerate() {
+            // Evenly spaced across the middle of the screen, a tenth of its height across
+            let x = ((i as f32 + 0.5) / count * 2.0 - 1.0) * 0.8 * half_height * camera.aspect;
+            let size = 0.1 * half_height * distance;
+            let center = eye + forward * *distance + right * x * *distance;
+            // The one behind first, see the top
+            for (offset, color) in [(Self::GAP, Self::BACK_COLOR), (0.0, Self::FRONT_COLOR)] {
+                let center = center + forward * offset;
+                let corner = |a: f32, b: f32| ZFightingVertex {
+                    position: (center + across * a * size + up * b * size).into(),
+                    color,
+                };
+                vertices.extend_from_slice(&[
+                    corner(-1.0, -1.0),
+                    corner(1.0, -1.0),
+                    corner(1.0, 1.0),
+                    corner(-1.0, -1.0),
+                    corner(1.0, 1.0),
+                    corner(-1.0, 1.0),
+                ]);
+            }
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    // Expects the screen & camera bind groups to already be set at @group(0) & @group(1)
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..Self::VERTEX_COUNT as u32, 0..1);
+    }
+}